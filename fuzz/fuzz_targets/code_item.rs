@@ -0,0 +1,20 @@
+#![no_main]
+use dex::{code::CodeItem, Dex, DexReader};
+use libfuzzer_sys::fuzz_target;
+use scroll::Pread;
+
+thread_local! {
+    /// The file whose strings, types and other items the parsed item references.
+    static DEX: Dex<&'static [u8]> =
+        DexReader::from_vec(&include_bytes!("../../resources/classes.dex")[..]).unwrap();
+}
+
+fuzz_target!(|data: &[u8]| {
+    DEX.with(|dex| {
+        if let Ok(code) = data.pread_with::<CodeItem>(0, dex) {
+            for instruction in code.instructions() {
+                let _ = instruction;
+            }
+        }
+    });
+});
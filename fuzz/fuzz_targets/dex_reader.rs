@@ -0,0 +1,24 @@
+#![no_main]
+use dex::{verifier, DexReader};
+use libfuzzer_sys::fuzz_target;
+
+// Loads a whole file and the items the accessors parse lazily.
+fuzz_target!(|data: &[u8]| {
+    let dex = match DexReader::from_vec(data.to_vec()) {
+        Ok(dex) => dex,
+        Err(_) => return,
+    };
+    let _ = verifier::verify_structure(&dex);
+    for string in dex.strings() {
+        let _ = string;
+    }
+    for class in dex.classes().flatten() {
+        for method in class.methods() {
+            if let Ok(Some(code)) = method.try_code() {
+                for instruction in code.instructions() {
+                    let _ = instruction;
+                }
+            }
+        }
+    }
+});
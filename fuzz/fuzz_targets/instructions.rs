@@ -0,0 +1,26 @@
+#![no_main]
+use dex::instruction::{InstructionIter, Quickening};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte selects the instruction set, the other ones are the code units.
+fuzz_target!(|data: &[u8]| {
+    let (quickening, data) = match data.split_first() {
+        Some((0, data)) => (Quickening::None, data),
+        Some((1, data)) => (Quickening::Dalvik, data),
+        Some((_, data)) => (Quickening::Art, data),
+        None => return,
+    };
+    let insns: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    for instruction in InstructionIter::with_quickening(&insns, quickening).flatten() {
+        let _ = (
+            instruction.registers(),
+            instruction.index(),
+            instruction.literal(),
+            instruction.branch_offset(),
+            instruction.payload(),
+        );
+    }
+});
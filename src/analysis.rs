@@ -0,0 +1,238 @@
+//! Detection of the libraries bundled in a `Dex` from structural fingerprints of their
+//! classes, see `detect_libraries`.
+use std::collections::{BTreeSet, HashMap};
+
+use getset::{CopyGetters, Getters};
+
+use crate::{deps::package, error::Error, Dex, Result};
+
+/// Structural fingerprints of the classes of a version of a library.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[get = "pub"]
+pub struct LibrarySignature {
+    /// Name of the library.
+    name: String,
+    /// Version of the library.
+    version: String,
+    /// `Fingerprint::hash` of the classes of the library.
+    class_hashes: BTreeSet<u64>,
+}
+
+impl LibrarySignature {
+    /// Signature of a library from the fingerprint hashes of its classes.
+    pub fn new<I: IntoIterator<Item = u64>>(name: &str, version: &str, class_hashes: I) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            class_hashes: class_hashes.into_iter().collect(),
+        }
+    }
+
+    /// Signature of a library from a `Dex` which contains only the classes of the library.
+    pub fn from_dex<T: AsRef<[u8]>>(name: &str, version: &str, dex: &Dex<T>) -> Result<Self> {
+        let mut class_hashes = BTreeSet::new();
+        for class in dex.classes() {
+            class_hashes.insert(class?.structural_fingerprint()?.hash());
+        }
+        Ok(Self::new(name, version, class_hashes))
+    }
+}
+
+/// A set of `LibrarySignature`s used by `detect_libraries`.
+#[derive(Debug, Clone, Getters, CopyGetters)]
+pub struct LibraryDatabase {
+    /// Signatures of the known libraries.
+    #[get = "pub"]
+    libraries: Vec<LibrarySignature>,
+    /// Fraction of the classes of a library which must be found for the library to be
+    /// reported. Defaults to `0.5`.
+    #[get_copy = "pub"]
+    min_ratio: f64,
+}
+
+impl Default for LibraryDatabase {
+    fn default() -> Self {
+        Self {
+            libraries: Vec::new(),
+            min_ratio: 0.5,
+        }
+    }
+}
+
+impl LibraryDatabase {
+    /// Empty database with the default `min_ratio`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the fraction of the classes of a library which must be found.
+    pub fn with_min_ratio(mut self, min_ratio: f64) -> Self {
+        self.min_ratio = min_ratio;
+        self
+    }
+
+    /// Adds a library to the database.
+    pub fn add(&mut self, library: LibrarySignature) {
+        self.libraries.push(library);
+    }
+
+    /// Parses a database with one library per line: its name, its version and the
+    /// hexadecimal fingerprint hashes of its classes, separated by whitespace. Empty lines
+    /// and lines starting with `#` are ignored.
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut database = Self::new();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (name, version) = match (parts.next(), parts.next()) {
+                (Some(name), Some(version)) => (name, version),
+                _ => {
+                    return Err(Error::MalFormed(format!(
+                        "Missing library version on line {}",
+                        number + 1
+                    )))
+                }
+            };
+            let class_hashes = parts
+                .map(|hash| {
+                    u64::from_str_radix(hash, 16).map_err(|_| {
+                        Error::MalFormed(format!(
+                            "Invalid class hash {} on line {}",
+                            hash,
+                            number + 1
+                        ))
+                    })
+                })
+                .collect::<Result<BTreeSet<_>>>()?;
+            database.add(LibrarySignature::new(name, version, class_hashes));
+        }
+        Ok(database)
+    }
+}
+
+/// A library found by `detect_libraries`.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct DetectedLibrary {
+    /// Name of the library.
+    #[get = "pub"]
+    name: String,
+    /// Version of the library which matches best.
+    #[get = "pub"]
+    version: String,
+    /// Number of classes of the library found in the `Dex`.
+    #[get_copy = "pub"]
+    matched_classes: usize,
+    /// Number of classes of the library.
+    #[get_copy = "pub"]
+    total_classes: usize,
+    /// Packages of the `Dex` which contain the classes of the library, e.g. `a.b`.
+    #[get = "pub"]
+    packages: BTreeSet<String>,
+}
+
+impl DetectedLibrary {
+    /// Fraction of the classes of the library found in the `Dex`.
+    pub fn ratio(&self) -> f64 {
+        self.matched_classes as f64 / self.total_classes as f64
+    }
+}
+
+/// Finds the libraries of `database` bundled in `dex` by comparing the structural
+/// fingerprints of the classes, so that libraries are found even if they are obfuscated.
+/// Only the best matching version of each library is reported.
+pub fn detect_libraries<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    database: &LibraryDatabase,
+) -> Result<Vec<DetectedLibrary>> {
+    let mut classes: HashMap<u64, Vec<String>> = HashMap::new();
+    for class in dex.classes() {
+        let class = class?;
+        classes
+            .entry(class.structural_fingerprint()?.hash())
+            .or_default()
+            .push(class.jtype().to_java_type());
+    }
+
+    let mut detected: Vec<DetectedLibrary> = Vec::new();
+    for library in database.libraries() {
+        if library.class_hashes().is_empty() {
+            continue;
+        }
+        let found: Vec<&String> = library
+            .class_hashes()
+            .iter()
+            .filter_map(|hash| classes.get(hash))
+            .flatten()
+            .collect();
+        let matched_classes = library
+            .class_hashes()
+            .iter()
+            .filter(|hash| classes.contains_key(hash))
+            .count();
+        let candidate = DetectedLibrary {
+            name: library.name().clone(),
+            version: library.version().clone(),
+            matched_classes,
+            total_classes: library.class_hashes().len(),
+            packages: found
+                .iter()
+                .map(|class| package(class).to_string())
+                .collect(),
+        };
+        if candidate.ratio() < database.min_ratio() {
+            continue;
+        }
+        match detected.iter_mut().find(|d| d.name == candidate.name) {
+            Some(previous) if previous.ratio() < candidate.ratio() => *previous = candidate,
+            Some(_) => {}
+            None => detected.push(candidate),
+        }
+    }
+    debug!(target: "analysis", "detected {} libraries", detected.len());
+    Ok(detected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_libraries, LibraryDatabase, LibrarySignature};
+
+    #[test]
+    fn test_detect_libraries() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let widget = dex
+            .find_class_by_name("Lmobi/intuitit/android/widget/BoundRemoteViews$BindingAction;")
+            .expect("failed to load class")
+            .expect("class not found")
+            .structural_fingerprint()
+            .unwrap()
+            .hash();
+
+        let database = LibraryDatabase::parse(&format!(
+            "# name version hashes\n\
+             widgets 1.0 {:x} 1\n\
+             widgets 2.0 {:x}\n\
+             missing 1.0 1 2\n",
+            widget, widget
+        ))
+        .expect("failed to parse database");
+        assert_eq!(database.libraries().len(), 3);
+
+        let detected = detect_libraries(&dex, &database).expect("failed to detect libraries");
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].name(), "widgets");
+        assert_eq!(detected[0].version(), "2.0");
+        assert_eq!(detected[0].matched_classes(), 1);
+        assert!(detected[0]
+            .packages()
+            .contains("mobi.intuitit.android.widget"));
+
+        let mut database = database.with_min_ratio(1.0);
+        database.add(LibrarySignature::new("other", "1.0", vec![3]));
+        assert_eq!(detect_libraries(&dex, &database).unwrap().len(), 1);
+        assert!(LibraryDatabase::parse("widgets").is_err());
+        assert!(LibraryDatabase::parse("widgets 1.0 xyz").is_err());
+    }
+}
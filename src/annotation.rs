@@ -11,7 +11,8 @@ use crate::{
     jtype::{Type, TypeId},
     method::MethodId,
     string::{DexString, StringId},
-    ubyte, uint,
+    ubyte, uint, ulong,
+    utils::Empty,
 };
 
 use num_derive::FromPrimitive;
@@ -20,6 +21,7 @@ use num_traits::FromPrimitive;
 /// Contains the type and parameters of an Annotation.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#encoded-annotation)
 #[derive(Debug, Getters, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[get = "pub"]
 pub struct EncodedAnnotation {
     /// Type of the annotation. Should be a class type.
@@ -65,6 +67,7 @@ where
 /// the annotation, this structure represents `name = "Benjamin Franklin"`.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#annotation-element)
 #[derive(Debug, Getters, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[get = "pub"]
 pub struct AnnotationElement {
     /// Name of the element. Should conform to the syntax defined
@@ -94,6 +97,7 @@ where
 /// Visibility of an annotation.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#visibility)
 #[derive(Debug, FromPrimitive, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Visibility {
     /// Visible only to the Build system.
     Build = 0x0,
@@ -106,6 +110,7 @@ pub enum Visibility {
 /// An Annotation along with its visibility.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#annotation-item)
 #[derive(Debug, Getters, CopyGetters)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct AnnotationItem {
     /// Visibility of this annotation.
     #[get_copy = "pub"]
@@ -150,11 +155,19 @@ where
 /// List of Annotation Sets. Used for method parameter annotations.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#set-ref-list)
 #[derive(Debug, Default, Getters)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 #[get = "pub"]
 pub struct AnnotationSetRefList {
     annotation_set_list: Vec<AnnotationSetItem>,
 }
 
+impl Empty for AnnotationSetRefList {
+    const EMPTY: &'static Self = &Self {
+        annotation_set_list: Vec::new(),
+    };
+}
+
 impl Deref for AnnotationSetRefList {
     type Target = Vec<AnnotationSetItem>;
 
@@ -193,11 +206,19 @@ where
 /// A set of annotations on an element.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#annotation-set-item)
 #[derive(Debug, Default, Getters)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 #[get = "pub"]
 pub struct AnnotationSetItem {
     annotations: Vec<AnnotationItem>,
 }
 
+impl Empty for AnnotationSetItem {
+    const EMPTY: &'static Self = &Self {
+        annotations: Vec::new(),
+    };
+}
+
 impl Deref for AnnotationSetItem {
     type Target = Vec<AnnotationItem>;
 
@@ -239,6 +260,7 @@ pub struct ParameterAnnotations {
     #[get_copy = "pub"]
     method_idx: MethodId,
     /// The list of annotation sets for the parameters.
+    #[get = "pub"]
     pub(crate) annotations: AnnotationSetRefList,
 }
 
@@ -271,6 +293,7 @@ where
 pub struct MethodAnnotations {
     #[get_copy = "pub"]
     method_idx: MethodId,
+    #[get = "pub"]
     pub(crate) annotations: AnnotationSetItem,
 }
 
@@ -331,10 +354,15 @@ where
 /// Annotations of the fields, methods and parameters of a class and the class itself.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#annotations-directory)
 #[derive(Debug, Default, Getters)]
+#[get = "pub"]
 pub struct AnnotationsDirectoryItem {
+    /// Annotations of the class.
     pub(crate) class_annotations: AnnotationSetItem,
+    /// Annotations of the fields, sorted by `FieldId`.
     pub(crate) field_annotations: Vec<FieldAnnotations>,
+    /// Annotations of the methods, sorted by `MethodId`.
     pub(crate) method_annotations: Vec<MethodAnnotations>,
+    /// Annotations of the parameters of the methods, sorted by `MethodId`.
     pub(crate) parameter_annotations: Vec<ParameterAnnotations>,
 }
 
@@ -370,3 +398,51 @@ where
         ))
     }
 }
+
+/// Offsets of the annotation sets of an `AnnotationsDirectoryItem`, read without parsing the
+/// sets so that `Class`es can load their annotations lazily.
+#[derive(Debug, Default)]
+pub(crate) struct AnnotationsDirectoryOffsets {
+    pub(crate) class_annotations_off: uint,
+    pub(crate) field_annotations: Vec<(FieldId, uint)>,
+    pub(crate) method_annotations: Vec<(MethodId, uint)>,
+    pub(crate) parameter_annotations: Vec<(MethodId, uint)>,
+}
+
+impl<'a, S> ctx::TryFromCtx<'a, &super::Dex<S>> for AnnotationsDirectoryOffsets
+where
+    S: AsRef<[u8]>,
+{
+    type Error = Error;
+    type Size = usize;
+
+    fn try_from_ctx(source: &'a [u8], ctx: &super::Dex<S>) -> super::Result<(Self, Self::Size)> {
+        let offset = &mut 0;
+        let endian = ctx.get_endian();
+        let class_annotations_off: uint = source.gread_with(offset, endian)?;
+        let fields_size: uint = source.gread_with(offset, endian)?;
+        let annotated_method_size: uint = source.gread_with(offset, endian)?;
+        let annotated_parameters_size: uint = source.gread_with(offset, endian)?;
+        let mut entries = |size: uint| {
+            (0..size)
+                .map(|_| {
+                    let idx: uint = source.gread_with(offset, endian)?;
+                    let off: uint = source.gread_with(offset, endian)?;
+                    Ok((ulong::from(idx), off))
+                })
+                .collect::<super::Result<Vec<_>>>()
+        };
+        let field_annotations = entries(fields_size)?;
+        let method_annotations = entries(annotated_method_size)?;
+        let parameter_annotations = entries(annotated_parameters_size)?;
+        Ok((
+            Self {
+                class_annotations_off,
+                field_annotations,
+                method_annotations,
+                parameter_annotations,
+            },
+            *offset,
+        ))
+    }
+}
@@ -0,0 +1,1453 @@
+//! Construction of dex files from scratch, like small test fixtures or instrumentation
+//! stubs. The classes are described with `ClassBuilder`s, and `DexBuilder::build` collects
+//! the strings, types, protos, fields and methods they use, deduplicates them and sorts them
+//! in the order the format requires. The code of the methods is given as code units, and
+//! the indexes of the items the instructions reference are patched in once they are known.
+use std::collections::{HashMap, HashSet};
+
+use cesu8::to_java_cesu8;
+use scroll::LE;
+
+use crate::{
+    class,
+    error::Error,
+    field,
+    instruction::{Format, IndexType, Opcode},
+    method, ubyte, uint, ushort, writer, ItemType, Result, ENDIAN_CONSTANT, NO_INDEX,
+};
+
+/// Size of the header of the written files.
+const HEADER_SIZE: uint = 0x70;
+
+/// Type descriptors, protos, fields and methods are referenced by 16-bit indexes.
+const MAX_INDEX: usize = 0x1_0000;
+
+/// An item referenced by an instruction, whose index `DexBuilder::build` patches into the
+/// index operand of the instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Reference {
+    /// A string, for `const-string`.
+    String(String),
+    /// A type, like `Ljava/lang/Object;`, for `new-instance` or `check-cast`.
+    Type(String),
+    /// A field, for the `iget`, `iput`, `sget` and `sput` instructions.
+    Field {
+        /// Descriptor of the class defining the field.
+        class: String,
+        /// Name of the field.
+        name: String,
+        /// Descriptor of the type of the field.
+        field_type: String,
+    },
+    /// A method, for the invokes.
+    Method {
+        /// Descriptor of the class defining the method.
+        class: String,
+        /// Name of the method.
+        name: String,
+        /// Descriptors of the parameters.
+        params: Vec<String>,
+        /// Descriptor of the return type.
+        return_type: String,
+    },
+}
+
+impl Reference {
+    /// The field `name` of type `field_type` of `class`.
+    pub fn field(class: &str, name: &str, field_type: &str) -> Self {
+        Reference::Field {
+            class: class.to_string(),
+            name: name.to_string(),
+            field_type: field_type.to_string(),
+        }
+    }
+
+    /// The method `name` of `class`.
+    pub fn method(class: &str, name: &str, params: &[&str], return_type: &str) -> Self {
+        Reference::Method {
+            class: class.to_string(),
+            name: name.to_string(),
+            params: params.iter().map(ToString::to_string).collect(),
+            return_type: return_type.to_string(),
+        }
+    }
+}
+
+/// The code of a method, see `MethodBuilder::with_code`. It has no try blocks and no debug
+/// info.
+#[derive(Debug, Clone)]
+pub struct CodeBuilder {
+    registers_size: ushort,
+    ins_size: ushort,
+    outs_size: ushort,
+    insns: Vec<ushort>,
+    references: Vec<(usize, Reference)>,
+}
+
+impl CodeBuilder {
+    /// Code of a method using `registers_size` registers, the last `ins_size` of them for
+    /// its arguments, and `outs_size` registers for the arguments of the methods it invokes.
+    pub fn new(
+        registers_size: ushort,
+        ins_size: ushort,
+        outs_size: ushort,
+        insns: Vec<ushort>,
+    ) -> Self {
+        Self {
+            registers_size,
+            ins_size,
+            outs_size,
+            insns,
+            references: Vec::new(),
+        }
+    }
+
+    /// Patches the index of `reference` into the instruction at `address`, in code units.
+    /// The kind of the reference must be the one the opcode of the instruction takes.
+    pub fn with_reference(mut self, address: usize, reference: Reference) -> Self {
+        self.references.push((address, reference));
+        self
+    }
+}
+
+/// A field of a `ClassBuilder`, static if its access flags have `STATIC`.
+#[derive(Debug, Clone)]
+pub struct FieldBuilder {
+    name: String,
+    field_type: String,
+    access_flags: field::AccessFlags,
+}
+
+impl FieldBuilder {
+    /// Field `name` of type `field_type`.
+    pub fn new(name: &str, field_type: &str, access_flags: field::AccessFlags) -> Self {
+        Self {
+            name: name.to_string(),
+            field_type: field_type.to_string(),
+            access_flags,
+        }
+    }
+}
+
+/// A method of a `ClassBuilder`. Static, private methods and constructors are direct
+/// methods, the other ones are virtual.
+#[derive(Debug, Clone)]
+pub struct MethodBuilder {
+    name: String,
+    proto: ProtoKey,
+    access_flags: method::AccessFlags,
+    code: Option<CodeBuilder>,
+}
+
+impl MethodBuilder {
+    /// Method `name` without code, like an abstract or a native method.
+    pub fn new(
+        name: &str,
+        params: &[&str],
+        return_type: &str,
+        access_flags: method::AccessFlags,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            proto: ProtoKey::new(params, return_type),
+            access_flags,
+            code: None,
+        }
+    }
+
+    /// Sets the code of the method.
+    pub fn with_code(mut self, code: CodeBuilder) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    fn is_direct(&self) -> bool {
+        self.access_flags.intersects(
+            method::AccessFlags::STATIC
+                | method::AccessFlags::PRIVATE
+                | method::AccessFlags::CONSTRUCTOR,
+        )
+    }
+}
+
+/// A class defined by a `DexBuilder`. Its superclass is `java.lang.Object` unless another
+/// one is given.
+#[derive(Debug, Clone)]
+pub struct ClassBuilder {
+    descriptor: String,
+    access_flags: class::AccessFlags,
+    superclass: Option<String>,
+    interfaces: Vec<String>,
+    source_file: Option<String>,
+    fields: Vec<FieldBuilder>,
+    methods: Vec<MethodBuilder>,
+}
+
+impl ClassBuilder {
+    /// Class of the type `descriptor`, like `Lcom/example/Foo;`.
+    pub fn new(descriptor: &str, access_flags: class::AccessFlags) -> Self {
+        let superclass = match descriptor {
+            "Ljava/lang/Object;" => None,
+            _ => Some("Ljava/lang/Object;".to_string()),
+        };
+        Self {
+            descriptor: descriptor.to_string(),
+            access_flags,
+            superclass,
+            interfaces: Vec::new(),
+            source_file: None,
+            fields: Vec::new(),
+            methods: Vec::new(),
+        }
+    }
+
+    /// Sets the superclass.
+    pub fn with_superclass(mut self, descriptor: &str) -> Self {
+        self.superclass = Some(descriptor.to_string());
+        self
+    }
+
+    /// Adds an implemented interface.
+    pub fn with_interface(mut self, descriptor: &str) -> Self {
+        self.interfaces.push(descriptor.to_string());
+        self
+    }
+
+    /// Sets the name of the source file, like `Foo.java`.
+    pub fn with_source_file(mut self, source_file: &str) -> Self {
+        self.source_file = Some(source_file.to_string());
+        self
+    }
+
+    /// Adds a field.
+    pub fn with_field(mut self, field: FieldBuilder) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// Adds a method.
+    pub fn with_method(mut self, method: MethodBuilder) -> Self {
+        self.methods.push(method);
+        self
+    }
+}
+
+/// Builder of a dex file, see the module documentation.
+#[derive(Debug, Clone, Default)]
+pub struct DexBuilder {
+    classes: Vec<ClassBuilder>,
+    strings: Vec<String>,
+    types: Vec<String>,
+    protos: Vec<ProtoKey>,
+}
+
+impl DexBuilder {
+    /// Builder of an empty dex file.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a class definition.
+    pub fn with_class(mut self, class: ClassBuilder) -> Self {
+        self.classes.push(class);
+        self
+    }
+
+    /// Adds a string, even if no item uses it.
+    pub fn with_string(mut self, string: &str) -> Self {
+        self.strings.push(string.to_string());
+        self
+    }
+
+    /// Adds a type, even if no item uses it.
+    pub fn with_type(mut self, descriptor: &str) -> Self {
+        self.types.push(descriptor.to_string());
+        self
+    }
+
+    /// Adds a proto, even if no method uses it.
+    pub fn with_proto(mut self, params: &[&str], return_type: &str) -> Self {
+        self.protos.push(ProtoKey::new(params, return_type));
+        self
+    }
+
+    /// Writes the dex file, in version `035`.
+    pub fn build(&self) -> Result<Vec<u8>> {
+        let pools = Pools::collect(self)?;
+        let order = class_order(&self.classes)?;
+        let mut sections = vec![(ItemType::Header, 1, 0)];
+        let mut offset = HEADER_SIZE;
+        let ids_sections = [
+            (ItemType::StringIdItem, pools.strings.len()),
+            (ItemType::TypeIdItem, pools.types.len()),
+            (ItemType::ProtoIdItem, pools.protos.len()),
+            (ItemType::FieldIdItem, pools.fields.len()),
+            (ItemType::MethodIdItem, pools.methods.len()),
+            (ItemType::ClassDefItem, self.classes.len()),
+        ];
+        for (item_type, count) in ids_sections {
+            if count > 0 {
+                sections.push((item_type, count as uint, offset));
+                offset += count as uint * item_type.item_size().unwrap_or(0);
+            }
+        }
+
+        let data_off = offset;
+        let mut data = DataSection {
+            bytes: Vec::new(),
+            start: data_off,
+        };
+        let mut code_offsets: HashMap<(usize, usize), uint> = HashMap::new();
+        // the code items and the type lists are aligned to 4 bytes.
+        let mut code_items = (0, data.offset());
+        for &class_index in &order {
+            for (method_index, method) in self.classes[class_index].methods.iter().enumerate() {
+                if let Some(code) = &method.code {
+                    data.align();
+                    code_offsets.insert((class_index, method_index), data.offset());
+                    data.write_code_item(code, &pools)?;
+                    code_items.0 += 1;
+                }
+            }
+        }
+        data.push_section(&mut sections, ItemType::CodeItem, code_items);
+
+        let mut type_lists: HashMap<Vec<String>, uint> = HashMap::new();
+        data.align();
+        let mut type_list_items = (0, data.offset());
+        let lists = pools
+            .protos
+            .iter()
+            .map(|proto| &proto.params)
+            .chain(self.classes.iter().map(|class| &class.interfaces));
+        for list in lists {
+            if list.is_empty() || type_lists.contains_key(list) {
+                continue;
+            }
+            data.align();
+            type_lists.insert(list.clone(), data.offset());
+            data.write_uint(list.len() as uint);
+            for descriptor in list {
+                data.write_ushort(pools.type_ids[descriptor] as ushort);
+            }
+            type_list_items.0 += 1;
+        }
+        data.push_section(&mut sections, ItemType::TypeList, type_list_items);
+
+        let mut string_data_offsets = Vec::with_capacity(pools.strings.len());
+        let string_data = (pools.strings.len() as uint, data.offset());
+        for string in &pools.strings {
+            string_data_offsets.push(data.offset());
+            data.write_uleb128(string.encode_utf16().count() as uint);
+            data.bytes.extend_from_slice(&to_java_cesu8(string));
+            data.bytes.push(0);
+        }
+        data.push_section(&mut sections, ItemType::StringDataItem, string_data);
+
+        let mut class_data_offsets = HashMap::new();
+        let mut class_data = (0, data.offset());
+        for &class_index in &order {
+            let class = &self.classes[class_index];
+            if class.fields.is_empty() && class.methods.is_empty() {
+                continue;
+            }
+            class_data_offsets.insert(class_index, data.offset());
+            data.write_class_data(class_index, class, &pools, &code_offsets)?;
+            class_data.0 += 1;
+        }
+        data.push_section(&mut sections, ItemType::ClassDataItem, class_data);
+
+        data.align();
+        let map_off = data.offset();
+        sections.push((ItemType::MapList, 1, map_off));
+        data.write_uint(sections.len() as uint);
+        for (item_type, count, offset) in &sections {
+            data.write_ushort(*item_type as ushort);
+            data.write_ushort(0);
+            data.write_uint(*count);
+            data.write_uint(*offset);
+        }
+
+        let mut bytes = Vec::with_capacity((data_off as usize) + data.bytes.len());
+        bytes.extend_from_slice(b"dex\n035\0");
+        // the checksum and the signature are written last.
+        bytes.resize(32, 0);
+        let file_size = data_off + data.bytes.len() as uint;
+        for value in [file_size, HEADER_SIZE] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&ENDIAN_CONSTANT);
+        for value in [0, 0, map_off] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for (item_type, _) in ids_sections {
+            let (count, offset) = sections
+                .iter()
+                .find(|(section, _, _)| *section == item_type)
+                .map_or((0, 0), |(_, count, offset)| (*count, *offset));
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(data.bytes.len() as uint).to_le_bytes());
+        bytes.extend_from_slice(&data_off.to_le_bytes());
+
+        for offset in string_data_offsets {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+        for descriptor in &pools.types {
+            bytes.extend_from_slice(&pools.string_ids[descriptor].to_le_bytes());
+        }
+        for proto in &pools.protos {
+            let params_off = type_lists.get(&proto.params).copied().unwrap_or(0);
+            for value in [
+                pools.string_ids[&proto.shorty()?],
+                pools.type_ids[&proto.return_type],
+                params_off,
+            ] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        for field in &pools.fields {
+            bytes.extend_from_slice(&(pools.type_ids[&field.class] as ushort).to_le_bytes());
+            bytes.extend_from_slice(&(pools.type_ids[&field.field_type] as ushort).to_le_bytes());
+            bytes.extend_from_slice(&pools.string_ids[&field.name].to_le_bytes());
+        }
+        for method in &pools.methods {
+            bytes.extend_from_slice(&(pools.type_ids[&method.class] as ushort).to_le_bytes());
+            bytes.extend_from_slice(&(pools.proto_ids[&method.proto] as ushort).to_le_bytes());
+            bytes.extend_from_slice(&pools.string_ids[&method.name].to_le_bytes());
+        }
+        for &class_index in &order {
+            let class = &self.classes[class_index];
+            let type_id = |descriptor: &Option<String>| {
+                descriptor
+                    .as_ref()
+                    .map_or(NO_INDEX, |descriptor| pools.type_ids[descriptor])
+            };
+            for value in [
+                pools.type_ids[&class.descriptor],
+                class.access_flags.bits(),
+                type_id(&class.superclass),
+                type_lists.get(&class.interfaces).copied().unwrap_or(0),
+                class
+                    .source_file
+                    .as_ref()
+                    .map_or(NO_INDEX, |source_file| pools.string_ids[source_file]),
+                0,
+                class_data_offsets.get(&class_index).copied().unwrap_or(0),
+                0,
+            ] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&data.bytes);
+        writer::write_checksum_and_signature(&mut bytes, LE)?;
+        debug!(target: "builder", "built a dex file of {} bytes", bytes.len());
+        Ok(bytes)
+    }
+}
+
+/// Return type and parameters of a method.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ProtoKey {
+    return_type: String,
+    params: Vec<String>,
+}
+
+impl ProtoKey {
+    fn new(params: &[&str], return_type: &str) -> Self {
+        Self {
+            return_type: return_type.to_string(),
+            params: params.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    /// The short form of the descriptors, with `L` for all the references.
+    fn shorty(&self) -> Result<String> {
+        std::iter::once(&self.return_type)
+            .chain(&self.params)
+            .map(|descriptor| match descriptor.chars().next() {
+                Some('[') => Ok('L'),
+                Some(c) => Ok(c),
+                None => Err(Error::MalFormed("Empty type descriptor".to_string())),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FieldKey {
+    class: String,
+    name: String,
+    field_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct MethodKey {
+    class: String,
+    name: String,
+    proto: ProtoKey,
+}
+
+/// The items of the ids sections, before they are sorted.
+#[derive(Debug, Default)]
+struct PoolSets {
+    strings: HashSet<String>,
+    types: HashSet<String>,
+    protos: HashSet<ProtoKey>,
+    fields: HashSet<FieldKey>,
+    methods: HashSet<MethodKey>,
+}
+
+impl PoolSets {
+    fn add_type(&mut self, descriptor: &str) {
+        self.strings.insert(descriptor.to_string());
+        self.types.insert(descriptor.to_string());
+    }
+
+    fn add_proto(&mut self, proto: &ProtoKey) -> Result<()> {
+        self.strings.insert(proto.shorty()?);
+        self.add_type(&proto.return_type);
+        for param in &proto.params {
+            self.add_type(param);
+        }
+        self.protos.insert(proto.clone());
+        Ok(())
+    }
+
+    fn add_reference(&mut self, reference: Reference) -> Result<()> {
+        match reference {
+            Reference::String(string) => {
+                self.strings.insert(string);
+            }
+            Reference::Type(descriptor) => self.add_type(&descriptor),
+            Reference::Field {
+                class,
+                name,
+                field_type,
+            } => {
+                self.add_type(&class);
+                self.add_type(&field_type);
+                self.strings.insert(name.clone());
+                self.fields.insert(FieldKey {
+                    class,
+                    name,
+                    field_type,
+                });
+            }
+            Reference::Method {
+                class,
+                name,
+                params,
+                return_type,
+            } => {
+                let proto = ProtoKey {
+                    return_type,
+                    params,
+                };
+                self.add_type(&class);
+                self.add_proto(&proto)?;
+                self.strings.insert(name.clone());
+                self.methods.insert(MethodKey { class, name, proto });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The sorted items of the ids sections, with their ids.
+#[derive(Debug)]
+struct Pools {
+    strings: Vec<String>,
+    string_ids: HashMap<String, uint>,
+    types: Vec<String>,
+    type_ids: HashMap<String, uint>,
+    protos: Vec<ProtoKey>,
+    proto_ids: HashMap<ProtoKey, uint>,
+    fields: Vec<FieldKey>,
+    field_ids: HashMap<FieldKey, uint>,
+    methods: Vec<MethodKey>,
+    method_ids: HashMap<MethodKey, uint>,
+}
+
+impl Pools {
+    fn collect(builder: &DexBuilder) -> Result<Self> {
+        let mut sets = PoolSets::default();
+        sets.strings.extend(builder.strings.iter().cloned());
+        for descriptor in &builder.types {
+            sets.add_type(descriptor);
+        }
+        for proto in &builder.protos {
+            sets.add_proto(proto)?;
+        }
+        for class in &builder.classes {
+            sets.add_type(&class.descriptor);
+            for descriptor in class.superclass.iter().chain(&class.interfaces) {
+                sets.add_type(descriptor);
+            }
+            sets.strings.extend(class.source_file.iter().cloned());
+            for field in &class.fields {
+                sets.add_reference(Reference::field(
+                    &class.descriptor,
+                    &field.name,
+                    &field.field_type,
+                ))?;
+            }
+            for method in &class.methods {
+                sets.add_reference(Reference::Method {
+                    class: class.descriptor.clone(),
+                    name: method.name.clone(),
+                    params: method.proto.params.clone(),
+                    return_type: method.proto.return_type.clone(),
+                })?;
+                for (_, reference) in method.code.iter().flat_map(|code| &code.references) {
+                    sets.add_reference(reference.clone())?;
+                }
+            }
+        }
+        let PoolSets {
+            strings,
+            types,
+            protos,
+            fields,
+            methods,
+        } = sets;
+
+        // strings are sorted by their UTF-16 code units.
+        let mut strings: Vec<String> = strings.into_iter().collect();
+        strings.sort_by_cached_key(|string| string.encode_utf16().collect::<Vec<_>>());
+        let string_ids = ids(&strings);
+        let mut types: Vec<String> = types.into_iter().collect();
+        types.sort_by_key(|descriptor| string_ids[descriptor]);
+        let type_ids = ids(&types);
+        let mut protos: Vec<ProtoKey> = protos.into_iter().collect();
+        protos.sort_by_cached_key(|proto| {
+            let params: Vec<uint> = proto.params.iter().map(|param| type_ids[param]).collect();
+            (type_ids[&proto.return_type], params)
+        });
+        let proto_ids = ids(&protos);
+        let mut fields: Vec<FieldKey> = fields.into_iter().collect();
+        fields.sort_by_key(|field| {
+            (
+                type_ids[&field.class],
+                string_ids[&field.name],
+                type_ids[&field.field_type],
+            )
+        });
+        let field_ids = ids(&fields);
+        let mut methods: Vec<MethodKey> = methods.into_iter().collect();
+        methods.sort_by_key(|method| {
+            (
+                type_ids[&method.class],
+                string_ids[&method.name],
+                proto_ids[&method.proto],
+            )
+        });
+        let method_ids = ids(&methods);
+        let pools = Pools {
+            strings,
+            string_ids,
+            types,
+            type_ids,
+            protos,
+            proto_ids,
+            fields,
+            field_ids,
+            methods,
+            method_ids,
+        };
+
+        for (section, len) in [
+            ("types", pools.types.len()),
+            ("protos", pools.protos.len()),
+            ("fields", pools.fields.len()),
+            ("methods", pools.methods.len()),
+        ] {
+            if len > MAX_INDEX {
+                return Err(Error::MalFormed(format!(
+                    "Too many {}: {}, at most {} can be referenced",
+                    section, len, MAX_INDEX
+                )));
+            }
+        }
+        Ok(pools)
+    }
+
+    /// The kind of the index of `reference` and the index.
+    fn index(&self, reference: &Reference) -> (IndexType, uint) {
+        match reference {
+            Reference::String(string) => (IndexType::String, self.string_ids[string]),
+            Reference::Type(descriptor) => (IndexType::Type, self.type_ids[descriptor]),
+            Reference::Field {
+                class,
+                name,
+                field_type,
+            } => {
+                let field = FieldKey {
+                    class: class.clone(),
+                    name: name.clone(),
+                    field_type: field_type.clone(),
+                };
+                (IndexType::Field, self.field_ids[&field])
+            }
+            Reference::Method {
+                class,
+                name,
+                params,
+                return_type,
+            } => {
+                let method = MethodKey {
+                    class: class.clone(),
+                    name: name.clone(),
+                    proto: ProtoKey {
+                        return_type: return_type.clone(),
+                        params: params.clone(),
+                    },
+                };
+                (IndexType::Method, self.method_ids[&method])
+            }
+        }
+    }
+}
+
+/// Ids of the items of a sorted pool.
+fn ids<K: Clone + Eq + std::hash::Hash>(items: &[K]) -> HashMap<K, uint> {
+    items
+        .iter()
+        .enumerate()
+        .map(|(id, item)| (item.clone(), id as uint))
+        .collect()
+}
+
+/// Order of the class definitions, where the superclass and the interfaces of a class come
+/// before it when they are defined too.
+fn class_order(classes: &[ClassBuilder]) -> Result<Vec<usize>> {
+    let mut by_descriptor = HashMap::new();
+    for (index, class) in classes.iter().enumerate() {
+        if by_descriptor
+            .insert(class.descriptor.as_str(), index)
+            .is_some()
+        {
+            return Err(Error::MalFormed(format!(
+                "Class {} is defined twice",
+                class.descriptor
+            )));
+        }
+    }
+    // visited: whether the class is in the order, or `false` while its supertypes are added.
+    let mut visited: HashMap<usize, bool> = HashMap::new();
+    let mut order = Vec::with_capacity(classes.len());
+    let mut stack: Vec<(usize, bool)> = (0..classes.len()).rev().map(|i| (i, false)).collect();
+    while let Some((index, supertypes_added)) = stack.pop() {
+        if supertypes_added {
+            visited.insert(index, true);
+            order.push(index);
+            continue;
+        }
+        match visited.get(&index) {
+            Some(true) => continue,
+            Some(false) => {
+                return Err(Error::MalFormed(format!(
+                    "Class {} is its own supertype",
+                    classes[index].descriptor
+                )))
+            }
+            None => {}
+        }
+        visited.insert(index, false);
+        stack.push((index, true));
+        let class = &classes[index];
+        for descriptor in class.superclass.iter().chain(&class.interfaces).rev() {
+            if let Some(&supertype) = by_descriptor.get(descriptor.as_str()) {
+                if visited.get(&supertype) == Some(&false) {
+                    return Err(Error::MalFormed(format!(
+                        "Class {} is its own supertype",
+                        classes[supertype].descriptor
+                    )));
+                }
+                stack.push((supertype, false));
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// The data section being written, which starts at `start` in the file.
+struct DataSection {
+    bytes: Vec<u8>,
+    start: uint,
+}
+
+impl DataSection {
+    fn offset(&self) -> uint {
+        self.start + self.bytes.len() as uint
+    }
+
+    fn align(&mut self) {
+        let aligned = (self.bytes.len() + 3) & !3;
+        self.bytes.resize(aligned, 0);
+    }
+
+    fn write_ushort(&mut self, value: ushort) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_uint(&mut self, value: uint) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_uleb128(&mut self, mut value: uint) {
+        loop {
+            let byte = (value & 0x7f) as ubyte;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    /// Adds the map list entry of a section of `count` items at `offset`, if it isn't
+    /// empty.
+    fn push_section(
+        &self,
+        sections: &mut Vec<(ItemType, uint, uint)>,
+        item_type: ItemType,
+        (count, offset): (uint, uint),
+    ) {
+        if count > 0 {
+            sections.push((item_type, count, offset));
+        }
+    }
+
+    fn write_code_item(&mut self, code: &CodeBuilder, pools: &Pools) -> Result<()> {
+        let mut insns = code.insns.clone();
+        for (address, reference) in &code.references {
+            patch_reference(&mut insns, *address, pools.index(reference))?;
+        }
+        for value in [code.registers_size, code.ins_size, code.outs_size, 0] {
+            self.write_ushort(value);
+        }
+        self.write_uint(0);
+        self.write_uint(insns.len() as uint);
+        for insn in insns {
+            self.write_ushort(insn);
+        }
+        Ok(())
+    }
+
+    fn write_class_data(
+        &mut self,
+        class_index: usize,
+        class: &ClassBuilder,
+        pools: &Pools,
+        code_offsets: &HashMap<(usize, usize), uint>,
+    ) -> Result<()> {
+        let field_id = |field: &FieldBuilder| {
+            let key = FieldKey {
+                class: class.descriptor.clone(),
+                name: field.name.clone(),
+                field_type: field.field_type.clone(),
+            };
+            pools.field_ids[&key]
+        };
+        let mut static_fields = Vec::new();
+        let mut instance_fields = Vec::new();
+        for field in &class.fields {
+            let access_flags = field.access_flags.bits() as uint;
+            if field.access_flags.contains(field::AccessFlags::STATIC) {
+                static_fields.push((field_id(field), access_flags, None));
+            } else {
+                instance_fields.push((field_id(field), access_flags, None));
+            }
+        }
+        let mut direct_methods = Vec::new();
+        let mut virtual_methods = Vec::new();
+        for (method_index, method) in class.methods.iter().enumerate() {
+            let key = MethodKey {
+                class: class.descriptor.clone(),
+                name: method.name.clone(),
+                proto: method.proto.clone(),
+            };
+            let code_off = code_offsets
+                .get(&(class_index, method_index))
+                .copied()
+                .unwrap_or(0);
+            let method_id = pools.method_ids[&key];
+            let access_flags = method.access_flags.bits() as uint;
+            if method.is_direct() {
+                direct_methods.push((method_id, access_flags, Some(code_off)));
+            } else {
+                virtual_methods.push((method_id, access_flags, Some(code_off)));
+            }
+        }
+
+        let members = [
+            static_fields,
+            instance_fields,
+            direct_methods,
+            virtual_methods,
+        ];
+        for list in &members {
+            self.write_uleb128(list.len() as uint);
+        }
+        for mut list in members {
+            list.sort_unstable_by_key(|(id, _, _)| *id);
+            let mut previous = None;
+            for (id, access_flags, code_off) in list {
+                if previous == Some(id) {
+                    return Err(Error::MalFormed(format!(
+                        "Class {} has two members with the same id {}",
+                        class.descriptor, id
+                    )));
+                }
+                self.write_uleb128(id - previous.unwrap_or(0));
+                self.write_uleb128(access_flags);
+                if let Some(code_off) = code_off {
+                    self.write_uleb128(code_off);
+                }
+                previous = Some(id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes `index` to the index operand of the instruction at `address` of `insns`.
+pub(crate) fn patch_reference(
+    insns: &mut [ushort],
+    address: usize,
+    (index_type, index): (IndexType, uint),
+) -> Result<()> {
+    let opcode = insns
+        .get(address)
+        .and_then(|insn| Opcode::from_standard((insn & 0xff) as ubyte))
+        .ok_or_else(|| Error::MalFormed(format!("No instruction at address {}", address)))?;
+    if opcode.index_type() != index_type {
+        return Err(Error::MalFormed(format!(
+            "{} at address {} doesn't take a {:?} index",
+            opcode.mnemonic(),
+            address,
+            index_type
+        )));
+    }
+    let out_of_bounds = || {
+        Error::MalFormed(format!(
+            "{} at address {} is truncated",
+            opcode.mnemonic(),
+            address
+        ))
+    };
+    if opcode.format() == Format::F31c {
+        let operand = insns
+            .get_mut(address + 1..address + 3)
+            .ok_or_else(out_of_bounds)?;
+        operand[0] = index as ushort;
+        operand[1] = (index >> 16) as ushort;
+    } else {
+        if index > uint::from(ushort::MAX) {
+            return Err(Error::MalFormed(format!(
+                "Index {} of {} at address {} doesn't fit in 16 bits",
+                index,
+                opcode.mnemonic(),
+                address
+            )));
+        }
+        *insns.get_mut(address + 1).ok_or_else(out_of_bounds)? = index as ushort;
+    }
+    Ok(())
+}
+
+/// `Arbitrary` impls generating builders which `DexBuilder::build` accepts: the names and
+/// the descriptors are valid, the members of a class are unique and the class hierarchy
+/// has no cycles.
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls {
+    use std::collections::HashSet;
+
+    use arbitrary::{Arbitrary, Result, Unstructured};
+
+    use super::{
+        ClassBuilder, CodeBuilder, DexBuilder, FieldBuilder, MethodBuilder, ProtoKey, Reference,
+    };
+
+    /// Characters of the names, with some which take 2, 3 and 6 bytes in MUTF-8.
+    const NAME_CHARS: &[char] = &[
+        'a',
+        'b',
+        'z',
+        'A',
+        'Z',
+        '0',
+        '9',
+        '_',
+        '$',
+        'é',
+        'λ',
+        '中',
+        '\u{1f600}',
+    ];
+
+    const PRIMITIVES: &[&str] = &["Z", "B", "S", "C", "I", "J", "F", "D"];
+
+    /// Most lists have at most this many items, to keep the files small.
+    const MAX_LEN: usize = 6;
+
+    fn name(u: &mut Unstructured) -> Result<String> {
+        let len = u.int_in_range(1..=8)?;
+        (0..len).map(|_| u.choose(NAME_CHARS).copied()).collect()
+    }
+
+    /// Any string, with chars of all the planes and NULs, which MUTF-8 encodes in 2 bytes.
+    fn string(u: &mut Unstructured) -> Result<String> {
+        let len = u.int_in_range(0..=8)?;
+        (0..len).map(|_| u.arbitrary::<char>()).collect()
+    }
+
+    fn class_descriptor(u: &mut Unstructured) -> Result<String> {
+        let mut segments = vec![name(u)?];
+        for _ in 0..u.int_in_range(0..=2)? {
+            segments.push(name(u)?);
+        }
+        Ok(format!("L{};", segments.join("/")))
+    }
+
+    fn field_type(u: &mut Unstructured) -> Result<String> {
+        let dimensions = if u.ratio(1, 4)? {
+            u.int_in_range(1..=2)?
+        } else {
+            0
+        };
+        let element = if u.arbitrary()? {
+            class_descriptor(u)?
+        } else {
+            u.choose(PRIMITIVES)?.to_string()
+        };
+        Ok("[".repeat(dimensions) + &element)
+    }
+
+    fn return_type(u: &mut Unstructured) -> Result<String> {
+        if u.ratio(1, 3)? {
+            Ok("V".to_string())
+        } else {
+            field_type(u)
+        }
+    }
+
+    fn list<'a, T>(
+        u: &mut Unstructured<'a>,
+        mut item: impl FnMut(&mut Unstructured<'a>) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        (0..u.int_in_range(0..=MAX_LEN)?).map(|_| item(u)).collect()
+    }
+
+    fn proto(u: &mut Unstructured) -> Result<ProtoKey> {
+        Ok(ProtoKey {
+            return_type: return_type(u)?,
+            params: list(u, |u| {
+                if u.ratio(1, 2)? {
+                    field_type(u)
+                } else {
+                    Ok(u.choose(PRIMITIVES)?.to_string())
+                }
+            })?,
+        })
+    }
+
+    impl<'a> Arbitrary<'a> for Reference {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(match u.int_in_range(0..=3)? {
+                0 => Reference::String(string(u)?),
+                1 => Reference::Type(field_type(u)?),
+                2 => Reference::Field {
+                    class: class_descriptor(u)?,
+                    name: name(u)?,
+                    field_type: field_type(u)?,
+                },
+                _ => {
+                    let ProtoKey {
+                        return_type,
+                        params,
+                    } = proto(u)?;
+                    Reference::Method {
+                        class: class_descriptor(u)?,
+                        name: name(u)?,
+                        params,
+                        return_type,
+                    }
+                }
+            })
+        }
+    }
+
+    /// Constants, and instructions referencing items, followed by a `return-void`.
+    impl<'a> Arbitrary<'a> for CodeBuilder {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let registers_size = u.int_in_range(1..=16)?;
+            let ins_size = u.int_in_range(0..=registers_size)?;
+            let outs_size = u.int_in_range(0..=5)?;
+            let mut code = CodeBuilder::new(registers_size, ins_size, outs_size, Vec::new());
+            for _ in 0..u.int_in_range(0..=MAX_LEN)? {
+                let address = code.insns.len();
+                let insns: &[u16] = match u.arbitrary()? {
+                    // nop
+                    None if u.arbitrary()? => &[0x0000],
+                    // const/4 v0, #literal
+                    None => {
+                        let literal: u16 = u.int_in_range(0..=15)?;
+                        code.insns.push(0x0012 | literal << 12);
+                        continue;
+                    }
+                    Some(reference) => {
+                        let insns: &[u16] = match &reference {
+                            // const-string/jumbo v0, string
+                            Reference::String(_) if u.arbitrary()? => &[0x001b, 0, 0],
+                            // const-string v0, string
+                            Reference::String(_) => &[0x001a, 0],
+                            // const-class v0, type
+                            Reference::Type(_) => &[0x001c, 0],
+                            // sget v0, field
+                            Reference::Field { .. } => &[0x0060, 0],
+                            // invoke-static {}, method
+                            Reference::Method { .. } => &[0x0071, 0, 0],
+                        };
+                        code.references.push((address, reference));
+                        insns
+                    }
+                };
+                code.insns.extend_from_slice(insns);
+            }
+            // return-void
+            code.insns.push(0x000e);
+            Ok(code)
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for FieldBuilder {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(FieldBuilder {
+                name: name(u)?,
+                field_type: field_type(u)?,
+                access_flags: u.arbitrary()?,
+            })
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for MethodBuilder {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(MethodBuilder {
+                name: name(u)?,
+                proto: proto(u)?,
+                access_flags: u.arbitrary()?,
+                code: u.arbitrary()?,
+            })
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for ClassBuilder {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let mut class = ClassBuilder::new(&class_descriptor(u)?, u.arbitrary()?);
+            class.interfaces = list(u, class_descriptor)?;
+            if u.arbitrary()? {
+                class.source_file = Some(string(u)?);
+            }
+            let mut fields = HashSet::new();
+            for field in list(u, FieldBuilder::arbitrary)? {
+                if fields.insert((field.name.clone(), field.field_type.clone())) {
+                    class.fields.push(field);
+                }
+            }
+            let mut methods = HashSet::new();
+            for method in list(u, MethodBuilder::arbitrary)? {
+                if methods.insert((method.name.clone(), method.proto.clone())) {
+                    class.methods.push(method);
+                }
+            }
+            Ok(class)
+        }
+    }
+
+    /// The supertypes of a class are only classes defined after it, which `build` moves
+    /// before it.
+    impl<'a> Arbitrary<'a> for DexBuilder {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            let mut builder = DexBuilder::new();
+            let mut descriptors = HashSet::new();
+            for class in list(u, ClassBuilder::arbitrary)? {
+                if descriptors.insert(class.descriptor.clone()) {
+                    builder.classes.push(class);
+                }
+            }
+            let descriptors: Vec<String> = builder
+                .classes
+                .iter()
+                .map(|class| class.descriptor.clone())
+                .collect();
+            for (index, class) in builder.classes.iter_mut().enumerate() {
+                let (defined, later) = descriptors.split_at(index + 1);
+                if !later.is_empty() && u.ratio(1, 2)? {
+                    class.superclass = Some(u.choose(later)?.clone());
+                }
+                if !later.is_empty() && u.ratio(1, 2)? {
+                    class.interfaces.push(u.choose(later)?.clone());
+                }
+                class
+                    .interfaces
+                    .retain(|descriptor| !defined.contains(descriptor));
+            }
+            builder.strings = list(u, string)?;
+            builder.types = list(u, field_type)?;
+            builder.protos = list(u, proto)?;
+            Ok(builder)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClassBuilder, CodeBuilder, DexBuilder, FieldBuilder, MethodBuilder, Reference};
+    use crate::{class, field, method, verifier, DexReader};
+
+    #[test]
+    fn test_build() {
+        let object_init = Reference::method("Ljava/lang/Object;", "<init>", &[], "V");
+        let hello = ClassBuilder::new("Lcom/example/Hello;", class::AccessFlags::PUBLIC)
+            .with_superclass("Lcom/example/Base;")
+            .with_source_file("Hello.java")
+            .with_field(FieldBuilder::new("count", "I", field::AccessFlags::PRIVATE))
+            .with_method(
+                MethodBuilder::new(
+                    "greet",
+                    &[],
+                    "Ljava/lang/String;",
+                    method::AccessFlags::PUBLIC | method::AccessFlags::STATIC,
+                )
+                // const-string v0, "hello"; return-object v0
+                .with_code(
+                    CodeBuilder::new(1, 0, 0, vec![0x001a, 0, 0x0011])
+                        .with_reference(0, Reference::String("hello".to_string())),
+                ),
+            );
+        let base = ClassBuilder::new("Lcom/example/Base;", class::AccessFlags::PUBLIC)
+            .with_method(
+                MethodBuilder::new(
+                    "<init>",
+                    &[],
+                    "V",
+                    method::AccessFlags::PUBLIC | method::AccessFlags::CONSTRUCTOR,
+                )
+                // invoke-direct {v0}, Ljava/lang/Object;-><init>()V; return-void
+                .with_code(
+                    CodeBuilder::new(1, 1, 1, vec![0x1070, 0, 0, 0x000e])
+                        .with_reference(0, object_init.clone()),
+                ),
+            )
+            .with_method(MethodBuilder::new(
+                "run",
+                &["I", "[Ljava/lang/String;"],
+                "V",
+                method::AccessFlags::PUBLIC | method::AccessFlags::ABSTRACT,
+            ));
+        let bytes = DexBuilder::new()
+            .with_class(hello)
+            .with_class(base)
+            .with_string("hello")
+            .build()
+            .unwrap();
+
+        let dex = DexReader::from_vec(bytes).expect("failed to load built dex");
+        assert!(dex.is_signature_valid());
+        assert_eq!(verifier::verify_structure(&dex).unwrap(), &[]);
+        let descriptors: Vec<_> = dex
+            .class_defs()
+            .map(|class_def| {
+                let class_def = class_def.unwrap();
+                dex.get_type(class_def.class_idx()).unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(descriptors, ["Lcom/example/Base;", "Lcom/example/Hello;"]);
+
+        let hello = dex
+            .find_class_by_name("Lcom/example/Hello;")
+            .unwrap()
+            .unwrap();
+        assert_eq!(hello.source_file().unwrap(), "Hello.java");
+        assert_eq!(hello.fields().count(), 1);
+        let greet = hello.methods().find(|m| *m.name() == "greet").unwrap();
+        let instruction = greet
+            .code()
+            .unwrap()
+            .instructions()
+            .next()
+            .unwrap()
+            .unwrap();
+        let string_id = instruction.index().unwrap() as crate::string::StringId;
+        assert_eq!(dex.get_string(string_id).unwrap(), "hello");
+
+        let base = dex
+            .find_class_by_name("Lcom/example/Base;")
+            .unwrap()
+            .unwrap();
+        let run = base.methods().find(|m| *m.name() == "run").unwrap();
+        assert_eq!(run.shorty(), "VIL");
+        assert!(run.code().is_none());
+        let init = base.methods().find(|m| *m.name() == "<init>").unwrap();
+        let instruction = init.code().unwrap().instructions().next().unwrap().unwrap();
+        let method = dex.get_method_item(instruction.index().unwrap()).unwrap();
+        let class = dex.get_type(method.class_idx().into()).unwrap();
+        assert_eq!(class.type_descriptor(), "Ljava/lang/Object;");
+
+        let bad_reference = DexBuilder::new().with_class(
+            ClassBuilder::new("LBad;", class::AccessFlags::PUBLIC).with_method(
+                MethodBuilder::new("f", &[], "V", method::AccessFlags::STATIC).with_code(
+                    CodeBuilder::new(0, 0, 0, vec![0x000e]).with_reference(0, object_init),
+                ),
+            ),
+        );
+        assert!(bad_reference.build().is_err());
+    }
+
+    /// Asserts that `dex` has the classes, members, code and strings of `builder`.
+    #[cfg(feature = "arbitrary")]
+    fn assert_built(dex: &crate::Dex<Vec<u8>>, builder: &DexBuilder) {
+        use crate::{
+            instruction::{Format, IndexType, InstructionIter},
+            string::StringId,
+        };
+
+        assert!(dex.is_signature_valid());
+        assert_eq!(verifier::verify_structure(dex).unwrap(), &[]);
+        let strings: Vec<String> = dex.strings().map(|s| s.unwrap().to_string()).collect();
+        for string in builder.strings.iter().chain(&builder.types) {
+            assert!(strings.contains(string), "missing string {:?}", string);
+        }
+        assert_eq!(dex.class_defs().count(), builder.classes.len());
+        for expected in &builder.classes {
+            let class = dex
+                .find_class_by_name(&expected.descriptor)
+                .unwrap()
+                .unwrap();
+            assert_eq!(class.access_flags(), expected.access_flags);
+            let descriptor = |jtype: &crate::jtype::Type| jtype.type_descriptor().to_string();
+            assert_eq!(class.superclass_type().map(descriptor), expected.superclass);
+            let interfaces: Vec<String> = class.interfaces().iter().map(descriptor).collect();
+            assert_eq!(interfaces, expected.interfaces);
+            let source_file = class.source_file().map(ToString::to_string);
+            assert_eq!(source_file, expected.source_file);
+
+            assert_eq!(class.fields().count(), expected.fields.len());
+            for field in &expected.fields {
+                let parsed = class
+                    .fields()
+                    .find(|f| {
+                        *f.name() == field.name.as_str() && *f.jtype() == field.field_type.as_str()
+                    })
+                    .unwrap();
+                assert_eq!(parsed.access_flags(), field.access_flags);
+            }
+            assert_eq!(class.methods().count(), expected.methods.len());
+            for method in &expected.methods {
+                let parsed = class
+                    .methods()
+                    .find(|m| {
+                        *m.name() == method.name.as_str()
+                            && *m.return_type() == method.proto.return_type.as_str()
+                            && m.params()
+                                .iter()
+                                .map(descriptor)
+                                .eq(method.proto.params.clone())
+                    })
+                    .unwrap();
+                assert_eq!(parsed.access_flags(), method.access_flags);
+                assert_eq!(
+                    class.direct_methods().iter().any(|m| m.id() == parsed.id()),
+                    method.is_direct()
+                );
+                let (code, parsed) = match (&method.code, parsed.code()) {
+                    (Some(code), Some(parsed)) => (code, parsed),
+                    (code, parsed) => {
+                        assert_eq!(code.is_some(), parsed.is_some());
+                        continue;
+                    }
+                };
+                assert_eq!(
+                    (
+                        parsed.registers_size(),
+                        parsed.ins_size(),
+                        parsed.outs_size()
+                    ),
+                    (code.registers_size, code.ins_size, code.outs_size)
+                );
+                assert_eq!(parsed.insns().len(), code.insns.len());
+                for (address, reference) in &code.references {
+                    let instruction = InstructionIter::new(&parsed.insns()[*address..])
+                        .next()
+                        .unwrap()
+                        .unwrap();
+                    let index = instruction.index().unwrap();
+                    let (resolved, expected) = match reference {
+                        Reference::String(string) => (
+                            dex.get_string(index as StringId).unwrap().to_string(),
+                            string.clone(),
+                        ),
+                        Reference::Type(jtype) => (
+                            descriptor(&dex.get_type(index as _).unwrap()),
+                            jtype.clone(),
+                        ),
+                        Reference::Field {
+                            class,
+                            name,
+                            field_type,
+                        } => (
+                            dex.get_field_item(index).unwrap().descriptor(dex).unwrap(),
+                            format!("{}->{}:{}", class, name, field_type),
+                        ),
+                        Reference::Method {
+                            class,
+                            name,
+                            params,
+                            return_type,
+                        } => (
+                            dex.get_method_item(index).unwrap().descriptor(dex).unwrap(),
+                            format!("{}->{}({}){}", class, name, params.concat(), return_type),
+                        ),
+                    };
+                    assert_eq!(resolved, expected);
+                    let jumbo = instruction.format() == Format::F31c;
+                    assert!(!jumbo || instruction.opcode().index_type() == IndexType::String);
+                }
+            }
+        }
+    }
+
+    /// Builds arbitrary dex files, loads them and writes them back, and checks that the
+    /// loaded files have what the builders describe.
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_build_round_trip() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        use crate::writer::DexWriter;
+
+        // xorshift, for the same inputs on every run.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next_byte = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        };
+        for _ in 0..200 {
+            let data: Vec<u8> = (0..4096).map(|_| next_byte()).collect();
+            let builder = DexBuilder::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            let bytes = builder.build().unwrap();
+            let dex = DexReader::from_vec(bytes).expect("failed to load built dex");
+            assert_built(&dex, &builder);
+
+            let written = DexWriter::new(&dex).write().unwrap();
+            let dex = DexReader::from_vec(written).expect("failed to load written dex");
+            assert_built(&dex, &builder);
+        }
+    }
+}
@@ -1,32 +1,151 @@
-use std::{cell::RefCell, cmp::Eq, hash::Hash, num::NonZeroUsize, rc::Rc};
+use std::{
+    cmp::Eq,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock},
+};
 
+use getset::CopyGetters;
 use lru::LruCache;
 
-/// LRU cache that provides interior mutability
+/// Number of items of each cache of a `Dex` by default.
+const DEFAULT_CACHE_SIZE: usize = 4096;
+
+/// Capacity of a cache of a `Dex`, see `CacheConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheSize {
+    /// Nothing is cached, the items are parsed on each access.
+    Disabled,
+    /// At most this many items are cached, the least recently used ones are evicted.
+    Bounded(NonZeroUsize),
+    /// Every item is kept once parsed.
+    Unbounded,
+}
+
+impl CacheSize {
+    /// Maximum number of cached items, `usize::MAX` if unbounded.
+    pub fn capacity(self) -> usize {
+        match self {
+            CacheSize::Disabled => 0,
+            CacheSize::Bounded(cap) => cap.get(),
+            CacheSize::Unbounded => usize::MAX,
+        }
+    }
+
+    fn lru<K: Hash + Eq, V>(self) -> Option<LruCache<K, V>> {
+        match self {
+            CacheSize::Disabled => None,
+            CacheSize::Bounded(cap) => Some(LruCache::new(cap)),
+            CacheSize::Unbounded => Some(LruCache::unbounded()),
+        }
+    }
+}
+
+impl Default for CacheSize {
+    fn default() -> Self {
+        CacheSize::Bounded(NonZeroUsize::new(DEFAULT_CACHE_SIZE).unwrap())
+    }
+}
+
+/// Capacities of the caches of a `Dex`, see `Dex::with_cache_config`. Each cache holds
+/// 4096 items by default. A one-shot tool reading every class once gains little from
+/// caching, while a long-lived process querying the same file may cache everything.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct CacheConfig {
+    /// Cache of the strings.
+    strings: CacheSize,
+    /// Cache of the types.
+    types: CacheSize,
+    /// Cache of the resolved prototypes.
+    protos: CacheSize,
+    /// Cache of the `FieldIdItem`s.
+    fields: CacheSize,
+    /// Cache of the `MethodIdItem`s.
+    methods: CacheSize,
+}
+
+impl CacheConfig {
+    /// The default capacities.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The same capacity for every cache.
+    pub fn all(size: CacheSize) -> Self {
+        Self {
+            strings: size,
+            types: size,
+            protos: size,
+            fields: size,
+            methods: size,
+        }
+    }
+
+    /// Sets the capacity of the cache of the strings.
+    pub fn with_strings(mut self, size: CacheSize) -> Self {
+        self.strings = size;
+        self
+    }
+
+    /// Sets the capacity of the cache of the types.
+    pub fn with_types(mut self, size: CacheSize) -> Self {
+        self.types = size;
+        self
+    }
+
+    /// Sets the capacity of the cache of the resolved prototypes.
+    pub fn with_protos(mut self, size: CacheSize) -> Self {
+        self.protos = size;
+        self
+    }
+
+    /// Sets the capacity of the cache of the `FieldIdItem`s.
+    pub fn with_fields(mut self, size: CacheSize) -> Self {
+        self.fields = size;
+        self
+    }
+
+    /// Sets the capacity of the cache of the `MethodIdItem`s.
+    pub fn with_methods(mut self, size: CacheSize) -> Self {
+        self.methods = size;
+        self
+    }
+}
+
+/// LRU cache that provides interior mutability, which can be shared between threads
 #[derive(Debug)]
 pub(crate) struct Cache<K: Hash + Eq, V> {
-    inner: Rc<RefCell<LruCache<K, V>>>,
+    /// `None` if caching is disabled.
+    inner: Option<Arc<Mutex<LruCache<K, V>>>>,
 }
 
 impl<K: Hash + Eq, V: Clone> Cache<K, V> {
     /// Get a new instance of cache with the given capacity
-    pub(crate) fn new(cap: NonZeroUsize) -> Self {
+    pub(crate) fn new(size: CacheSize) -> Self {
         Self {
-            inner: Rc::new(RefCell::new(LruCache::new(cap))),
+            inner: size.lru().map(|lru| Arc::new(Mutex::new(lru))),
         }
     }
 
     /// Get a reference to the value at key from the cache, if found
     pub(crate) fn get(&self, key: &K) -> Option<V> {
-        self.inner
-            .borrow_mut()
-            .get(key)
-            .map(std::clone::Clone::clone)
+        self.lock()?.get(key).map(std::clone::Clone::clone)
     }
 
     /// Insert a new key value pair into the cache
     pub(crate) fn put(&self, key: K, value: V) {
-        self.inner.borrow_mut().put(key, value);
+        if let Some(mut lru) = self.lock() {
+            lru.put(key, value);
+        }
+    }
+
+    /// The entries are only cached values, a panic while the lock was held can't leave
+    /// them in a state worse than empty, so poisoning is ignored.
+    fn lock(&self) -> Option<MutexGuard<'_, LruCache<K, V>>> {
+        let inner = self.inner.as_ref()?;
+        Some(inner.lock().unwrap_or_else(PoisonError::into_inner))
     }
 }
 
@@ -37,3 +156,136 @@ impl<K: Hash + Eq, V> Clone for Cache<K, V> {
         }
     }
 }
+
+/// Number of shards of a `ShardedCache`.
+const SHARDS: usize = 16;
+
+/// Cache for values which are read much more often than they are inserted, like the strings
+/// of a `Dex`. The entries are split between shards by the hash of their key, each behind a
+/// read-write lock, so threads reading cached values don't wait for each other and threads
+/// inserting values only lock one shard. Reads don't update the LRU order, the entries are
+/// evicted in the order they were inserted in.
+#[derive(Debug)]
+pub(crate) struct ShardedCache<K: Hash + Eq, V> {
+    /// Empty if caching is disabled.
+    shards: Arc<[RwLock<LruCache<K, V>>]>,
+}
+
+impl<K: Hash + Eq, V: Clone> ShardedCache<K, V> {
+    /// Get a new instance of cache with the given total capacity. A capacity smaller than
+    /// `SHARDS` is a single shard, a larger one is split evenly between the shards, rounded
+    /// up to a multiple of `SHARDS`.
+    pub(crate) fn new(size: CacheSize) -> Self {
+        let (shards, shard_size) = match size {
+            CacheSize::Bounded(cap) if cap.get() < SHARDS => (1, size),
+            CacheSize::Bounded(cap) => {
+                let shard_cap = NonZeroUsize::new(cap.get().div_ceil(SHARDS)).unwrap_or(cap);
+                (SHARDS, CacheSize::Bounded(shard_cap))
+            }
+            size => (SHARDS, size),
+        };
+        Self {
+            shards: (0..shards)
+                .map_while(|_| shard_size.lru().map(RwLock::new))
+                .collect(),
+        }
+    }
+
+    /// Get a clone of the value at key from the cache, if found
+    pub(crate) fn get(&self, key: &K) -> Option<V> {
+        self.shard(key)?
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .peek(key)
+            .cloned()
+    }
+
+    /// Insert a new key value pair into the cache
+    pub(crate) fn put(&self, key: K, value: V) {
+        if let Some(shard) = self.shard(&key) {
+            shard
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .put(key, value);
+        }
+    }
+
+    fn shard(&self, key: &K) -> Option<&RwLock<LruCache<K, V>>> {
+        if self.shards.is_empty() {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.shards
+            .get(hasher.finish() as usize % self.shards.len())
+    }
+}
+
+impl<K: Hash + Eq, V> Clone for ShardedCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            shards: self.shards.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use super::{Cache, CacheSize, ShardedCache, SHARDS};
+
+    #[test]
+    fn test_sharded_cache() {
+        let cache = ShardedCache::new(CacheSize::Bounded(NonZeroUsize::new(SHARDS * 2).unwrap()));
+        std::thread::scope(|scope| {
+            for thread in 0..4u32 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    for key in (thread..1000).step_by(4) {
+                        cache.put(key, key.to_string());
+                        assert!(cache.get(&key).is_none_or(|value| value == key.to_string()));
+                    }
+                });
+            }
+        });
+        // each shard keeps its last two entries.
+        let cached = (0..1000).filter(|key| cache.get(key).is_some()).count();
+        assert!(cached <= SHARDS * 2, "{} entries cached", cached);
+        let shared = cache.clone();
+        shared.put(1000, "1000".to_string());
+        assert_eq!(cache.get(&1000).as_deref(), Some("1000"));
+    }
+
+    #[test]
+    fn test_cache_sizes() {
+        let disabled = Cache::new(CacheSize::Disabled);
+        disabled.put(1, 1);
+        assert_eq!(disabled.get(&1), None);
+        let disabled = ShardedCache::new(CacheSize::Disabled);
+        disabled.put(1, 1);
+        assert_eq!(disabled.get(&1), None);
+
+        let unbounded = Cache::new(CacheSize::Unbounded);
+        let sharded = ShardedCache::new(CacheSize::Unbounded);
+        for key in 0..10_000 {
+            unbounded.put(key, key);
+            sharded.put(key, key);
+        }
+        assert!((0..10_000).all(|key| unbounded.get(&key) == Some(key)));
+        assert!((0..10_000).all(|key| sharded.get(&key) == Some(key)));
+
+        let bounded = Cache::new(CacheSize::Bounded(NonZeroUsize::new(2).unwrap()));
+        for key in 0..3 {
+            bounded.put(key, key);
+        }
+        assert_eq!(bounded.get(&0), None);
+        assert_eq!(bounded.get(&2), Some(2));
+
+        let sharded = ShardedCache::new(CacheSize::Bounded(NonZeroUsize::new(2).unwrap()));
+        for key in 0..100 {
+            sharded.put(key, key);
+        }
+        assert_eq!((0..100).filter(|key| sharded.get(key).is_some()).count(), 2);
+    }
+}
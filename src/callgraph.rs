@@ -0,0 +1,246 @@
+//! Call graph of the methods of a `Dex`, built from the invoke instructions of their code.
+//! The nodes are `MethodId`s, so the methods of other `Dex`es and of the framework are
+//! nodes too, and each invoke is an edge.
+use std::collections::{BTreeSet, HashMap};
+
+use getset::CopyGetters;
+
+use crate::{
+    instruction::{Instruction, Opcode},
+    jtype::TypeId,
+    method::MethodId,
+    provenance::Provenance,
+    xref, Dex, Result,
+};
+
+/// How a method is invoked, the `/range` variants are the same kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvokeKind {
+    /// `invoke-virtual`.
+    Virtual,
+    /// `invoke-super`.
+    Super,
+    /// `invoke-direct`, for constructors and private methods.
+    Direct,
+    /// `invoke-static`.
+    Static,
+    /// `invoke-interface`.
+    Interface,
+    /// `invoke-polymorphic`, for signature polymorphic methods like `MethodHandle.invoke`.
+    Polymorphic,
+}
+
+impl InvokeKind {
+    /// The kind of `instruction` and the method it invokes, if it is an invoke.
+    fn of(instruction: &Instruction<'_>) -> Option<(Self, MethodId)> {
+        use Opcode::*;
+
+        let kind = match instruction.opcode() {
+            InvokeVirtual | InvokeVirtualRange => InvokeKind::Virtual,
+            InvokeSuper | InvokeSuperRange => InvokeKind::Super,
+            InvokeDirect | InvokeDirectRange | InvokeObjectInitRange => InvokeKind::Direct,
+            InvokeStatic | InvokeStaticRange => InvokeKind::Static,
+            InvokeInterface | InvokeInterfaceRange => InvokeKind::Interface,
+            InvokePolymorphic | InvokePolymorphicRange => InvokeKind::Polymorphic,
+            _ => return None,
+        };
+        Some((kind, instruction.index()?))
+    }
+}
+
+/// An invoke of `callee` by `caller`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, CopyGetters)]
+#[get_copy = "pub"]
+pub struct CallEdge {
+    /// The invoking method.
+    caller: MethodId,
+    /// The invoked method.
+    callee: MethodId,
+    /// Address of the invoke, in code units from the start of the caller's code.
+    address: usize,
+    /// How the method is invoked.
+    kind: InvokeKind,
+}
+
+/// Which methods `build` puts in the graph.
+#[derive(Debug, Clone, Default)]
+pub struct CallGraphOptions {
+    /// Type descriptor prefixes of the packages, all the classes if empty.
+    packages: Vec<String>,
+}
+
+impl CallGraphOptions {
+    /// Options building the graph of all the methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restricts the graph to the methods of the classes of `package`, like `com.example`,
+    /// and of its subpackages. Can be called more than once to keep several packages.
+    pub fn with_package(mut self, package: &str) -> Self {
+        let package = package.replace('.', "/");
+        self.packages.push(format!("L{}/", package));
+        self
+    }
+
+    /// Whether the methods of the class `descriptor` are kept.
+    fn matches(&self, descriptor: &str) -> bool {
+        self.packages.is_empty()
+            || self
+                .packages
+                .iter()
+                .any(|package| descriptor.starts_with(package.as_str()))
+    }
+}
+
+/// Graph of the methods which have code and of the methods they invoke.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallGraph {
+    nodes: BTreeSet<MethodId>,
+    edges: Vec<CallEdge>,
+    provenance: Option<Provenance>,
+}
+
+impl CallGraph {
+    /// The methods, in the order of their ids.
+    pub fn nodes(&self) -> impl Iterator<Item = MethodId> + '_ {
+        self.nodes.iter().copied()
+    }
+
+    /// The invokes, in the order of the code of the callers.
+    pub fn edges(&self) -> &[CallEdge] {
+        &self.edges
+    }
+
+    /// Provenance of the `Dex` the graph was built from.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    /// The invokes in the code of `caller`.
+    pub fn callees(&self, caller: MethodId) -> impl Iterator<Item = &CallEdge> + '_ {
+        self.edges.iter().filter(move |edge| edge.caller == caller)
+    }
+
+    /// The invokes of `callee`.
+    pub fn callers(&self, callee: MethodId) -> impl Iterator<Item = &CallEdge> + '_ {
+        self.edges.iter().filter(move |edge| edge.callee == callee)
+    }
+
+    /// The graph as a JSON object with `nodes` and `edges` arrays, and a `provenance`
+    /// object if the provenance is known.
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<String> = self.nodes.iter().map(ToString::to_string).collect();
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                format!(
+                    "{{\"caller\":{},\"callee\":{},\"address\":{},\"kind\":\"{:?}\"}}",
+                    edge.caller, edge.callee, edge.address, edge.kind
+                )
+            })
+            .collect();
+        let provenance = self
+            .provenance
+            .as_ref()
+            .map(|provenance| format!(",\"provenance\":{}", provenance.to_json()))
+            .unwrap_or_default();
+        format!(
+            "{{\"nodes\":[{}],\"edges\":[{}]{}}}",
+            nodes.join(","),
+            edges.join(","),
+            provenance
+        )
+    }
+}
+
+/// Builds the call graph of `dex`. With a package filter, only the methods of the classes
+/// in the packages are nodes, and only the invokes between them are edges.
+pub fn build<T: AsRef<[u8]>>(dex: &Dex<T>, options: &CallGraphOptions) -> Result<CallGraph> {
+    let mut graph = CallGraph {
+        provenance: Some(dex.provenance()),
+        ..CallGraph::default()
+    };
+    let mut kept_classes: HashMap<TypeId, bool> = HashMap::new();
+    let mut is_kept = |class: TypeId| -> Result<bool> {
+        if let Some(kept) = kept_classes.get(&class) {
+            return Ok(*kept);
+        }
+        let kept = options.matches(dex.get_type(class)?.type_descriptor());
+        kept_classes.insert(class, kept);
+        Ok(kept)
+    };
+    xref::visit_instructions(dex, |location, instruction| {
+        if !is_kept(location.class())? {
+            return Ok(());
+        }
+        graph.nodes.insert(location.method());
+        if let Some((kind, callee)) = InvokeKind::of(instruction) {
+            let class = dex.get_method_item(callee)?.class_idx();
+            if is_kept(TypeId::from(class))? {
+                graph.nodes.insert(callee);
+                graph.edges.push(CallEdge {
+                    caller: location.method(),
+                    callee,
+                    address: location.address(),
+                    kind,
+                });
+            }
+        }
+        Ok(())
+    })?;
+    let (nodes, edges) = (graph.nodes.len(), graph.edges.len());
+    debug!(target: "callgraph", "call graph with {} nodes and {} edges", nodes, edges);
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallGraphOptions, InvokeKind};
+    use crate::DexReader;
+
+    #[test]
+    fn test_call_graph() {
+        let dex = DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let graph = dex.call_graph(&CallGraphOptions::new()).unwrap();
+        let to_string = dex
+            .find_method("Ljava/lang/StringBuilder;->toString()Ljava/lang/String;")
+            .unwrap()
+            .unwrap()
+            .id();
+        let callers: Vec<_> = graph
+            .callers(to_string)
+            .map(|edge| (edge.caller(), edge.address()))
+            .collect();
+        let expected: Vec<_> = dex
+            .callers_of(to_string)
+            .unwrap()
+            .iter()
+            .map(|location| (location.method(), location.address()))
+            .collect();
+        assert_eq!(callers, expected);
+        assert!(graph
+            .callers(to_string)
+            .all(|edge| edge.kind() == InvokeKind::Virtual));
+        assert!(graph
+            .edges()
+            .iter()
+            .any(|edge| edge.kind() == InvokeKind::Direct));
+        assert!(graph.to_json().contains("\"kind\":\"Static\""));
+
+        let launcher = dex
+            .call_graph(&CallGraphOptions::new().with_package("org.adw.launcher"))
+            .unwrap();
+        assert!(!launcher.edges().is_empty());
+        assert!(launcher.edges().len() < graph.edges().len());
+        assert_eq!(launcher.callers(to_string).count(), 0);
+        for method_id in launcher.nodes() {
+            let class = dex.get_method_item(method_id).unwrap().class_idx();
+            let descriptor = dex.get_type(class.into()).unwrap();
+            assert!(descriptor
+                .type_descriptor()
+                .starts_with("Lorg/adw/launcher/"));
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! A C interface to the library, for static analysis frameworks written in C or C++. Build
+//! the crate as a `cdylib` or `staticlib` with the `capi` feature to use it.
+//!
+//! `Dex` files and classes are opaque handles, which are freed with `dex_free` and
+//! `dex_class_free`. Methods are borrowed from their class and are valid until the class is
+//! freed. Functions which fail return `NULL`, and `dex_last_error` describes the error.
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    ptr,
+};
+
+use memmap2::Mmap;
+
+use crate::{class::Class, method::Method, ushort, Dex, DexReader, Error};
+
+/// A `Dex` opened by `dex_open`.
+pub struct DexFile(Dex<Mmap>);
+
+/// A class found by `dex_find_class`, which doesn't borrow its `DexFile`.
+pub struct DexClass(Class<'static>);
+
+/// A method of a `DexClass`.
+pub type DexMethod = Method<'static>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: &Error) {
+    let message = CString::new(error.to_string().replace('\0', " ")).ok();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+fn to_str<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        set_last_error(&Error::MalFormed(
+            "Expected a string, found NULL".to_string(),
+        ));
+        return None;
+    }
+    match unsafe { CStr::from_ptr(string) }.to_str() {
+        Ok(string) => Some(string),
+        Err(e) => {
+            set_last_error(&Error::MalFormed(e.to_string()));
+            None
+        }
+    }
+}
+
+/// Copies `string` and a terminating NUL into `buf` if it fits in `len` bytes. Returns the
+/// length of `string`, callers retry with a larger buffer if it isn't less than `len`.
+unsafe fn copy_to_buf(string: &str, buf: *mut c_char, len: usize) -> usize {
+    if !buf.is_null() && string.len() < len {
+        ptr::copy_nonoverlapping(string.as_ptr() as *const c_char, buf, string.len());
+        *buf.add(string.len()) = 0;
+    }
+    string.len()
+}
+
+/// Message of the last error of the calling thread, `NULL` if there was none. Valid until
+/// the next call of a function of the library on the same thread.
+#[no_mangle]
+pub extern "C" fn dex_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Opens the dex file at `path`, `NULL` if it can't be read or parsed.
+///
+/// # Safety
+/// `path` must be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn dex_open(path: *const c_char) -> *mut DexFile {
+    let path = match to_str(path) {
+        Some(path) => path,
+        None => return ptr::null_mut(),
+    };
+    match DexReader::from_file(path) {
+        Ok(dex) => Box::into_raw(Box::new(DexFile(dex))),
+        Err(e) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Frees a `DexFile` returned by `dex_open`. Classes found in it stay valid.
+///
+/// # Safety
+/// `dex` must be `NULL` or returned by `dex_open`, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn dex_free(dex: *mut DexFile) {
+    if !dex.is_null() {
+        drop(Box::from_raw(dex));
+    }
+}
+
+/// Finds the class with the type descriptor `descriptor`, like `Lcom/foo/Bar;`, and parses
+/// the code and the annotations of its members. `NULL` if the class isn't defined in `dex`
+/// or can't be parsed, `dex_last_error` is only set in the latter case.
+///
+/// # Safety
+/// `dex` must be returned by `dex_open` and `descriptor` must be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn dex_find_class(
+    dex: *const DexFile,
+    descriptor: *const c_char,
+) -> *mut DexClass {
+    let descriptor = match (dex.as_ref(), to_str(descriptor)) {
+        (Some(_), Some(descriptor)) => descriptor,
+        _ => return ptr::null_mut(),
+    };
+    let class = (*dex).0.find_class_by_name(descriptor);
+    match class.and_then(|class| class.map(Class::into_owned).transpose()) {
+        Ok(Some(class)) => Box::into_raw(Box::new(DexClass(class))),
+        Ok(None) => ptr::null_mut(),
+        Err(e) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Frees a `DexClass` returned by `dex_find_class`, and the methods borrowed from it.
+///
+/// # Safety
+/// `class` must be `NULL` or returned by `dex_find_class`, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn dex_class_free(class: *mut DexClass) {
+    if !class.is_null() {
+        drop(Box::from_raw(class));
+    }
+}
+
+/// Number of methods of `class`, direct methods first.
+///
+/// # Safety
+/// `class` must be returned by `dex_find_class`.
+#[no_mangle]
+pub unsafe extern "C" fn dex_class_method_count(class: *const DexClass) -> usize {
+    class.as_ref().map_or(0, |class| class.0.methods().count())
+}
+
+/// The method of `class` at `index`, `NULL` if `index` is out of bounds.
+///
+/// # Safety
+/// `class` must be returned by `dex_find_class`.
+#[no_mangle]
+pub unsafe extern "C" fn dex_class_method(
+    class: *const DexClass,
+    index: usize,
+) -> *const DexMethod {
+    class
+        .as_ref()
+        .and_then(|class| class.0.methods().nth(index))
+        .map_or(ptr::null(), |method| method as *const Method)
+}
+
+/// Copies the reference of `method` in smali notation, like `Lcom/foo/Bar;->baz(I)V`, into
+/// `buf`, see `dex_method_name`.
+///
+/// # Safety
+/// `method` must be returned by `dex_class_method` and `buf` must be `NULL` or point to `len`
+/// writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_method_descriptor(
+    method: *const DexMethod,
+    buf: *mut c_char,
+    len: usize,
+) -> usize {
+    method
+        .as_ref()
+        .map_or(0, |method| copy_to_buf(&method.descriptor(), buf, len))
+}
+
+/// Copies the name of `method` and a terminating NUL into `buf` if they fit in `len` bytes.
+/// Returns the length of the name, the name was truncated if it isn't less than `len`.
+///
+/// # Safety
+/// `method` must be returned by `dex_class_method` and `buf` must be `NULL` or point to `len`
+/// writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dex_method_name(
+    method: *const DexMethod,
+    buf: *mut c_char,
+    len: usize,
+) -> usize {
+    method.as_ref().map_or(0, |method| {
+        copy_to_buf(&method.name().to_string(), buf, len)
+    })
+}
+
+/// The code units of the instructions of `method`, and their number in `len`. `NULL` if the
+/// method is abstract or native or if its code is malformed, `dex_last_error` is only set in
+/// the latter case.
+///
+/// # Safety
+/// `method` must be returned by `dex_class_method` and `len` must point to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn dex_method_code(
+    method: *const DexMethod,
+    len: *mut usize,
+) -> *const ushort {
+    let code = match method.as_ref().map(|method| method.try_code()) {
+        Some(Ok(code)) => code,
+        Some(Err(e)) => {
+            set_last_error(&e);
+            None
+        }
+        None => None,
+    };
+    let insns = code.map(|code| code.insns());
+    if let Some(len) = len.as_mut() {
+        *len = insns.map_or(0, <[ushort]>::len);
+    }
+    insns.map_or(ptr::null(), <[ushort]>::as_ptr)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CString, os::raw::c_char, ptr};
+
+    use super::*;
+
+    #[test]
+    fn test_capi() {
+        unsafe {
+            let path = CString::new("resources/classes.dex").unwrap();
+            let dex = dex_open(path.as_ptr());
+            assert!(!dex.is_null());
+            let descriptor = CString::new("Lorg/adw/launcher/Launcher;").unwrap();
+            let class = dex_find_class(dex, descriptor.as_ptr());
+            assert!(!class.is_null());
+            dex_free(dex);
+
+            let count = dex_class_method_count(class);
+            assert_eq!(count, (*class).0.methods().count());
+            assert!(dex_class_method(class, count).is_null());
+            let mut with_code = 0;
+            for index in 0..count {
+                let method = dex_class_method(class, index);
+                let mut name = [0 as c_char; 256];
+                let name_len = dex_method_name(method, name.as_mut_ptr(), name.len());
+                assert_eq!(
+                    CStr::from_ptr(name.as_ptr()).to_str().unwrap(),
+                    (*method).name().to_string()
+                );
+                assert_eq!(name_len, (*method).name().to_string().len());
+                let mut len = 0;
+                let code = dex_method_code(method, &mut len);
+                if let Some(insns) = (*method).code().map(|code| code.insns()) {
+                    assert_eq!(std::slice::from_raw_parts(code, len), &insns[..]);
+                    with_code += 1;
+                } else {
+                    assert!(code.is_null());
+                }
+            }
+            assert!(with_code > 0);
+
+            let method = dex_class_method(class, 0);
+            let mut small = [0 as c_char; 4];
+            let len = dex_method_descriptor(method, small.as_mut_ptr(), small.len());
+            assert_eq!(len, (*method).descriptor().len());
+            assert_eq!(small, [0; 4]);
+            dex_class_free(class);
+
+            let path = CString::new("resources/missing.dex").unwrap();
+            assert!(dex_open(path.as_ptr()).is_null());
+            assert!(!dex_last_error().is_null());
+            assert!(dex_open(ptr::null()).is_null());
+        }
+    }
+}
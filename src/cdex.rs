@@ -0,0 +1,334 @@
+//! Compact dex (`cdex001`) files, which ART writes to the `dalvik-cache` and to VDEX files.
+//!
+//! Compact dex files have the ids sections of a standard dex file, but their data section
+//! can be shared by several dex files, their code items are packed and the offsets of the
+//! debug info items are kept in a table indexed by method. They are read with
+//! `DexReader::from_cdex_file` into a `Dex` like any other dex file.
+use std::convert::TryFrom;
+
+use getset::CopyGetters;
+use num_traits::CheckedAdd;
+use scroll::{Pread, Pwrite, Uleb128};
+
+use crate::{
+    dex::{read_endian, Dex},
+    error::Error,
+    method::MethodId,
+    uint, ushort, Result,
+};
+
+/// Magic value at the start of compact dex files, followed by the version.
+pub(crate) const MAGIC: &[u8; 4] = b"cdex";
+
+/// Offset of the compact dex fields, which follow the fields of the standard header.
+pub(crate) const HEADER_EXTENSION_OFFSET: usize = 0x70;
+
+/// Offsets of the `data_size` and `data_off` fields of the header.
+const DATA_SIZE_OFFSET: usize = 0x68;
+const DATA_OFF_OFFSET: usize = 0x6c;
+
+/// Number of methods whose debug info offsets are encoded in one block of the table.
+const DEBUG_INFO_OFFSETS_PER_BLOCK: usize = 16;
+
+/// Fields of the header of compact dex files, which follow the standard ones.
+#[derive(Debug, Clone, Copy, Pread, CopyGetters)]
+#[get_copy = "pub"]
+pub struct CompactHeader {
+    /// Features used by the file, like default methods.
+    feature_flags: uint,
+    /// Offset of the debug info offsets table from the start of the data section.
+    debug_info_offsets_pos: uint,
+    /// Offset of the index of the blocks of the table, from `debug_info_offsets_pos`.
+    debug_info_offsets_table_offset: uint,
+    /// Offset the debug info offsets in the table are relative to.
+    debug_info_base: uint,
+    /// Start of the part of the shared data section owned by this file.
+    owned_data_begin: uint,
+    /// End of the part of the shared data section owned by this file.
+    owned_data_end: uint,
+}
+
+impl CompactHeader {
+    /// Whether the file has interfaces with default methods.
+    pub fn has_default_methods(&self) -> bool {
+        self.feature_flags & 0x1 != 0
+    }
+}
+
+/// Sizes of a compact code item. The four bit fields of the item are extended by a
+/// "pre-header" stored right before it when the sizes don't fit.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CompactCodeItemSizes {
+    pub(crate) registers_size: ushort,
+    pub(crate) ins_size: ushort,
+    pub(crate) outs_size: ushort,
+    pub(crate) tries_size: ushort,
+    pub(crate) insns_size: uint,
+}
+
+const FLAG_PRE_HEADER_REGISTERS_SIZE: ushort = 0x1;
+const FLAG_PRE_HEADER_INS_SIZE: ushort = 0x2;
+const FLAG_PRE_HEADER_OUTS_SIZE: ushort = 0x4;
+const FLAG_PRE_HEADER_TRIES_SIZE: ushort = 0x8;
+const FLAG_PRE_HEADER_INSNS_SIZE: ushort = 0x10;
+const INSNS_SIZE_SHIFT: ushort = 5;
+
+impl CompactCodeItemSizes {
+    /// Reads the sizes of the compact code item at `offset`, whose instructions start at
+    /// `offset + 4`.
+    pub(crate) fn read(source: &[u8], offset: usize, endian: scroll::Endian) -> Result<Self> {
+        let fields: ushort = source.pread_with(offset, endian)?;
+        let insns_count_and_flags: ushort = source.pread_with(offset + 2, endian)?;
+        let mut sizes = Self {
+            registers_size: (fields >> 12) & 0xf,
+            ins_size: (fields >> 8) & 0xf,
+            outs_size: (fields >> 4) & 0xf,
+            tries_size: fields & 0xf,
+            insns_size: (insns_count_and_flags >> INSNS_SIZE_SHIFT).into(),
+        };
+        // the pre-header is read backwards from the start of the item.
+        let mut pre_header = offset;
+        let mut previous = || -> Result<ushort> {
+            pre_header = pre_header.checked_sub(2).ok_or_else(|| {
+                Error::BadOffset(offset, "Pre-header of code item out of bounds".to_string())
+            })?;
+            Ok(source.pread_with(pre_header, endian)?)
+        };
+        let has = |flag| insns_count_and_flags & flag != 0;
+        if has(FLAG_PRE_HEADER_INSNS_SIZE) {
+            add_size(&mut sizes.insns_size, uint::from(previous()?), offset)?;
+            add_size(&mut sizes.insns_size, uint::from(previous()?) << 16, offset)?;
+        }
+        if has(FLAG_PRE_HEADER_REGISTERS_SIZE) {
+            add_size(&mut sizes.registers_size, previous()?, offset)?;
+        }
+        if has(FLAG_PRE_HEADER_INS_SIZE) {
+            add_size(&mut sizes.ins_size, previous()?, offset)?;
+        }
+        if has(FLAG_PRE_HEADER_OUTS_SIZE) {
+            add_size(&mut sizes.outs_size, previous()?, offset)?;
+        }
+        if has(FLAG_PRE_HEADER_TRIES_SIZE) {
+            add_size(&mut sizes.tries_size, previous()?, offset)?;
+        }
+        // the registers size of the item doesn't count the incoming arguments.
+        add_size(&mut sizes.registers_size, sizes.ins_size, offset)?;
+        Ok(sizes)
+    }
+}
+
+/// Adds `value` to a size of the compact code item at `offset`.
+fn add_size<N: CheckedAdd>(size: &mut N, value: N, offset: usize) -> Result<()> {
+    *size = size
+        .checked_add(&value)
+        .ok_or_else(|| Error::MalFormed(format!("Sizes of code item at {:#x} overflow", offset)))?;
+    Ok(())
+}
+
+/// Offset of the debug info item of the method `method_id` of a compact dex file, `0` if
+/// the method has none.
+pub(crate) fn debug_info_offset<S: AsRef<[u8]>>(dex: &Dex<S>, method_id: MethodId) -> Result<uint> {
+    let header = match dex.compact_header() {
+        Some(header) => header,
+        None => return Ok(0),
+    };
+    let source = dex.source.as_ref();
+    let table = header.debug_info_offsets_pos as usize;
+    let index = method_id as usize;
+    let block_offset: uint = source.pread_with(
+        table
+            + header.debug_info_offsets_table_offset as usize
+            + index / DEBUG_INFO_OFFSETS_PER_BLOCK * 4,
+        dex.get_endian(),
+    )?;
+    let offset = &mut (table + block_offset as usize);
+    // the block starts with a big endian bit mask of the methods which have debug info,
+    // followed by the deltas of their offsets.
+    let bit_mask: ushort = source.gread_with(offset, scroll::BE)?;
+    let bit = index % DEBUG_INFO_OFFSETS_PER_BLOCK;
+    if bit_mask & (1 << bit) == 0 {
+        return Ok(0);
+    }
+    let mut debug_info_off = header.debug_info_base;
+    for _ in 0..=(bit_mask & ((1 << bit) - 1)).count_ones() {
+        let delta = Uleb128::read(source, offset)?;
+        debug_info_off = uint::try_from(delta)
+            .ok()
+            .and_then(|delta| debug_info_off.checked_add(delta))
+            .ok_or_else(|| {
+                Error::MalFormed(format!(
+                    "Debug info offset of method {} overflows",
+                    method_id
+                ))
+            })?;
+    }
+    Ok(debug_info_off)
+}
+
+/// Moves the data section of the compact dex file `dex` to the start of the returned
+/// buffer, so that the offsets into it, which are relative to the data section, become
+/// offsets into the buffer. Returns the buffer and the offset of the header in it.
+pub(crate) fn relocate_data_section(dex: &[u8]) -> Result<(Vec<u8>, uint)> {
+    let endian = read_endian(dex, 0)?;
+    let data_size: uint = dex.pread_with(DATA_SIZE_OFFSET, endian)?;
+    let data_off: uint = dex.pread_with(DATA_OFF_OFFSET, endian)?;
+    if data_off == 0 {
+        return Ok((dex.to_vec(), 0));
+    }
+    let (data_off, data_size) = (data_off as usize, data_size as usize);
+    let data = dex.get(data_off..data_off + data_size).ok_or_else(|| {
+        Error::BadOffset(
+            data_off,
+            "Data section of compact dex out of bounds".to_string(),
+        )
+    })?;
+    let header_offset = (data_size + 3) & !3;
+    let mut relocated = Vec::with_capacity(header_offset + data_off);
+    relocated.extend_from_slice(data);
+    relocated.resize(header_offset, 0);
+    relocated.extend_from_slice(&dex[..data_off]);
+    relocated.pwrite_with(0 as uint, header_offset + DATA_OFF_OFFSET, endian)?;
+    Ok((relocated, header_offset as uint))
+}
+
+#[cfg(test)]
+mod tests {
+    use scroll::{Pread, Pwrite, LE};
+
+    use crate::{uint, ushort, DexReader};
+
+    fn align(bytes: &mut Vec<u8>) -> usize {
+        bytes.resize((bytes.len() + 3) & !3, 0);
+        bytes.len()
+    }
+
+    #[test]
+    fn test_code_item_sizes_overflow() {
+        // registers size 15 in the item, plus 0xffff in the pre-header.
+        let mut source = Vec::new();
+        for unit in [0xffffu16, 0xf000, super::FLAG_PRE_HEADER_REGISTERS_SIZE] {
+            source.extend_from_slice(&unit.to_le_bytes());
+        }
+        assert!(super::CompactCodeItemSizes::read(&source, 2, LE).is_err());
+
+        // the largest instruction count of the item, plus a full pre-header.
+        let mut source = Vec::new();
+        let flags = 0x7ff << super::INSNS_SIZE_SHIFT | super::FLAG_PRE_HEADER_INSNS_SIZE;
+        for unit in [0xffffu16, 0xffff, 0, flags] {
+            source.extend_from_slice(&unit.to_le_bytes());
+        }
+        assert!(super::CompactCodeItemSizes::read(&source, 4, LE).is_err());
+    }
+
+    #[test]
+    fn test_compact_dex() {
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let dex = DexReader::from_vec(bytes.clone()).expect("failed to load dex");
+        let launcher = "Lorg/adw/launcher/Launcher;";
+        let class = dex.find_class_by_name(launcher).unwrap().unwrap();
+        // a method whose sizes fit in a compact code item without a pre-header.
+        let method = class
+            .methods()
+            .find(|method| {
+                method.code().is_some_and(|code| {
+                    code.tries().is_empty()
+                        && code.debug_info_item().is_some()
+                        && code.registers_size() - code.ins_size() < 16
+                        && code.ins_size() < 16
+                        && code.outs_size() < 16
+                        && code.insns().len() < 2048
+                })
+            })
+            .expect("no method with small code");
+        let code = method.code().unwrap();
+        let code_off = method.code_off() as usize;
+        let debug_info_off: uint = bytes.pread_with(code_off + 8, LE).unwrap();
+
+        // only the code item of `method` is made compact, the instructions move to its start.
+        let fields = (code.registers_size() - code.ins_size()) << 12
+            | code.ins_size() << 8
+            | code.outs_size() << 4;
+        bytes.pwrite_with(fields, code_off, LE).unwrap();
+        let insns_count = (code.insns().len() as ushort) << 5;
+        bytes.pwrite_with(insns_count, code_off + 2, LE).unwrap();
+        for (index, insn) in code.insns().iter().enumerate() {
+            bytes
+                .pwrite_with(*insn, code_off + 4 + index * 2, LE)
+                .unwrap();
+        }
+
+        // the string ids are moved to make room for the compact header fields.
+        let string_ids_off = dex.header().string_ids_off() as usize;
+        let string_ids_len = dex.header().string_ids_size() as usize * 4;
+        let moved_string_ids = align(&mut bytes);
+        bytes.extend_from_within(string_ids_off..string_ids_off + string_ids_len);
+        bytes
+            .pwrite_with(moved_string_ids as uint, 0x3c, LE)
+            .unwrap();
+
+        // a block without debug info, then the block of `method`, in which the first method
+        // has debug info too.
+        let method_id = method.id() as usize;
+        let bit = method_id % 16;
+        let debug_info_base = debug_info_off - 10;
+        let pos = align(&mut bytes);
+        bytes.extend_from_slice(&[0, 0]);
+        let mask = (1 << bit) | 1u16;
+        bytes.extend_from_slice(&mask.to_be_bytes());
+        if bit != 0 {
+            bytes.extend_from_slice(&[3, 7]);
+        } else {
+            bytes.push(10);
+        }
+        let table_offset = bytes.len() - pos;
+        for block in 0..=dex.header().method_ids_size() as usize / 16 {
+            let block_offset: uint = if block == method_id / 16 { 2 } else { 0 };
+            bytes.extend_from_slice(&block_offset.to_le_bytes());
+        }
+
+        bytes[..8].copy_from_slice(b"cdex001\0");
+        let len = align(&mut bytes) as uint;
+        for (offset, value) in [
+            (0x20, len),
+            (0x24, 0x88),
+            (0x68, len),
+            (0x6c, 0),
+            (0x70, 0),
+            (0x74, pos as uint),
+            (0x78, table_offset as uint),
+            (0x7c, debug_info_base),
+        ] {
+            bytes.pwrite_with(value, offset, LE).unwrap();
+        }
+
+        let compact = DexReader::from_cdex_vec(&bytes).expect("failed to load compact dex");
+        let header = compact.compact_header().expect("not a compact dex file");
+        assert_eq!(header.debug_info_base(), debug_info_base);
+        assert!(!header.has_default_methods());
+        let class = compact.find_class_by_name(launcher).unwrap().unwrap();
+        let compact_method = class.methods().find(|m| m.id() == method.id()).unwrap();
+        let compact_code = compact_method.code().expect("failed to load compact code");
+        assert_eq!(compact_code.insns(), code.insns());
+        assert_eq!(compact_code.registers_size(), code.registers_size());
+        assert_eq!(compact_code.ins_size(), code.ins_size());
+        assert_eq!(compact_code.outs_size(), code.outs_size());
+        assert_eq!(
+            compact_code.debug_info_item().unwrap().positions(),
+            code.debug_info_item().unwrap().positions()
+        );
+        assert_eq!(&*compact.get_insns(code_off as u64).unwrap(), code.insns());
+        let other_block = if method_id / 16 == 0 { 16 } else { 0 };
+        assert_eq!(super::debug_info_offset(&compact, other_block).unwrap(), 0);
+
+        // a file whose data section, a copy of the whole file, follows the main section.
+        let mut shared = bytes.clone();
+        shared.extend_from_within(..);
+        shared.pwrite_with(len, 0x6c, LE).unwrap();
+        assert!(DexReader::from_vec(&shared).is_err());
+        let relocated = DexReader::from_cdex_vec(&shared).expect("failed to load compact dex");
+        assert_eq!(relocated.header_offset(), len);
+        let class = relocated.find_class_by_name(launcher).unwrap().unwrap();
+        let relocated_method = class.methods().find(|m| m.id() == method.id()).unwrap();
+        assert_eq!(relocated_method.code().unwrap().insns(), code.insns());
+    }
+}
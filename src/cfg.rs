@@ -0,0 +1,286 @@
+//! Control flow graphs of the code of methods.
+use std::collections::{BTreeSet, VecDeque};
+
+use getset::{CopyGetters, Getters};
+
+use crate::{
+    code::{CodeItem, TryCatchHandlers},
+    error::Error,
+    instruction::{Instruction, InstructionIter, Opcode, Payload, Quickening},
+    ushort, Result,
+};
+
+/// A sequence of instructions which is only entered at its first instruction and only left
+/// after its last one, or through an exception.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct BasicBlock {
+    /// Offset in code units of the first instruction.
+    #[get_copy = "pub"]
+    start: usize,
+    /// Offset in code units after the last instruction.
+    #[get_copy = "pub"]
+    end: usize,
+    /// Opcode of the last instruction.
+    #[get_copy = "pub"]
+    last_opcode: Opcode,
+    /// Indices of the blocks which may run after this one, by branching or falling through.
+    #[get = "pub"]
+    successors: Vec<usize>,
+    /// Indices of the blocks of the catch handlers of the instructions which may throw.
+    #[get = "pub"]
+    handlers: Vec<usize>,
+    /// `true` if the block only contains `nop`s, like the padding before payloads.
+    padding: bool,
+}
+
+/// Control flow graph of the code of a method. Payloads of switch and array instructions
+/// aren't code, so they aren't part of any block.
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct Cfg {
+    /// Blocks sorted by offset, the first one is the entry of the method.
+    #[get = "pub"]
+    blocks: Vec<BasicBlock>,
+    /// Whether each block can be reached from the entry.
+    reachable: Vec<bool>,
+}
+
+impl Cfg {
+    /// Builds the graph of `code`.
+    pub fn new(code: &CodeItem) -> Result<Self> {
+        Self::build(code.insns(), code.tries())
+    }
+
+    pub(crate) fn build(insns: &[ushort], tries: &[TryCatchHandlers]) -> Result<Self> {
+        let instructions = InstructionIter::new(insns)
+            .with_offsets()
+            .collect::<Result<Vec<_>>>()?;
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        for try_block in tries {
+            let start = try_block.start_addr() as usize;
+            leaders.insert(start);
+            leaders.insert(start + usize::from(try_block.insn_count()));
+            for handler in try_block.catch_handlers() {
+                leaders.insert(handler.addr() as usize);
+            }
+        }
+        for (offset, instruction) in &instructions {
+            let next = offset + instruction.len();
+            let targets = branch_targets(insns, *offset, instruction)?;
+            if !targets.is_empty() || !instruction.opcode().can_continue() {
+                leaders.insert(next);
+            }
+            leaders.extend(targets);
+        }
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut last_instruction: Vec<usize> = Vec::new();
+        for (index, (offset, instruction)) in instructions.iter().enumerate() {
+            let opcode = instruction.opcode();
+            if is_payload(opcode) {
+                continue;
+            }
+            let starts_block = match blocks.last() {
+                Some(block) => leaders.contains(offset) || block.end != *offset,
+                None => true,
+            };
+            if starts_block {
+                blocks.push(BasicBlock {
+                    start: *offset,
+                    end: *offset,
+                    last_opcode: opcode,
+                    successors: Vec::new(),
+                    handlers: Vec::new(),
+                    padding: true,
+                });
+                last_instruction.push(index);
+            }
+            let block = blocks.last_mut().expect("a block was just pushed");
+            block.end = offset + instruction.len();
+            block.last_opcode = opcode;
+            block.padding &= opcode == Opcode::Nop;
+            *last_instruction
+                .last_mut()
+                .expect("a block was just pushed") = index;
+        }
+
+        let block_at = |offset: usize| -> Result<usize> {
+            blocks
+                .binary_search_by_key(&offset, |block| block.start)
+                .map_err(|_| Error::MalFormed(format!("No instruction at offset {}", offset)))
+        };
+        let mut edges = Vec::with_capacity(blocks.len());
+        for (block, last) in blocks.iter().zip(&last_instruction) {
+            let (offset, instruction) = &instructions[*last];
+            let mut successors = branch_targets(insns, *offset, instruction)?
+                .into_iter()
+                .map(block_at)
+                .collect::<Result<Vec<_>>>()?;
+            // the next instruction is a payload if no block starts there, like after the
+            // padding before a payload.
+            if instruction.opcode().can_continue() {
+                successors.extend(block_at(block.end).ok());
+            }
+            successors.sort_unstable();
+            successors.dedup();
+            let mut handlers = Vec::new();
+            let throwing = instructions
+                .iter()
+                .skip_while(|(offset, _)| *offset < block.start)
+                .take_while(|(offset, _)| *offset < block.end)
+                .filter(|(_, instruction)| instruction.opcode().can_throw());
+            for (offset, _) in throwing {
+                let covering = tries.iter().filter(|try_block| {
+                    let start = try_block.start_addr() as usize;
+                    (start..start + usize::from(try_block.insn_count())).contains(offset)
+                });
+                for try_block in covering {
+                    for handler in try_block.catch_handlers() {
+                        handlers.push(block_at(handler.addr() as usize)?);
+                    }
+                }
+            }
+            handlers.sort_unstable();
+            handlers.dedup();
+            edges.push((successors, handlers));
+        }
+        for (block, (successors, handlers)) in blocks.iter_mut().zip(edges) {
+            block.successors = successors;
+            block.handlers = handlers;
+        }
+
+        let mut reachable = vec![false; blocks.len()];
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        if !blocks.is_empty() {
+            reachable[0] = true;
+            queue.push_back(0);
+        }
+        while let Some(index) = queue.pop_front() {
+            let block = &blocks[index];
+            for next in block.successors.iter().chain(&block.handlers) {
+                if !reachable[*next] {
+                    reachable[*next] = true;
+                    queue.push_back(*next);
+                }
+            }
+        }
+        debug!(target: "cfg", "{} blocks, {} reachable", blocks.len(),
+            reachable.iter().filter(|reachable| **reachable).count());
+        Ok(Self { blocks, reachable })
+    }
+
+    /// Index of the block which starts at `offset`.
+    pub fn block_at(&self, offset: usize) -> Option<usize> {
+        self.blocks
+            .binary_search_by_key(&offset, |block| block.start)
+            .ok()
+    }
+
+    /// Returns `true` if the block at `index` can be reached from the entry of the method,
+    /// by branches, fall throughs or exceptions.
+    pub fn is_reachable(&self, index: usize) -> bool {
+        self.reachable.get(index).copied().unwrap_or(false)
+    }
+
+    /// The blocks which can't be reached from the entry of the method, like code after an
+    /// unconditional `throw` or `return` which nothing branches to. Blocks which only
+    /// contain `nop`s are padding and aren't reported.
+    pub fn unreachable_blocks(&self) -> Vec<&BasicBlock> {
+        self.blocks
+            .iter()
+            .zip(&self.reachable)
+            .filter(|(block, reachable)| !**reachable && !block.padding)
+            .map(|(block, _)| block)
+            .collect()
+    }
+}
+
+fn is_payload(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::PackedSwitchPayload | Opcode::SparseSwitchPayload | Opcode::FillArrayDataPayload
+    )
+}
+
+/// Offsets the instruction at `offset` may branch to, besides the next instruction.
+fn branch_targets(
+    insns: &[ushort],
+    offset: usize,
+    instruction: &Instruction,
+) -> Result<Vec<usize>> {
+    let opcode = instruction.opcode();
+    let branch_offset = match instruction.branch_offset() {
+        Some(branch_offset) if opcode != Opcode::FillArrayData => branch_offset,
+        _ => return Ok(Vec::new()),
+    };
+    let target = |relative: i32| -> Result<usize> {
+        let target = offset as i64 + i64::from(relative);
+        if target < 0 || target >= insns.len() as i64 {
+            return Err(Error::MalFormed(format!(
+                "Branch target {} of {} at {} is outside the code",
+                target,
+                opcode.mnemonic(),
+                offset
+            )));
+        }
+        Ok(target as usize)
+    };
+    let payload_offset = target(branch_offset)?;
+    match opcode {
+        Opcode::PackedSwitch | Opcode::SparseSwitch => {
+            let payload = Instruction::decode(&insns[payload_offset..], Quickening::None)?;
+            match payload.payload() {
+                Some(Payload::PackedSwitch { targets, .. })
+                | Some(Payload::SparseSwitch { targets, .. }) => {
+                    targets.into_iter().map(target).collect()
+                }
+                _ => Err(Error::MalFormed(format!(
+                    "Expected a switch payload for {} at {}, found {}",
+                    opcode.mnemonic(),
+                    offset,
+                    payload.opcode().mnemonic()
+                ))),
+            }
+        }
+        _ => Ok(vec![payload_offset]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cfg;
+
+    #[test]
+    fn test_unreachable_blocks() {
+        // const/4 v0, 0; return v0; const/4 v1, 0; return-void
+        let cfg = Cfg::build(&[0x0012, 0x000f, 0x0112, 0x000e], &[]).unwrap();
+        assert_eq!(cfg.blocks().len(), 2);
+        let unreachable = cfg.unreachable_blocks();
+        assert_eq!(unreachable.len(), 1);
+        assert_eq!((unreachable[0].start(), unreachable[0].end()), (2, 4));
+
+        // if-eqz v0, +3; return-void; return-void
+        let cfg = Cfg::build(&[0x0038, 0x0003, 0x000e, 0x000e], &[]).unwrap();
+        assert_eq!(cfg.blocks().len(), 3);
+        assert_eq!(cfg.blocks()[0].successors(), &[1, 2]);
+        assert!(cfg.unreachable_blocks().is_empty());
+
+        // return-void; nop
+        let cfg = Cfg::build(&[0x000e, 0x0000], &[]).unwrap();
+        assert!(!cfg.is_reachable(1));
+        assert!(cfg.unreachable_blocks().is_empty());
+
+        // goto +5, outside the code
+        assert!(Cfg::build(&[0x0528], &[]).is_err());
+    }
+
+    #[test]
+    fn test_cfg_of_methods() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        for (_, code) in dex.method_code_items().unwrap() {
+            let cfg = Cfg::new(&code).expect("failed to build cfg");
+            assert_eq!(cfg.blocks()[0].start(), 0);
+            assert!(cfg.is_reachable(0));
+        }
+    }
+}
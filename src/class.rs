@@ -1,19 +1,23 @@
 //! Dex `Class` and supporting structures.
-use std::clone::Clone;
+use std::{clone::Clone, collections::HashMap, fmt, sync::Arc};
 
 use getset::{CopyGetters, Getters};
 use scroll::{ctx, Pread, Uleb128};
 
 use crate::{
-    annotation::{AnnotationSetItem, AnnotationsDirectoryItem},
+    annotation::{AnnotationSetItem, AnnotationSetRefList, AnnotationsDirectoryOffsets},
     encoded_item::EncodedItemArrayCtx,
-    error::Error,
-    field::{EncodedFieldArray, Field},
-    jtype::Type,
-    method::{EncodedMethodArray, Method},
+    encoded_value::{EncodedArray, EncodedValue},
+    error::{Error, ResultExt},
+    field::{EncodedFieldArray, Field, FieldId},
+    fingerprint::Fingerprint,
+    jtype::{self, JavaNameOptions, Type},
+    method::{EncodedMethodArray, Method, MethodId, MethodIdItem},
     source::Source,
     string::DexString,
-    uint, utils,
+    uint, ulong,
+    utils::{self, Lazy, Loader},
+    ItemType,
 };
 
 /// `ClassId` is an index into the Types section. The corresponding `Type` denotes the type of
@@ -36,9 +40,13 @@ bitflags! {
     }
 }
 
+/// Suffix which D8 gives to the name of an interface for the class holding its default and
+/// static methods, when desugaring them for API levels below 24.
+const COMPANION_SUFFIX: &str = "$-CC;";
+
 /// A `Dex` Class. This is constructed from a `ClassDefItem` and a `ClassDataItem`.
 #[derive(Debug, Getters, CopyGetters)]
-pub struct Class {
+pub struct Class<'a> {
     /// Index into `TypeId`s. TypeId should refer to a class type.
     #[get_copy = "pub"]
     pub(crate) id: ClassId,
@@ -53,6 +61,8 @@ pub struct Class {
     /// Index into the `TypeId`s for the super class, if there is one.
     #[get_copy = "pub"]
     pub(crate) super_class: Option<ClassId>,
+    /// Type of the super class, if there is one.
+    pub(crate) superclass_type: Option<Type>,
     /// List of the interfaces implemented by this class.
     #[get = "pub"]
     pub(crate) interfaces: Vec<Type>,
@@ -60,22 +70,26 @@ pub struct Class {
     pub(crate) source_file: Option<DexString>,
     /// Static fields defined in the class.
     #[get = "pub"]
-    pub(crate) static_fields: Vec<Field>,
+    pub(crate) static_fields: Vec<Field<'a>>,
     /// Instance fields defined in the class.
     #[get = "pub"]
-    pub(crate) instance_fields: Vec<Field>,
+    pub(crate) instance_fields: Vec<Field<'a>>,
     /// List of static, private methods and constructors defined in the class.
     #[get = "pub"]
-    pub(crate) direct_methods: Vec<Method>,
+    pub(crate) direct_methods: Vec<Method<'a>>,
     /// List of parent class methods overriden by this class.
     #[get = "pub"]
-    pub(crate) virtual_methods: Vec<Method>,
-    /// Annotations of the class.
-    #[get = "pub"]
-    pub(crate) annotations: AnnotationSetItem,
+    pub(crate) virtual_methods: Vec<Method<'a>>,
+    /// Annotations of the class, parsed on first access.
+    pub(crate) annotations: Lazy<'a, AnnotationSetItem>,
+    /// The interface whose default and static methods are held by this class, if it is a
+    /// companion class generated by D8.
+    pub(crate) companion_of: Option<Type>,
+    /// The companion class generated by D8 for this interface, if its type is in the `Dex`.
+    pub(crate) companion_class: Option<Type>,
 }
 
-impl Class {
+impl<'a> Class<'a> {
     gen_is_flag_set!(is_public, PUBLIC);
     gen_is_flag_set!(is_private, PRIVATE);
     gen_is_flag_set!(is_protected, PROTECTED);
@@ -89,7 +103,145 @@ impl Class {
 
     /// Returns the value of `dalvik.annotation.Signature`.
     pub fn signature(&self) -> super::Result<Option<String>> {
-        utils::get_signature(self.annotations())
+        utils::get_signature(self.try_annotations()?)
+    }
+
+    /// Annotations of the class, parsed on first access. Empty if they are malformed,
+    /// `try_annotations` returns the error instead.
+    pub fn annotations(&self) -> &AnnotationSetItem {
+        self.annotations.get_or_empty()
+    }
+
+    /// Annotations of the class, parsed on first access.
+    pub fn try_annotations(&self) -> super::Result<&AnnotationSetItem> {
+        self.annotations.get()
+    }
+
+    /// Returns the Java name of the class, rendered with `options`. The `InnerClass` and
+    /// `EnclosingClass` annotations tell nested and anonymous classes apart when they are
+    /// present, otherwise a `$` in the name is taken to start a nested class.
+    pub fn to_java_name(&self, options: JavaNameOptions) -> super::Result<String> {
+        let inner_name = match self.system_annotation("InnerClass", "name")? {
+            Some(_) => self.declared_name()?.map(ToString::to_string),
+            None => return Ok(self.jtype.to_java_type_with(options)),
+        };
+        let binary_name = self.jtype.to_java_type();
+        let inner_name = match inner_name {
+            Some(inner_name) => inner_name,
+            None if options.is_describe_anonymous() => {
+                return Ok(format!("<anonymous {}>", binary_name))
+            }
+            None => return Ok(binary_name),
+        };
+        let outer = match self.enclosing_class()? {
+            Some(outer) => outer.to_java_type_with(options),
+            None => match binary_name.strip_suffix(&inner_name) {
+                Some(outer) if outer.ends_with('$') => outer[..outer.len() - 1].to_string(),
+                _ => return Ok(binary_name),
+            },
+        };
+        Ok(jtype::nested_name("", &[&outer, &inner_name], options))
+    }
+
+    /// Package of the class in Java notation, like `com.example`.
+    pub fn package_name(&self) -> String {
+        self.jtype.package_name()
+    }
+
+    /// Name of the class without its package and enclosing classes, like `Inner` for
+    /// `com.example.Outer$Inner`. The name comes from the `InnerClass` annotation if there
+    /// is one, anonymous classes keep their number.
+    pub fn simple_name(&self) -> super::Result<String> {
+        Ok(match self.declared_name()? {
+            Some(name) => name.to_string(),
+            None => self.jtype.simple_name(),
+        })
+    }
+
+    /// Name of the class in the source code, from its `InnerClass` annotation. `None` for
+    /// top level and anonymous classes.
+    pub fn declared_name(&self) -> super::Result<Option<&DexString>> {
+        Ok(match self.system_annotation("InnerClass", "name")? {
+            Some(EncodedValue::String(name)) => Some(name),
+            _ => None,
+        })
+    }
+
+    /// Returns `true` if the `InnerClass` annotation of the class marks it as anonymous.
+    pub fn is_anonymous(&self) -> super::Result<bool> {
+        Ok(self.system_annotation("InnerClass", "name")? == Some(&EncodedValue::Null))
+    }
+
+    /// The class this class is a member of, from its `EnclosingClass` annotation. `None`
+    /// for top level classes, and for anonymous and local classes, which have an enclosing
+    /// method instead.
+    pub fn enclosing_class(&self) -> super::Result<Option<&Type>> {
+        Ok(match self.system_annotation("EnclosingClass", "value")? {
+            Some(EncodedValue::Type(jtype)) => Some(jtype),
+            _ => None,
+        })
+    }
+
+    /// The method in which this anonymous or local class is defined, from its
+    /// `EnclosingMethod` annotation.
+    pub fn enclosing_method(&self) -> super::Result<Option<&MethodIdItem>> {
+        Ok(match self.system_annotation("EnclosingMethod", "value")? {
+            Some(EncodedValue::Method(method)) => Some(method),
+            _ => None,
+        })
+    }
+
+    /// The member classes of this class, from its `MemberClasses` annotation. Anonymous and
+    /// local classes aren't members.
+    pub fn inner_classes(&self) -> super::Result<Vec<&Type>> {
+        Ok(match self.system_annotation("MemberClasses", "value")? {
+            Some(EncodedValue::Array(values)) => values
+                .iter()
+                .filter_map(|value| match value {
+                    EncodedValue::Type(jtype) => Some(jtype),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        })
+    }
+
+    /// Default values of the elements of an annotation type, from its `AnnotationDefault`
+    /// annotation. Elements without a default aren't included, and the map is empty for
+    /// classes which aren't annotation types.
+    pub fn annotation_defaults(&self) -> super::Result<HashMap<&DexString, &EncodedValue>> {
+        if !self.is_annotation() {
+            return Ok(HashMap::new());
+        }
+        Ok(match self.system_annotation("AnnotationDefault", "value")? {
+            Some(EncodedValue::Annotation(defaults)) => defaults
+                .elements()
+                .iter()
+                .map(|element| (element.name(), element.value()))
+                .collect(),
+            _ => HashMap::new(),
+        })
+    }
+
+    /// Value of the `element` of the `dalvik.annotation` annotation `name` of the class.
+    fn system_annotation(
+        &self,
+        name: &str,
+        element: &str,
+    ) -> super::Result<Option<&EncodedValue>> {
+        let descriptor = format!("Ldalvik/annotation/{};", name);
+        Ok(self
+            .try_annotations()?
+            .iter()
+            .find(|item| item.jtype() == descriptor.as_str())
+            .and_then(|item| item.annotation().find_element(element))
+            .map(|element| element.value()))
+    }
+
+    /// Type of the super class, `None` for `java.lang.Object`. See `Dex::superclass_chain`
+    /// for the super classes of the super class.
+    pub fn superclass_type(&self) -> Option<&Type> {
+        self.superclass_type.as_ref()
     }
 
     /// The file in which this class is found in the source code.
@@ -97,125 +249,453 @@ impl Class {
         self.source_file.as_ref()
     }
 
+    /// The interface whose default and static methods are held by this class, if it is a
+    /// companion class generated by D8 for code targeting API levels below 24.
+    pub fn companion_of(&self) -> Option<&Type> {
+        self.companion_of.as_ref()
+    }
+
+    /// The companion class generated by D8 to hold the default and static methods of this
+    /// interface, if its type is in the `Dex`.
+    pub fn companion_class(&self) -> Option<&Type> {
+        self.companion_class.as_ref()
+    }
+
     /// List of fields defined in this class.
-    pub fn fields(&self) -> impl Iterator<Item = &Field> + '_ {
+    pub fn fields(&self) -> impl Iterator<Item = &Field<'a>> + '_ {
         self.static_fields()
             .iter()
             .chain(self.instance_fields().iter())
     }
 
+    /// The field of this class with the given name. Fields are sorted by name in a `Dex`,
+    /// so they are binary searched.
+    pub fn field(&self, name: &str) -> Option<&Field<'a>> {
+        let find = |fields: &'_ [Field<'a>]| {
+            fields
+                .binary_search_by(|field| field.name().encode_utf16().cmp(name.encode_utf16()))
+                .ok()
+        };
+        find(&self.static_fields)
+            .map(|index| &self.static_fields[index])
+            .or_else(|| find(&self.instance_fields).map(|index| &self.instance_fields[index]))
+    }
+
     /// List of methods defined in this class.
-    pub fn methods(&self) -> impl Iterator<Item = &Method> + '_ {
+    pub fn methods(&self) -> impl Iterator<Item = &Method<'a>> + '_ {
         self.direct_methods()
             .iter()
             .chain(self.virtual_methods().iter())
     }
 
+    /// Overloads of the method with the given name defined in this class.
+    pub fn methods_named<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'b Method<'a>> + 'b {
+        self.methods().filter(move |method| method.name() == name)
+    }
+
+    /// The method defined in this class with the given name and parameter type descriptors,
+    /// like `method("onCreate", &["Landroid/os/Bundle;"])`.
+    pub fn method(&self, name: &str, param_descriptors: &[&str]) -> Option<&Method<'a>> {
+        self.methods().find(|method| {
+            method.name() == name
+                && method.params().len() == param_descriptors.len()
+                && method
+                    .params()
+                    .iter()
+                    .zip(param_descriptors)
+                    .all(|(param, descriptor)| param == descriptor)
+        })
+    }
+
+    /// Fingerprint of the structure of this class which doesn't depend on the names of the
+    /// class and its members, see `fingerprint::match_classes`. Fails if the code of a
+    /// method is malformed.
+    pub fn structural_fingerprint(&self) -> super::Result<Fingerprint> {
+        Fingerprint::from_class(self)
+    }
+
+    /// The class with the code and annotations of its members parsed, which doesn't borrow
+    /// the `Dex` anymore, like the classes of a `FrozenDex`. Fails if they are malformed.
+    pub fn into_owned(self) -> super::Result<Class<'static>> {
+        let fields = |fields: Vec<Field<'a>>| {
+            fields
+                .into_iter()
+                .map(Field::into_owned)
+                .collect::<super::Result<_>>()
+        };
+        let methods = |methods: Vec<Method<'a>>| {
+            methods
+                .into_iter()
+                .map(Method::into_owned)
+                .collect::<super::Result<_>>()
+        };
+        Ok(Class {
+            id: self.id,
+            jtype: self.jtype,
+            access_flags: self.access_flags,
+            super_class: self.super_class,
+            superclass_type: self.superclass_type,
+            interfaces: self.interfaces,
+            source_file: self.source_file,
+            static_fields: fields(self.static_fields)?,
+            instance_fields: fields(self.instance_fields)?,
+            direct_methods: methods(self.direct_methods)?,
+            virtual_methods: methods(self.virtual_methods)?,
+            annotations: self.annotations.into_loaded()?,
+            companion_of: self.companion_of,
+            companion_class: self.companion_class,
+        })
+    }
+
     pub(crate) fn try_from_dex<T: AsRef<[u8]>>(
-        dex: &super::Dex<T>,
+        dex: &'a super::Dex<T>,
         class_def: &ClassDefItem,
     ) -> super::Result<Self> {
+        let span = phase_span!("class", class_idx = class_def.class_idx; fields, methods);
         debug!(target: "class", "trying to load class: {}", class_def.class_idx);
         let jtype = dex.get_type(class_def.class_idx)?;
 
         debug!(target: "class", "class: {}, jtype: {}", class_def.class_idx, jtype);
 
-        let data_off = class_def.class_data_off;
-
-        let AnnotationsDirectoryItem {
-            class_annotations,
-            mut field_annotations,
-            mut method_annotations,
-            mut parameter_annotations,
-        } = dex.get_annotations_directory_item(class_def.annotations_off)?;
+        let annotations = ClassAnnotations::new(dex, class_def)?;
         let static_values = dex.get_static_values(class_def.static_values_off)?;
-        let (static_fields, instance_fields, direct_methods, virtual_methods) = dex
-            .get_class_data(data_off)?
-            .map(move |c| {
-                let mut static_values = static_values.into_inner();
-                // the order of static values corresponds to the fields list.
-                // reversing the values so that the pop below returns values in
-                // correct order.
-                static_values.reverse();
-                Ok((
-                    try_from_item!(c.static_fields, |encoded_field| {
-                        dex.get_field(
-                            &encoded_field,
-                            static_values.pop(),
-                            field_annotations
-                                .binary_search_by_key(&encoded_field.field_id(), |f| f.field_idx())
-                                .map(|index| field_annotations.remove(index).annotations)
-                                .unwrap_or_else(|_| Default::default()),
-                        )
-                    }),
-                    try_from_item!(c.instance_fields, |encoded_field| {
-                        dex.get_field(
-                            &encoded_field,
-                            None,
-                            field_annotations
-                                .binary_search_by_key(&encoded_field.field_id(), |f| f.field_idx())
-                                .map(|index| field_annotations.remove(index).annotations)
-                                .unwrap_or_else(|_| Default::default()),
-                        )
-                    }),
-                    try_from_item!(c.direct_methods, |encoded_method| {
-                        let method_annotations = method_annotations
-                            .binary_search_by_key(&encoded_method.method_id(), |m| m.method_idx())
-                            .map(|index| method_annotations.remove(index).annotations)
-                            .unwrap_or_else(|_| Default::default());
-                        let parameter_annotations = parameter_annotations
-                            .binary_search_by_key(&encoded_method.method_id(), |m| m.method_idx())
-                            .map(|index| parameter_annotations.remove(index).annotations)
-                            .unwrap_or_else(|_| Default::default());
-                        dex.get_method(&encoded_method, method_annotations, parameter_annotations)
-                    }),
-                    try_from_item!(c.virtual_methods, |encoded_method| {
-                        let method_annotations = method_annotations
-                            .binary_search_by_key(&encoded_method.method_id(), |m| m.method_idx())
-                            .map(|index| method_annotations.remove(index).annotations)
-                            .unwrap_or_else(|_| Default::default());
-                        let parameter_annotations = parameter_annotations
-                            .binary_search_by_key(&encoded_method.method_id(), |m| m.method_idx())
-                            .map(|index| parameter_annotations.remove(index).annotations)
-                            .unwrap_or_else(|_| Default::default());
-                        dex.get_method(&encoded_method, method_annotations, parameter_annotations)
-                    }),
-                ))
-            })
-            .unwrap_or_else(|| Ok::<_, Error>(Default::default()))?;
+        let class_data_off = class_def.class_data_off as usize;
+        let (static_fields, instance_fields, direct_methods, virtual_methods) =
+            match dex.get_class_data(class_def.class_data_off)? {
+                Some(class_data) => (
+                    load_fields(
+                        dex,
+                        class_data.static_fields,
+                        static_values.into_inner(),
+                        &annotations,
+                    )
+                    .with_item(ItemType::ClassDataItem, class_data_off)?,
+                    load_fields(dex, class_data.instance_fields, Vec::new(), &annotations)
+                        .with_item(ItemType::ClassDataItem, class_data_off)?,
+                    load_methods(dex, class_data.direct_methods, &annotations)
+                        .with_item(ItemType::ClassDataItem, class_data_off)?,
+                    load_methods(dex, class_data.virtual_methods, &annotations)
+                        .with_item(ItemType::ClassDataItem, class_data_off)?,
+                ),
+                None => Default::default(),
+            };
+
+        record!(
+            span,
+            fields = static_fields.len() + instance_fields.len(),
+            methods = direct_methods.len() + virtual_methods.len()
+        );
 
         debug!(target: "class", "super class id: {}", class_def.superclass_idx);
-        let super_class = if class_def.superclass_idx != super::NO_INDEX {
-            Some(class_def.superclass_idx)
+        debug!(target: "class", "access flags: {}", class_def.access_flags);
+
+        let access_flags = class_def.flags()?;
+        let descriptor = jtype.type_descriptor();
+        let companion_of = match interface_of_companion(descriptor) {
+            Some(interface) if access_flags.contains(AccessFlags::SYNTHETIC) => {
+                dex.get_type_from_descriptor(&interface)?
+            }
+            _ => None,
+        };
+        let companion_class = if access_flags.contains(AccessFlags::INTERFACE) {
+            dex.get_type_from_descriptor(&companion_of_interface(descriptor))?
         } else {
             None
         };
-        debug!(target: "class", "access flags: {}", class_def.access_flags);
 
         Ok(Class {
             id: class_def.class_idx,
             jtype,
-            super_class,
+            super_class: class_def.super_class(),
+            superclass_type: class_def
+                .super_class()
+                .map(|super_class| dex.get_type(super_class))
+                .transpose()?,
             interfaces: dex.get_interfaces(class_def.interfaces_off)?,
-            access_flags: AccessFlags::from_bits(class_def.access_flags).ok_or_else(|| {
-                Error::InvalidId(format!(
-                    "Invalid Access flags in class {}",
-                    class_def.class_idx
-                ))
-            })?,
+            access_flags,
             source_file: dex.get_source_file(class_def.source_file_idx)?,
             static_fields,
             instance_fields,
             direct_methods,
             virtual_methods,
-            annotations: class_annotations,
+            annotations: annotations.class(),
+            companion_of,
+            companion_class,
+        })
+    }
+}
+
+/// Type descriptor of the companion class of the interface `descriptor`.
+fn companion_of_interface(descriptor: &str) -> String {
+    format!("{}{}", descriptor.trim_end_matches(';'), COMPANION_SUFFIX)
+}
+
+/// Type descriptor of the interface of the companion class `descriptor`, `None` if
+/// `descriptor` isn't named like a companion class.
+fn interface_of_companion(descriptor: &str) -> Option<String> {
+    descriptor
+        .strip_suffix(COMPANION_SUFFIX)
+        .filter(|name| name.len() > 1)
+        .map(|name| format!("{};", name))
+}
+
+/// A class of which only the `ClassDefItem` has been read, see `Dex::class_refs`. The other
+/// parts of the class are parsed on each call, `load` parses the whole `Class` at once.
+pub struct ClassRef<T> {
+    dex: super::Dex<T>,
+    class_def: ClassDefItem,
+}
+
+impl<T> ClassRef<T> {
+    pub(crate) fn new(dex: &super::Dex<T>, class_def: ClassDefItem) -> Self {
+        Self {
+            dex: dex.clone(),
+            class_def,
+        }
+    }
+
+    /// The `ClassDefItem` of the class.
+    pub fn class_def(&self) -> ClassDefItem {
+        self.class_def
+    }
+
+    /// Index of the class in the `TypeId`s list.
+    pub fn id(&self) -> ClassId {
+        self.class_def.class_idx
+    }
+
+    /// Id of the super class, `None` for `java.lang.Object`.
+    pub fn super_class(&self) -> Option<ClassId> {
+        self.class_def.super_class()
+    }
+
+    /// Access flags of the class.
+    pub fn access_flags(&self) -> super::Result<AccessFlags> {
+        self.class_def.flags()
+    }
+}
+
+impl<T: AsRef<[u8]>> ClassRef<T> {
+    /// `Type` of the class.
+    pub fn jtype(&self) -> super::Result<Type> {
+        self.dex.get_type(self.class_def.class_idx)
+    }
+
+    /// Interfaces implemented by the class.
+    pub fn interfaces(&self) -> super::Result<Vec<Type>> {
+        self.dex.get_interfaces(self.class_def.interfaces_off)
+    }
+
+    /// The file in which the class is found in the source code.
+    pub fn source_file(&self) -> super::Result<Option<DexString>> {
+        self.dex.get_source_file(self.class_def.source_file_idx)
+    }
+
+    /// Initial values of the static fields, in the order of `static_fields`. Fields at the
+    /// end of the list may have no value.
+    pub fn static_values(&self) -> super::Result<EncodedArray> {
+        self.dex.get_static_values(self.class_def.static_values_off)
+    }
+
+    /// Annotations of the class.
+    pub fn annotations(&self) -> super::Result<AnnotationSetItem> {
+        let offsets = self
+            .dex
+            .get_annotations_directory_offsets(self.class_def.annotations_off)?;
+        self.dex
+            .get_annotation_set_item(offsets.class_annotations_off)
+    }
+}
+
+impl<T: AsRef<[u8]>> ClassRef<T> {
+    /// Static fields defined in the class.
+    pub fn static_fields(&self) -> super::Result<Vec<Field<'_>>> {
+        let class_data = match self.class_data()? {
+            Some(class_data) => class_data,
+            None => return Ok(Vec::new()),
+        };
+        load_fields(
+            &self.dex,
+            class_data.static_fields,
+            self.static_values()?.into_inner(),
+            &ClassAnnotations::new(&self.dex, &self.class_def)?,
+        )
+    }
+
+    /// Instance fields defined in the class.
+    pub fn instance_fields(&self) -> super::Result<Vec<Field<'_>>> {
+        let class_data = match self.class_data()? {
+            Some(class_data) => class_data,
+            None => return Ok(Vec::new()),
+        };
+        load_fields(
+            &self.dex,
+            class_data.instance_fields,
+            Vec::new(),
+            &ClassAnnotations::new(&self.dex, &self.class_def)?,
+        )
+    }
+
+    /// Static, private methods and constructors defined in the class.
+    pub fn direct_methods(&self) -> super::Result<Vec<Method<'_>>> {
+        let class_data = match self.class_data()? {
+            Some(class_data) => class_data,
+            None => return Ok(Vec::new()),
+        };
+        load_methods(
+            &self.dex,
+            class_data.direct_methods,
+            &ClassAnnotations::new(&self.dex, &self.class_def)?,
+        )
+    }
+
+    /// Virtual methods defined in the class.
+    pub fn virtual_methods(&self) -> super::Result<Vec<Method<'_>>> {
+        let class_data = match self.class_data()? {
+            Some(class_data) => class_data,
+            None => return Ok(Vec::new()),
+        };
+        load_methods(
+            &self.dex,
+            class_data.virtual_methods,
+            &ClassAnnotations::new(&self.dex, &self.class_def)?,
+        )
+    }
+
+    /// Parses the whole class.
+    pub fn load(&self) -> super::Result<Class<'_>> {
+        Class::try_from_dex(&self.dex, &self.class_def)
+    }
+
+    fn class_data(&self) -> super::Result<Option<ClassDataItem>> {
+        self.dex.get_class_data(self.class_def.class_data_off)
+    }
+}
+
+impl<T> Clone for ClassRef<T> {
+    fn clone(&self) -> Self {
+        Self::new(&self.dex, self.class_def)
+    }
+}
+
+impl<T> fmt::Debug for ClassRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClassRef")
+            .field("class_def", &self.class_def)
+            .finish()
+    }
+}
+
+/// Annotations of a class and its members. Only the offsets are read up front, the
+/// annotation sets are parsed on first access.
+struct ClassAnnotations<'a> {
+    offsets: AnnotationsDirectoryOffsets,
+    sets: Loader<'a, AnnotationSetItem>,
+    ref_lists: Loader<'a, AnnotationSetRefList>,
+}
+
+impl<'a> ClassAnnotations<'a> {
+    fn new<T: AsRef<[u8]>>(
+        dex: &'a super::Dex<T>,
+        class_def: &ClassDefItem,
+    ) -> super::Result<Self> {
+        let _span = phase_span!("annotations", annotations_off = class_def.annotations_off);
+        let offsets = dex.get_annotations_directory_offsets(class_def.annotations_off)?;
+        let dex = Arc::new(dex.borrowed());
+        let sets: Loader<'a, AnnotationSetItem> = {
+            let dex = dex.clone();
+            Arc::new(move |offset| dex.get_annotation_set_item(offset as uint))
+        };
+        let ref_lists: Loader<'a, AnnotationSetRefList> =
+            Arc::new(move |offset| dex.get_annotation_set_ref_list(offset as uint));
+        Ok(Self {
+            offsets,
+            sets,
+            ref_lists,
         })
     }
+
+    fn class(&self) -> Lazy<'a, AnnotationSetItem> {
+        Lazy::new(
+            ulong::from(self.offsets.class_annotations_off),
+            self.sets.clone(),
+        )
+    }
+
+    fn field(&self, field_id: FieldId) -> Lazy<'a, AnnotationSetItem> {
+        let offset = annotations_off(&self.offsets.field_annotations, field_id);
+        Lazy::new(offset, self.sets.clone())
+    }
+
+    fn method(&self, method_id: MethodId) -> Lazy<'a, AnnotationSetItem> {
+        let offset = annotations_off(&self.offsets.method_annotations, method_id);
+        Lazy::new(offset, self.sets.clone())
+    }
+
+    fn parameters(&self, method_id: MethodId) -> Lazy<'a, AnnotationSetRefList> {
+        let offset = annotations_off(&self.offsets.parameter_annotations, method_id);
+        Lazy::new(offset, self.ref_lists.clone())
+    }
+}
+
+/// Offset of the annotations of the field or method `id` in the entries of an annotations
+/// directory, `0` if it has none.
+fn annotations_off(entries: &[(ulong, uint)], id: ulong) -> ulong {
+    entries
+        .binary_search_by_key(&id, |&(idx, _)| idx)
+        .map_or(0, |index| ulong::from(entries[index].1))
+}
+
+/// Parses the `fields` of a class. The order of `initial_values` corresponds to the fields.
+fn load_fields<'a, T: AsRef<[u8]>>(
+    dex: &super::Dex<T>,
+    fields: Option<EncodedFieldArray>,
+    initial_values: Vec<EncodedValue>,
+    annotations: &ClassAnnotations<'a>,
+) -> super::Result<Vec<Field<'a>>> {
+    let mut initial_values = initial_values.into_iter();
+    let mut fields: Vec<Field> = try_from_item!(fields, |encoded_field| {
+        Field::try_from_dex(
+            dex,
+            &encoded_field,
+            initial_values.next(),
+            annotations.field(encoded_field.field_id()),
+        )
+        .with_id("field_id", encoded_field.field_id())
+    });
+    fields
+        .iter_mut()
+        .enumerate()
+        .for_each(|(slot, field)| field.slot = slot);
+    Ok(fields)
+}
+
+/// Parses the `methods` of a class.
+fn load_methods<'a, T: AsRef<[u8]>>(
+    dex: &'a super::Dex<T>,
+    methods: Option<EncodedMethodArray>,
+    annotations: &ClassAnnotations<'a>,
+) -> super::Result<Vec<Method<'a>>> {
+    let mut methods: Vec<Method> = try_from_item!(methods, |encoded_method| {
+        let method_id = encoded_method.method_id();
+        Method::try_from_dex(
+            dex,
+            &encoded_method,
+            annotations.method(method_id),
+            annotations.parameters(method_id),
+        )
+        .with_id("method_id", method_id)
+    });
+    methods
+        .iter_mut()
+        .enumerate()
+        .for_each(|(slot, method)| method.slot = slot);
+    Ok(methods)
 }
 
 /// Contains the details about fields and methods of a class.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#class-data-item)
-#[derive(Getters)]
+#[derive(Debug, Getters)]
 pub struct ClassDataItem {
     /// The list of static fields in this class.
     static_fields: Option<EncodedFieldArray>,
@@ -306,6 +786,22 @@ pub struct ClassDefItem {
     pub(crate) static_values_off: uint,
 }
 
+impl ClassDefItem {
+    pub(crate) fn super_class(&self) -> Option<ClassId> {
+        if self.superclass_idx != super::NO_INDEX {
+            Some(self.superclass_idx)
+        } else {
+            None
+        }
+    }
+
+    fn flags(&self) -> super::Result<AccessFlags> {
+        AccessFlags::from_bits(self.access_flags).ok_or_else(|| {
+            Error::InvalidId(format!("Invalid Access flags in class {}", self.class_idx))
+        })
+    }
+}
+
 /// Iterator over the class_def_items in the class_defs section.
 pub(crate) struct ClassDefItemIter<T> {
     /// Source file of the parent `Dex`.
@@ -342,3 +838,194 @@ impl<T: AsRef<[u8]>> Iterator for ClassDefItemIter<T> {
         Some(class_item)
     }
 }
+
+/// Serialized as the bits of the flags.
+#[cfg(feature = "serde")]
+impl serde::Serialize for AccessFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.bits(), serializer)
+    }
+}
+
+/// Any combination of the defined flags.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for AccessFlags {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::from_bits_truncate(u.arbitrary()?))
+    }
+}
+
+/// Serialized with the members of the class and its annotations. Malformed annotations are
+/// serialized as empty.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Class<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Class", 10)?;
+        state.serialize_field("type", &self.jtype)?;
+        state.serialize_field("access_flags", &self.access_flags)?;
+        state.serialize_field("superclass", &self.superclass_type)?;
+        state.serialize_field("interfaces", &self.interfaces)?;
+        state.serialize_field("source_file", &self.source_file)?;
+        state.serialize_field("static_fields", &self.static_fields)?;
+        state.serialize_field("instance_fields", &self.instance_fields)?;
+        state.serialize_field("direct_methods", &self.direct_methods)?;
+        state.serialize_field("virtual_methods", &self.virtual_methods)?;
+        state.serialize_field("annotations", self.annotations())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{companion_of_interface, interface_of_companion};
+    use crate::jtype::JavaNameOptions;
+
+    #[test]
+    fn test_companion_names() {
+        assert_eq!(
+            companion_of_interface("Lcom/example/Foo;"),
+            "Lcom/example/Foo$-CC;"
+        );
+        assert_eq!(
+            interface_of_companion("Lcom/example/Foo$-CC;").as_deref(),
+            Some("Lcom/example/Foo;")
+        );
+        assert_eq!(interface_of_companion("Lcom/example/Foo;"), None);
+        assert_eq!(interface_of_companion("L$-CC;"), None);
+
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        for class in dex.classes() {
+            let class = class.expect("failed to load class");
+            assert_eq!(class.companion_of(), None);
+            assert_eq!(class.companion_class(), None);
+        }
+    }
+
+    #[test]
+    fn test_to_java_name() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let options = JavaNameOptions::default()
+            .dotted_nested()
+            .describe_anonymous();
+        let find = |descriptor: &str| {
+            dex.get_class(
+                dex.get_type_from_descriptor(descriptor)
+                    .unwrap()
+                    .unwrap()
+                    .id(),
+            )
+            .unwrap()
+            .unwrap()
+        };
+        let inner = find("Lorg/adw/launcher/Launcher$DesktopBinder;");
+        assert_eq!(
+            inner.to_java_name(options).unwrap(),
+            "org.adw.launcher.Launcher.DesktopBinder"
+        );
+        assert_eq!(
+            inner.to_java_name(Default::default()).unwrap(),
+            inner.jtype().to_java_type()
+        );
+        let anonymous = find("Lorg/adw/launcher/Launcher$1;");
+        assert_eq!(
+            anonymous.to_java_name(options).unwrap(),
+            "<anonymous org.adw.launcher.Launcher$1>"
+        );
+        let outer = find("Lorg/adw/launcher/Launcher;");
+        assert_eq!(
+            outer.to_java_name(options).unwrap(),
+            "org.adw.launcher.Launcher"
+        );
+
+        assert_eq!(inner.declared_name().unwrap().unwrap(), "DesktopBinder");
+        assert_eq!(inner.enclosing_class().unwrap(), Some(outer.jtype()));
+        assert!(!inner.is_anonymous().unwrap());
+        let inner_classes = outer.inner_classes().unwrap();
+        assert!(inner_classes.contains(&inner.jtype()));
+        assert!(!inner_classes.contains(&anonymous.jtype()));
+        assert!(anonymous.is_anonymous().unwrap());
+        assert_eq!(anonymous.declared_name().unwrap(), None);
+        assert_eq!(anonymous.enclosing_class().unwrap(), None);
+        let enclosing = anonymous
+            .enclosing_method()
+            .unwrap()
+            .expect("no enclosing method");
+        assert_eq!(u32::from(enclosing.class_idx()), outer.id());
+        assert_eq!(outer.declared_name().unwrap(), None);
+        assert!(outer.enclosing_method().unwrap().is_none());
+        assert_eq!(inner.simple_name().unwrap(), "DesktopBinder");
+        assert_eq!(inner.package_name(), "org.adw.launcher");
+        assert_eq!(anonymous.simple_name().unwrap(), "1");
+        assert_eq!(outer.simple_name().unwrap(), "Launcher");
+    }
+
+    #[test]
+    fn test_field() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        for class in dex.classes() {
+            let class = class.expect("failed to load class");
+            for field in class.fields() {
+                assert_eq!(class.field(field.name()).map(|f| f.id()), Some(field.id()));
+            }
+            assert!(class.field("not a field").is_none());
+        }
+    }
+
+    #[test]
+    fn test_methods_named() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let class = dex
+            .find_class_by_name("Lorg/adw/launcher/Launcher;")
+            .unwrap()
+            .expect("class not found");
+        let overloads: Vec<_> = class.methods_named("onCreate").collect();
+        assert_eq!(overloads.len(), 1);
+        let method = class
+            .method("onCreate", &["Landroid/os/Bundle;"])
+            .expect("method not found");
+        assert_eq!(method.id(), overloads[0].id());
+        assert!(class.method("onCreate", &[]).is_none());
+        assert!(class
+            .method("onCreate", &["Landroid/os/Bundle;", "I"])
+            .is_none());
+        assert_eq!(class.methods_named("missing").count(), 0);
+        for method in class.methods() {
+            let params: Vec<String> = method.params().iter().map(ToString::to_string).collect();
+            let params: Vec<&str> = params.iter().map(String::as_str).collect();
+            let found = class
+                .method(method.name(), &params)
+                .expect("method not found");
+            assert_eq!(found.id(), method.id());
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let class = dex
+            .find_class_by_name("Lorg/adw/launcher/Launcher$DesktopBinder;")
+            .unwrap()
+            .expect("class not found");
+        let json = serde_json::to_value(&class).unwrap();
+        assert_eq!(json["type"], "Lorg/adw/launcher/Launcher$DesktopBinder;");
+        assert_eq!(json["access_flags"], class.access_flags().bits());
+        assert_eq!(json["source_file"], "Launcher.java");
+        let methods = json["direct_methods"].as_array().unwrap();
+        assert_eq!(methods.len(), class.direct_methods().len());
+        assert_eq!(methods[0]["class"], json["type"]);
+        let annotation = json["annotations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|item| item["annotation"]["jtype"] == "Ldalvik/annotation/InnerClass;")
+            .expect("no InnerClass annotation");
+        assert_eq!(annotation["visibility"], "System");
+        assert_eq!(
+            annotation["annotation"]["elements"][1],
+            serde_json::json!({"name": "name", "value": {"String": "DesktopBinder"}})
+        );
+    }
+}
@@ -1,14 +1,44 @@
 //! Structures defining the contents of a `Method`'s code.
-use scroll::{ctx, Pread, Uleb128};
-use std::{fmt, ops::Deref};
+use scroll::{ctx, Endian, Pread, Sleb128, Uleb128};
+use std::{borrow::Cow, fmt, ops::Deref};
 
 use getset::{CopyGetters, Getters};
 
 use crate::{
-    encoded_item::EncodedCatchHandlers, error::Error, jtype::Type, string::DexString, uint, ulong,
-    ushort,
+    cdex::{self, CompactCodeItemSizes},
+    encoded_item::EncodedCatchHandlers,
+    error::Error,
+    hierarchy::ClassHierarchy,
+    instruction::InstructionIter,
+    jtype::Type,
+    method::MethodId,
+    string::DexString,
+    ubyte, uint, ulong, ushort,
 };
 
+// Opcodes of the debug info state machine.
+pub(crate) const DBG_END_SEQUENCE: ubyte = 0x00;
+pub(crate) const DBG_ADVANCE_PC: ubyte = 0x01;
+pub(crate) const DBG_ADVANCE_LINE: ubyte = 0x02;
+pub(crate) const DBG_START_LOCAL: ubyte = 0x03;
+pub(crate) const DBG_START_LOCAL_EXTENDED: ubyte = 0x04;
+pub(crate) const DBG_END_LOCAL: ubyte = 0x05;
+pub(crate) const DBG_RESTART_LOCAL: ubyte = 0x06;
+pub(crate) const DBG_SET_FILE: ubyte = 0x09;
+const DBG_FIRST_SPECIAL: ubyte = 0x0a;
+const DBG_LINE_BASE: i64 = -4;
+const DBG_LINE_RANGE: ubyte = 15;
+
+/// An entry of the positions table of a method, emitted by the debug info state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct Position {
+    /// Address of the instruction in code units, relative to the start of the method's code.
+    address: uint,
+    /// Source line of the instructions starting at `address`.
+    line: usize,
+}
+
 /// Debug Info of a method.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#debug-info-item)
 #[derive(Debug, Getters, CopyGetters)]
@@ -19,11 +49,48 @@ pub struct DebugInfoItem {
     /// Names of the incoming parameters.
     #[get = "pub"]
     parameter_names: Vec<Option<DexString>>,
+    /// Address to line number mappings, sorted by address.
+    #[get = "pub"]
+    positions: Vec<Position>,
+    /// Addresses from which the instructions come from another source file than the one of
+    /// the class, `None` when they come from it again. Sorted by address.
+    source_files: Vec<(uint, Option<DexString>)>,
+}
+
+impl DebugInfoItem {
+    /// Source line of the instruction at `address`, given in code units.
+    pub fn line_for_address(&self, address: uint) -> Option<usize> {
+        let index = self.positions.partition_point(|p| p.address <= address);
+        index.checked_sub(1).map(|i| self.positions[i].line)
+    }
+
+    /// Source file of the instruction at `address`, if it isn't the one of the class, like
+    /// for code inlined from another file.
+    pub fn source_file_for_address(&self, address: uint) -> Option<&DexString> {
+        let index = self
+            .source_files
+            .partition_point(|(start, _)| *start <= address);
+        index
+            .checked_sub(1)
+            .and_then(|i| self.source_files[i].1.as_ref())
+    }
+
+    /// Start addresses of the instructions mapped to `line`.
+    pub fn addresses_for_line(&self, line: usize) -> Vec<uint> {
+        let mut addresses: Vec<uint> = self
+            .positions
+            .iter()
+            .filter(|p| p.line == line)
+            .map(|p| p.address)
+            .collect();
+        addresses.dedup();
+        addresses
+    }
 }
 
 /// Code and Debug Info of a method.
 #[derive(Getters, CopyGetters)]
-pub struct CodeItem {
+pub struct CodeItem<'a> {
     /// The number of registers the method must use.
     #[get_copy = "pub"]
     registers_size: ushort,
@@ -35,22 +102,102 @@ pub struct CodeItem {
     /// Number of words for outgoing arguments required for invocation.
     #[get_copy = "pub"]
     outs_size: ushort,
-    /// Code instructions for this method.
-    #[get = "pub"]
-    insns: Vec<ushort>,
+    /// Code instructions for this method, borrowed from the `Dex` if possible, see
+    /// `read_insns`.
+    insns: Cow<'a, [ushort]>,
     /// Try, Exception handling information of this method.
     #[get = "pub"]
     tries: Tries,
 }
 
-impl CodeItem {
+impl<'a> CodeItem<'a> {
+    /// Reads the compact code item at `offset` of `source`, the bytes of a compact dex file.
+    /// Its debug info is looked up by `method_id`, the item doesn't hold its offset.
+    pub(crate) fn try_from_compact<S: AsRef<[u8]>>(
+        dex: &super::Dex<S>,
+        source: &'a [u8],
+        offset: usize,
+        method_id: Option<MethodId>,
+    ) -> super::Result<Self> {
+        let endian = dex.get_endian();
+        let sizes = CompactCodeItemSizes::read(source, offset, endian)?;
+        let insns = read_insns(source, offset + 4, sizes.insns_size, endian)?;
+        let tries = if sizes.tries_size != 0 {
+            // the try items are 4 byte aligned.
+            let tries_offset = (offset + 4 + insns.len() * 2 + 3) & !3;
+            source.pread_with(tries_offset, (sizes.tries_size as usize, dex))?
+        } else {
+            Default::default()
+        };
+        let debug_info_item = match method_id {
+            Some(method_id) => dex.get_debug_info_item(cdex::debug_info_offset(dex, method_id)?)?,
+            None => None,
+        };
+        Ok(Self {
+            registers_size: sizes.registers_size,
+            debug_info_item,
+            ins_size: sizes.ins_size,
+            outs_size: sizes.outs_size,
+            insns,
+            tries,
+        })
+    }
+
     /// Line number and source file information.
     pub fn debug_info_item(&self) -> Option<&DebugInfoItem> {
         self.debug_info_item.as_ref()
     }
+
+    /// Code instructions for this method.
+    pub fn insns(&self) -> &[ushort] {
+        &self.insns
+    }
+
+    /// Iterator over the decoded instructions of this method.
+    pub fn instructions(&self) -> InstructionIter<'_> {
+        InstructionIter::new(&self.insns)
+    }
+
+    /// The code item with its instructions copied if they are borrowed from the `Dex`.
+    pub(crate) fn into_owned(self) -> CodeItem<'static> {
+        CodeItem {
+            registers_size: self.registers_size,
+            debug_info_item: self.debug_info_item,
+            ins_size: self.ins_size,
+            outs_size: self.outs_size,
+            insns: Cow::Owned(self.insns.into_owned()),
+            tries: self.tries,
+        }
+    }
 }
 
-impl fmt::Debug for CodeItem {
+/// Reads `len` code units at `offset` of `source`. They are borrowed when the byte order
+/// of the `Dex` is the host's and they are aligned, so scanning the code of many methods
+/// doesn't copy it.
+pub(crate) fn read_insns(
+    source: &[u8],
+    offset: usize,
+    len: uint,
+    endian: Endian,
+) -> super::Result<Cow<'_, [ushort]>> {
+    let bytes = offset
+        .checked_add(len as usize * 2)
+        .and_then(|end| source.get(offset..end))
+        .ok_or_else(|| Error::BadOffset(offset, "Instructions out of bounds".to_string()))?;
+    if endian == scroll::NATIVE {
+        // every bit pattern is a valid u16, only the alignment has to be checked.
+        let (prefix, insns, suffix) = unsafe { bytes.align_to::<ushort>() };
+        if prefix.is_empty() && suffix.is_empty() {
+            return Ok(Cow::Borrowed(insns));
+        }
+    }
+    let insns = (0..len as usize)
+        .map(|index| bytes.pread_with(index * 2, endian))
+        .collect::<Result<Vec<ushort>, _>>()?;
+    Ok(Cow::Owned(insns))
+}
+
+impl fmt::Debug for CodeItem<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "CodeItem {{ registers_size: {}, debug_info: {}, ins_size: {}, outs_size: {}, tries: {} }}",
             self.registers_size, self.debug_info_item.is_some(), self.ins_size, self.outs_size, self.tries.len())
@@ -103,6 +250,27 @@ pub struct TryCatchHandlers {
     catch_handlers: Vec<CatchHandler>,
 }
 
+impl TryCatchHandlers {
+    /// Returns the handler that catches an exception of type `exception` (a type descriptor)
+    /// thrown inside this try block. Handlers are checked in declaration order, like the VM
+    /// does, and the catch-all handler matches any exception. A typed handler only matches if
+    /// `hierarchy` knows that `exception` is a sub class of the handled type.
+    pub fn handler_for(
+        &self,
+        exception: &str,
+        hierarchy: &ClassHierarchy,
+    ) -> Option<&CatchHandler> {
+        self.catch_handlers
+            .iter()
+            .find(|handler| match handler.exception {
+                ExceptionType::BaseException => true,
+                ExceptionType::Ty(ref jtype) => {
+                    hierarchy.is_subclass_of(exception, jtype.type_descriptor())
+                }
+            })
+    }
+}
+
 /// List of try-catch blocks found in this method.
 #[derive(Debug, Default, Getters, CopyGetters)]
 pub struct Tries {
@@ -170,26 +338,71 @@ where
         let offset = &mut 0;
         let line_start = Uleb128::read(source, offset)? as usize;
         let parameters_size = Uleb128::read(source, offset)?;
-        let mut parameter_names = Vec::with_capacity(parameters_size as usize);
+        let mut parameter_names = Vec::with_capacity((parameters_size as usize).min(source.len()));
         for _ in 0..parameters_size {
-            let string_id = Uleb128::read(source, offset)? + 1;
-            parameter_names.push(if string_id != u64::from(crate::NO_INDEX) {
-                Some(dex.get_string(string_id as uint)?)
+            // uleb128p1: the stored value is the `StringId` plus one, zero for `NO_INDEX`.
+            let string_id = Uleb128::read(source, offset)?;
+            parameter_names.push(if string_id != 0 {
+                Some(dex.get_string((string_id - 1) as uint)?)
             } else {
                 None
             });
         }
+        let (mut address, mut line) = (0u64, line_start as i64);
+        let mut positions = Vec::new();
+        let mut source_files = Vec::new();
+        loop {
+            let opcode: ubyte = source.gread(offset)?;
+            match opcode {
+                DBG_END_SEQUENCE => break,
+                // the address and the line of malformed items can overflow, they wrap.
+                DBG_ADVANCE_PC => address = address.wrapping_add(Uleb128::read(source, offset)?),
+                DBG_ADVANCE_LINE => line = line.wrapping_add(Sleb128::read(source, offset)?),
+                DBG_END_LOCAL | DBG_RESTART_LOCAL => {
+                    Uleb128::read(source, offset)?;
+                }
+                DBG_START_LOCAL | DBG_START_LOCAL_EXTENDED => {
+                    let operands = if opcode == DBG_START_LOCAL { 3 } else { 4 };
+                    for _ in 0..operands {
+                        Uleb128::read(source, offset)?;
+                    }
+                }
+                DBG_SET_FILE => {
+                    // uleb128p1 like the parameter names.
+                    let string_id = Uleb128::read(source, offset)?;
+                    let file = if string_id != 0 {
+                        Some(dex.get_string((string_id - 1) as uint)?)
+                    } else {
+                        None
+                    };
+                    source_files.push((address as uint, file));
+                }
+                // prologue end and epilogue begin only mark the current address.
+                opcode if opcode < DBG_FIRST_SPECIAL => {}
+                opcode => {
+                    let adjusted = opcode - DBG_FIRST_SPECIAL;
+                    line = line.wrapping_add(DBG_LINE_BASE + i64::from(adjusted % DBG_LINE_RANGE));
+                    address = address.wrapping_add(u64::from(adjusted / DBG_LINE_RANGE));
+                    positions.push(Position {
+                        address: address as uint,
+                        line: line as usize,
+                    });
+                }
+            }
+        }
         Ok((
             Self {
                 line_start,
                 parameter_names,
+                positions,
+                source_files,
             },
             *offset,
         ))
     }
 }
 
-impl<'a, S> ctx::TryFromCtx<'a, &super::Dex<S>> for CodeItem
+impl<'a, S> ctx::TryFromCtx<'a, &super::Dex<S>> for CodeItem<'a>
 where
     S: AsRef<[u8]>,
 {
@@ -207,13 +420,10 @@ where
         let outs_size = source.gread_with(offset, endian)?;
         let tries_size: ushort = source.gread_with(offset, endian)?;
         let debug_info_off = source.gread_with(offset, endian)?;
-        let debug_info_item = if debug_info_off != 0 {
-            Some(dex.get_debug_info_item(debug_info_off)?)
-        } else {
-            None
-        };
+        let debug_info_item = dex.get_debug_info_item(debug_info_off)?;
         let insns_size: uint = source.gread_with(offset, endian)?;
-        let insns: Vec<ushort> = try_gread_vec_with!(source, offset, insns_size, endian);
+        let insns = read_insns(source, *offset, insns_size, endian)?;
+        *offset += insns.len() * 2;
         if insns_size % 2 != 0 && tries_size != 0 {
             source.gread_with::<ushort>(offset, endian)?;
         }
@@ -235,3 +445,176 @@ where
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CatchHandler, ExceptionType, TryCatchHandlers};
+    use crate::{hierarchy::ClassHierarchy, jtype::Type, string::DexString};
+
+    fn handler(descriptor: Option<&str>, addr: u64) -> CatchHandler {
+        CatchHandler {
+            exception: match descriptor {
+                Some(descriptor) => ExceptionType::Ty(Type {
+                    id: 0,
+                    type_descriptor: DexString::from(descriptor.to_string()),
+                }),
+                None => ExceptionType::BaseException,
+            },
+            addr,
+        }
+    }
+
+    #[test]
+    fn test_handler_for() {
+        let mut hierarchy = ClassHierarchy::default();
+        hierarchy.insert(
+            "Ljava/lang/Exception;",
+            Some("Ljava/lang/Throwable;"),
+            vec![],
+        );
+        hierarchy.insert(
+            "Ljava/io/IOException;",
+            Some("Ljava/lang/Exception;"),
+            vec![],
+        );
+        hierarchy.insert(
+            "Ljava/io/FileNotFoundException;",
+            Some("Ljava/io/IOException;"),
+            vec![],
+        );
+        let try_block = TryCatchHandlers {
+            start_addr: 0,
+            insn_count: 10,
+            catch_handlers: vec![
+                handler(Some("Ljava/io/FileNotFoundException;"), 12),
+                handler(Some("Ljava/lang/Exception;"), 14),
+            ],
+        };
+        let addr = |exception| {
+            try_block
+                .handler_for(exception, &hierarchy)
+                .map(|h| h.addr())
+        };
+        assert_eq!(addr("Ljava/io/FileNotFoundException;"), Some(12));
+        assert_eq!(addr("Ljava/io/IOException;"), Some(14));
+        assert_eq!(addr("Ljava/lang/Error;"), None);
+
+        let try_block = TryCatchHandlers {
+            catch_handlers: vec![
+                handler(Some("Ljava/io/IOException;"), 12),
+                handler(None, 16),
+            ],
+            ..try_block
+        };
+        assert_eq!(
+            try_block
+                .handler_for("Ljava/lang/Error;", &hierarchy)
+                .map(|h| h.addr()),
+            Some(16)
+        );
+    }
+
+    #[test]
+    fn test_instructions() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        for class in dex.classes() {
+            let class = class.expect("can't load class");
+            for code in class.methods().filter_map(|m| m.code()) {
+                let len: usize = code
+                    .instructions()
+                    .map(|i| i.expect("decode failed").len())
+                    .sum();
+                assert_eq!(len, code.insns().len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_line_for_address() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let class = dex
+            .find_class_by_name("Lorg/adw/launcher/Launcher;")
+            .expect("failed to load class")
+            .expect("class not found");
+        let clinit = class
+            .methods()
+            .find(|m| m.name() == "<clinit>")
+            .expect("method not found");
+        assert_eq!(clinit.line_for_address(0), Some(208));
+        assert_eq!(clinit.line_for_address(6), Some(208));
+        assert_eq!(clinit.line_for_address(7), Some(210));
+        assert_eq!(clinit.line_for_address(100), Some(211));
+        assert_eq!(clinit.addresses_for_line(210), vec![7]);
+        assert!(clinit.addresses_for_line(209).is_empty());
+
+        let init = class
+            .methods()
+            .find(|m| m.name() == "<init>")
+            .expect("method not found");
+        assert_eq!(init.line_for_address(4), None);
+        assert_eq!(init.addresses_for_line(362), vec![107, 115]);
+    }
+
+    #[test]
+    fn test_debug_info_overflow() {
+        use scroll::Pread;
+
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        // line_start of i64::MAX, no parameters, a special opcode advancing the line.
+        let mut bytes = vec![0xff; 8];
+        bytes.extend_from_slice(&[0x7f, 0x00, 0xff, 0x00]);
+        let debug_info: super::DebugInfoItem = bytes.pread_with(0, &dex).unwrap();
+        assert_eq!(debug_info.positions().len(), 1);
+    }
+
+    #[test]
+    fn test_parameters() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let class = dex
+            .find_class_by_name("Lorg/adw/launcher/Launcher;")
+            .expect("failed to load class")
+            .expect("class not found");
+        let parameters = |name: &str| {
+            class
+                .methods()
+                .find(|m| m.name() == name)
+                .expect("method not found")
+                .parameters()
+                .into_iter()
+                .map(|(name, jtype)| {
+                    (
+                        name.map(|n| n.to_string()),
+                        jtype.type_descriptor().to_string(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let param =
+            |name: Option<&str>, descriptor: &str| (name.map(String::from), descriptor.to_string());
+        // `this` isn't part of the parameters of instance methods.
+        assert_eq!(
+            parameters("bindDrawer"),
+            vec![
+                param(Some("binder"), "Lorg/adw/launcher/Launcher$DesktopBinder;"),
+                param(
+                    Some("drawerAdapter"),
+                    "Lorg/adw/launcher/ApplicationsAdapter;"
+                ),
+            ]
+        );
+        assert_eq!(
+            parameters("bindAppWidgets"),
+            vec![
+                param(Some("binder"), "Lorg/adw/launcher/Launcher$DesktopBinder;"),
+                param(None, "Ljava/util/LinkedList;"),
+            ]
+        );
+        assert_eq!(
+            parameters("access$1402"),
+            vec![
+                param(Some("x0"), "Lorg/adw/launcher/Launcher;"),
+                param(Some("x1"), "Z"),
+            ]
+        );
+    }
+}
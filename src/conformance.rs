@@ -0,0 +1,579 @@
+//! Rules of the dex format specification which the parser doesn't need to read a file, like
+//! section alignments and sort orders, checked against a `Dex`. Each finding refers to the
+//! section of the [specification](https://source.android.com/devices/tech/dalvik/dex-format)
+//! it comes from, to back up reports about unusual files.
+use std::cmp::Ordering;
+
+use getset::{CopyGetters, Getters};
+use scroll::Pread;
+
+use crate::{provenance::Provenance, uint, utils::quote, Dex, ItemType, MapItem, Result};
+
+/// Size of the header up to version `039`.
+const HEADER_SIZE: uint = 0x70;
+/// Size of the header from version `041`, which adds the container fields.
+const CONTAINER_HEADER_SIZE: uint = 0x78;
+
+/// A rule of the specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// The header has the size of its version.
+    HeaderSize,
+    /// `file_size` is the size of the file.
+    FileSize,
+    /// The data section is a multiple of 4 bytes.
+    DataSize,
+    /// The items of a section start at its alignment.
+    Alignment(ItemType),
+    /// The entries of the map list are sorted by offset and don't overlap.
+    MapListOrder,
+    /// The header comes first, then the ids sections in the order of their item types,
+    /// before the data section.
+    SectionOrder(ItemType),
+    /// The offset and size of an ids section in the header match its map list entry.
+    HeaderOffsets(ItemType),
+    /// A section ends before the end of the file.
+    SectionBounds(ItemType),
+    /// The items of the data section, and the offsets to them, are in the data section.
+    DataSection(ItemType),
+    /// Strings are sorted by their UTF-16 code points, without duplicates.
+    StringIdsOrder,
+    /// Types are sorted by the index of their descriptor, without duplicates.
+    TypeIdsOrder,
+    /// Prototypes are sorted by return type, then by parameter types, without duplicates.
+    ProtoIdsOrder,
+    /// Fields are sorted by class, then name, then type, without duplicates.
+    FieldIdsOrder,
+    /// Methods are sorted by class, then name, then prototype, without duplicates.
+    MethodIdsOrder,
+    /// The UTF-16 size of string data is a valid `uleb128` which matches the string.
+    StringDataSize,
+}
+
+impl Rule {
+    /// Short name of the rule, like `string-ids-order`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::HeaderSize => "header-size",
+            Rule::FileSize => "file-size",
+            Rule::DataSize => "data-size",
+            Rule::Alignment(_) => "alignment",
+            Rule::MapListOrder => "map-list-order",
+            Rule::SectionOrder(_) => "section-order",
+            Rule::HeaderOffsets(_) => "header-offsets",
+            Rule::SectionBounds(_) => "section-bounds",
+            Rule::DataSection(_) => "data-section",
+            Rule::StringIdsOrder => "string-ids-order",
+            Rule::TypeIdsOrder => "type-ids-order",
+            Rule::ProtoIdsOrder => "proto-ids-order",
+            Rule::FieldIdsOrder => "field-ids-order",
+            Rule::MethodIdsOrder => "method-ids-order",
+            Rule::StringDataSize => "string-data-size",
+        }
+    }
+
+    /// Anchor of the section of the specification which states the rule, like
+    /// `string-id-item`.
+    pub fn spec_section(self) -> &'static str {
+        match self {
+            Rule::HeaderSize | Rule::FileSize | Rule::DataSize | Rule::HeaderOffsets(_) => {
+                "header-item"
+            }
+            Rule::Alignment(item_type) | Rule::DataSection(item_type) => item_section(item_type),
+            Rule::MapListOrder | Rule::SectionBounds(_) => "map-list",
+            Rule::SectionOrder(_) => "file-layout",
+            Rule::StringIdsOrder => "string-id-item",
+            Rule::TypeIdsOrder => "type-id-item",
+            Rule::ProtoIdsOrder => "proto-id-item",
+            Rule::FieldIdsOrder => "field-id-item",
+            Rule::MethodIdsOrder => "method-id-item",
+            Rule::StringDataSize => "string-data-item",
+        }
+    }
+
+    /// Link to the section of the specification which states the rule.
+    pub fn spec_url(self) -> String {
+        format!(
+            "https://source.android.com/devices/tech/dalvik/dex-format#{}",
+            self.spec_section()
+        )
+    }
+}
+
+fn item_section(item_type: ItemType) -> &'static str {
+    match item_type {
+        ItemType::Header => "header-item",
+        ItemType::StringIdItem => "string-id-item",
+        ItemType::TypeIdItem => "type-id-item",
+        ItemType::ProtoIdItem => "proto-id-item",
+        ItemType::FieldIdItem => "field-id-item",
+        ItemType::MethodIdItem => "method-id-item",
+        ItemType::ClassDefItem => "class-def-item",
+        ItemType::CallSiteIdItem => "call-site-id-item",
+        ItemType::MethodHandleItem => "method-handle-item",
+        ItemType::MapList => "map-list",
+        ItemType::TypeList => "type-list",
+        ItemType::AnnotationSetRefList => "set-ref-list",
+        ItemType::AnnotationSetItem => "annotation-set-item",
+        ItemType::ClassDataItem => "class-data-item",
+        ItemType::CodeItem => "code-item",
+        ItemType::StringDataItem => "string-data-item",
+        ItemType::DebugInfoItem => "debug-info-item",
+        ItemType::AnnotationItem => "annotation-item",
+        ItemType::EncodedArrayItem => "encoded-array-item",
+        ItemType::AnnotationsDirectoryItem => "annotations-directory",
+    }
+}
+
+/// Alignment of the items of a section in bytes.
+fn alignment(item_type: ItemType) -> uint {
+    match item_type {
+        ItemType::ClassDataItem
+        | ItemType::StringDataItem
+        | ItemType::DebugInfoItem
+        | ItemType::AnnotationItem
+        | ItemType::EncodedArrayItem => 1,
+        _ => 4,
+    }
+}
+
+/// A rule which the file breaks, see `check` and `verifier::verify_structure`.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct Finding {
+    /// The rule.
+    #[get_copy = "pub"]
+    rule: Rule,
+    /// Offset in the file of the offending item, if there is one.
+    #[get_copy = "pub"]
+    offset: Option<usize>,
+    /// What is wrong.
+    #[get = "pub"]
+    message: String,
+}
+
+impl Finding {
+    pub(crate) fn new(rule: Rule, offset: Option<uint>, message: String) -> Self {
+        Self {
+            rule,
+            offset: offset.map(|offset| offset as usize),
+            message,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"rule\":{},\"section\":{},\"url\":{},\"offset\":{},\"message\":{}}}",
+            quote(self.rule.name()),
+            quote(self.rule.spec_section()),
+            quote(&self.rule.spec_url()),
+            self.offset
+                .map_or("null".to_string(), |offset| offset.to_string()),
+            quote(&self.message)
+        )
+    }
+}
+
+/// Findings of `check`.
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct ConformanceReport {
+    /// Rules the file breaks, only the first out of order item of each section is reported.
+    #[get = "pub"]
+    findings: Vec<Finding>,
+    provenance: Option<Provenance>,
+}
+
+impl ConformanceReport {
+    /// Returns `true` if the file doesn't break any of the checked rules.
+    pub fn is_conformant(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Provenance of the `Dex` the report is about.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    /// The report as JSON, each finding with the section of the specification and its link.
+    pub fn to_json(&self) -> String {
+        let findings: Vec<String> = self.findings.iter().map(Finding::to_json).collect();
+        let provenance = self
+            .provenance
+            .as_ref()
+            .map(|provenance| format!(",\"provenance\":{}", provenance.to_json()))
+            .unwrap_or_default();
+        format!(
+            "{{\"conformant\":{},\"findings\":[{}]{}}}",
+            self.is_conformant(),
+            findings.join(","),
+            provenance
+        )
+    }
+}
+
+/// Checks `dex` against the rules of the specification.
+pub fn check<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<ConformanceReport> {
+    let mut findings = Vec::new();
+    let mut finding = |rule, offset: Option<uint>, message: String| {
+        findings.push(Finding::new(rule, offset, message))
+    };
+    let header = dex.header();
+    let source = dex.source.as_ref();
+    let endian = dex.get_endian();
+
+    let expected_header_size = match header.version() {
+        Some(version) if version >= 41 => CONTAINER_HEADER_SIZE,
+        _ => HEADER_SIZE,
+    };
+    if header.header_size() != expected_header_size {
+        finding(
+            Rule::HeaderSize,
+            Some(0),
+            format!(
+                "header_size is {:#x}, expected {:#x} for version {:?}",
+                header.header_size(),
+                expected_header_size,
+                header.version()
+            ),
+        );
+    }
+    if header.file_size() as usize != source.len() {
+        finding(
+            Rule::FileSize,
+            Some(0),
+            format!(
+                "file_size is {}, the file has {} bytes",
+                header.file_size(),
+                source.len()
+            ),
+        );
+    }
+    if !header.data_size().is_multiple_of(4) {
+        finding(
+            Rule::DataSize,
+            Some(0),
+            format!("data_size {} isn't a multiple of 4", header.data_size()),
+        );
+    }
+
+    let map_items = dex.map_list().items();
+    for item in map_items {
+        let (item_type, offset) = (item.item_type(), item.offset());
+        if !offset.is_multiple_of(alignment(item_type)) {
+            finding(
+                Rule::Alignment(item_type),
+                Some(offset),
+                format!(
+                    "{:?} section at {:#x} isn't aligned to {} bytes",
+                    item_type,
+                    offset,
+                    alignment(item_type)
+                ),
+            );
+        }
+    }
+    let offsets: Vec<uint> = map_items.iter().map(|item| item.offset()).collect();
+    if let Some(index) = first_unordered(&offsets, |a, b| a.cmp(b)) {
+        finding(
+            Rule::MapListOrder,
+            Some(header.map_off()),
+            format!("map list entry at {:#x} is out of order", offsets[index]),
+        );
+    }
+
+    match map_items.first() {
+        Some(item) if item.item_type() == ItemType::Header && item.offset() == 0 => {}
+        _ => finding(
+            Rule::SectionOrder(ItemType::Header),
+            Some(header.map_off()),
+            "the map list doesn't start with the header at offset 0".to_string(),
+        ),
+    }
+    let ids_sections = [
+        (
+            ItemType::StringIdItem,
+            header.string_ids_off(),
+            header.string_ids_size(),
+        ),
+        (
+            ItemType::TypeIdItem,
+            header.type_ids_off(),
+            header.type_ids_size(),
+        ),
+        (
+            ItemType::ProtoIdItem,
+            header.proto_ids_off(),
+            header.proto_ids_size(),
+        ),
+        (
+            ItemType::FieldIdItem,
+            header.field_ids_off(),
+            header.field_ids_size(),
+        ),
+        (
+            ItemType::MethodIdItem,
+            header.method_ids_off(),
+            header.method_ids_size(),
+        ),
+        (
+            ItemType::ClassDefItem,
+            header.class_defs_off(),
+            header.class_defs_size(),
+        ),
+    ];
+    for (item_type, offset, size) in ids_sections {
+        let mapped = dex.map_list().get(item_type);
+        match mapped {
+            Some(item) if item.offset() == offset && item.size() == size => {}
+            None if size == 0 => {}
+            _ => finding(
+                Rule::HeaderOffsets(item_type),
+                Some(offset),
+                format!(
+                    "{:?} section has {} items at {:#x} in the header, {:?} in the map list",
+                    item_type,
+                    size,
+                    offset,
+                    mapped.map(|item| (item.size(), item.offset()))
+                ),
+            ),
+        }
+    }
+
+    let mut sections: Vec<MapItem> = map_items.to_vec();
+    sections.sort_by_key(|item| item.offset());
+    let mut previous_ids_section: Option<ItemType> = None;
+    for (index, item) in sections.iter().enumerate() {
+        let (item_type, offset) = (item.item_type(), item.offset());
+        let in_data_section = dex.is_offset_in_data_section(offset);
+        if (item_type as u16) < ItemType::MapList as u16 {
+            match previous_ids_section {
+                Some(previous) if previous as u16 > item_type as u16 => finding(
+                    Rule::SectionOrder(item_type),
+                    Some(offset),
+                    format!("{:?} section is after {:?} section", item_type, previous),
+                ),
+                _ if in_data_section => finding(
+                    Rule::SectionOrder(item_type),
+                    Some(offset),
+                    format!(
+                        "{:?} section at {:#x} is in the data section",
+                        item_type, offset
+                    ),
+                ),
+                _ => {}
+            }
+            previous_ids_section = Some(item_type);
+        } else if !in_data_section {
+            finding(
+                Rule::DataSection(item_type),
+                Some(offset),
+                format!(
+                    "{:?} section at {:#x} isn't in the data section",
+                    item_type, offset
+                ),
+            );
+        }
+
+        let end = match section_size(dex, item) {
+            Some(size) => u64::from(offset) + size,
+            None => continue,
+        };
+        if end > source.len() as u64 {
+            finding(
+                Rule::SectionBounds(item_type),
+                Some(offset),
+                format!("{:?} section ends at {:#x}, after the file", item_type, end),
+            );
+        }
+        if let Some(next) = sections.get(index + 1) {
+            if end > u64::from(next.offset()) {
+                finding(
+                    Rule::MapListOrder,
+                    Some(offset),
+                    format!(
+                        "{:?} section ends at {:#x}, after the start of {:?} section",
+                        item_type,
+                        end,
+                        next.item_type()
+                    ),
+                );
+            }
+        }
+    }
+
+    let string_id = |index: uint| -> Result<uint> {
+        Ok(source.pread_with((header.string_ids_off() + index * 4) as usize, endian)?)
+    };
+    let strings = (0..header.string_ids_size())
+        .map(|index| dex.get_string(index))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(index) = first_unordered(&strings, |a, b| a.encode_utf16().cmp(b.encode_utf16())) {
+        finding(
+            Rule::StringIdsOrder,
+            Some(string_id(index as uint)?),
+            format!("string {:?} is out of order", &*strings[index]),
+        );
+    }
+    for (index, string) in strings.iter().enumerate() {
+        let offset = string_id(index as uint)?;
+        let (utf16_size, len) = read_uleb128(&source[offset as usize..]);
+        let utf16_len = string.encode_utf16().count() as u64;
+        let valid = len <= 5 && (len < 5 || source[offset as usize + 4] <= 0x0f);
+        if !valid || utf16_size != utf16_len {
+            finding(
+                Rule::StringDataSize,
+                Some(offset),
+                format!(
+                    "utf16_size of {:?} is {} encoded in {} bytes, expected {}",
+                    &**string, utf16_size, len, utf16_len
+                ),
+            );
+            break;
+        }
+    }
+
+    let type_ids = (0..header.type_ids_size())
+        .map(|index| {
+            Ok(source.pread_with::<uint>((header.type_ids_off() + index * 4) as usize, endian)?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(index) = first_unordered(&type_ids, |a, b| a.cmp(b)) {
+        finding(
+            Rule::TypeIdsOrder,
+            Some(header.type_ids_off() + index as uint * 4),
+            format!("type id {} is out of order", index),
+        );
+    }
+
+    let protos = dex
+        .proto_ids()
+        .map(|proto| {
+            let proto = proto?;
+            let params: Vec<uint> = dex
+                .get_type_list(proto.params_off())?
+                .iter()
+                .map(|jtype| jtype.id())
+                .collect();
+            Ok((proto.return_type(), params))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(index) = first_unordered(&protos, |a, b| a.cmp(b)) {
+        finding(
+            Rule::ProtoIdsOrder,
+            Some(header.proto_ids_off() + index as uint * 12),
+            format!("proto id {} is out of order", index),
+        );
+    }
+
+    let fields = dex
+        .field_ids()
+        .map(|field| {
+            let field = field?;
+            Ok((field.class_idx(), field.name_idx(), field.type_idx()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(index) = first_unordered(&fields, |a, b| a.cmp(b)) {
+        finding(
+            Rule::FieldIdsOrder,
+            Some(header.field_ids_off() + index as uint * 8),
+            format!("field id {} is out of order", index),
+        );
+    }
+
+    let methods = dex
+        .method_ids()
+        .map(|method| {
+            let method = method?;
+            Ok((method.class_idx(), method.name_idx(), method.proto_idx()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(index) = first_unordered(&methods, |a, b| a.cmp(b)) {
+        finding(
+            Rule::MethodIdsOrder,
+            Some(header.method_ids_off() + index as uint * 8),
+            format!("method id {} is out of order", index),
+        );
+    }
+
+    debug!(target: "conformance", "found {} findings", findings.len());
+    Ok(ConformanceReport {
+        findings,
+        provenance: Some(dex.provenance()),
+    })
+}
+
+/// Size of the section of `item` in bytes, `None` if its items don't have a fixed size.
+pub(crate) fn section_size<T: AsRef<[u8]>>(dex: &Dex<T>, item: &MapItem) -> Option<u64> {
+    match item.item_type() {
+        ItemType::Header => Some(dex.header().header_size().into()),
+        ItemType::MapList => {
+            let entries = dex.map_list().items().len() + dex.map_list().unknown_items().len();
+            Some(4 + 12 * entries as u64)
+        }
+        item_type => Some(u64::from(item_type.item_size()?) * u64::from(item.size())),
+    }
+}
+
+/// Index of the first item which isn't strictly after the previous one.
+fn first_unordered<I, F>(items: &[I], compare: F) -> Option<usize>
+where
+    F: Fn(&I, &I) -> Ordering,
+{
+    items
+        .windows(2)
+        .position(|w| compare(&w[0], &w[1]) != Ordering::Less)
+        .map(|index| index + 1)
+}
+
+/// Reads a `uleb128` without checking its length, returns the value and the number of bytes.
+fn read_uleb128(source: &[u8]) -> (u64, usize) {
+    let mut value = 0u64;
+    for (index, byte) in source.iter().enumerate() {
+        if index < 10 {
+            value |= u64::from(byte & 0x7f) << (7 * index);
+        }
+        if byte & 0x80 == 0 {
+            return (value, index + 1);
+        }
+    }
+    (value, source.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, first_unordered, read_uleb128, Rule};
+    use crate::ItemType;
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(read_uleb128(&[0x00]), (0, 1));
+        assert_eq!(read_uleb128(&[0x80, 0x7f]), (16256, 2));
+        assert_eq!(read_uleb128(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]).1, 6);
+        assert_eq!(first_unordered(&[1, 2, 3], |a, b| a.cmp(b)), None);
+        assert_eq!(first_unordered(&[1, 3, 3], |a, b| a.cmp(b)), Some(2));
+        assert_eq!(
+            Rule::Alignment(ItemType::CodeItem).spec_url(),
+            "https://source.android.com/devices/tech/dalvik/dex-format#code-item"
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let report = check(&dex).unwrap();
+        assert_eq!(report.findings(), &[]);
+        assert!(report
+            .to_json()
+            .starts_with("{\"conformant\":true,\"findings\":[]"));
+
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        bytes.extend_from_slice(&[0; 4]);
+        let checksum = adler32::adler32(&bytes[12..]).unwrap();
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+        let dex = crate::DexReader::from_vec(bytes).expect("failed to load dex");
+        let report = check(&dex).unwrap();
+        assert_eq!(report.findings().len(), 1);
+        assert_eq!(report.findings()[0].rule(), Rule::FileSize);
+        assert!(report.to_json().contains("\"section\":\"header-item\""));
+    }
+}
@@ -0,0 +1,154 @@
+//! Decoders for sections the parser doesn't know about, like the ones added to the format by
+//! vendors or packers. Decoders are registered with `Dex::with_decoder` and their items are
+//! read with `Dex::custom_items`.
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+};
+
+use crate::{uint, ushort, Endian, Result};
+
+/// Where the items of a custom section are found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// The entry of the map list with this type code. Standard item types can be given
+    /// too, like `ItemType::CodeItem as ushort`.
+    Map(ushort),
+    /// `count` items at `offset` from the start of the file, for sections which aren't
+    /// listed in the map list.
+    Offset {
+        /// Offset of the first item.
+        offset: uint,
+        /// Number of items.
+        count: uint,
+    },
+}
+
+/// Decodes the items of a custom section.
+pub trait SectionDecoder {
+    /// Type of the decoded items, each decoder is registered for a single type.
+    type Item: 'static;
+
+    /// Where the section is.
+    fn location(&self) -> Location;
+
+    /// Decodes `count` items from `source`, which starts at the section and ends at the end
+    /// of the file.
+    fn decode(&self, source: &[u8], count: uint, endian: Endian) -> Result<Vec<Self::Item>>;
+}
+
+/// Decoders registered with a `Dex`, by the type of their items.
+#[derive(Clone, Default)]
+pub(crate) struct Decoders {
+    decoders: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Decoders {
+    pub(crate) fn insert<D>(&mut self, decoder: D)
+    where
+        D: SectionDecoder + Send + Sync + 'static,
+    {
+        let decoder: Box<dyn SectionDecoder<Item = D::Item> + Send + Sync> = Box::new(decoder);
+        self.decoders
+            .insert(TypeId::of::<D::Item>(), Arc::new(decoder));
+    }
+
+    pub(crate) fn get<I: 'static>(&self) -> Option<&dyn SectionDecoder<Item = I>> {
+        self.decoders
+            .get(&TypeId::of::<I>())
+            .and_then(|decoder| {
+                decoder.downcast_ref::<Box<dyn SectionDecoder<Item = I> + Send + Sync>>()
+            })
+            .map(|decoder| decoder.as_ref() as &dyn SectionDecoder<Item = I>)
+    }
+}
+
+impl fmt::Debug for Decoders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Decoders({})", self.decoders.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scroll::Pread;
+
+    use super::{Location, SectionDecoder};
+    use crate::{uint, ushort, Endian, ItemType, Result};
+
+    #[derive(Debug, PartialEq)]
+    struct Offset(uint);
+
+    struct OffsetsDecoder(Location);
+
+    impl SectionDecoder for OffsetsDecoder {
+        type Item = Offset;
+
+        fn location(&self) -> Location {
+            self.0
+        }
+
+        fn decode(&self, source: &[u8], count: uint, endian: Endian) -> Result<Vec<Offset>> {
+            (0..count as usize)
+                .map(|index| Ok(Offset(source.pread_with(index * 4, endian)?)))
+                .collect()
+        }
+    }
+
+    struct MagicDecoder;
+
+    impl SectionDecoder for MagicDecoder {
+        type Item = u8;
+
+        fn location(&self) -> Location {
+            Location::Offset {
+                offset: 0,
+                count: 4,
+            }
+        }
+
+        fn decode(&self, source: &[u8], count: uint, _: Endian) -> Result<Vec<u8>> {
+            Ok(source[..count as usize].to_vec())
+        }
+    }
+
+    #[test]
+    fn test_custom_items() {
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let dex = crate::DexReader::from_vec(bytes.clone())
+            .expect("failed to load dex")
+            .with_decoder(OffsetsDecoder(Location::Map(
+                ItemType::StringIdItem as ushort,
+            )))
+            .with_decoder(MagicDecoder);
+        let offsets = dex.custom_items::<Offset>().unwrap();
+        assert_eq!(offsets.len(), dex.header().string_ids_size() as usize);
+        assert!(offsets.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(dex.custom_items::<u8>().unwrap(), b"dex\n");
+        assert!(dex.custom_items::<u16>().is_err());
+
+        // turn the debug info entry of the map list into a vendor section.
+        let map_off = dex.header().map_off() as usize;
+        let len: uint = bytes.pread(map_off).unwrap();
+        let entry = (0..len as usize)
+            .map(|index| map_off + 4 + index * 12)
+            .find(|entry| bytes.pread::<ushort>(*entry).unwrap() == 0x2003)
+            .unwrap();
+        bytes[entry..entry + 2].copy_from_slice(&0xf0f0u16.to_le_bytes());
+        let checksum = adler32::adler32(&bytes[12..]).unwrap();
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+        let dex = crate::DexReader::from_vec(bytes)
+            .expect("failed to load dex with a vendor section")
+            .with_decoder(OffsetsDecoder(Location::Map(0xf0f0)));
+        let unknown = dex.map_list().unknown_items();
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].type_code(), 0xf0f0);
+        assert!(dex.map_list().get(ItemType::DebugInfoItem).is_none());
+        assert_eq!(
+            dex.custom_items::<Offset>().unwrap().len(),
+            unknown[0].size() as usize
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Dependency graphs between the classes and the packages of a `Dex`, see
+//! `Dex::class_dependencies` and `Dex::package_dependencies`.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::{
+    instruction::IndexType,
+    jtype::{Type, TypeId},
+    provenance::Provenance,
+    utils::quote,
+    Dex, Result,
+};
+
+/// Directed graph of the dependencies between classes or packages. Edges are weighted by
+/// the number of references from the source to the target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DependencyGraph {
+    nodes: BTreeSet<String>,
+    edges: BTreeMap<(String, String), usize>,
+    provenance: Option<Provenance>,
+}
+
+impl DependencyGraph {
+    /// Names of the nodes, in Java notation.
+    pub fn nodes(&self) -> impl Iterator<Item = &str> + '_ {
+        self.nodes.iter().map(String::as_str)
+    }
+
+    /// Edges of the graph as `(from, to, weight)`.
+    pub fn edges(&self) -> impl Iterator<Item = (&str, &str, usize)> + '_ {
+        self.edges
+            .iter()
+            .map(|((from, to), weight)| (from.as_str(), to.as_str(), *weight))
+    }
+
+    /// Provenance of the `Dex` the graph was built from.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    /// Nodes which `node` depends on.
+    pub fn dependencies<'a>(&'a self, node: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.edges
+            .keys()
+            .filter(move |(from, _)| from == node)
+            .map(|(_, to)| to.as_str())
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str) {
+        self.nodes.insert(from.to_string());
+        self.nodes.insert(to.to_string());
+        if from != to {
+            *self
+                .edges
+                .entry((from.to_string(), to.to_string()))
+                .or_insert(0) += 1;
+        }
+    }
+
+    /// Graph of the packages of the nodes, with the weights of the edges between
+    /// their classes added up.
+    fn packages(&self) -> Self {
+        let mut packages = Self {
+            provenance: self.provenance.clone(),
+            ..Self::default()
+        };
+        for node in &self.nodes {
+            packages.nodes.insert(package(node).to_string());
+        }
+        for ((from, to), weight) in &self.edges {
+            let (from, to) = (package(from), package(to));
+            if from != to {
+                *packages
+                    .edges
+                    .entry((from.to_string(), to.to_string()))
+                    .or_insert(0) += weight;
+            }
+        }
+        packages
+    }
+
+    /// The graph in the Graphviz DOT format. The provenance, if any, is written as a comment.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        if let Some(provenance) = &self.provenance {
+            let _ = writeln!(out, "// {}", provenance.to_json());
+        }
+        out.push_str("digraph dependencies {\n");
+        for node in &self.nodes {
+            let _ = writeln!(out, "    {};", quote(node));
+        }
+        for (from, to, weight) in self.edges() {
+            let _ = writeln!(
+                out,
+                "    {} -> {} [weight={}];",
+                quote(from),
+                quote(to),
+                weight
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// The graph as a JSON object with `nodes` and `edges` arrays, and a `provenance`
+    /// object if the provenance is known.
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<String> = self.nodes.iter().map(|n| quote(n)).collect();
+        let edges: Vec<String> = self
+            .edges()
+            .map(|(from, to, weight)| {
+                format!(
+                    "{{\"from\":{},\"to\":{},\"weight\":{}}}",
+                    quote(from),
+                    quote(to),
+                    weight
+                )
+            })
+            .collect();
+        let provenance = self
+            .provenance
+            .as_ref()
+            .map(|provenance| format!(",\"provenance\":{}", provenance.to_json()))
+            .unwrap_or_default();
+        format!(
+            "{{\"nodes\":[{}],\"edges\":[{}]{}}}",
+            nodes.join(","),
+            edges.join(","),
+            provenance
+        )
+    }
+}
+
+/// Builds the graph of the classes of `dex` and of the classes they reference through
+/// their super class, interfaces, members and instructions.
+pub(crate) fn class_dependencies<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<DependencyGraph> {
+    let mut graph = DependencyGraph {
+        provenance: Some(dex.provenance()),
+        ..DependencyGraph::default()
+    };
+    for class in dex.classes() {
+        let class = class?;
+        let name = class.jtype().to_java_type();
+        graph.nodes.insert(name.clone());
+        let mut referenced: Vec<Type> = class.interfaces().clone();
+        if let Some(super_class) = class.super_class() {
+            referenced.push(dex.get_type(super_class)?);
+        }
+        for field in class.fields() {
+            referenced.push(field.jtype().clone());
+        }
+        for method in class.methods() {
+            referenced.extend(method.params().iter().cloned());
+            referenced.push(method.return_type().clone());
+            let code = match method.try_code()? {
+                Some(code) => code,
+                None => continue,
+            };
+            for instruction in code.instructions() {
+                let instruction = instruction?;
+                let index = match instruction.index() {
+                    Some(index) => index,
+                    None => continue,
+                };
+                match instruction.opcode().index_type() {
+                    IndexType::Type => referenced.push(dex.get_type(index as TypeId)?),
+                    IndexType::Field => {
+                        let field = dex.get_field_item(index)?;
+                        referenced.push(dex.get_type(TypeId::from(field.class_idx()))?);
+                    }
+                    IndexType::Method => {
+                        let method = dex.get_method_item(index)?;
+                        referenced.push(dex.get_type(TypeId::from(method.class_idx()))?);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for jtype in referenced {
+            let descriptor: &str = jtype.type_descriptor();
+            if descriptor.trim_start_matches('[').starts_with('L') {
+                graph.add_edge(&name, jtype.to_java_type().trim_end_matches("[]"));
+            }
+        }
+    }
+    Ok(graph)
+}
+
+/// Builds the graph of the packages of `dex`, see `class_dependencies`.
+pub(crate) fn package_dependencies<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<DependencyGraph> {
+    Ok(class_dependencies(dex)?.packages())
+}
+
+pub(crate) fn package(class: &str) -> &str {
+    class.rfind('.').map_or("", |index| &class[..index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quote;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let classes = dex.class_dependencies().expect("failed to build graph");
+        let launcher = "org.adw.launcher.Launcher";
+        let dependencies: Vec<_> = classes.dependencies(launcher).collect();
+        assert!(dependencies.contains(&"android.app.Activity"));
+        assert!(!dependencies.contains(&launcher));
+
+        let packages = dex.package_dependencies().expect("failed to build graph");
+        assert!(packages
+            .dependencies("org.adw.launcher")
+            .any(|p| p == "android.app"));
+        assert!(packages
+            .edges()
+            .all(|(from, to, weight)| from != to && weight > 0));
+
+        let dot = packages.to_dot();
+        assert!(dot.starts_with("// {\"path\":\"resources/classes.dex\""));
+        assert!(dot.contains("\ndigraph dependencies {\n"));
+        assert!(dot.contains("\"org.adw.launcher\" -> \"android.app\""));
+        let json = packages.to_json();
+        assert!(json.starts_with("{\"nodes\":[\""));
+        assert!(json.contains("{\"from\":\"org.adw.launcher\",\"to\":\"android.app\",\"weight\":"));
+        assert!(json.contains(",\"provenance\":{\"path\":\"resources/classes.dex\","));
+    }
+}
@@ -1,4 +1,12 @@
-use std::{fs::File, io::BufReader, num::NonZeroUsize, ops::Range};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufReader,
+    ops::Range,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
 
 use adler32;
 use getset::{CopyGetters, Getters};
@@ -6,31 +14,53 @@ use memmap2::{Mmap, MmapOptions};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use scroll::{ctx, Pread};
+use sha1::{Digest, Sha1};
 
 use super::Result;
 use crate::{
     annotation::{
         AnnotationItem, AnnotationSetItem, AnnotationSetRefList, AnnotationsDirectoryItem,
+        AnnotationsDirectoryOffsets,
     },
-    class::{Class, ClassDataItem, ClassDefItem, ClassDefItemIter},
-    code::{CodeItem, DebugInfoItem},
+    cache::{Cache, CacheConfig},
+    callgraph::{self, CallGraph, CallGraphOptions},
+    cdex::{self, CompactHeader},
+    class::{Class, ClassDataItem, ClassDefItem, ClassDefItemIter, ClassId, ClassRef},
+    code::{self, CodeItem, DebugInfoItem},
+    conformance,
+    custom::{Decoders, Location, SectionDecoder},
+    deps::{self, DependencyGraph},
+    edit,
     encoded_value::{EncodedArray, EncodedValue},
-    error::{self, Error},
-    field::{EncodedField, Field, FieldId, FieldIdItem},
-    jtype::{Type, TypeId},
+    error::{self, Error, ResultExt},
+    field::{EncodedField, Field, FieldId, FieldIdData, FieldIdItem, ResolvedField},
+    frozen::FrozenDex,
+    jtype::{self, Type, TypeId, TypeList},
+    layout::{self, DexStats, ItemLocation, SectionSizes},
     method::{
-        EncodedMethod, Method, MethodHandleId, MethodHandleItem, MethodId, MethodIdItem, ProtoId,
-        ProtoIdItem,
+        CallSiteId, CallSiteItem, EncodedMethod, Method, MethodHandleId, MethodHandleItem, MethodId,
+        MethodIdData, MethodIdItem, ProtoId, ProtoIdItem, Prototype, ResolvedMethod,
     },
-    search::Section,
+    multidex::MultiDex,
+    odex::{self, Container},
+    opcodes::{self, OpcodeHistogram},
+    provenance::{self, Origin, Provenance},
+    rename::{self, Renames},
+    search::{self, Section},
     source::Source,
     string::{DexString, StringId, Strings, StringsIter},
-    ubyte, uint, ulong, ushort, utils, Endian, ENDIAN_CONSTANT, NO_INDEX, REVERSE_ENDIAN_CONSTANT,
+    ubyte, uint, ulong, ushort,
+    utils::{self, Lazy, OptionalOffset},
+    visit::{self, Visitor},
+    writer::DexWriter,
+    xref::{self, CodeLocation, FieldAccess, XrefIndex},
+    Endian, ENDIAN_CONSTANT, NO_INDEX, REVERSE_ENDIAN_CONSTANT,
 };
 use std::path::Path;
 
 /// Dex file header
-#[derive(Debug, Pread, CopyGetters)]
+#[derive(Debug, Clone, Pread, CopyGetters)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[get_copy = "pub"]
 pub struct Header {
     /// Magic value that must appear at the beginning of the header section
@@ -94,13 +124,21 @@ pub struct Header {
 }
 
 impl Header {
+    /// Version of the dex format given in the magic value, like `35` for `dex\n035\0`.
+    pub fn version(&self) -> Option<uint> {
+        std::str::from_utf8(&self.magic[4..7]).ok()?.parse().ok()
+    }
+
     fn data_section(&self) -> Range<uint> {
-        self.data_off..self.data_off + self.data_size
+        self.data_off..self.data_off.saturating_add(self.data_size)
     }
 }
 
+/// Size of the header of a version `041` dex file, which has the container fields.
+const CONTAINER_HEADER_SIZE: uint = 0x78;
+
 /// Wrapper type for Dex
-#[derive(Debug, Getters, CopyGetters)]
+#[derive(Debug, Clone, Getters, CopyGetters)]
 pub(crate) struct DexInner {
     /// The header
     #[get = "pub"]
@@ -110,11 +148,21 @@ pub(crate) struct DexInner {
     map_list: MapList,
     #[get_copy = "pub"]
     endian: Endian,
+    /// Offset of the header from the start of the container, 0 unless the file is a
+    /// version `041` container.
+    #[get_copy = "pub"]
+    header_offset: uint,
+    /// Size of the container holding this dex file, for version `041` files.
+    #[get_copy = "pub"]
+    container_size: Option<uint>,
+    /// The additional fields of the header of compact dex files.
+    #[get = "pub"]
+    compact_header: Option<CompactHeader>,
 }
 
 impl DexInner {
     pub(crate) fn strings_offset(&self) -> uint {
-        self.header.string_ids_off
+        self.header_offset + self.header.string_ids_off
     }
 
     pub(crate) fn strings_len(&self) -> uint {
@@ -126,11 +174,11 @@ impl DexInner {
     }
 
     pub(crate) fn field_ids_offset(&self) -> uint {
-        self.header.field_ids_off
+        self.header_offset + self.header.field_ids_off
     }
 
     pub(crate) fn class_defs_offset(&self) -> uint {
-        self.header.class_defs_off
+        self.header_offset + self.header.class_defs_off
     }
 
     pub(crate) fn class_defs_len(&self) -> uint {
@@ -138,7 +186,7 @@ impl DexInner {
     }
 
     pub(crate) fn method_ids_offset(&self) -> uint {
-        self.header.method_ids_off
+        self.header_offset + self.header.method_ids_off
     }
 
     pub(crate) fn method_ids_len(&self) -> uint {
@@ -146,7 +194,7 @@ impl DexInner {
     }
 
     pub(crate) fn proto_ids_offset(&self) -> uint {
-        self.header.proto_ids_off
+        self.header_offset + self.header.proto_ids_off
     }
 
     pub(crate) fn proto_ids_len(&self) -> uint {
@@ -154,59 +202,164 @@ impl DexInner {
     }
 
     pub(crate) fn type_ids_offset(&self) -> uint {
-        self.header.type_ids_off
+        self.header_offset + self.header.type_ids_off
     }
 
     pub(crate) fn type_ids_len(&self) -> uint {
         self.header.type_ids_size
     }
 
+    /// The data section, the whole container for version `041` files which share it.
     fn data_section(&self) -> Range<uint> {
-        self.header.data_section()
+        match self.container_size {
+            Some(container_size) if self.header.data_size == 0 => 0..container_size,
+            _ => self.header.data_section(),
+        }
     }
 
-    fn method_handles_offset(&self) -> Option<uint> {
-        self.map_list.get_offset(ItemType::MethodHandleItem)
+    // like the ids sections, these are relative to the header in containers.
+    pub(crate) fn call_site_ids_offset(&self) -> Option<uint> {
+        self.map_list
+            .get_offset(ItemType::CallSiteIdItem)
+            .map(|offset| self.header_offset.saturating_add(offset))
     }
 
-    fn method_handles_len(&self) -> Option<uint> {
+    pub(crate) fn call_site_ids_len(&self) -> Option<uint> {
+        self.map_list.get_len(ItemType::CallSiteIdItem)
+    }
+
+    pub(crate) fn method_handles_offset(&self) -> Option<uint> {
+        self.map_list
+            .get_offset(ItemType::MethodHandleItem)
+            .map(|offset| self.header_offset.saturating_add(offset))
+    }
+
+    pub(crate) fn method_handles_len(&self) -> Option<uint> {
         self.map_list.get_len(ItemType::MethodHandleItem)
     }
 }
 
+/// Byte order of the dex file whose header is at `start`, given by its endian tag.
+pub(crate) fn read_endian(source: &[u8], start: usize) -> Result<Endian> {
+    if source.len() <= start + 44 {
+        debug!("malformed dex: size < minimum header size");
+        return Err(Error::MalFormed("Invalid dex file".to_string()));
+    }
+    let mut endian_tag = [0; 4];
+    endian_tag.copy_from_slice(&source[start + 40..start + 44]);
+    match endian_tag {
+        ENDIAN_CONSTANT => Ok(scroll::LE),
+        REVERSE_ENDIAN_CONSTANT => Ok(scroll::BE),
+        _ => Err(Error::BadEndianTag { found: endian_tag }),
+    }
+}
+
 // TODO: this should be try_from_dex
-impl<'a> ctx::TryFromCtx<'a, ()> for DexInner {
+/// Reads the dex file whose header is at `header_offset` in `source`.
+impl<'a> ctx::TryFromCtx<'a, uint> for DexInner {
     type Error = error::Error;
     type Size = usize;
 
-    fn try_from_ctx(source: &'a [u8], _: ()) -> Result<(Self, Self::Size)> {
-        if source.len() <= 44 {
-            debug!("malformed dex: size < minimum header size");
-            return Err(Error::MalFormed("Invalid dex file".to_string()));
+    fn try_from_ctx(source: &'a [u8], header_offset: uint) -> Result<(Self, Self::Size)> {
+        let span = phase_span!("header", header_offset = header_offset);
+        let start = header_offset as usize;
+        let bytes = source.get(start..).unwrap_or_default();
+        match Container::detect(bytes) {
+            Some(container) if container.is_wrapper() => {
+                return Err(Error::MalFormed(format!(
+                    "{} file, read its dex files with DexReader::from_odex_file",
+                    container
+                )));
+            }
+            Some(_) => {}
+            None => {
+                return Err(Error::BadMagic {
+                    found: bytes.iter().take(8).copied().collect(),
+                })
+            }
+        }
+        let endian = read_endian(source, start)?;
+        let header = source.pread_with::<Header>(start, endian)?;
+        let compact_header = if header.magic[..4] == cdex::MAGIC[..] {
+            // offsets into the data section are relative to its start.
+            if header.data_off != 0 {
+                return Err(Error::MalFormed(
+                    "Compact dex file, read it with DexReader::from_cdex_file".to_string(),
+                ));
+            }
+            Some(source.pread_with(start + cdex::HEADER_EXTENSION_OFFSET, endian)?)
+        } else {
+            None
+        };
+        let container_size = if header.version().is_some_and(|version| version >= 41)
+            && header.header_size >= CONTAINER_HEADER_SIZE
+        {
+            let container_size: uint = source.pread_with(start + 0x70, endian)?;
+            let found_offset: uint = source.pread_with(start + 0x74, endian)?;
+            if found_offset != header_offset || container_size as usize > source.len() {
+                return Err(Error::MalFormed(format!(
+                    "Invalid container: header at {:#x} has header_offset {:#x} and \
+                     container_size {:#x}",
+                    header_offset, found_offset, container_size
+                )));
+            }
+            Some(container_size)
+        } else {
+            None
+        };
+        let end = match container_size {
+            Some(_) => start + header.file_size as usize,
+            None => source.len(),
+        };
+        if end <= start + 12 || end > source.len() {
+            return Err(Error::MalFormed(format!(
+                "Invalid file_size {:#x} of the dex file at {:#x}",
+                header.file_size, header_offset
+            )));
+        }
+        // the ids sections must be in the file, so the offsets of their items don't overflow.
+        let ids_sections = [
+            ("string_ids", header.string_ids_off, header.string_ids_size, 4),
+            ("type_ids", header.type_ids_off, header.type_ids_size, 4),
+            ("proto_ids", header.proto_ids_off, header.proto_ids_size, 12),
+            ("field_ids", header.field_ids_off, header.field_ids_size, 8),
+            ("method_ids", header.method_ids_off, header.method_ids_size, 8),
+            ("class_defs", header.class_defs_off, header.class_defs_size, 32),
+        ];
+        for (name, offset, size, item_size) in ids_sections {
+            let section_end = start as u64 + u64::from(offset) + u64::from(size) * item_size;
+            if size != 0 && section_end > end as u64 {
+                return Err(Error::BadOffset(
+                    offset as usize,
+                    format!("{} section of {} items out of bounds", name, size),
+                ));
+            }
         }
-        let endian_tag = &source[40..44];
-        let endian = match (endian_tag[0], endian_tag[1], endian_tag[2], endian_tag[3]) {
-            ENDIAN_CONSTANT => scroll::BE,
-            REVERSE_ENDIAN_CONSTANT => scroll::LE,
-            _ => return Err(error::Error::MalFormed("Bad endian tag".to_string())),
+        let data_section = match container_size {
+            Some(container_size) if header.data_size == 0 => 0..container_size,
+            _ => header.data_section(),
         };
-        let header = source.pread_with::<Header>(0, endian)?;
-        if !header.data_section().contains(&header.map_off) {
+        if !data_section.contains(&header.map_off) {
             return Err(error::Error::BadOffset(
                 header.map_off as usize,
                 "map_list not in data section".to_string(),
             ));
         }
-        let found = header.checksum();
-        let computed = adler32::adler32(BufReader::new(&source[12..]))?;
-        if computed != found {
-            return Err(Error::MalFormed(format!(
-                "File corrupted, adler32 checksum doesn't match: computed: {}, found: {}",
-                computed, found
-            )));
+        let expected = header.checksum();
+        // like ART, the checksums of compact dex files aren't verified.
+        let found = match compact_header {
+            Some(_) => expected,
+            None => adler32::adler32(BufReader::new(&source[start + 12..end]))?,
+        };
+        if found != expected {
+            return Err(Error::ChecksumMismatch { expected, found });
         }
 
-        let map_list = source.pread_with(header.map_off as usize, endian)?;
+        drop(span);
+
+        let span = phase_span!("map_list", map_off = header.map_off; items);
+        let map_list: MapList = source.pread_with(header.map_off as usize, endian)?;
+        record!(span, items = map_list.items().len());
         debug!(target: "initialization", "header: {:?}, endian-ness: {:?}", header, endian);
         debug!(target: "initialization", "map_list: {:?}", map_list);
         Ok((
@@ -214,6 +367,9 @@ impl<'a> ctx::TryFromCtx<'a, ()> for DexInner {
                 header,
                 map_list,
                 endian,
+                header_offset,
+                container_size,
+                compact_header,
             },
             0,
         ))
@@ -222,9 +378,11 @@ impl<'a> ctx::TryFromCtx<'a, ()> for DexInner {
 
 /// List of the entire contents of a file, in order. A given type must appear at most
 /// once in a map, entries must be ordered by initial offset and must not overlap.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MapList {
     map_items: Vec<MapItem>,
+    /// Items with a type the parser doesn't know about, like sections added by vendors.
+    unknown_items: Vec<UnknownMapItem>,
 }
 
 impl<'a> ctx::TryFromCtx<'a, Endian> for MapList {
@@ -234,9 +392,26 @@ impl<'a> ctx::TryFromCtx<'a, Endian> for MapList {
     fn try_from_ctx(source: &'a [u8], endian: Endian) -> Result<(Self, Self::Size)> {
         let offset = &mut 0;
         let size: uint = source.gread_with(offset, endian)?;
+        let mut map_items = Vec::new();
+        let mut unknown_items = Vec::new();
+        for _ in 0..size {
+            let item: UnknownMapItem = source.gread_with(offset, endian)?;
+            match ItemType::from_u16(item.type_code) {
+                Some(item_type) => map_items.push(MapItem {
+                    item_type,
+                    size: item.size,
+                    offset: item.offset,
+                }),
+                None => {
+                    debug!(target: "map-list", "unknown item type: {:#x}", item.type_code);
+                    unknown_items.push(item);
+                }
+            }
+        }
         Ok((
             Self {
-                map_items: try_gread_vec_with!(source, offset, size, endian),
+                map_items,
+                unknown_items,
             },
             *offset,
         ))
@@ -261,6 +436,169 @@ impl MapList {
     pub fn get_len(&self, item_type: ItemType) -> Option<uint> {
         self.get(item_type).map(|map_item| map_item.size)
     }
+
+    /// Items with a type the parser knows about, in the order of the map list.
+    pub(crate) fn items(&self) -> &[MapItem] {
+        &self.map_items
+    }
+
+    /// Items with a type the parser doesn't know about.
+    pub fn unknown_items(&self) -> &[UnknownMapItem] {
+        &self.unknown_items
+    }
+
+    /// Parses the items of the section of `item` in `dex`. The items of the data section
+    /// are read one after the other from the start of the section, which isn't supported
+    /// for the code items of compact dex files.
+    pub fn parse_item<'a, S>(&self, dex: &'a Dex<S>, item: MapItem) -> Result<SectionItems<'a>>
+    where
+        S: AsRef<[u8]>,
+    {
+        let source = dex.source.as_ref();
+        let endian = dex.get_endian();
+        let items = match item.item_type {
+            ItemType::Header => SectionItems::Header(dex.header().clone()),
+            ItemType::StringIdItem => {
+                SectionItems::StringIds(dex.strings_uncached().collect::<Result<_>>()?)
+            }
+            ItemType::TypeIdItem => SectionItems::TypeIds(dex.types().collect::<Result<_>>()?),
+            ItemType::ProtoIdItem => {
+                SectionItems::ProtoIds(dex.proto_ids().collect::<Result<_>>()?)
+            }
+            ItemType::FieldIdItem => {
+                SectionItems::FieldIds(dex.field_ids().collect::<Result<_>>()?)
+            }
+            ItemType::MethodIdItem => {
+                SectionItems::MethodIds(dex.method_ids().collect::<Result<_>>()?)
+            }
+            ItemType::ClassDefItem => {
+                SectionItems::ClassDefs(dex.class_defs().collect::<Result<_>>()?)
+            }
+            ItemType::CallSiteIdItem => {
+                SectionItems::CallSiteIds(dex.call_sites().collect::<Result<_>>()?)
+            }
+            ItemType::MethodHandleItem => {
+                SectionItems::MethodHandles(dex.method_handles().collect::<Result<_>>()?)
+            }
+            ItemType::MapList => SectionItems::MapList(self.clone()),
+            ItemType::TypeList => SectionItems::TypeLists(read_data_items(item, 4, |offset| {
+                source.gread_with(offset, endian)
+            })?),
+            ItemType::AnnotationSetRefList => {
+                SectionItems::AnnotationSetRefLists(read_data_items(item, 4, |offset| {
+                    source.gread_with(offset, dex)
+                })?)
+            }
+            ItemType::AnnotationSetItem => {
+                SectionItems::AnnotationSets(read_data_items(item, 4, |offset| {
+                    source.gread_with(offset, dex)
+                })?)
+            }
+            ItemType::ClassDataItem => {
+                SectionItems::ClassData(read_data_items(item, 1, |offset| {
+                    source.gread_with(offset, dex)
+                })?)
+            }
+            ItemType::CodeItem => {
+                if dex.compact_header().is_some() {
+                    return Err(Error::MalFormed(
+                        "Code items of compact dex files can't be read in sequence".to_string(),
+                    ));
+                }
+                SectionItems::CodeItems(read_data_items(item, 4, |offset| {
+                    source.gread_with(offset, dex)
+                })?)
+            }
+            ItemType::StringDataItem => {
+                SectionItems::StringData(read_data_items(item, 1, |offset| {
+                    let string = source.gread_with(offset, endian)?;
+                    // the terminating null byte isn't part of the string.
+                    *offset += 1;
+                    Ok(string)
+                })?)
+            }
+            ItemType::DebugInfoItem => {
+                SectionItems::DebugInfo(read_data_items(item, 1, |offset| {
+                    source.gread_with(offset, dex)
+                })?)
+            }
+            ItemType::AnnotationItem => {
+                SectionItems::Annotations(read_data_items(item, 1, |offset| {
+                    source.gread_with(offset, dex)
+                })?)
+            }
+            ItemType::EncodedArrayItem => {
+                SectionItems::EncodedArrays(read_data_items(item, 1, |offset| {
+                    source.gread_with(offset, dex)
+                })?)
+            }
+            ItemType::AnnotationsDirectoryItem => {
+                SectionItems::AnnotationsDirectories(read_data_items(item, 4, |offset| {
+                    source.gread_with(offset, dex)
+                })?)
+            }
+        };
+        Ok(items)
+    }
+
+    /// Returns the offset and length of the item with the type code `type_code`, known
+    /// or not.
+    pub(crate) fn get_by_code(&self, type_code: ushort) -> Option<(uint, uint)> {
+        self.map_items
+            .iter()
+            .find(|map_item| map_item.item_type as ushort == type_code)
+            .map(|map_item| (map_item.offset, map_item.size))
+            .or_else(|| {
+                self.unknown_items
+                    .iter()
+                    .find(|map_item| map_item.type_code == type_code)
+                    .map(|map_item| (map_item.offset, map_item.size))
+            })
+    }
+}
+
+/// The parsed items of a section of the map list, see `MapList::parse_item`. The items of
+/// the ids sections are in the order of their ids, the ones of the data section are along
+/// with their offset.
+#[derive(Debug)]
+pub enum SectionItems<'a> {
+    Header(Header),
+    /// The strings of the string ids.
+    StringIds(Vec<DexString>),
+    TypeIds(Vec<Type>),
+    ProtoIds(Vec<ProtoIdItem>),
+    FieldIds(Vec<FieldIdItem>),
+    MethodIds(Vec<MethodIdItem>),
+    ClassDefs(Vec<ClassDefItem>),
+    CallSiteIds(Vec<CallSiteItem>),
+    MethodHandles(Vec<MethodHandleItem>),
+    MapList(MapList),
+    TypeLists(Vec<(uint, TypeList)>),
+    AnnotationSetRefLists(Vec<(uint, AnnotationSetRefList)>),
+    AnnotationSets(Vec<(uint, AnnotationSetItem)>),
+    ClassData(Vec<(uint, ClassDataItem)>),
+    CodeItems(Vec<(uint, CodeItem<'a>)>),
+    StringData(Vec<(uint, DexString)>),
+    DebugInfo(Vec<(uint, DebugInfoItem)>),
+    Annotations(Vec<(uint, AnnotationItem)>),
+    EncodedArrays(Vec<(uint, EncodedArray)>),
+    AnnotationsDirectories(Vec<(uint, AnnotationsDirectoryItem)>),
+}
+
+/// Reads the items of the data section `item` with `read`, which advances the offset past
+/// each item, along with their offsets. Each item is aligned to `align` bytes.
+fn read_data_items<T, F>(item: MapItem, align: usize, mut read: F) -> Result<Vec<(uint, T)>>
+where
+    F: FnMut(&mut usize) -> Result<T>,
+{
+    let mut items = Vec::new();
+    let mut offset = item.offset as usize;
+    for _ in 0..item.size {
+        offset = (offset + align - 1) & !(align - 1);
+        let start = offset as uint;
+        items.push((start, read(&mut offset)?));
+    }
+    Ok(items)
 }
 
 /// ItemType that appear in MapList
@@ -288,6 +626,22 @@ pub enum ItemType {
     AnnotationsDirectoryItem = 0x2006,
 }
 
+impl ItemType {
+    /// Size in bytes of the items of this type, `None` if they don't have a fixed size.
+    /// The header and the map list are single items with a size of their own.
+    pub fn item_size(self) -> Option<uint> {
+        match self {
+            ItemType::StringIdItem | ItemType::TypeIdItem | ItemType::CallSiteIdItem => Some(4),
+            ItemType::FieldIdItem | ItemType::MethodIdItem | ItemType::MethodHandleItem => {
+                Some(8)
+            }
+            ItemType::ProtoIdItem => Some(12),
+            ItemType::ClassDefItem => Some(32),
+            _ => None,
+        }
+    }
+}
+
 /// Single item of the MapList.
 #[derive(Debug, Clone, Copy, CopyGetters)]
 #[get_copy = "pub"]
@@ -324,6 +678,39 @@ impl<'a> ctx::TryFromCtx<'a, Endian> for MapItem {
     }
 }
 
+/// Item of the MapList with a type the parser doesn't know about.
+#[derive(Debug, Clone, Copy, CopyGetters)]
+#[get_copy = "pub"]
+pub struct UnknownMapItem {
+    /// Type code of the item.
+    type_code: ushort,
+    /// Count of the number of items to be found at the indicated offset
+    size: uint,
+    /// Offset from the start of the file to the item
+    offset: uint,
+}
+
+impl<'a> ctx::TryFromCtx<'a, Endian> for UnknownMapItem {
+    type Error = error::Error;
+    type Size = usize;
+
+    fn try_from_ctx(source: &'a [u8], endian: Endian) -> Result<(Self, Self::Size)> {
+        let offset = &mut 0;
+        let type_code: ushort = source.gread_with(offset, endian)?;
+        let _: ushort = source.gread_with(offset, endian)?;
+        let size: uint = source.gread_with(offset, endian)?;
+        let item_offset: uint = source.gread_with(offset, endian)?;
+        Ok((
+            Self {
+                type_code,
+                size,
+                offset: item_offset,
+            },
+            *offset,
+        ))
+    }
+}
+
 /// Represents a Dex file
 #[derive(Debug)]
 pub struct Dex<T> {
@@ -331,7 +718,45 @@ pub struct Dex<T> {
     pub(crate) source: Source<T>,
     /// Items in string_ids section are cached here.
     pub(crate) strings: Strings<T>,
-    pub(crate) inner: DexInner,
+    /// Items in type_ids section are cached here.
+    pub(crate) types: Cache<TypeId, Type>,
+    /// Resolved items of the proto_ids section are cached here.
+    pub(crate) protos: Cache<ProtoId, Arc<Prototype>>,
+    /// Items in field_ids section are cached here.
+    pub(crate) field_items: Cache<FieldId, FieldIdItem>,
+    /// Items in method_ids section are cached here.
+    pub(crate) method_items: Cache<MethodId, MethodIdItem>,
+    pub(crate) inner: Arc<DexInner>,
+    /// Where the file was loaded from, see `provenance`.
+    pub(crate) origin: Arc<Origin>,
+    /// SHA-256 of the file, computed on the first call to `provenance`.
+    pub(crate) sha256: Arc<OnceLock<String>>,
+    /// Decoders of custom sections, see `with_decoder`.
+    pub(crate) decoders: Arc<Decoders>,
+    /// Indices of the class defs by type descriptor, see `with_class_index`.
+    pub(crate) class_index: Arc<OnceLock<HashMap<String, uint>>>,
+    /// Indices of the class defs sorted by type id, built on the first lookup by type.
+    pub(crate) class_defs_by_type: Arc<OnceLock<Vec<(TypeId, uint)>>>,
+}
+
+// shallow copy
+impl<T> Clone for Dex<T> {
+    fn clone(&self) -> Self {
+        Self {
+            source: self.source.clone(),
+            strings: self.strings.clone(),
+            types: self.types.clone(),
+            protos: self.protos.clone(),
+            field_items: self.field_items.clone(),
+            method_items: self.method_items.clone(),
+            inner: self.inner.clone(),
+            origin: self.origin.clone(),
+            sha256: self.sha256.clone(),
+            decoders: self.decoders.clone(),
+            class_index: self.class_index.clone(),
+            class_defs_by_type: self.class_defs_by_type.clone(),
+        }
+    }
 }
 
 impl<T> Dex<T>
@@ -343,10 +768,209 @@ where
         self.inner.header()
     }
 
+    /// Where this `Dex` was loaded from, with the SHA-256 of its bytes. The hash is computed
+    /// on the first call.
+    pub fn provenance(&self) -> Provenance {
+        let sha256 = self
+            .sha256
+            .get_or_init(|| provenance::sha256(self.source.as_ref()));
+        self.origin.provenance(sha256.clone())
+    }
+
+    /// Records the name of the entry of the container this `Dex` was read from, like
+    /// `classes2.dex` in an APK, in its `provenance`.
+    pub fn with_container<S: Into<String>>(mut self, container: S) -> Self {
+        Arc::make_mut(&mut self.origin).container = Some(container.into());
+        self
+    }
+
+    /// Replaces the caches of this `Dex` with empty ones of the capacities of `config`.
+    /// Clones of this `Dex` made before keep sharing the old caches.
+    pub fn with_cache_config(mut self, config: CacheConfig) -> Self {
+        self.strings = self.strings.with_cache_size(config.strings());
+        self.types = Cache::new(config.types());
+        self.protos = Cache::new(config.protos());
+        self.field_items = Cache::new(config.fields());
+        self.method_items = Cache::new(config.methods());
+        Arc::make_mut(&mut self.origin).string_cache_size = config.strings().capacity();
+        self
+    }
+
+    /// A shallow copy of this `Dex` which borrows its bytes, used by `Class`es, `Method`s and
+    /// `Field`s to parse their parts lazily. Unlike the `Dex` it is `Send` and `Sync` whatever
+    /// the source.
+    pub(crate) fn borrowed(&self) -> Dex<&[u8]> {
+        let source = Source::new(self.source.as_ref());
+        Dex {
+            strings: self.strings.with_source(source.clone()),
+            source,
+            types: self.types.clone(),
+            protos: self.protos.clone(),
+            field_items: self.field_items.clone(),
+            method_items: self.method_items.clone(),
+            inner: self.inner.clone(),
+            origin: self.origin.clone(),
+            sha256: self.sha256.clone(),
+            decoders: self.decoders.clone(),
+            class_index: self.class_index.clone(),
+            class_defs_by_type: self.class_defs_by_type.clone(),
+        }
+    }
+
+    /// Registers a decoder for a custom section, whose items are then read with
+    /// `custom_items`. A decoder registered earlier for the same type of items is replaced.
+    pub fn with_decoder<D>(mut self, decoder: D) -> Self
+    where
+        D: SectionDecoder + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.decoders).insert(decoder);
+        self
+    }
+
+    /// Builds an index of the class defs by type descriptor, after which `find_class_by_name`
+    /// doesn't search for the type of the class. Worth it when looking up many
+    /// classes.
+    pub fn with_class_index(self) -> Result<Self> {
+        if self.class_index.get().is_none() {
+            let index = self
+                .class_defs()
+                .enumerate()
+                .map(|(index, class_def)| {
+                    let jtype = self.get_type(class_def?.class_idx)?;
+                    Ok((jtype.type_descriptor().to_string(), index as uint))
+                })
+                .collect::<Result<HashMap<_, _>>>()?;
+            debug!(target: "class-index", "indexed {} classes", index.len());
+            let _ = self.class_index.set(index);
+        }
+        Ok(self)
+    }
+
+    /// Returns the `ClassDefItem` at `index` in the class_defs section.
+    pub(crate) fn class_def_at(&self, index: uint) -> Result<ClassDefItem> {
+        if index >= self.inner.class_defs_len() {
+            return Err(Error::IndexOutOfBounds {
+                section: ItemType::ClassDefItem,
+                index: index.into(),
+            });
+        }
+        let offset = self.inner.class_defs_offset() as usize + index as usize * 32;
+        self.source
+            .as_ref()
+            .pread_with(offset, self.get_endian())
+            .map_err(Error::from)
+            .with_item(ItemType::ClassDefItem, offset)
+    }
+
+    /// Returns the `ClassDefItem` of the class `class_id`, `None` if the class isn't defined in
+    /// this `Dex`. The class defs aren't sorted by type, so they are indexed on the first call.
+    pub fn get_class_def(&self, class_id: ClassId) -> Result<Option<ClassDefItem>> {
+        let class_defs = match self.class_defs_by_type.get() {
+            Some(class_defs) => class_defs,
+            None => {
+                let section = self.class_defs_section()?;
+                let section = section.as_ref();
+                let endian = self.get_endian();
+                // class_idx is the first field of each 32 byte class def.
+                let mut class_defs = (0..self.inner.class_defs_len())
+                    .map(|index| {
+                        let type_id: TypeId = section.pread_with(index as usize * 32, endian)?;
+                        Ok((type_id, index))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                class_defs.sort_unstable();
+                debug!(target: "class-defs", "indexed {} class defs by type", class_defs.len());
+                self.class_defs_by_type.get_or_init(|| class_defs)
+            }
+        };
+        match class_defs.binary_search_by_key(&class_id, |(type_id, _)| *type_id) {
+            Ok(position) => Ok(Some(self.class_def_at(class_defs[position].1)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Decodes the items of the custom section whose decoder yields `I`s. Returns an empty
+    /// list if the section isn't in the map list.
+    pub fn custom_items<I: 'static>(&self) -> Result<Vec<I>> {
+        let decoder = self.decoders.get::<I>().ok_or_else(|| {
+            Error::InvalidId(format!(
+                "No decoder registered for {}",
+                std::any::type_name::<I>()
+            ))
+        })?;
+        let (offset, count) = match decoder.location() {
+            Location::Map(type_code) => match self.inner.map_list.get_by_code(type_code) {
+                Some(item) => item,
+                None => return Ok(Vec::new()),
+            },
+            Location::Offset { offset, count } => (offset, count),
+        };
+        debug!(target: "custom-items", "custom section offset: {}, count: {}", offset, count);
+        let source = self.source.as_ref();
+        if offset as usize >= source.len() {
+            return Err(Error::BadOffset(
+                offset as usize,
+                "Custom section offset past the end of the file".to_string(),
+            ));
+        }
+        decoder.decode(&source[offset as usize..], count, self.get_endian())
+    }
+
     pub fn map_list(&self) -> &MapList {
         &self.inner.map_list
     }
 
+    /// Offset of the header from the start of the container, 0 unless this `Dex` was read
+    /// from a version `041` container by `DexReader::from_container_file`.
+    pub fn header_offset(&self) -> uint {
+        self.inner.header_offset()
+    }
+
+    /// Size of the version `041` container this `Dex` was read from, `None` for older
+    /// versions.
+    pub fn container_size(&self) -> Option<uint> {
+        self.inner.container_size()
+    }
+
+    /// The additional header fields of compact dex files, `None` for standard dex files.
+    pub fn compact_header(&self) -> Option<&CompactHeader> {
+        self.inner.compact_header().as_ref()
+    }
+
+    /// Adler32 checksum of the bytes of the file after the `checksum` field, computed from
+    /// the current bytes. The header of a valid file holds the same value. Compact dex files
+    /// use another checksum.
+    pub fn compute_checksum(&self) -> uint {
+        adler32::RollingAdler32::from_buffer(&self.file_bytes()[12..]).hash()
+    }
+
+    /// SHA-1 of the bytes of the file after the `signature` field, computed from the current
+    /// bytes. The header of a valid file holds the same value.
+    pub fn compute_signature(&self) -> [ubyte; 20] {
+        Sha1::digest(&self.file_bytes()[32..]).into()
+    }
+
+    /// Whether the checksum in the header matches `compute_checksum`.
+    pub fn is_checksum_valid(&self) -> bool {
+        self.compute_checksum() == self.header().checksum()
+    }
+
+    /// Whether the signature in the header matches `compute_signature`.
+    pub fn is_signature_valid(&self) -> bool {
+        self.compute_signature() == self.header().signature()
+    }
+
+    /// The bytes of this dex file, from its header to the end of the file or, in a
+    /// container, to the end of the dex file.
+    fn file_bytes(&self) -> &[u8] {
+        let start = self.inner.header_offset() as usize;
+        let source = self.source.as_ref();
+        match self.inner.container_size() {
+            Some(_) => &source[start..start + self.header().file_size() as usize],
+            None => &source[start..],
+        }
+    }
+
     pub(crate) fn is_offset_in_data_section(&self, offset: uint) -> bool {
         self.inner.data_section().contains(&offset)
     }
@@ -363,43 +987,66 @@ where
     /// Returns a reference to the `DexString` represented by the given id.
     pub fn get_string(&self, string_id: StringId) -> Result<DexString> {
         if self.inner.strings_len() <= string_id {
-            return Err(Error::InvalidId(format!(
-                "Invalid string id: {}",
-                string_id
-            )));
+            return Err(Error::IndexOutOfBounds {
+                section: ItemType::StringIdItem,
+                index: string_id.into(),
+            });
         }
-        self.strings.get(string_id)
+        self.strings.get(string_id).with_id("string_id", string_id)
     }
 
     /// Returns the `Type` corresponding to the descriptor.
     pub fn get_type_from_descriptor(&self, descriptor: &str) -> Result<Option<Type>> {
-        if let Some(string_id) = self.strings.get_id(descriptor)? {
-            if let Some(type_id) = self.get_type_id(string_id)? {
-                return Ok(Some(self.get_type(type_id)?));
-            }
-        }
-        Ok(None)
+        self.find_type_id_by_name(descriptor)?
+            .map(|type_id| self.get_type(type_id))
+            .transpose()
     }
 
     /// Returns the `Type` represented by the give type_id.
     pub fn get_type(&self, type_id: TypeId) -> Result<Type> {
-        let max_offset = self.inner.type_ids_offset() + (self.inner.type_ids_len() - 1) * 4;
+        if type_id >= self.inner.type_ids_len() {
+            return Err(Error::IndexOutOfBounds {
+                section: ItemType::TypeIdItem,
+                index: type_id.into(),
+            });
+        }
         let offset = self.inner.type_ids_offset() + type_id * 4;
-        if offset > max_offset {
-            return Err(Error::InvalidId(format!("Invalid type id: {}", type_id)));
+        if let Some(jtype) = self.types.get(&type_id) {
+            return Ok(jtype);
         }
         let string_id = self
             .source
             .as_ref()
             .pread_with(offset as usize, self.get_endian())?;
-        self.get_string(string_id).map(|type_descriptor| Type {
+        let jtype = Type {
             id: type_id,
-            type_descriptor,
-        })
+            type_descriptor: self
+                .get_string(string_id)
+                .with_item(ItemType::TypeIdItem, offset as usize)?,
+        };
+        self.types.put(type_id, jtype.clone());
+        Ok(jtype)
+    }
+
+    /// Returns the id of the type with the given descriptor, `None` if the `Dex` doesn't
+    /// refer to the type.
+    pub(crate) fn find_type_id_by_name(&self, type_descriptor: &str) -> Result<Option<TypeId>> {
+        let string_id = match self.strings.get_id(type_descriptor)? {
+            Some(string_id) => string_id,
+            None => {
+                debug!(target: "find-type-by-name", "type name: {} not found in strings", type_descriptor);
+                return Ok(None);
+            }
+        };
+        let type_id = self.get_type_id(string_id)?;
+        if type_id.is_none() {
+            debug!(target: "find-type-by-name", "no type id found for string id: {}", string_id);
+        }
+        Ok(type_id)
     }
 
     pub(crate) fn get_type_id(&self, string_id: StringId) -> Result<Option<TypeId>> {
-        let types_section = self.type_ids_section();
+        let types_section = self.type_ids_section()?;
         Ok(types_section
             .binary_search(
                 &string_id,
@@ -409,167 +1056,252 @@ where
             .map(|s| s as TypeId))
     }
 
-    pub(crate) fn type_ids_section(&self) -> Section {
-        let type_ids_offset = self.inner.type_ids_offset() as usize;
-        let (start, end) = (
-            type_ids_offset,
-            type_ids_offset + self.inner.type_ids_len() as usize * 4,
-        );
-        let type_ids_section = &self.source[start..end];
-        Section::new(type_ids_section)
+    /// The `len` items of `item_size` bytes at `offset`, an error if they don't fit in the
+    /// file.
+    fn ids_section(
+        &self,
+        name: &str,
+        offset: uint,
+        len: uint,
+        item_size: usize,
+    ) -> Result<Section<'_>> {
+        let start = offset as usize;
+        let section = (len as usize)
+            .checked_mul(item_size)
+            .and_then(|size| start.checked_add(size))
+            .and_then(|end| self.source.as_ref().get(start..end))
+            .ok_or_else(|| Error::BadOffset(start, format!("{} section out of bounds", name)))?;
+        Ok(Section::new(section))
     }
 
-    #[allow(unused)]
-    pub(crate) fn class_defs_section(&self) -> Section {
-        let class_defs_offset = self.inner.class_defs_offset() as usize;
-        let (start, end) = (
-            class_defs_offset,
-            class_defs_offset + self.inner.class_defs_len() as usize * 32,
-        );
-        let class_defs_section = &self.source[start..end];
-        Section::new(class_defs_section)
+    pub(crate) fn type_ids_section(&self) -> Result<Section<'_>> {
+        let (offset, len) = (self.inner.type_ids_offset(), self.inner.type_ids_len());
+        self.ids_section("type_ids", offset, len, 4)
     }
 
-    pub(crate) fn find_class_by_type(&self, type_id: TypeId) -> Result<Option<Class>> {
-        for class_def in self.class_defs() {
-            let class_def = class_def?;
-            if class_def.class_idx == type_id {
-                return Ok(Some(Class::try_from_dex(self, &class_def)?));
-            }
-        }
-        Ok(None)
+    pub(crate) fn class_defs_section(&self) -> Result<Section<'_>> {
+        let (offset, len) = (self.inner.class_defs_offset(), self.inner.class_defs_len());
+        self.ids_section("class_defs", offset, len, 32)
     }
 
-    /// Finds `Class` by the given class name. The name should be in smali format.
-    /// This method uses binary search to find the class definition using the property
-    /// that the strings, type ids and class defs sections are in sorted.
-    pub fn find_class_by_name(&self, type_descriptor: &str) -> Result<Option<Class>> {
-        let string_id = self.strings.get_id(type_descriptor)?;
-        if string_id.is_none() {
-            debug!(target: "find-class-by-name", "class name: {} not found in strings", type_descriptor);
-            return Ok(None);
-        }
-        let type_id = self.get_type_id(string_id.unwrap())?;
-        if type_id.is_none() {
-            debug!(target: "find-class-by-name", "no type id found for string id: {}", string_id.unwrap());
-            return Ok(None);
-        }
-        self.find_class_by_type(type_id.unwrap())
+    pub(crate) fn proto_ids_section(&self) -> Result<Section<'_>> {
+        let (offset, len) = (self.inner.proto_ids_offset(), self.inner.proto_ids_len());
+        self.ids_section("proto_ids", offset, len, 12)
+    }
+
+    pub(crate) fn field_ids_section(&self) -> Result<Section<'_>> {
+        let (offset, len) = (self.inner.field_ids_offset(), self.inner.field_ids_len());
+        self.ids_section("field_ids", offset, len, 8)
+    }
+
+    pub(crate) fn method_ids_section(&self) -> Result<Section<'_>> {
+        let (offset, len) = (self.inner.method_ids_offset(), self.inner.method_ids_len());
+        self.ids_section("method_ids", offset, len, 8)
+    }
+
+    /// Returns the id of the prototype with the given return and parameter types, `None` if
+    /// the `Dex` doesn't refer to it. Prototypes are sorted by return type, then by the
+    /// parameter types.
+    pub(crate) fn find_proto_id(
+        &self,
+        return_type: TypeId,
+        params: &[TypeId],
+    ) -> Result<Option<ProtoId>> {
+        let index = self.proto_ids_section()?.binary_search(
+            &(return_type, params),
+            self.get_endian(),
+            |item: &ProtoIdItem, element| {
+                let item_params: Vec<TypeId> = self
+                    .get_type_list(item.params_off())?
+                    .iter()
+                    .map(Type::id)
+                    .collect();
+                Ok(element.cmp(&(item.return_type(), item_params.as_slice())))
+            },
+        )?;
+        Ok(index.map(|index| index as ProtoId))
+    }
+
+    /// Returns the id of a field of the class with the given name, `None` if the `Dex`
+    /// doesn't refer to it. Fields are sorted by class, then name, then type, so any of the
+    /// fields with the name may be found if there are several with different types.
+    pub(crate) fn find_field_id(
+        &self,
+        class_id: ClassId,
+        name_id: StringId,
+    ) -> Result<Option<FieldId>> {
+        let index = self.field_ids_section()?.binary_search(
+            &(class_id, name_id),
+            self.get_endian(),
+            |item: &FieldIdData, element| {
+                Ok(element.cmp(&(ClassId::from(item.class_idx), item.name_idx)))
+            },
+        )?;
+        Ok(index.map(|index| index as FieldId))
+    }
+
+    /// Returns the id of the method with the given class, name and prototype, `None` if the
+    /// `Dex` doesn't refer to it. Methods are sorted by class, then name, then prototype.
+    pub(crate) fn find_method_id(
+        &self,
+        class_id: ClassId,
+        name_id: StringId,
+        proto_id: ProtoId,
+    ) -> Result<Option<MethodId>> {
+        let index = self.method_ids_section()?.binary_search(
+            &(ulong::from(class_id), name_id, proto_id),
+            self.get_endian(),
+            |item: &MethodIdData, element| {
+                let item = (
+                    ulong::from(item.class_idx),
+                    item.name_idx,
+                    ulong::from(item.proto_idx),
+                );
+                Ok(element.cmp(&item))
+            },
+        )?;
+        Ok(index.map(|index| index as MethodId))
     }
 
     /// Returns the list of types which represent the interfaces of a class.
     pub fn get_interfaces(&self, offset: uint) -> Result<Vec<Type>> {
         debug!(target: "interfaces", "interfaces offset: {}", offset);
-        if offset == 0 {
-            return Ok(Default::default());
-        }
-        if !self.is_offset_in_data_section(offset) {
+        self.get_type_list(offset)
+    }
+
+    /// Returns the types of the `TypeList` at `offset`, like the interfaces of a class or the
+    /// parameters of a prototype. Returns an empty list if `offset` is `0`, the offset must
+    /// be in the data section and aligned to 4 bytes.
+    pub fn get_type_list(&self, offset: uint) -> Result<Vec<Type>> {
+        let offset = match OptionalOffset(offset).resolve(self, "Type list")? {
+            Some(offset) => offset,
+            None => return Ok(Default::default()),
+        };
+        if !offset.is_multiple_of(4) {
             return Err(Error::BadOffset(
-                offset as usize,
-                "Interfaces offset not in data section".to_string(),
+                offset,
+                "Type list offset not aligned to 4 bytes".to_string(),
             ));
         }
-        let mut offset = offset as usize;
-        let source = &self.source;
-        let endian = self.get_endian();
-        let len = source.gread_with::<uint>(&mut offset, endian)?;
-        debug!(target: "interfaces", "interfaces length: {}", len);
-        let offset = &mut offset;
-        let type_ids: Vec<ushort> = try_gread_vec_with!(source, offset, len, endian);
-        utils::get_types(self, &type_ids)
+        let type_list: TypeList = self
+            .source
+            .pread_with(offset, self.get_endian())
+            .with_item(ItemType::TypeList, offset)?;
+        debug!(target: "type-list", "type list at {}: {:?}", offset, type_list);
+        utils::get_types(self, type_list.type_ids()).with_item(ItemType::TypeList, offset)
     }
 
     /// Returns the `FieldIdItem` represented by a `FieldId`.
     pub fn get_field_item(&self, field_id: FieldId) -> Result<FieldIdItem> {
+        if field_id >= ulong::from(self.inner.field_ids_len()) {
+            return Err(Error::IndexOutOfBounds {
+                section: ItemType::FieldIdItem,
+                index: field_id,
+            });
+        }
         let offset = ulong::from(self.inner.field_ids_offset()) + field_id * 8;
-        let max_offset = self.inner.field_ids_offset() + (self.inner.field_ids_len() - 1) * 8;
-        let max_offset = ulong::from(max_offset);
-        debug!(target: "field-id-item", "current offset: {}, min_offset: {}, max_offset: {}",
-                offset, self.inner.field_ids_offset(), max_offset);
-        if offset > max_offset {
-            return Err(error::Error::InvalidId(format!(
-                "Invalid field id: {}",
-                field_id
-            )));
+        debug!(target: "field-id-item", "current offset: {}", offset);
+        if let Some(field_item) = self.field_items.get(&field_id) {
+            return Ok(field_item);
         }
-        FieldIdItem::try_from_dex(self, offset, field_id)
+        let field_item = FieldIdItem::try_from_dex(self, offset, field_id)
+            .with_item(ItemType::FieldIdItem, offset as usize)?;
+        self.field_items.put(field_id, field_item.clone());
+        Ok(field_item)
     }
 
     /// Returns the `ProtoIdItem` represented by `ProtoId`.
     pub fn get_proto_item(&self, proto_id: ProtoId) -> Result<ProtoIdItem> {
+        if proto_id >= ulong::from(self.inner.proto_ids_len()) {
+            return Err(Error::IndexOutOfBounds {
+                section: ItemType::ProtoIdItem,
+                index: proto_id,
+            });
+        }
         let offset = ulong::from(self.inner.proto_ids_offset()) + proto_id * 12;
-        let max_offset = ulong::from(self.inner.proto_ids_offset())
-            + ulong::from((self.inner.proto_ids_len() - 1) * 12);
-        debug!(target: "proto-item", "proto item current offset: {}, min_offset: {}, max_offset: {}",
-            offset, self.inner.proto_ids_offset(), max_offset);
-        if offset > max_offset {
-            return Err(error::Error::InvalidId(format!(
-                "Invalid proto id: {}",
-                proto_id
-            )));
+        debug!(target: "proto-item", "proto item current offset: {}", offset);
+        ProtoIdItem::try_from_dex(self, offset).with_item(ItemType::ProtoIdItem, offset as usize)
+    }
+
+    /// Returns the shorty, return type and params of the prototype represented by `ProtoId`.
+    pub(crate) fn get_prototype(&self, proto_id: ProtoId) -> Result<Arc<Prototype>> {
+        if let Some(prototype) = self.protos.get(&proto_id) {
+            return Ok(prototype);
         }
-        ProtoIdItem::try_from_dex(self, offset)
+        let proto_item = self.get_proto_item(proto_id)?;
+        let prototype = Arc::new(Prototype::try_from_dex(self, &proto_item)?);
+        self.protos.put(proto_id, prototype.clone());
+        Ok(prototype)
     }
 
     /// Returns the `MethodIdItem` represented by `MethodId`.
     pub fn get_method_item(&self, method_id: MethodId) -> Result<MethodIdItem> {
+        if method_id >= ulong::from(self.inner.method_ids_len()) {
+            return Err(Error::IndexOutOfBounds {
+                section: ItemType::MethodIdItem,
+                index: method_id,
+            });
+        }
         let offset = ulong::from(self.inner.method_ids_offset()) + method_id * 8;
-        let max_offset = self.inner.method_ids_offset() + (self.inner.method_ids_len() - 1) * 8;
-        let max_offset = ulong::from(max_offset);
-        debug!(target: "method-item", "method item current offset: {}, min_offset: {}, max_offset: {}",
-            offset, self.inner.method_ids_offset(), max_offset);
-        if offset > max_offset {
-            return Err(error::Error::InvalidId(format!(
-                "Invalid method id: {}",
-                method_id
-            )));
+        debug!(target: "method-item", "method item current offset: {}", offset);
+        if let Some(method_item) = self.method_items.get(&method_id) {
+            return Ok(method_item);
         }
-        MethodIdItem::try_from_dex(self, offset, method_id)
+        let method_item = MethodIdItem::try_from_dex(self, offset, method_id)
+            .with_item(ItemType::MethodIdItem, offset as usize)?;
+        self.method_items.put(method_id, method_item.clone());
+        Ok(method_item)
     }
 
-    /// Iterator over the strings
+    /// Iterator over the strings. They are added to the string cache, use
+    /// `strings_uncached` to read all of them once.
     pub fn strings(&self) -> impl Iterator<Item = Result<DexString>> {
         StringsIter::new(self.strings.clone(), self.inner.strings_len() as usize)
     }
 
+    /// The strings which start with `prefix` with their ids, like the type descriptors of
+    /// the classes of a package with `Lcom/example/`. The strings are sorted, so only the
+    /// matching strings are read, and they aren't added to the string cache.
+    pub fn strings_with_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<impl Iterator<Item = Result<(StringId, DexString)>> + '_> {
+        let ids = self.strings.prefix_range(prefix)?;
+        Ok(ids.map(move |id| Ok((id, self.strings.get_uncached(id)?))))
+    }
+
+    /// Iterator over the strings which doesn't add them to the string cache, so dumping all
+    /// the strings doesn't evict the ones used to resolve classes and members.
+    pub fn strings_uncached(&self) -> impl Iterator<Item = Result<DexString>> {
+        StringsIter::uncached(self.strings.clone(), self.inner.strings_len() as usize)
+    }
+
     /// Returns a `Field` given its component items.
     pub fn get_field(
         &self,
         encoded_field: &EncodedField,
         initial_value: Option<EncodedValue>,
         annotations: AnnotationSetItem,
-    ) -> Result<Field> {
-        Field::try_from_dex(self, encoded_field, initial_value, annotations)
-    }
-
-    /// Returns a `Method` given its component items.
-    pub fn get_method(
-        &self,
-        encoded_method: &EncodedMethod,
-        method_annotations: AnnotationSetItem,
-        parameter_annotations: AnnotationSetRefList,
-    ) -> Result<Method> {
-        Method::try_from_dex(
+    ) -> Result<Field<'_>> {
+        Field::try_from_dex(
             self,
-            encoded_method,
-            method_annotations,
-            parameter_annotations,
+            encoded_field,
+            initial_value,
+            Lazy::loaded(annotations),
         )
     }
 
     /// Returns the `ClassDataItem` at the given offset.
     pub fn get_class_data(&self, offset: uint) -> Result<Option<ClassDataItem>> {
         debug!(target: "class-data", "class data offset: {}", offset);
-        if offset == 0 {
-            return Ok(None);
-        }
-        if !self.is_offset_in_data_section(offset) {
-            return Err(Error::BadOffset(
-                offset as usize,
-                "ClassData offset not in data section".to_string(),
-            ));
-        }
-        Ok(Some(self.source.pread_with(offset as usize, self)?))
+        OptionalOffset(offset)
+            .resolve(self, "ClassData")?
+            .map(|offset| {
+                self.source
+                    .pread_with(offset, self)
+                    .with_item(ItemType::ClassDataItem, offset)
+            })
+            .transpose()
     }
 
     /// Returns the `MethodHandleItem` represented by the `MethodHandleId`.
@@ -577,15 +1309,80 @@ where
         &self,
         method_handle_id: MethodHandleId,
     ) -> Result<MethodHandleItem> {
-        let err = || Error::InvalidId(format!("Invalid method handle id: {}", method_handle_id));
+        self.check_version("Method handles", 38)?;
+        let err = || Error::IndexOutOfBounds {
+            section: ItemType::MethodHandleItem,
+            index: method_handle_id.into(),
+        };
         let offset = self.inner.method_handles_offset().ok_or_else(err)?;
         let len = self.inner.method_handles_len().ok_or_else(err)?;
-        let max_offset = offset + (len - 1) * 8;
-        let offset = offset + method_handle_id * 8;
-        if offset > max_offset {
+        if method_handle_id >= len {
+            return Err(err());
+        }
+        let offset = offset as usize + method_handle_id as usize * 8;
+        self.source
+            .gread_with(&mut { offset }, self)
+            .with_item(ItemType::MethodHandleItem, offset)
+    }
+
+    /// Returns the values of the call site represented by the `CallSiteId`. The first three
+    /// values are the bootstrap linker method handle, the method name and the method type,
+    /// followed by the extra arguments of the bootstrap method.
+    /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#call-site-item)
+    pub fn get_call_site(&self, call_site_id: CallSiteId) -> Result<Vec<EncodedValue>> {
+        self.check_version("Call sites", 38)?;
+        let err = || Error::IndexOutOfBounds {
+            section: ItemType::CallSiteIdItem,
+            index: call_site_id.into(),
+        };
+        let offset = self.inner.call_site_ids_offset().ok_or_else(err)?;
+        let len = self.inner.call_site_ids_len().ok_or_else(err)?;
+        if call_site_id >= len {
             return Err(err());
         }
-        self.source.gread_with(&mut (offset as usize), self)
+        let call_site_off: uint = self
+            .source
+            .pread_with(offset as usize + call_site_id as usize * 4, self.get_endian())?;
+        if !self.is_offset_in_data_section(call_site_off) {
+            return Err(Error::BadOffset(
+                call_site_off as usize,
+                "Call site offset not in data section".to_string(),
+            ));
+        }
+        let values: EncodedArray = self
+            .source
+            .pread_with(call_site_off as usize, self)
+            .with_item(ItemType::EncodedArrayItem, call_site_off as usize)
+            .with_id("call_site_id", call_site_id)?;
+        Ok(values.into_inner())
+    }
+
+    /// Returns an error if the version of the file is older than `required`, the first
+    /// version supporting `feature`. Compact dex files and files whose version isn't a
+    /// number aren't checked.
+    pub(crate) fn check_version(&self, feature: &'static str, required: uint) -> Result<()> {
+        match self.header().version() {
+            Some(found) if found < required && self.compact_header().is_none() => {
+                Err(Error::UnsupportedVersion {
+                    feature,
+                    required,
+                    found,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the call site of `call_site_id` with its bootstrap method, method name,
+    /// method type and extra arguments decoded.
+    pub fn get_call_site_item(&self, call_site_id: CallSiteId) -> Result<CallSiteItem> {
+        CallSiteItem::from_values(call_site_id, self.get_call_site(call_site_id)?)
+    }
+
+    /// Iterator over the call_site_ids section.
+    pub fn call_sites(&self) -> impl Iterator<Item = Result<CallSiteItem>> + '_ {
+        let call_site_ids_len = self.inner.call_site_ids_len().unwrap_or(0);
+        (0..call_site_ids_len).map(move |call_site_id| self.get_call_site_item(call_site_id))
     }
 
     /// Returns the endianness in the header section.
@@ -602,6 +1399,56 @@ where
         ClassDefItemIter::new(source, defs_offset, defs_len, endian)
     }
 
+    /// Iterator over lightweight handles to the classes, which only read the
+    /// `ClassDefItem`s. Use it instead of `classes` when only some parts of the classes
+    /// are needed.
+    pub fn class_refs(&self) -> impl Iterator<Item = Result<ClassRef<T>>> + '_ {
+        self.class_defs()
+            .map(move |class_def| Ok(ClassRef::new(self, class_def?)))
+    }
+
+    /// Iterator over the super classes of `class`, from its super class upward. The chain
+    /// follows the classes defined in this `Dex` and ends with the first super class which
+    /// isn't, like `java.lang.Object` or a class of the framework.
+    pub fn superclass_chain<'a>(
+        &'a self,
+        class: &Class,
+    ) -> impl Iterator<Item = Result<Type>> + 'a {
+        let mut next = class.super_class();
+        // a malformed file may have a cycle of super classes.
+        let mut remaining = self.inner.class_defs_len();
+        std::iter::from_fn(move || {
+            let class_id = next.take()?;
+            let jtype = match self.get_type(class_id) {
+                Ok(jtype) => jtype,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.get_class_def(class_id) {
+                Ok(Some(class_def)) if remaining > 0 => {
+                    remaining -= 1;
+                    next = class_def.super_class();
+                }
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            Some(Ok(jtype))
+        })
+    }
+
+    /// Iterator over the packages of the classes defined in this `Dex`, in Java notation
+    /// and sorted by name, with the number of classes in each. Classes in the default
+    /// package are counted under `""`. Only the class defs and the type names are read.
+    pub fn packages(&self) -> Result<impl Iterator<Item = (String, usize)>> {
+        let mut packages = BTreeMap::new();
+        for class_def in self.class_defs() {
+            let name = self.get_type(class_def?.class_idx())?.to_java_type();
+            *packages
+                .entry(deps::package(&name).to_string())
+                .or_insert(0) += 1;
+        }
+        Ok(packages.into_iter())
+    }
+
     /// Iterator over the type_ids section.
     pub fn types(&self) -> impl Iterator<Item = Result<Type>> + '_ {
         let type_ids_len = self.inner.type_ids_len();
@@ -634,28 +1481,177 @@ where
         })
     }
 
-    /// Iterator over the classes
-    pub fn classes(&self) -> impl Iterator<Item = Result<Class>> + '_ {
-        self.class_defs()
-            .map(move |class_def_item| Class::try_from_dex(&self, &class_def_item?))
+    /// The section and, where it can be found, the item which contain the byte at `offset`,
+    /// `None` if it isn't in a section. See `layout::ItemLocation`.
+    pub fn item_at_offset(&self, offset: uint) -> Result<Option<ItemLocation>> {
+        layout::item_at_offset(self, offset)
     }
 
-    /// Returns the `CodeItem` at the offset.
-    pub fn get_code_item(&self, code_off: ulong) -> Result<Option<CodeItem>> {
-        if code_off == 0 {
-            return Ok(None);
-        }
-        if !self.is_offset_in_data_section(code_off as uint) {
-            return Err(Error::BadOffset(
-                code_off as usize,
-                "CodeItem offset not in data section".to_string(),
-            ));
+    /// Raw bytes of the section of the items of `item_type`, `None` if there is no such
+    /// section or if it isn't in the file. The sections of items of a fixed size end after
+    /// their last item, the other ones at the start of the next section.
+    pub fn section_bytes(&self, item_type: ItemType) -> Option<&[u8]> {
+        let item = self.map_list().get(item_type)?;
+        let start = item.offset() as usize;
+        let size = match conformance::section_size(self, &item) {
+            Some(size) => size as usize,
+            None => self.section_sizes().get(item_type)?.size(),
+        };
+        self.source.as_ref().get(start..start.checked_add(size)?)
+    }
+
+    /// Raw bytes of the link section of a statically linked file, `None` if the file has
+    /// no link section or if it isn't in the file.
+    pub fn link_data(&self) -> Option<&[u8]> {
+        let header = self.header();
+        if header.link_size() == 0 {
+            return None;
         }
-        Ok(Some(self.source.pread_with(code_off as usize, self)?))
+        let start = header.link_off() as usize;
+        let end = start.checked_add(header.link_size() as usize)?;
+        self.source.as_ref().get(start..end)
     }
 
-    /// Returns the `AnnotationItem` at the offset.
-    pub fn get_annotation_item(&self, annotation_off: uint) -> Result<AnnotationItem> {
+    /// The bytes of this `Dex` with the contents of the string `string_id` replaced by
+    /// `value`, see `edit::replace_string`.
+    pub fn replace_string(&self, string_id: StringId, value: &str) -> Result<Vec<u8>> {
+        edit::replace_string(self, string_id, value)
+    }
+
+    /// The bytes of this `Dex` without its debug info, see `edit::strip_debug_info`.
+    pub fn strip_debug_info(&self) -> Result<Vec<u8>> {
+        edit::strip_debug_info(self)
+    }
+
+    /// The bytes of this `Dex` with the classes and members of `renames` renamed, see
+    /// `rename::rename`.
+    pub fn rename(&self, renames: &Renames) -> Result<Vec<u8>> {
+        rename::rename(self, renames)
+    }
+
+    /// Walks the classes of this `Dex` with `visitor`, see `visit::walk`.
+    pub fn walk<V: Visitor + ?Sized>(&self, visitor: &mut V) -> Result<()> {
+        visit::walk(self, visitor)
+    }
+
+    /// The bytes of this `Dex` as a dex file, see `writer::DexWriter`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        DexWriter::new(self).write()
+    }
+
+    /// Bytes taken by each section of the map list, see `layout::SectionSizes`.
+    pub fn section_sizes(&self) -> SectionSizes {
+        layout::section_sizes(self)
+    }
+
+    /// Numbers of the methods, fields, types, strings, protos and classes, and how close
+    /// they are to the limit of references of a `Dex`.
+    pub fn stats(&self) -> DexStats {
+        layout::stats(self)
+    }
+
+    /// Number of times each opcode occurs in the code of all the methods.
+    pub fn opcode_histogram(&self) -> Result<OpcodeHistogram> {
+        opcodes::opcode_histogram(self)
+    }
+
+    /// The `CodeItem`s of all the methods which have code, along with their `MethodId`.
+    pub(crate) fn method_code_items(&self) -> Result<Vec<(MethodId, CodeItem<'_>)>> {
+        let mut code_items = Vec::new();
+        for class_def in self.class_defs() {
+            let class_data = match self.get_class_data(class_def?.class_data_off())? {
+                Some(class_data) => class_data,
+                None => continue,
+            };
+            let methods = class_data
+                .direct_methods()
+                .into_iter()
+                .chain(class_data.virtual_methods())
+                .flat_map(|methods| methods.inner());
+            for method in methods {
+                let code_off = *method.code_offset();
+                if let Some(code) = self.get_method_code_item(method.method_id(), code_off)? {
+                    code_items.push((method.method_id(), code));
+                }
+            }
+        }
+        Ok(code_items)
+    }
+
+    /// Returns the `CodeItem` at the offset. The debug info of the code items of compact dex
+    /// files is looked up by method, so they don't have any when read by offset alone, see
+    /// `Method::code`.
+    pub fn get_code_item(&self, code_off: ulong) -> Result<Option<CodeItem<'_>>> {
+        self.read_code_item(self.source.as_ref(), code_off, None)
+    }
+
+    /// Returns the `CodeItem` of the method `method_id` at the offset.
+    pub(crate) fn get_method_code_item(
+        &self,
+        method_id: MethodId,
+        code_off: ulong,
+    ) -> Result<Option<CodeItem<'_>>> {
+        self.read_code_item(self.source.as_ref(), code_off, Some(method_id))
+            .with_id("method_id", method_id)
+    }
+
+    /// Reads the `CodeItem` at the offset of `source`, which holds the bytes of this `Dex`,
+    /// with its instructions borrowed from `source`.
+    pub(crate) fn read_code_item<'a>(
+        &self,
+        source: &'a [u8],
+        code_off: ulong,
+        method_id: Option<MethodId>,
+    ) -> Result<Option<CodeItem<'a>>> {
+        let span = phase_span!("code_item", code_off = code_off; insns);
+        let code = OptionalOffset(code_off as uint)
+            .resolve(self, "CodeItem")?
+            .map(|offset| {
+                match self.compact_header() {
+                    Some(_) => CodeItem::try_from_compact(self, source, offset, method_id),
+                    None => source.pread_with(offset, self),
+                }
+                .with_item(ItemType::CodeItem, offset)
+            })
+            .transpose()?;
+        if let Some(code) = &code {
+            record!(span, insns = code.insns().len());
+        }
+        Ok(code)
+    }
+
+    /// Offset of the debug info item of the method `method_id`, whose code is at `code_off`.
+    pub(crate) fn debug_info_offset(&self, method_id: MethodId, code_off: ulong) -> Result<uint> {
+        match self.compact_header() {
+            Some(_) => cdex::debug_info_offset(self, method_id),
+            // debug_info_off follows registers, ins, outs and tries sizes.
+            None => Ok(self.source.pread_with(code_off as usize + 8, self.get_endian())?),
+        }
+    }
+
+    /// Returns the instructions of the `CodeItem` at the offset without loading the rest of
+    /// the `CodeItem`. Like those of a `CodeItem`, the code units are borrowed from the
+    /// source when the byte order of the `Dex` matches the host's. Use `ClassDataItem` and
+    /// `EncodedMethod::code_offset` to find the offsets without loading the classes.
+    pub fn get_insns(&self, code_off: ulong) -> Result<Cow<'_, [ushort]>> {
+        let code_off = match OptionalOffset(code_off as uint).resolve(self, "CodeItem")? {
+            Some(code_off) => code_off,
+            None => return Ok(Cow::Borrowed(&[])),
+        };
+        let endian = self.get_endian();
+        let (insns_size, start) = if self.compact_header().is_some() {
+            let sizes = cdex::CompactCodeItemSizes::read(self.source.as_ref(), code_off, endian)?;
+            (sizes.insns_size, code_off + 4)
+        } else {
+            // insns_size follows the 12 byte header of the code item.
+            let offset = code_off + 12;
+            (self.source.pread_with::<uint>(offset, endian)?, offset + 4)
+        };
+        code::read_insns(self.source.as_ref(), start, insns_size, endian)
+    }
+
+    /// Returns the `AnnotationItem` at the offset.
+    pub fn get_annotation_item(&self, annotation_off: uint) -> Result<AnnotationItem> {
         debug!(target: "annotaion-item", "annotation item offset: {}", annotation_off);
         if !self.is_offset_in_data_section(annotation_off) {
             return Err(Error::BadOffset(
@@ -663,7 +1659,9 @@ where
                 "AnnotationItem offset not in data section".to_string(),
             ));
         }
-        Ok(self.source.pread_with(annotation_off as usize, self)?)
+        self.source
+            .pread_with(annotation_off as usize, self)
+            .with_item(ItemType::AnnotationItem, annotation_off as usize)
     }
 
     /// Returns the `AnnotationSetItem` at the offset.
@@ -672,48 +1670,43 @@ where
         annotation_set_item_off: uint,
     ) -> Result<AnnotationSetItem> {
         debug!(target: "annotation-set-item", "annotation set item offset: {}", annotation_set_item_off);
-        if annotation_set_item_off == 0 {
-            return Ok(Default::default());
-        }
-        if !self.is_offset_in_data_section(annotation_set_item_off) {
-            return Err(Error::BadOffset(
-                annotation_set_item_off as usize,
-                "AnnotationSetItem offset not in data section".to_string(),
-            ));
-        }
-        self.source
-            .pread_with(annotation_set_item_off as usize, self)
-    }
-
-    /// Returns the `AnnotationSetRefList` at the offset.
+        let span = phase_span!("annotation_set", offset = annotation_set_item_off; annotations);
+        let set: AnnotationSetItem =
+            match OptionalOffset(annotation_set_item_off).resolve(self, "AnnotationSetItem")? {
+                Some(offset) => self
+                    .source
+                    .pread_with(offset, self)
+                    .with_item(ItemType::AnnotationSetItem, offset)?,
+                None => Default::default(),
+            };
+        record!(span, annotations = set.len());
+        Ok(set)
+    }
+
+    /// Returns the `AnnotationSetRefList` at the offset, an empty list if the offset is `0`.
     pub fn get_annotation_set_ref_list(
         &self,
         annotation_set_ref_list_off: uint,
     ) -> Result<AnnotationSetRefList> {
-        if !self.is_offset_in_data_section(annotation_set_ref_list_off) {
-            return Err(Error::BadOffset(
-                annotation_set_ref_list_off as usize,
-                "AnnotationSetRefList offset not in data section".to_string(),
-            ));
+        match OptionalOffset(annotation_set_ref_list_off).resolve(self, "AnnotationSetRefList")? {
+            Some(offset) => self
+                .source
+                .pread_with(offset, self)
+                .with_item(ItemType::AnnotationSetRefList, offset),
+            None => Ok(Default::default()),
         }
-        Ok(self
-            .source
-            .pread_with(annotation_set_ref_list_off as usize, self)?)
     }
 
     /// Returns the `EncodedArray` representing the static values of a class at the given offset.
     pub fn get_static_values(&self, static_values_off: uint) -> Result<EncodedArray> {
         debug!(target: "class", "static values offset: {}", static_values_off);
-        if static_values_off == 0 {
-            return Ok(Default::default());
+        match OptionalOffset(static_values_off).resolve(self, "Class static values")? {
+            Some(offset) => self
+                .source
+                .pread_with(offset, self)
+                .with_item(ItemType::EncodedArrayItem, offset),
+            None => Ok(Default::default()),
         }
-        if !self.is_offset_in_data_section(static_values_off) {
-            return Err(Error::BadOffset(
-                static_values_off as usize,
-                "Class static values offset not in data section".to_string(),
-            ));
-        }
-        self.source.pread_with(static_values_off as usize, self)
     }
 
     /// Returns the `AnnotationsDirectoryItem` at the offset.
@@ -722,29 +1715,299 @@ where
         annotations_directory_item_off: uint,
     ) -> Result<AnnotationsDirectoryItem> {
         debug!(target: "class", "annotations directory offset: {}", annotations_directory_item_off);
-        if annotations_directory_item_off == 0 {
-            return Ok(Default::default());
+        match OptionalOffset(annotations_directory_item_off)
+            .resolve(self, "Annotations directory")?
+        {
+            Some(offset) => self
+                .source
+                .pread_with(offset, self)
+                .with_item(ItemType::AnnotationsDirectoryItem, offset),
+            None => Ok(Default::default()),
         }
-        if !self.is_offset_in_data_section(annotations_directory_item_off) {
-            return Err(Error::BadOffset(
-                annotations_directory_item_off as usize,
-                "Annotations directory offset not in data section".to_string(),
-            ));
+    }
+
+    /// Returns the offsets of the annotation sets of the `AnnotationsDirectoryItem` at the
+    /// offset.
+    pub(crate) fn get_annotations_directory_offsets(
+        &self,
+        annotations_directory_item_off: uint,
+    ) -> Result<AnnotationsDirectoryOffsets> {
+        match OptionalOffset(annotations_directory_item_off)
+            .resolve(self, "Annotations directory")?
+        {
+            Some(offset) => self
+                .source
+                .pread_with(offset, self)
+                .with_item(ItemType::AnnotationsDirectoryItem, offset),
+            None => Ok(Default::default()),
         }
-        self.source
-            .pread_with(annotations_directory_item_off as usize, self)
     }
 
-    /// Returns the `DebugInfoItem` at the offset.
-    pub fn get_debug_info_item(&self, debug_info_off: uint) -> Result<DebugInfoItem> {
-        if !self.is_offset_in_data_section(debug_info_off) {
-            return Err(Error::BadOffset(
-                debug_info_off as usize,
-                "DebugInfoItem offset not in data section".to_string(),
-            ));
+    /// Returns the `DebugInfoItem` at the offset, `None` if the offset is `0`.
+    pub fn get_debug_info_item(&self, debug_info_off: uint) -> Result<Option<DebugInfoItem>> {
+        OptionalOffset(debug_info_off)
+            .resolve(self, "DebugInfoItem")?
+            .map(|offset| {
+                self.source
+                    .pread_with(offset, self)
+                    .with_item(ItemType::DebugInfoItem, offset)
+            })
+            .transpose()
+    }
+
+    /// Returns the `Class` represented by `class_id`, like `Field::class`, `None` if the class
+    /// isn't defined in this `Dex`.
+    pub fn get_class(&self, class_id: ClassId) -> Result<Option<Class<'_>>> {
+        match self.get_class_def(class_id)? {
+            Some(class_def) => Ok(Some(Class::try_from_dex(self, &class_def)?)),
+            None => Ok(None),
         }
+    }
 
-        Ok(self.source.pread_with(debug_info_off as usize, self)?)
+    fn find_indexed_class(&self, type_descriptor: &str) -> Result<Option<Class<'_>>> {
+        let index = self.class_index.get().and_then(|index| index.get(type_descriptor));
+        match index {
+            Some(index) => Ok(Some(Class::try_from_dex(self, &self.class_def_at(*index)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds `Class` by the given class name. The name should be in smali format.
+    /// This method uses binary search to find the class definition using the property
+    /// that the strings, type ids and class defs sections are in sorted.
+    /// If the class defs have been indexed with `with_class_index`, the index is used instead.
+    pub fn find_class_by_name(&self, type_descriptor: &str) -> Result<Option<Class<'_>>> {
+        if self.class_index.get().is_some() {
+            return self.find_indexed_class(type_descriptor);
+        }
+        match self.find_type_id_by_name(type_descriptor)? {
+            Some(type_id) => self.get_class(type_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds `Class` by its Java name, like `com.example.Foo` or `com.example.Foo$Inner` for
+    /// inner classes, see `find_class_by_name`.
+    pub fn find_class_by_java_name(&self, java_name: &str) -> Result<Option<Class<'_>>> {
+        self.find_class_by_name(&jtype::to_descriptor(java_name))
+    }
+
+    /// Finds a method by its smali reference, like `Lcom/Foo;->bar(ILjava/lang/String;)V`.
+    /// Returns the `Method` if its class is defined in this `Dex`, otherwise the
+    /// `MethodIdItem` which refers to it. Returns `None` if the `Dex` doesn't refer to the
+    /// method at all.
+    pub fn find_method(&self, reference: &str) -> Result<Option<ResolvedMethod<'_>>> {
+        let invalid = || Error::MalFormed(format!("Invalid method reference: {}", reference));
+        let (class, rest) = reference.split_once("->").ok_or_else(invalid)?;
+        let (name, proto) = rest.split_at(rest.find('(').ok_or_else(invalid)?);
+        let (params, return_type) = proto[1..].split_once(')').ok_or_else(invalid)?;
+        let params = jtype::split_descriptors(params).ok_or_else(invalid)?;
+        if jtype::split_descriptors(return_type).is_none_or(|types| types.len() != 1) {
+            return Err(invalid());
+        }
+
+        let class_id = match self.find_type_id_by_name(class)? {
+            Some(class_id) => class_id,
+            None => return Ok(None),
+        };
+        let name_id = match self.strings.get_id(name)? {
+            Some(name_id) => name_id,
+            None => return Ok(None),
+        };
+        let return_type = match self.find_type_id_by_name(return_type)? {
+            Some(return_type) => return_type,
+            None => return Ok(None),
+        };
+        let mut param_ids = Vec::with_capacity(params.len());
+        for param in params {
+            match self.find_type_id_by_name(param)? {
+                Some(param_id) => param_ids.push(param_id),
+                None => return Ok(None),
+            }
+        }
+        let method_id = match self.find_proto_id(return_type, &param_ids)? {
+            Some(proto_id) => self.find_method_id(class_id, name_id, proto_id)?,
+            None => None,
+        };
+        let method_id = match method_id {
+            Some(method_id) => method_id,
+            None => return Ok(None),
+        };
+        if let Some(class) = self.get_class(class_id)? {
+            let method = class
+                .direct_methods
+                .into_iter()
+                .chain(class.virtual_methods)
+                .find(|method| method.id() == method_id);
+            if let Some(method) = method {
+                return Ok(Some(ResolvedMethod::Defined(Box::new(method))));
+            }
+        }
+        Ok(Some(ResolvedMethod::Referenced(
+            self.get_method_item(method_id)?,
+        )))
+    }
+
+    /// Finds a field by the type descriptor of its class and its name, using the sorted
+    /// field ids instead of loading the fields of classes. Returns the `Field` if its class
+    /// is defined in this `Dex`, otherwise the `FieldIdItem` which refers to it. Returns
+    /// `None` if the `Dex` doesn't refer to the field at all.
+    pub fn find_field(
+        &self,
+        class_descriptor: &str,
+        field_name: &str,
+    ) -> Result<Option<ResolvedField<'_>>> {
+        let class_id = match self.find_type_id_by_name(class_descriptor)? {
+            Some(class_id) => class_id,
+            None => return Ok(None),
+        };
+        let field_id = match self.strings.get_id(field_name)? {
+            Some(name_id) => self.find_field_id(class_id, name_id)?,
+            None => None,
+        };
+        let field_id = match field_id {
+            Some(field_id) => field_id,
+            None => return Ok(None),
+        };
+        if let Some(class) = self.get_class(class_id)? {
+            let field = class
+                .static_fields
+                .into_iter()
+                .chain(class.instance_fields)
+                .find(|field| field.id() == field_id);
+            if let Some(field) = field {
+                return Ok(Some(ResolvedField::Defined(field)));
+            }
+        }
+        Ok(Some(ResolvedField::Referenced(
+            self.get_field_item(field_id)?,
+        )))
+    }
+
+    /// Iterator over the classes whose type descriptor matches the glob `pattern`, like
+    /// `Lcom/example/**` for all the classes of a package and its subpackages. `*` doesn't
+    /// match `/`, so `Lcom/example/*` only matches the classes of the package itself. Only
+    /// the matching classes are loaded.
+    pub fn find_classes_matching<'a>(
+        &'a self,
+        pattern: &'a str,
+    ) -> impl Iterator<Item = Result<Class<'a>>> + 'a {
+        self.classes_where(move |descriptor| search::glob_match(pattern, descriptor))
+    }
+
+    /// Iterator over the classes whose type descriptor matches `regex`, see
+    /// `find_classes_matching`.
+    #[cfg(feature = "regex")]
+    pub fn find_classes_matching_regex<'a>(
+        &'a self,
+        regex: &'a regex::Regex,
+    ) -> impl Iterator<Item = Result<Class<'a>>> + 'a {
+        self.classes_where(move |descriptor| regex.is_match(descriptor))
+    }
+
+    fn classes_where<'a, F>(&'a self, predicate: F) -> impl Iterator<Item = Result<Class<'a>>> + 'a
+    where
+        F: Fn(&str) -> bool + 'a,
+    {
+        let load = move |class_def: ClassDefItem| -> Result<Option<Class>> {
+            let jtype = self.get_type(class_def.class_idx())?;
+            if !predicate(jtype.type_descriptor()) {
+                return Ok(None);
+            }
+            Class::try_from_dex(self, &class_def).map(Some)
+        };
+        self.class_defs()
+            .filter_map(move |class_def| class_def.and_then(&load).transpose())
+    }
+
+    /// Returns a `Method` given its component items.
+    pub fn get_method(
+        &self,
+        encoded_method: &EncodedMethod,
+        method_annotations: AnnotationSetItem,
+        parameter_annotations: AnnotationSetRefList,
+    ) -> Result<Method<'_>> {
+        Method::try_from_dex(
+            self,
+            encoded_method,
+            Lazy::loaded(method_annotations),
+            Lazy::loaded(parameter_annotations),
+        )
+    }
+
+    /// Iterator over the classes
+    pub fn classes(&self) -> impl Iterator<Item = Result<Class<'_>>> + '_ {
+        self.class_defs()
+            .map(move |class_def_item| Class::try_from_dex(&self, &class_def_item?))
+    }
+
+    /// Parses all the strings, types, ids and classes into a snapshot of this `Dex` with
+    /// unbounded caches, which is only read from afterwards, see `FrozenDex`.
+    pub fn freeze(&self) -> Result<FrozenDex<T>> {
+        FrozenDex::new(self)
+    }
+
+    /// Parallel iterator over the classes, which are loaded on the threads of the rayon
+    /// pool, in the order of the class_defs section.
+    #[cfg(feature = "parallel")]
+    pub fn par_classes(
+        &self,
+    ) -> impl rayon::iter::IndexedParallelIterator<Item = Result<Class<'_>>> + '_
+    where
+        T: Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        (0..self.inner.class_defs_len())
+            .into_par_iter()
+            .map(move |index| Class::try_from_dex(self, &self.class_def_at(index)?))
+    }
+
+    /// Graph of the classes of this `Dex` and the classes they reference.
+    pub fn class_dependencies(&self) -> Result<DependencyGraph> {
+        deps::class_dependencies(self)
+    }
+
+    /// Graph of the packages of this `Dex` and the packages they reference through types,
+    /// field accesses and invokes.
+    pub fn package_dependencies(&self) -> Result<DependencyGraph> {
+        deps::package_dependencies(self)
+    }
+
+    /// Call graph of the methods of this `Dex`, see `callgraph::build`.
+    pub fn call_graph(&self, options: &CallGraphOptions) -> Result<CallGraph> {
+        callgraph::build(self, options)
+    }
+
+    /// The instructions loading the string `string_id`, see `xref::string_xrefs`.
+    pub fn string_xrefs(&self, string_id: StringId) -> Result<Vec<CodeLocation>> {
+        xref::string_xrefs(self, string_id)
+    }
+
+    /// The invokes of the method `method_id`, see `xref::callers_of`. Build an `XrefIndex`
+    /// with `xref_index` for repeated queries.
+    pub fn callers_of(&self, method_id: MethodId) -> Result<Vec<CodeLocation>> {
+        xref::callers_of(self, method_id)
+    }
+
+    /// The instructions reading or writing the field `field_id`, see
+    /// `xref::field_accesses`.
+    pub fn field_accesses(&self, field_id: FieldId) -> Result<Vec<FieldAccess>> {
+        xref::field_accesses(self, field_id)
+    }
+
+    /// Index of the cross references of the code of this `Dex`.
+    pub fn xref_index(&self) -> Result<XrefIndex> {
+        XrefIndex::new(self)
+    }
+
+    /// The instructions loading the string `string`, none if it isn't in the strings of
+    /// this `Dex`.
+    pub fn string_literal_xrefs(&self, string: &str) -> Result<Vec<CodeLocation>> {
+        match self.strings.get_id(string)? {
+            Some(string_id) => self.string_xrefs(string_id),
+            None => Ok(Vec::new()),
+        }
     }
 }
 
@@ -756,42 +2019,203 @@ impl DexReader {
     /// the file is not a dex or in case of I/O errors
     pub fn from_file<P: AsRef<Path>>(file: P) -> Result<Dex<Mmap>> {
         let map = unsafe { MmapOptions::new().map(&File::open(file.as_ref())?)? };
-        let inner: DexInner = map.pread(0)?;
-        let endian = inner.endian();
-        let source = Source::new(map);
-        let cache = Strings::new(
-            source.clone(),
-            endian,
-            inner.strings_offset(),
-            inner.strings_len(),
-            NonZeroUsize::new(4096).unwrap(),
-            inner.data_section(),
-        );
-        Ok(Dex {
-            source: source.clone(),
-            strings: cache,
-            inner,
-        })
+        Self::load(map, Some(file.as_ref().to_path_buf()), true)
     }
 
     /// Loads a `Dex` from a `Vec<u8>`
     pub fn from_vec<B: AsRef<[u8]>>(buf: B) -> Result<Dex<B>> {
-        let inner: DexInner = buf.as_ref().pread(0)?;
-        let endian = inner.endian();
+        Self::load(buf, None, false)
+    }
+
+    /// Reads the compact dex file at `path`, like the ones in the `dalvik-cache` of devices.
+    /// The data section is moved to the start of the file when it is loaded, so the file
+    /// is read into memory instead of being mapped.
+    pub fn from_cdex_file<P: AsRef<Path>>(file: P) -> Result<Dex<Vec<u8>>> {
+        let bytes = std::fs::read(file.as_ref())?;
+        let (bytes, header_offset) = cdex::relocate_data_section(&bytes)?;
+        Self::load_at(
+            Source::new(bytes),
+            Some(file.as_ref().to_path_buf()),
+            false,
+            header_offset,
+        )
+    }
+
+    /// Loads a compact dex file from a `Vec<u8>`, see `from_cdex_file`.
+    pub fn from_cdex_vec<B: AsRef<[u8]>>(buf: B) -> Result<Dex<Vec<u8>>> {
+        let (bytes, header_offset) = cdex::relocate_data_section(buf.as_ref())?;
+        Self::load_at(Source::new(bytes), None, false, header_offset)
+    }
+
+    /// Reads the dex files wrapped in the ODEX, OAT or VDEX file at `path`, like the files
+    /// in the `oat` directories of apps. The offset of each dex file in the wrapper is
+    /// recorded in its `provenance`. A dex file which isn't wrapped is read as is.
+    pub fn from_odex_file<P: AsRef<Path>>(file: P) -> Result<Vec<Dex<Vec<u8>>>> {
+        let bytes = std::fs::read(file.as_ref())?;
+        Self::load_odex(&bytes, Some(file.as_ref().to_path_buf()))
+    }
+
+    /// Reads the dex files wrapped in an ODEX, OAT or VDEX file from a `Vec<u8>`, see
+    /// `from_odex_file`.
+    pub fn from_odex_vec<B: AsRef<[u8]>>(buf: B) -> Result<Vec<Dex<Vec<u8>>>> {
+        Self::load_odex(buf.as_ref(), None)
+    }
+
+    fn load_odex(bytes: &[u8], path: Option<PathBuf>) -> Result<Vec<Dex<Vec<u8>>>> {
+        let container = Container::detect(bytes).ok_or_else(|| {
+            Error::MalFormed("Not a dex, ODEX, OAT or VDEX file".to_string())
+        })?;
+        let load = |offset: usize| {
+            let embedded = bytes.get(offset..).ok_or_else(|| {
+                Error::BadOffset(offset, "Dex file out of bounds".to_string())
+            })?;
+            let (bytes, header_offset) = match Container::detect(embedded) {
+                Some(Container::CompactDex) => cdex::relocate_data_section(embedded)?,
+                _ => {
+                    let file_size: uint = embedded.pread_with(32, read_endian(embedded, 0)?)?;
+                    let embedded = embedded.get(..file_size as usize).ok_or_else(|| {
+                        Error::BadOffset(offset, "Dex file out of bounds".to_string())
+                    })?;
+                    (embedded.to_vec(), 0)
+                }
+            };
+            Self::load_at(Source::new(bytes), path.clone(), false, header_offset)
+        };
+        odex::embedded_dex_offsets(bytes, container)?
+            .into_iter()
+            .map(|offset| {
+                let dex = load(offset).map_err(|e| {
+                    Error::MalFormed(format!(
+                        "Invalid dex file at {:#x} of {} file: {}",
+                        offset, container, e
+                    ))
+                })?;
+                if container.is_wrapper() {
+                    Ok(dex.with_container(format!("{}@{:#x}", container, offset)))
+                } else {
+                    Ok(dex)
+                }
+            })
+            .collect()
+    }
+
+    /// Reads the dex files of the version `041` container at `path`, which hold several
+    /// dex files sharing one data section. `from_file` only reads the first of them. A file
+    /// of an older version is read as a single dex file.
+    pub fn from_container_file<P: AsRef<Path>>(file: P) -> Result<MultiDex<Mmap>> {
+        let map = unsafe { MmapOptions::new().map(&File::open(file.as_ref())?)? };
+        Self::load_container(map, Some(file.as_ref().to_path_buf()), true)
+    }
+
+    /// Reads the dex files of a version `041` container from a `Vec<u8>`, see
+    /// `from_container_file`.
+    pub fn from_container_vec<B: AsRef<[u8]>>(buf: B) -> Result<MultiDex<B>> {
+        Self::load_container(buf, None, false)
+    }
+
+    /// Reads the dex files of the APK at `path`: `classes.dex`, `classes2.dex`, ... in the
+    /// order Android loads them, up to the first missing one. The name of the entry of each
+    /// `Dex` is recorded in its `provenance`.
+    #[cfg(feature = "zip")]
+    pub fn from_apk<P: AsRef<Path>>(path: P) -> Result<Vec<Dex<Vec<u8>>>> {
+        use std::io::Read;
+
+        let zip_error = |e: zip::result::ZipError| match e {
+            zip::result::ZipError::Io(e) => Error::IO(e),
+            e => Error::MalFormed(format!("Invalid APK: {}", e)),
+        };
+        let mut archive = zip::ZipArchive::new(File::open(path.as_ref())?).map_err(zip_error)?;
+        let mut dexes = Vec::new();
+        // android loads classes.dex, classes2.dex, classes3.dex, ... until one is missing
+        for number in 1.. {
+            let name = match number {
+                1 => "classes.dex".to_string(),
+                _ => format!("classes{}.dex", number),
+            };
+            let mut entry = match archive.by_name(&name) {
+                Ok(entry) => entry,
+                Err(zip::result::ZipError::FileNotFound) => break,
+                Err(e) => return Err(zip_error(e)),
+            };
+            // the declared size of the entry isn't trusted for preallocation
+            let mut bytes = Vec::new();
+            entry.read_to_end(&mut bytes)?;
+            dexes.push(
+                Self::load(bytes, Some(path.as_ref().to_path_buf()), false)?.with_container(name),
+            );
+        }
+        debug!(target: "dex-reader", "dex files in apk: {}", dexes.len());
+        Ok(dexes)
+    }
+
+    fn load<B: AsRef<[u8]>>(
+        buf: B,
+        path: Option<PathBuf>,
+        memory_mapped: bool,
+    ) -> Result<Dex<B>> {
+        Self::load_at(Source::new(buf), path, memory_mapped, 0)
+    }
+
+    fn load_container<B: AsRef<[u8]>>(
+        buf: B,
+        path: Option<PathBuf>,
+        memory_mapped: bool,
+    ) -> Result<MultiDex<B>> {
         let source = Source::new(buf);
+        let mut dexes = Vec::new();
+        let mut header_offset = 0;
+        loop {
+            let dex = Self::load_at(source.clone(), path.clone(), memory_mapped, header_offset)?;
+            // the header of the next dex file follows the end of the previous one.
+            header_offset += dex.header().file_size();
+            let container_size = dex.container_size();
+            dexes.push(dex);
+            match container_size {
+                Some(container_size) if header_offset < container_size => {}
+                _ => break,
+            }
+        }
+        debug!(target: "dex-reader", "dex files in container: {}", dexes.len());
+        Ok(MultiDex::new(dexes))
+    }
+
+    fn load_at<B: AsRef<[u8]>>(
+        source: Source<B>,
+        path: Option<PathBuf>,
+        memory_mapped: bool,
+        header_offset: uint,
+    ) -> Result<Dex<B>> {
+        let inner: DexInner = source.as_ref().pread_with(0, header_offset)?;
+        let config = CacheConfig::default();
+        let origin = Origin::new(path, memory_mapped, config.strings().capacity());
+        Ok(Self::from_parts(source, inner, origin))
+    }
+
+    /// A `Dex` over `source` with an already parsed header and map list.
+    fn from_parts<B: AsRef<[u8]>>(source: Source<B>, inner: DexInner, origin: Origin) -> Dex<B> {
+        let config = CacheConfig::default();
         let cache = Strings::new(
             source.clone(),
-            endian,
+            inner.endian(),
             inner.strings_offset(),
             inner.strings_len(),
-            NonZeroUsize::new(4096).unwrap(),
+            config.strings(),
             inner.data_section(),
         );
-        Ok(Dex {
+        Dex {
             source: source.clone(),
             strings: cache,
-            inner,
-        })
+            types: Cache::new(config.types()),
+            protos: Cache::new(config.protos()),
+            field_items: Cache::new(config.fields()),
+            method_items: Cache::new(config.methods()),
+            inner: Arc::new(inner),
+            origin: Arc::new(origin),
+            sha256: Default::default(),
+            decoders: Default::default(),
+            class_index: Default::default(),
+            class_defs_by_type: Default::default(),
+        }
     }
 }
 
@@ -802,6 +2226,7 @@ mod tests {
     use std::fs::File;
     use super::Result;
     use std::path::Path;
+    use crate::{field::Field, method::Method};
 
     #[test]
     fn test_find_class_by_name() {
@@ -819,6 +2244,331 @@ mod tests {
         assert!(count > 0);
     }
 
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<super::Dex<Vec<u8>>>();
+        assert_send_sync::<super::Dex<memmap2::Mmap>>();
+        assert_send_sync::<crate::class::Class>();
+
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let len = dex.header().class_defs_size();
+        let counts: Vec<usize> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|thread| {
+                    let dex = &dex;
+                    scope.spawn(move || {
+                        (thread..len)
+                            .step_by(4)
+                            .map(|index| {
+                                let class_def = dex.class_def_at(index).unwrap();
+                                let class = crate::class::Class::try_from_dex(dex, &class_def);
+                                class.unwrap().methods().count()
+                            })
+                            .sum()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        let expected: usize = dex.classes().map(|class| class.unwrap().methods().count()).sum();
+        assert_eq!(counts.iter().sum::<usize>(), expected);
+    }
+
+    #[test]
+    fn test_class_index() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let indexed = dex.clone().with_class_index().expect("can't index classes");
+        assert_eq!(
+            indexed.class_index.get().map(|index| index.len()),
+            Some(dex.header().class_defs_size() as usize)
+        );
+        for class_def in dex.class_defs() {
+            let class_def = class_def.expect("can't load class");
+            let jtype = dex.get_type(class_def.class_idx()).expect("bad type");
+            let class = indexed
+                .find_class_by_name(&jtype.type_descriptor().to_string())
+                .expect("can't find class")
+                .expect("class not in the index");
+            assert_eq!(class.id(), class_def.class_idx());
+            let class = indexed
+                .get_class(jtype.id())
+                .expect("can't find class")
+                .expect("class not in the index");
+            assert_eq!(class.jtype(), &jtype);
+        }
+        assert!(indexed
+            .find_class_by_name("Lcom/example/Missing;")
+            .unwrap()
+            .is_none());
+        assert!(indexed.class_def_at(dex.header().class_defs_size()).is_err());
+    }
+
+    #[test]
+    fn test_find_class_def_by_type() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert!(dex.class_defs_by_type.get().is_none());
+        for class_def in dex.class_defs() {
+            let class_def = class_def.expect("can't load class");
+            let found = dex
+                .get_class_def(class_def.class_idx())
+                .expect("can't find class def")
+                .expect("class def not found");
+            assert_eq!(found.class_idx(), class_def.class_idx());
+        }
+        assert_eq!(
+            dex.class_defs_by_type.get().map(Vec::len),
+            Some(dex.header().class_defs_size() as usize)
+        );
+        let java_object = dex
+            .get_type_from_descriptor("Ljava/lang/Object;")
+            .unwrap()
+            .unwrap();
+        assert!(dex.get_class_def(java_object.id()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_class_by_java_name() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let class = dex
+            .find_class_by_java_name("org.adw.launcher.Launcher")
+            .unwrap()
+            .expect("class not found");
+        assert_eq!(class.jtype().type_descriptor(), "Lorg/adw/launcher/Launcher;");
+        let inner = dex
+            .classes()
+            .map(|class| class.expect("can't load class"))
+            .find(|class| class.jtype().type_descriptor().contains('$'))
+            .expect("no inner class");
+        let found = dex
+            .find_class_by_java_name(&inner.jtype().to_java_type())
+            .unwrap()
+            .expect("inner class not found");
+        assert_eq!(found.jtype(), inner.jtype());
+        assert!(dex
+            .find_class_by_java_name("org.adw.launcher.Missing")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_find_classes_matching() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let descriptors = |pattern: &str| -> Vec<String> {
+            dex.find_classes_matching(pattern)
+                .map(|class| class.expect("can't load class").jtype().to_string())
+                .collect()
+        };
+        let all = descriptors("L**");
+        assert_eq!(all.len(), dex.header().class_defs_size() as usize);
+        let launcher = descriptors("Lorg/adw/launcher/*");
+        assert!(launcher.contains(&"Lorg/adw/launcher/Launcher;".to_string()));
+        assert!(launcher.iter().all(|name| !name[18..].contains('/')));
+        assert_eq!(
+            descriptors("Lorg/adw/launcher/Launcher;"),
+            vec!["Lorg/adw/launcher/Launcher;".to_string()]
+        );
+        assert!(descriptors("Lnet/missing/**").is_empty());
+
+        #[cfg(feature = "regex")]
+        {
+            let regex = regex::Regex::new(r"^Lorg/adw/launcher/Launcher\$\d+;$").unwrap();
+            let anonymous: Vec<_> = dex
+                .find_classes_matching_regex(&regex)
+                .map(|class| class.expect("can't load class"))
+                .collect();
+            assert!(!anonymous.is_empty());
+            assert!(anonymous
+                .iter()
+                .all(|class| launcher.contains(&class.jtype().to_string())));
+        }
+    }
+
+    #[test]
+    fn test_find_method() {
+        use crate::method::ResolvedMethod;
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        for method_id in (0..dex.header().method_ids_size()).step_by(7) {
+            let method_id = u64::from(method_id);
+            let reference = crate::smali::method_reference(&dex, method_id).unwrap();
+            let found = dex
+                .find_method(&reference)
+                .unwrap()
+                .expect("method not found");
+            assert_eq!(found.id(), method_id, "{}", reference);
+        }
+        match dex.find_method("Ljava/lang/Object;-><init>()V").unwrap() {
+            Some(ResolvedMethod::Referenced(item)) => {
+                assert_eq!(dex.get_string(item.name_idx()).unwrap(), "<init>")
+            }
+            found => panic!("expected a referenced method, found {:?}", found),
+        }
+        match dex
+            .find_method("Lorg/adw/launcher/Launcher;->onCreate(Landroid/os/Bundle;)V")
+            .unwrap()
+        {
+            Some(ResolvedMethod::Defined(method)) => assert_eq!(method.name(), "onCreate"),
+            found => panic!("expected a defined method, found {:?}", found),
+        }
+        // a name which is a prefix of the names of other methods.
+        assert!(dex
+            .find_method("Lorg/adw/launcher/Launcher;->onCreat(Landroid/os/Bundle;)V")
+            .unwrap()
+            .is_none());
+        assert!(dex
+            .find_method("Lorg/adw/launcher/Launcher;->onCreate(Lnot/Found;)V")
+            .unwrap()
+            .is_none());
+        assert!(dex.find_method("Lorg/adw/launcher/Launcher;->onCreate").is_err());
+        assert!(dex.find_method("Lorg/adw/launcher/Launcher;->a()VV").is_err());
+    }
+
+    #[test]
+    fn test_find_field() {
+        use crate::field::ResolvedField;
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let mut defined = 0;
+        for field in dex.field_ids() {
+            let field = field.unwrap();
+            let class = dex.get_type(field.class_idx().into()).unwrap();
+            let name = dex.get_string(field.name_idx()).unwrap();
+            let found = dex
+                .find_field(class.type_descriptor(), &name)
+                .unwrap()
+                .expect("field not found");
+            assert_eq!(found.id(), field.id(), "{}->{}", class, name);
+            if let ResolvedField::Defined(found) = found {
+                assert_eq!(found.name(), &name);
+                defined += 1;
+            }
+        }
+        assert!(defined > 0);
+        assert!(dex
+            .find_field("Lorg/adw/launcher/Launcher;", "missing")
+            .unwrap()
+            .is_none());
+        assert!(dex.find_field("Lnot/Found;", "field").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_get_type_list() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert!(dex.get_type_list(0).unwrap().is_empty());
+        let interfaces_off = dex
+            .class_defs()
+            .map(|class_def| class_def.unwrap().interfaces_off())
+            .find(|offset| *offset != 0)
+            .expect("no class with interfaces");
+        let interfaces = dex.get_type_list(interfaces_off).unwrap();
+        assert!(!interfaces.is_empty());
+        assert!(interfaces.iter().all(|jtype| jtype.is_class()));
+        assert_eq!(dex.get_interfaces(interfaces_off).unwrap(), interfaces);
+        assert!(dex.get_type_list(interfaces_off + 2).is_err());
+    }
+
+    #[test]
+    fn test_superclass_chain() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let mut longest = 0;
+        for class in dex.classes() {
+            let class = class.expect("can't load class");
+            let chain: Vec<crate::jtype::Type> = dex
+                .superclass_chain(&class)
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(chain.first(), class.superclass_type());
+            let (external, defined) = match chain.split_last() {
+                Some(split) => split,
+                None => continue,
+            };
+            assert!(dex.get_class_def(external.id()).unwrap().is_none());
+            for jtype in defined {
+                assert!(dex.get_class_def(jtype.id()).unwrap().is_some());
+            }
+            longest = longest.max(chain.len());
+        }
+        assert!(longest > 1);
+    }
+
+    #[test]
+    fn test_packages() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let packages: Vec<(String, usize)> = dex.packages().unwrap().collect();
+        assert!(packages.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(
+            packages.iter().map(|(_, count)| count).sum::<usize>(),
+            dex.header().class_defs_size() as usize
+        );
+        let launcher = dex.find_classes_matching("Lorg/adw/launcher/*").count();
+        assert!(packages.contains(&("org.adw.launcher".to_string(), launcher)));
+    }
+
+    #[test]
+    fn test_get_class() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let class = dex
+            .find_class_by_name("Lorg/adw/launcher/Launcher;")
+            .unwrap()
+            .unwrap();
+        let field = class.fields().next().expect("class without fields");
+        let owner = dex.get_class(field.class()).unwrap().expect("class not found");
+        assert_eq!(owner.jtype(), class.jtype());
+        let class_def = dex.get_class_def(class.id()).unwrap().unwrap();
+        assert_eq!(class_def.class_idx(), class.id());
+        let java_object = dex
+            .get_type_from_descriptor("Ljava/lang/Object;")
+            .unwrap()
+            .unwrap();
+        assert!(dex.get_class(java_object.id()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_optional_offsets() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert!(dex.get_annotation_set_ref_list(0).unwrap().annotation_set_list().is_empty());
+        assert!(dex.get_annotation_set_item(0).unwrap().is_empty());
+        assert!(dex.get_debug_info_item(0).unwrap().is_none());
+        assert!(dex.get_code_item(0).unwrap().is_none());
+        assert!(dex.get_class_data(0).unwrap().is_none());
+        assert!(dex.get_interfaces(0).unwrap().is_empty());
+        assert!(dex.get_insns(0).unwrap().is_empty());
+        // the header is never in the data section.
+        assert!(dex.get_annotation_set_ref_list(8).is_err());
+        assert!(dex.get_debug_info_item(8).is_err());
+        assert!(dex.get_code_item(8).is_err());
+    }
+
+    #[test]
+    fn test_member_slots() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        for class in dex.classes() {
+            let class = class.expect("can't load class");
+            for fields in &[class.static_fields(), class.instance_fields()] {
+                for (slot, field) in fields.iter().enumerate() {
+                    assert_eq!(field.slot(), slot);
+                }
+            }
+            for methods in &[class.direct_methods(), class.virtual_methods()] {
+                for (slot, method) in methods.iter().enumerate() {
+                    assert_eq!(method.slot(), slot);
+                }
+            }
+        }
+    }
+
     fn load_example_dex_as_vec<P: AsRef<Path>>(file: P) -> Result<Vec<u8>> {
         let map = unsafe { MmapOptions::new().map(&File::open(file.as_ref())?)? };
         let data = map.to_vec();
@@ -853,4 +2603,834 @@ mod tests {
         let jtype = jtype.unwrap();
         assert_eq!(jtype.type_descriptor(), "Lorg/adw/launcher/Launcher;")
     }
+
+    #[test]
+    fn test_lazy_code() {
+        let bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        // the source of the `Dex` borrows the bytes.
+        let dex = super::DexReader::from_vec(&bytes).expect("cannot load dex");
+        let class = dex
+            .find_class_by_name("Lorg/adw/launcher/Launcher;")
+            .expect("can't load class")
+            .expect("class not found");
+        for method in class.methods() {
+            let code = method.try_code().expect("can't load code");
+            assert_eq!(code.is_some(), method.code_off() != 0);
+            if let Some(code) = code {
+                // parsed once, then borrowed from the method.
+                assert!(std::ptr::eq(code, method.code().unwrap()));
+                assert!(!code.insns().is_empty());
+            }
+        }
+
+        let class = class.into_owned().expect("can't load class");
+        drop(dex);
+        assert!(class
+            .methods()
+            .all(|method| method.code().is_some() == (method.code_off() != 0)));
+    }
+
+    #[test]
+    fn test_lazy_annotations() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let mut annotated = 0;
+        for (class_def, class) in dex.class_defs().zip(dex.classes()) {
+            let class_def = class_def.expect("can't load class def");
+            let class = class.expect("can't load class");
+            let directory = dex
+                .get_annotations_directory_item(class_def.annotations_off)
+                .expect("can't load annotations directory");
+            assert_eq!(
+                format!("{:?}", class.try_annotations().unwrap()),
+                format!("{:?}", directory.class_annotations())
+            );
+            for field_annotations in directory.field_annotations() {
+                let field = class
+                    .fields()
+                    .find(|field| field.id() == field_annotations.field_idx())
+                    .unwrap();
+                assert_eq!(
+                    format!("{:?}", field.annotations()),
+                    format!("{:?}", field_annotations.annotations())
+                );
+                annotated += 1;
+            }
+            for method_annotations in directory.method_annotations() {
+                let method = class
+                    .methods()
+                    .find(|method| method.id() == method_annotations.method_idx())
+                    .unwrap();
+                assert_eq!(
+                    format!("{:?}", method.annotations()),
+                    format!("{:?}", method_annotations.annotations())
+                );
+                annotated += 1;
+            }
+            for parameter_annotations in directory.parameter_annotations() {
+                let method = class
+                    .methods()
+                    .find(|method| method.id() == parameter_annotations.method_idx())
+                    .unwrap();
+                assert_eq!(
+                    format!("{:?}", method.try_param_annotations().unwrap()),
+                    format!("{:?}", parameter_annotations.annotations())
+                );
+            }
+        }
+        assert!(annotated > 0);
+    }
+
+    #[test]
+    fn test_malformed_annotations() {
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let dex = super::DexReader::from_vec(bytes.clone()).expect("cannot open dex file");
+        let class_def = dex
+            .class_defs()
+            .zip(dex.classes())
+            .map(|(class_def, class)| (class_def.unwrap(), class.unwrap()))
+            .find(|(_, class)| class.jtype() == "Lorg/adw/launcher/Launcher$DesktopBinder;")
+            .map(|(class_def, _)| class_def)
+            .expect("class not found");
+        // points the class annotations past the end of the file.
+        let offset = class_def.annotations_off as usize;
+        bytes[offset..offset + 4].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        let checksum = adler32::adler32(&bytes[12..]).unwrap();
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+        let dex = super::DexReader::from_vec(bytes).expect("cannot open dex file");
+        let load = || {
+            dex.find_class_by_name("Lorg/adw/launcher/Launcher$DesktopBinder;")
+                .expect("can't load class")
+                .expect("class not found")
+        };
+
+        let class = load();
+        assert!(class.annotations().is_empty());
+        assert!(class.try_annotations().is_err());
+        assert!(class.annotations().is_empty());
+        assert!(class.enclosing_class().is_err());
+        assert!(class.to_java_name(Default::default()).is_err());
+
+        let class = load();
+        assert!(class.try_annotations().is_err());
+        assert!(class.annotations().is_empty());
+        assert!(class.try_annotations().is_err());
+    }
+
+    #[test]
+    fn test_class_refs() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let mut count = 0;
+        for (class_ref, class) in dex.class_refs().zip(dex.classes()) {
+            let (class_ref, class) = (class_ref.unwrap(), class.unwrap());
+            assert_eq!(class_ref.id(), class.id());
+            assert_eq!(class_ref.jtype().unwrap(), *class.jtype());
+            assert_eq!(class_ref.super_class(), class.super_class());
+            assert_eq!(class_ref.access_flags().unwrap(), class.access_flags());
+            assert_eq!(class_ref.interfaces().unwrap(), *class.interfaces());
+            assert_eq!(
+                class_ref.source_file().unwrap().as_ref(),
+                class.source_file()
+            );
+            let names = |fields: &[Field]| -> Vec<_> {
+                fields.iter().map(|f| f.name().to_string()).collect()
+            };
+            let static_fields = class_ref.static_fields().unwrap();
+            assert_eq!(names(&static_fields), names(class.static_fields()));
+            for (field, loaded) in static_fields.iter().zip(class.static_fields()) {
+                assert_eq!(field.slot(), loaded.slot());
+                assert_eq!(
+                    format!("{:?}", field.initial_value()),
+                    format!("{:?}", loaded.initial_value())
+                );
+            }
+            assert_eq!(
+                names(&class_ref.instance_fields().unwrap()),
+                names(class.instance_fields())
+            );
+            let ids = |methods: &[Method]| -> Vec<_> { methods.iter().map(|m| m.id()).collect() };
+            assert_eq!(
+                ids(&class_ref.direct_methods().unwrap()),
+                ids(class.direct_methods())
+            );
+            assert_eq!(
+                ids(&class_ref.virtual_methods().unwrap()),
+                ids(class.virtual_methods())
+            );
+            assert_eq!(
+                class_ref.annotations().unwrap().len(),
+                class.annotations().len()
+            );
+            assert_eq!(class_ref.load().unwrap().jtype(), class.jtype());
+            count += 1;
+        }
+        assert_eq!(count, dex.header().class_defs_size());
+    }
+
+    #[test]
+    fn test_type_cache() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert!(dex.types.get(&1).is_none());
+        let jtype = dex.get_type(1).expect("can't load type");
+        assert_eq!(dex.types.get(&1), Some(jtype.clone()));
+        assert_eq!(dex.clone().get_type(1).unwrap(), jtype);
+        assert!(dex.get_type(dex.header().type_ids_size()).is_err());
+    }
+
+    #[test]
+    fn test_member_item_caches() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert!(dex.field_items.get(&1).is_none());
+        let field_item = dex.get_field_item(1).expect("can't load field item");
+        assert_eq!(dex.field_items.get(&1), Some(field_item.clone()));
+        assert_eq!(dex.clone().get_field_item(1).unwrap(), field_item);
+        assert!(dex
+            .get_field_item(u64::from(dex.header().field_ids_size()))
+            .is_err());
+
+        assert!(dex.method_items.get(&1).is_none());
+        let method_item = dex.get_method_item(1).expect("can't load method item");
+        assert_eq!(dex.method_items.get(&1), Some(method_item.clone()));
+        assert_eq!(dex.clone().get_method_item(1).unwrap(), method_item);
+        assert!(dex
+            .get_method_item(u64::from(dex.header().method_ids_size()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_cache_config() {
+        use crate::{CacheConfig, CacheSize};
+
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert_eq!(dex.provenance().string_cache_size(), 4096);
+        let config = CacheConfig::all(CacheSize::Disabled).with_types(CacheSize::Unbounded);
+        let configured = dex.clone().with_cache_config(config);
+        let jtype = configured.get_type(1).unwrap();
+        assert_eq!(configured.get_string(1).unwrap(), dex.get_string(1).unwrap());
+        assert_eq!(configured.get_method_item(1).unwrap(), dex.get_method_item(1).unwrap());
+        assert!(configured.method_items.get(&1).is_none());
+        assert_eq!(configured.types.get(&1), Some(jtype));
+        assert!(dex.types.get(&1).is_none());
+        assert_eq!(configured.provenance().string_cache_size(), 0);
+
+        let unbounded = dex.with_cache_config(CacheConfig::all(CacheSize::Unbounded));
+        for jtype in unbounded.types() {
+            jtype.unwrap();
+        }
+        let len = unbounded.header().type_ids_size();
+        assert!((0..len).all(|type_id| unbounded.types.get(&type_id).is_some()));
+        assert_eq!(unbounded.provenance().string_cache_size(), usize::MAX);
+    }
+
+    #[test]
+    fn test_section_bytes() {
+        use crate::ItemType;
+
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let bytes = std::fs::read("resources/classes.dex").unwrap();
+        let header = dex.header();
+        assert_eq!(dex.section_bytes(ItemType::Header), Some(&bytes[..0x70]));
+        let start = header.method_ids_off() as usize;
+        let end = start + 8 * header.method_ids_size() as usize;
+        assert_eq!(dex.section_bytes(ItemType::MethodIdItem), Some(&bytes[start..end]));
+        let map_list = dex.section_bytes(ItemType::MapList).unwrap();
+        let start = header.map_off() as usize;
+        let end = start + 4 + 12 * dex.map_list().items().len();
+        assert_eq!(map_list, &bytes[start..end]);
+
+        let code = dex.section_bytes(ItemType::CodeItem).unwrap();
+        let sizes = dex.section_sizes();
+        assert_eq!(code.len(), sizes.get(ItemType::CodeItem).unwrap().size());
+        assert!(dex.section_bytes(ItemType::CallSiteIdItem).is_none());
+    }
+
+    #[test]
+    fn test_link_data() {
+        let with_link = |link_size: u32, link_off: u32| {
+            let mut bytes = std::fs::read("resources/classes.dex").unwrap();
+            bytes[0x2c..0x30].copy_from_slice(&link_size.to_le_bytes());
+            bytes[0x30..0x34].copy_from_slice(&link_off.to_le_bytes());
+            let checksum = adler32::adler32(&bytes[12..]).unwrap();
+            bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+            super::DexReader::from_vec(bytes).expect("cannot load dex")
+        };
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert_eq!(dex.header().link_size(), 0);
+        assert!(dex.link_data().is_none());
+
+        let bytes = std::fs::read("resources/classes.dex").unwrap();
+        let file_size = bytes.len() as u32;
+        let dex = with_link(16, file_size - 16);
+        assert_eq!(dex.link_data(), Some(&bytes[bytes.len() - 16..]));
+        assert!(with_link(32, file_size - 16).link_data().is_none());
+        assert!(with_link(16, u32::MAX).link_data().is_none());
+    }
+
+    #[test]
+    fn test_parse_map_items() {
+        use crate::{uint, ItemType, SectionItems};
+
+        fn offsets<T>(items: Vec<(uint, T)>) -> (usize, Vec<uint>) {
+            (
+                items.len(),
+                items.iter().map(|(offset, _)| *offset).collect(),
+            )
+        }
+
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let strings: Vec<_> = dex.strings().map(Result::unwrap).collect();
+        for item in dex.map_list().items() {
+            let items = dex.map_list().parse_item(&dex, *item).unwrap();
+            let (len, offsets) = match items {
+                SectionItems::Header(_) | SectionItems::MapList(_) => continue,
+                SectionItems::StringIds(items) => {
+                    assert_eq!(items, strings);
+                    (items.len(), vec![])
+                }
+                SectionItems::StringData(items) => {
+                    let mut data: Vec<_> = items.iter().map(|(_, s)| s.clone()).collect();
+                    data.sort();
+                    assert_eq!(data, strings);
+                    (items.len(), items.iter().map(|(off, _)| *off).collect())
+                }
+                SectionItems::TypeIds(items) => (items.len(), vec![]),
+                SectionItems::ProtoIds(items) => (items.len(), vec![]),
+                SectionItems::FieldIds(items) => (items.len(), vec![]),
+                SectionItems::MethodIds(items) => (items.len(), vec![]),
+                SectionItems::ClassDefs(items) => (items.len(), vec![]),
+                SectionItems::CallSiteIds(items) => (items.len(), vec![]),
+                SectionItems::MethodHandles(items) => (items.len(), vec![]),
+                SectionItems::TypeLists(items) => offsets(items),
+                SectionItems::AnnotationSetRefLists(items) => offsets(items),
+                SectionItems::AnnotationSets(items) => offsets(items),
+                SectionItems::ClassData(items) => offsets(items),
+                SectionItems::CodeItems(items) => offsets(items),
+                SectionItems::DebugInfo(items) => offsets(items),
+                SectionItems::Annotations(items) => offsets(items),
+                SectionItems::EncodedArrays(items) => offsets(items),
+                SectionItems::AnnotationsDirectories(items) => offsets(items),
+            };
+            assert_eq!(len, item.size() as usize, "{:?}", item.item_type());
+            if let Some(first) = offsets.first() {
+                assert_eq!(*first, item.offset());
+            }
+        }
+
+        let code_items = dex.map_list().get(ItemType::CodeItem).unwrap();
+        let code_offsets: Vec<_> = match dex.map_list().parse_item(&dex, code_items).unwrap() {
+            SectionItems::CodeItems(items) => items.into_iter().map(|(off, _)| off).collect(),
+            _ => unreachable!(),
+        };
+        for class_def in dex.class_defs() {
+            let class_data_off = class_def.unwrap().class_data_off();
+            let class_data = match dex.get_class_data(class_data_off).unwrap() {
+                Some(class_data) => class_data,
+                None => continue,
+            };
+            let methods = class_data
+                .direct_methods()
+                .into_iter()
+                .chain(class_data.virtual_methods())
+                .flat_map(|methods| methods.inner());
+            for method in methods.filter(|method| *method.code_offset() != 0) {
+                assert!(code_offsets.contains(&(*method.code_offset() as uint)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_prototype_cache() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert!(dex.protos.get(&1).is_none());
+        let prototype = dex.get_prototype(1).expect("can't load prototype");
+        let proto_item = dex.get_proto_item(1).unwrap();
+        assert_eq!(prototype.shorty, dex.get_string(proto_item.shorty()).unwrap());
+        assert_eq!(prototype.return_type, dex.get_type(proto_item.return_type()).unwrap());
+        assert_eq!(
+            prototype.params,
+            dex.get_interfaces(proto_item.params_off()).unwrap()
+        );
+        assert!(std::sync::Arc::ptr_eq(&dex.protos.get(&1).unwrap(), &prototype));
+        assert!(std::sync::Arc::ptr_eq(&dex.clone().get_prototype(1).unwrap(), &prototype));
+        assert!(dex
+            .get_prototype(u64::from(dex.header().proto_ids_size()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_provenance() {
+        let sha256 = "c0348f7cbd8ba2da6d034448915d6e67dc9518d4c7f43a9af8159700b9c722a2";
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let provenance = dex.provenance();
+        assert_eq!(provenance.path(), Some(Path::new("resources/classes.dex")));
+        assert_eq!(provenance.container(), None);
+        assert_eq!(provenance.sha256(), sha256);
+        assert!(provenance.memory_mapped());
+        assert_eq!(dex.provenance(), provenance);
+
+        let bytes = std::fs::read("resources/classes.dex").expect("cannot read dex file");
+        let dex = super::DexReader::from_vec(bytes)
+            .expect("cannot load dex")
+            .with_container("classes.dex");
+        let provenance = dex.provenance();
+        assert_eq!(provenance.path(), None);
+        assert_eq!(provenance.container(), Some("classes.dex"));
+        assert_eq!(provenance.sha256(), sha256);
+        assert!(!provenance.memory_mapped());
+        assert!(provenance
+            .to_json()
+            .starts_with("{\"path\":null,\"container\":\"classes.dex\",\"sha256\":"));
+    }
+
+    #[test]
+    fn test_get_insns() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        let mut count = 0;
+        for class_def in dex.class_defs() {
+            let class_def = class_def.expect("can't load class def");
+            let class_data = match dex.get_class_data(class_def.class_data_off()).unwrap() {
+                Some(class_data) => class_data,
+                None => continue,
+            };
+            let methods = class_data
+                .direct_methods()
+                .into_iter()
+                .chain(class_data.virtual_methods())
+                .flat_map(|methods| methods.inner());
+            for method in methods {
+                let insns = dex.get_insns(*method.code_offset()).expect("can't read insns");
+                let code = dex.get_code_item(*method.code_offset()).unwrap();
+                assert_eq!(&*insns, code.as_ref().map_or(&[][..], |c| c.insns()));
+                if cfg!(target_endian = "little") && !insns.is_empty() {
+                    assert!(matches!(insns, std::borrow::Cow::Borrowed(_)));
+                    // the code item borrows the same code units from the source
+                    assert_eq!(code.unwrap().insns().as_ptr(), insns.as_ptr());
+                }
+                count += 1;
+            }
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn test_get_call_site_without_call_sites() {
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert!(dex.get_call_site(0).is_err());
+        assert!(dex.get_call_site_item(0).is_err());
+        assert_eq!(dex.call_sites().count(), 0);
+    }
+
+    #[test]
+    fn test_version_gating() {
+        use crate::{error::Error, ItemType};
+
+        let dex =
+            super::DexReader::from_file("resources/classes.dex").expect("cannot open dex file");
+        assert_eq!(dex.header().version(), Some(35));
+        let error = dex.get_method_handle_item(0).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::UnsupportedVersion {
+                required: 38,
+                found: 35,
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Method handles require dex version 038, the file is version 035"
+        );
+        assert!(matches!(
+            dex.get_call_site(0),
+            Err(Error::UnsupportedVersion { .. })
+        ));
+        assert!(dex.check_version("Strings", 35).is_ok());
+
+        // the magic value isn't covered by the checksum.
+        let mut bytes = std::fs::read("resources/classes.dex").unwrap();
+        bytes[4..7].copy_from_slice(b"038");
+        let dex = super::DexReader::from_vec(bytes).expect("cannot load dex");
+        assert!(matches!(
+            dex.get_method_handle_item(0),
+            Err(Error::IndexOutOfBounds {
+                section: ItemType::MethodHandleItem,
+                index: 0
+            })
+        ));
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_from_apk() {
+        use std::io::Write;
+
+        let data = load_example_dex_as_vec("resources/classes.dex").expect("failed to read dex");
+        let dir = tempfile::TempDir::new().expect("cannot create temporary directory");
+        let path = dir.path().join("app.apk");
+        let mut apk = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default();
+        for name in &["classes4.dex", "AndroidManifest.xml", "classes2.dex", "classes.dex"] {
+            apk.start_file(*name, options).unwrap();
+            apk.write_all(&data).unwrap();
+        }
+        for name in &["classes1.dex", "assets/classes3.dex", "classes03.dex"] {
+            apk.start_file(*name, options).unwrap();
+            apk.write_all(b"not a dex").unwrap();
+        }
+        apk.finish().unwrap();
+
+        let dexes = super::DexReader::from_apk(&path).expect("failed to read apk");
+        let containers: Vec<_> = dexes
+            .iter()
+            .map(|dex| dex.provenance().container().unwrap().to_string())
+            .collect();
+        // classes4.dex isn't loaded without a classes3.dex
+        assert_eq!(containers, ["classes.dex", "classes2.dex"]);
+        assert_eq!(dexes[0].provenance().path(), Some(path.as_path()));
+        assert!(!dexes[0].provenance().memory_mapped());
+        assert_eq!(dexes[1].header().class_defs_size(), dexes[0].header().class_defs_size());
+
+        assert!(super::DexReader::from_apk("resources/classes.dex").is_err());
+    }
+
+    #[test]
+    fn test_checksum_and_signature() {
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let dex = super::DexReader::from_vec(bytes.clone()).expect("cannot read dex");
+        assert_eq!(dex.compute_checksum(), dex.header().checksum());
+        assert!(dex.is_checksum_valid());
+        assert!(dex.is_signature_valid());
+
+        // changes the last byte and fixes the checksum, but not the signature.
+        *bytes.last_mut().unwrap() ^= 1;
+        let checksum = adler32::adler32(&bytes[12..]).unwrap();
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+        let dex = super::DexReader::from_vec(bytes).expect("cannot read dex");
+        assert!(dex.is_checksum_valid());
+        assert!(!dex.is_signature_valid());
+        assert_ne!(dex.compute_signature(), dex.header().signature());
+    }
+
+    #[test]
+    fn test_container() {
+        // two empty version 041 dex files, whose map lists only hold the header.
+        let dex_size = 0x78 + 16;
+        let mut container = vec![0u8; 2 * dex_size];
+        for start in &[0, dex_size] {
+            let put = |dex: &mut [u8], offset: usize, value: usize| {
+                dex[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes())
+            };
+            let dex = &mut container[*start..*start + dex_size];
+            dex[..8].copy_from_slice(b"dex\n041\0");
+            put(dex, 32, dex_size);
+            put(dex, 36, 0x78);
+            put(dex, 40, 0x1234_5678);
+            // map_off is relative to the container, which holds the shared data section.
+            put(dex, 52, *start + 0x78);
+            put(dex, 0x70, 2 * dex_size);
+            put(dex, 0x74, *start);
+            put(dex, 0x78, 1);
+            put(dex, 0x80, 1);
+            let checksum = adler32::adler32(&dex[12..]).unwrap() as usize;
+            put(dex, 8, checksum);
+        }
+
+        let multidex =
+            super::DexReader::from_container_vec(container.clone()).expect("cannot read container");
+        let dexes = multidex.dexes();
+        assert_eq!(dexes.len(), 2);
+        assert_eq!(dexes[1].header_offset(), dex_size as u32);
+        assert_eq!(dexes[1].container_size(), Some(2 * dex_size as u32));
+        assert_eq!(dexes[1].header().version(), Some(41));
+        assert!(dexes[1].map_list().get(super::ItemType::Header).is_some());
+        assert_eq!(multidex.classes().count(), 0);
+        assert!(dexes[1].is_checksum_valid());
+        let first = super::DexReader::from_vec(container.clone()).expect("cannot read dex");
+        assert_eq!(first.header_offset(), 0);
+
+        let dexes = super::DexReader::from_container_file("resources/classes.dex")
+            .expect("cannot open dex file");
+        assert_eq!(dexes.dexes().len(), 1);
+        assert_eq!(dexes.dexes()[0].container_size(), None);
+
+        container[dex_size + 0x74] = 0;
+        assert!(super::DexReader::from_container_vec(container).is_err());
+    }
+
+    /// Converts the little endian dex file `le` to the byte-swapped form, by reversing the
+    /// bytes of each of its fixed-width values.
+    fn byte_swap(le: &[u8]) -> Vec<u8> {
+        use scroll::{Pread, LE};
+        let read = |offset: usize| le.pread_with::<u32>(offset, LE).unwrap() as usize;
+        let mut be = le.to_vec();
+        let mut swap = |offset: usize, widths: &[usize]| {
+            let mut offset = offset;
+            for width in widths {
+                be[offset..offset + width].reverse();
+                offset += width;
+            }
+            offset
+        };
+        swap(8, &[4]);
+        swap(32, &[4; 20]);
+        let map_off = read(52);
+        let mut entry = swap(map_off, &[4]);
+        for _ in 0..read(map_off) {
+            let item_type = le.pread_with::<u16>(entry, LE).unwrap();
+            let (size, mut offset) = (read(entry + 4), read(entry + 8));
+            entry = swap(entry, &[2, 2, 4, 4]);
+            let widths: &[usize] = match item_type {
+                0x1 | 0x2 | 0x7 => &[4],
+                0x3 => &[4, 4, 4],
+                0x4 | 0x5 => &[2, 2, 4],
+                0x6 => &[4; 8],
+                0x8 => &[2, 2, 2, 2],
+                0x1001 => {
+                    for _ in 0..size {
+                        let len = read(offset);
+                        offset = swap(offset, &[4]);
+                        offset = (swap(offset, &vec![2; len]) + 3) & !3;
+                    }
+                    continue;
+                }
+                0x1002 | 0x1003 => {
+                    for _ in 0..size {
+                        let len = read(offset);
+                        offset = swap(offset, &vec![4; len + 1]);
+                    }
+                    continue;
+                }
+                0x2006 => {
+                    for _ in 0..size {
+                        let len = read(offset + 4) + read(offset + 8) + read(offset + 12);
+                        offset = swap(offset, &vec![4; 4 + 2 * len]);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            for _ in 0..size {
+                offset = swap(offset, widths);
+            }
+        }
+
+        let dex = super::DexReader::from_vec(le).expect("cannot read dex");
+        for class_def in dex.class_defs() {
+            let class_data_off = class_def.unwrap().class_data_off();
+            let class_data = match dex.get_class_data(class_data_off).unwrap() {
+                Some(class_data) => class_data,
+                None => continue,
+            };
+            let methods = class_data
+                .direct_methods()
+                .into_iter()
+                .chain(class_data.virtual_methods())
+                .flat_map(|methods| methods.inner());
+            for method in methods {
+                let code_off = *method.code_offset() as usize;
+                if code_off == 0 {
+                    continue;
+                }
+                let tries = le.pread_with::<u16>(code_off + 6, LE).unwrap() as usize;
+                let insns = read(code_off + 12);
+                let offset = swap(code_off, &[2, 2, 2, 2, 4, 4]);
+                let offset = swap(offset, &vec![2; insns]);
+                if tries > 0 {
+                    swap((offset + 3) & !3, &[4, 2, 2].repeat(tries));
+                }
+            }
+        }
+        let checksum = adler32::adler32(&be[12..]).unwrap();
+        be[8..12].copy_from_slice(&checksum.to_be_bytes());
+        be
+    }
+
+    #[test]
+    fn test_big_endian() {
+        let le = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let be = byte_swap(&le);
+        assert_eq!(&be[40..44], &[0x12, 0x34, 0x56, 0x78]);
+        let le_dex = super::DexReader::from_vec(le).expect("cannot read dex");
+        let be_dex = super::DexReader::from_vec(be).expect("cannot read big endian dex");
+        assert_eq!(le_dex.get_endian(), scroll::LE);
+        assert_eq!(be_dex.get_endian(), scroll::BE);
+        assert!(be_dex.is_checksum_valid());
+        assert_eq!(crate::verifier::verify_structure(&be_dex).unwrap(), &[]);
+        let (le_header, be_header) = (le_dex.header(), be_dex.header());
+        assert_eq!(be_header.map_off(), le_header.map_off());
+        assert_eq!(be_header.class_defs_size(), le_header.class_defs_size());
+        assert_eq!(be_header.data_section(), le_header.data_section());
+
+        let le_classes = le_dex.classes().collect::<Result<Vec<_>>>().unwrap();
+        let be_classes = be_dex.classes().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(format!("{:?}", be_classes), format!("{:?}", le_classes));
+        for (le_class, be_class) in le_classes.iter().zip(&be_classes) {
+            for (le_method, be_method) in le_class.methods().zip(be_class.methods()) {
+                assert_eq!(
+                    format!("{:?}", be_method.annotations()),
+                    format!("{:?}", le_method.annotations())
+                );
+                if le_method.code().is_none() {
+                    continue;
+                }
+                let code_off = le_method.code_off();
+                assert_eq!(
+                    be_dex.get_insns(code_off).unwrap(),
+                    le_dex.get_insns(code_off).unwrap()
+                );
+                assert_eq!(
+                    crate::smali::disassemble(&be_dex, be_method).unwrap(),
+                    crate::smali::disassemble(&le_dex, le_method).unwrap()
+                );
+            }
+        }
+    }
+
+    /// Runs the parsers on `bytes`, errors are expected but nothing may panic.
+    fn parse_all(bytes: Vec<u8>) {
+        let dex = match super::DexReader::from_vec(bytes) {
+            Ok(dex) => dex,
+            Err(_) => return,
+        };
+        dex.strings().for_each(drop);
+        dex.types().for_each(drop);
+        dex.proto_ids().for_each(drop);
+        dex.field_ids().for_each(drop);
+        dex.method_ids().for_each(drop);
+        dex.method_handles().for_each(drop);
+        let _ = crate::verifier::verify_structure(&dex);
+        for class in dex.classes().take(20).flatten() {
+            for method in class.methods() {
+                let _ = method.try_code();
+                let _ = crate::smali::disassemble(&dex, method);
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        let bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let with_checksum = |mut bytes: Vec<u8>| {
+            if bytes.len() > 12 {
+                let checksum = adler32::adler32(&bytes[12..]).unwrap();
+                bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+            }
+            bytes
+        };
+        for len in (0..0x100).chain((0x100..bytes.len()).step_by(0x1111)) {
+            parse_all(with_checksum(bytes[..len].to_vec()));
+            let _ = super::DexReader::from_odex_vec(&bytes[..len]);
+        }
+        for offset in (32..0x70).step_by(4) {
+            for value in [0x7fff_ffffu32, 0xffff_fff0, bytes.len() as u32 - 2] {
+                let mut bytes = bytes.clone();
+                bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                parse_all(with_checksum(bytes));
+            }
+        }
+        let mut strings = bytes.clone();
+        strings[56..60].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+        assert!(matches!(
+            super::DexReader::from_vec(with_checksum(strings)),
+            Err(super::Error::BadOffset(0x70, _))
+        ));
+
+        // overwrites a few words, with a xorshift generator so the test is reproducible.
+        let mut seed = 0x2545_f491u32;
+        for _ in 0..256 {
+            let mut bytes = bytes.clone();
+            for _ in 0..(seed % 8 + 1) {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                let offset = seed as usize % (bytes.len() - 4);
+                bytes[offset..offset + 4].copy_from_slice(&seed.to_le_bytes());
+            }
+            parse_all(with_checksum(bytes));
+        }
+    }
+
+    #[test]
+    fn test_error_context() {
+        use scroll::{Pread, LE};
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let dex = super::DexReader::from_vec(bytes.clone()).expect("cannot read dex");
+        let class_def = dex.class_defs().next().unwrap().unwrap();
+        let type_id_off = dex.header().type_ids_off() as usize + class_def.class_idx() as usize * 4;
+        let string_id: u32 = bytes.pread_with(type_id_off, LE).unwrap();
+        // the string data of the name of the first class is in the header.
+        let string_id_off = dex.header().string_ids_off() as usize + string_id as usize * 4;
+        bytes[string_id_off..string_id_off + 4].copy_from_slice(&0x10u32.to_le_bytes());
+        let checksum = adler32::adler32(&bytes[12..]).unwrap();
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+
+        let dex = super::DexReader::from_vec(bytes).expect("cannot read dex");
+        let error = dex.classes().next().unwrap().unwrap_err();
+        assert!(matches!(error.root(), super::Error::BadOffset(0x10, _)));
+        let context = error.context().expect("error without context");
+        assert_eq!(context.id(), Some(("string_id", u64::from(string_id))));
+        assert_eq!(context.item_type(), Some(super::ItemType::TypeIdItem));
+        assert_eq!(context.offset(), Some(type_id_off));
+        assert_eq!(error.offset(), Some(type_id_off));
+        let message = format!("string_id {} in TypeIdItem at {:#x}", string_id, type_id_off);
+        assert!(error.to_string().ends_with(&format!("({})", message)), "{}", error);
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        use super::{Error, ItemType};
+        use std::error::Error as _;
+        let bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        assert!(matches!(
+            super::DexReader::from_vec(b"not a dex file"),
+            Err(Error::BadMagic { found }) if found == b"not a de"
+        ));
+        let mut corrupted = bytes.clone();
+        corrupted[40] = 0;
+        assert!(matches!(
+            super::DexReader::from_vec(&corrupted),
+            Err(Error::BadEndianTag {
+                found: [0, 0x56, 0x34, 0x12]
+            })
+        ));
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        let expected = super::DexReader::from_vec(&bytes).unwrap().header().checksum();
+        match super::DexReader::from_vec(&corrupted) {
+            Err(Error::ChecksumMismatch {
+                expected: header,
+                found,
+            }) => {
+                assert_eq!(header, expected);
+                assert_eq!(found, adler32::adler32(&corrupted[12..]).unwrap());
+            }
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+
+        let dex = super::DexReader::from_vec(&bytes).unwrap();
+        let len = dex.header().method_ids_size();
+        let error = dex.get_method_item(len.into()).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::IndexOutOfBounds {
+                section: ItemType::MethodIdItem,
+                index,
+            } if index == u64::from(len)
+        ));
+        assert_eq!(error.to_string(), format!("Invalid method id: {}", len));
+        assert!(error.source().is_none());
+        let error = Error::from(std::io::Error::other("disk"));
+        assert_eq!(error.source().unwrap().to_string(), "disk");
+    }
 }
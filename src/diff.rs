@@ -0,0 +1,417 @@
+//! Reports of the changes between two versions of an app, each given as the `Dex` files of
+//! its APK (`classes.dex`, `classes2.dex`...).
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use getset::{CopyGetters, Getters};
+
+use crate::{
+    deps, instruction::IndexType, jtype::TypeId, provenance::Provenance, smali, utils::quote, Dex,
+    Result,
+};
+
+/// Methods guarded by a permission, as `class->name`, with the permission.
+const SENSITIVE_APIS: &[(&str, &str)] = &[
+    (
+        "Landroid/accounts/AccountManager;->getAccounts",
+        "GET_ACCOUNTS",
+    ),
+    (
+        "Landroid/bluetooth/BluetoothAdapter;->startDiscovery",
+        "BLUETOOTH_ADMIN",
+    ),
+    ("Landroid/hardware/Camera;->open", "CAMERA"),
+    (
+        "Landroid/hardware/camera2/CameraManager;->openCamera",
+        "CAMERA",
+    ),
+    (
+        "Landroid/location/LocationManager;->getLastKnownLocation",
+        "ACCESS_FINE_LOCATION",
+    ),
+    (
+        "Landroid/location/LocationManager;->requestLocationUpdates",
+        "ACCESS_FINE_LOCATION",
+    ),
+    ("Landroid/media/AudioRecord;-><init>", "RECORD_AUDIO"),
+    (
+        "Landroid/media/MediaRecorder;->setAudioSource",
+        "RECORD_AUDIO",
+    ),
+    (
+        "Landroid/net/wifi/WifiManager;->getScanResults",
+        "ACCESS_FINE_LOCATION",
+    ),
+    (
+        "Landroid/telephony/SmsManager;->sendMultipartTextMessage",
+        "SEND_SMS",
+    ),
+    (
+        "Landroid/telephony/SmsManager;->sendTextMessage",
+        "SEND_SMS",
+    ),
+    (
+        "Landroid/telephony/TelephonyManager;->getDeviceId",
+        "READ_PHONE_STATE",
+    ),
+    (
+        "Landroid/telephony/TelephonyManager;->getImei",
+        "READ_PHONE_STATE",
+    ),
+    (
+        "Landroid/telephony/TelephonyManager;->getLine1Number",
+        "READ_PHONE_NUMBERS",
+    ),
+    (
+        "Landroid/telephony/TelephonyManager;->getSimSerialNumber",
+        "READ_PHONE_STATE",
+    ),
+    (
+        "Landroid/telephony/TelephonyManager;->getSubscriberId",
+        "READ_PHONE_STATE",
+    ),
+    ("Ljava/net/Socket;-><init>", "INTERNET"),
+    ("Ljava/net/URL;->openConnection", "INTERNET"),
+];
+
+/// Kind of a `Change`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    /// The class is only in the new version.
+    ClassAdded,
+    /// The class is only in the old version.
+    ClassRemoved,
+    /// The method is only in the new version of the class. A method whose signature
+    /// changed is reported as removed and added.
+    MethodAdded,
+    /// The method is only in the old version of the class.
+    MethodRemoved,
+    /// The native method is only in the new version of the class.
+    NativeMethodAdded,
+    /// The class calls a method guarded by `permission` which it didn't call before.
+    SensitiveCallAdded {
+        /// Name of the permission, like `READ_PHONE_STATE`.
+        permission: &'static str,
+    },
+}
+
+impl ChangeKind {
+    fn name(self) -> &'static str {
+        match self {
+            ChangeKind::ClassAdded => "class-added",
+            ChangeKind::ClassRemoved => "class-removed",
+            ChangeKind::MethodAdded => "method-added",
+            ChangeKind::MethodRemoved => "method-removed",
+            ChangeKind::NativeMethodAdded => "native-method-added",
+            ChangeKind::SensitiveCallAdded { .. } => "sensitive-call-added",
+        }
+    }
+
+    fn permission(self) -> Option<&'static str> {
+        match self {
+            ChangeKind::SensitiveCallAdded { permission } => Some(permission),
+            _ => None,
+        }
+    }
+}
+
+/// A change to a class between two versions of an app.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Getters, CopyGetters)]
+pub struct Change {
+    /// The class, in Java notation.
+    #[get = "pub"]
+    class: String,
+    /// What changed.
+    #[get_copy = "pub"]
+    kind: ChangeKind,
+    /// The method for method changes, like `run(I)V`, and the called method for sensitive
+    /// calls, like `Landroid/telephony/TelephonyManager;->getDeviceId`.
+    member: Option<String>,
+}
+
+impl Change {
+    /// The method for method changes, like `run(I)V`, and the called method for sensitive
+    /// calls, like `Landroid/telephony/TelephonyManager;->getDeviceId`.
+    pub fn member(&self) -> Option<&str> {
+        self.member.as_deref()
+    }
+
+    fn to_json(&self) -> String {
+        let optional = |value: Option<&str>| value.map_or("null".to_string(), quote);
+        format!(
+            "{{\"kind\":\"{}\",\"class\":{},\"member\":{},\"permission\":{}}}",
+            self.kind.name(),
+            quote(&self.class),
+            optional(self.member()),
+            optional(self.kind.permission())
+        )
+    }
+
+    fn to_markdown(&self) -> String {
+        let member = self.member().unwrap_or_default();
+        match self.kind {
+            ChangeKind::ClassAdded => format!("Added class `{}`", self.class),
+            ChangeKind::ClassRemoved => format!("Removed class `{}`", self.class),
+            ChangeKind::MethodAdded => format!("Added method `{}.{}`", self.class, member),
+            ChangeKind::MethodRemoved => format!("Removed method `{}.{}`", self.class, member),
+            ChangeKind::NativeMethodAdded => {
+                format!("Added native method `{}.{}`", self.class, member)
+            }
+            ChangeKind::SensitiveCallAdded { permission } => format!(
+                "Added call to `{}` ({}) in `{}`",
+                member, permission, self.class
+            ),
+        }
+    }
+}
+
+/// Changes between two versions of an app grouped by package, see `apk_report`.
+#[derive(Debug, Clone, Default, PartialEq, Getters)]
+pub struct ApkReport {
+    /// Changes by package, in Java notation. Classes in the default package are under `""`.
+    #[get = "pub"]
+    packages: BTreeMap<String, Vec<Change>>,
+    /// Provenance of the `Dex` files of the old version.
+    #[get = "pub"]
+    old: Vec<Provenance>,
+    /// Provenance of the `Dex` files of the new version.
+    #[get = "pub"]
+    new: Vec<Provenance>,
+}
+
+impl ApkReport {
+    /// All the changes, by package.
+    pub fn changes(&self) -> impl Iterator<Item = &Change> + '_ {
+        self.packages.values().flatten()
+    }
+
+    /// Returns `true` if the versions have the same classes and methods.
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// The report as a JSON object with `old` and `new` arrays of provenances and a
+    /// `packages` array of objects with the `package` name and its `changes`.
+    pub fn to_json(&self) -> String {
+        let provenances = |provenances: &[Provenance]| {
+            provenances
+                .iter()
+                .map(Provenance::to_json)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let packages: Vec<String> = self
+            .packages
+            .iter()
+            .map(|(package, changes)| {
+                let changes: Vec<String> = changes.iter().map(Change::to_json).collect();
+                format!(
+                    "{{\"package\":{},\"changes\":[{}]}}",
+                    quote(package),
+                    changes.join(",")
+                )
+            })
+            .collect();
+        format!(
+            "{{\"old\":[{}],\"new\":[{}],\"packages\":[{}]}}",
+            provenances(&self.old),
+            provenances(&self.new),
+            packages.join(",")
+        )
+    }
+
+    /// The report as a markdown document with a section per package.
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::from("# APK diff\n");
+        if self.is_empty() {
+            out.push_str("\nNo changes.\n");
+        }
+        for (package, changes) in &self.packages {
+            let package = if package.is_empty() {
+                "(default package)"
+            } else {
+                package
+            };
+            let _ = writeln!(out, "\n## `{}`\n", package);
+            for change in changes {
+                let _ = writeln!(out, "- {}", change.to_markdown());
+            }
+        }
+        out
+    }
+}
+
+/// The parts of a class which are compared.
+#[derive(Default)]
+struct ClassSummary {
+    methods: BTreeSet<String>,
+    native_methods: BTreeSet<String>,
+    /// Sensitive methods called by the class, with their permission.
+    sensitive_calls: BTreeMap<String, &'static str>,
+}
+
+/// Summaries of the classes of all the `Dex` files of an app, by class name. A class defined
+/// in more than one file is taken from the first one, like the runtime does.
+fn summarize<T: AsRef<[u8]>>(dexes: &[Dex<T>]) -> Result<BTreeMap<String, ClassSummary>> {
+    let mut classes = BTreeMap::new();
+    for dex in dexes {
+        for class in dex.classes() {
+            let class = class?;
+            let name = class.jtype().to_java_type();
+            if classes.contains_key(&name) {
+                continue;
+            }
+            let mut summary = ClassSummary::default();
+            for method in class.methods() {
+                let signature = format!(
+                    "{}{}",
+                    method.name(),
+                    smali::signature(method.params(), method.return_type())
+                );
+                if method.is_native() {
+                    summary.native_methods.insert(signature.clone());
+                }
+                summary.methods.insert(signature);
+                let code = match method.try_code()? {
+                    Some(code) => code,
+                    None => continue,
+                };
+                for instruction in code.instructions() {
+                    let instruction = instruction?;
+                    let index = match instruction.index() {
+                        Some(index) if instruction.opcode().index_type() == IndexType::Method => {
+                            index
+                        }
+                        _ => continue,
+                    };
+                    let method = dex.get_method_item(index)?;
+                    let api = format!(
+                        "{}->{}",
+                        dex.get_type(TypeId::from(method.class_idx()))?
+                            .type_descriptor(),
+                        dex.get_string(method.name_idx())?
+                    );
+                    if let Some((_, permission)) = SENSITIVE_APIS
+                        .iter()
+                        .find(|(sensitive, _)| *sensitive == api)
+                    {
+                        summary.sensitive_calls.insert(api, permission);
+                    }
+                }
+            }
+            classes.insert(name, summary);
+        }
+    }
+    Ok(classes)
+}
+
+/// Compares the classes of two versions of an app, each given as the `Dex` files of its APK.
+/// Reports the added and removed classes and methods, the added native methods and the calls
+/// to methods guarded by permissions which weren't made before, grouped by package.
+pub fn apk_report<T, U>(old: &[Dex<T>], new: &[Dex<U>]) -> Result<ApkReport>
+where
+    T: AsRef<[u8]>,
+    U: AsRef<[u8]>,
+{
+    let old_classes = summarize(old)?;
+    let new_classes = summarize(new)?;
+    let empty = ClassSummary::default();
+    let mut changes = BTreeSet::new();
+    let mut change = |class: &str, kind, member: Option<&String>| {
+        changes.insert(Change {
+            class: class.to_string(),
+            kind,
+            member: member.cloned(),
+        });
+    };
+    for name in old_classes
+        .keys()
+        .filter(|name| !new_classes.contains_key(*name))
+    {
+        change(name, ChangeKind::ClassRemoved, None);
+    }
+    for (name, summary) in &new_classes {
+        let old_summary = match old_classes.get(name) {
+            Some(old_summary) => old_summary,
+            None => {
+                change(name, ChangeKind::ClassAdded, None);
+                &empty
+            }
+        };
+        if old_classes.contains_key(name) {
+            for method in summary.methods.difference(&old_summary.methods) {
+                change(name, ChangeKind::MethodAdded, Some(method));
+            }
+            for method in old_summary.methods.difference(&summary.methods) {
+                change(name, ChangeKind::MethodRemoved, Some(method));
+            }
+        }
+        for method in summary
+            .native_methods
+            .difference(&old_summary.native_methods)
+        {
+            change(name, ChangeKind::NativeMethodAdded, Some(method));
+        }
+        for (api, permission) in &summary.sensitive_calls {
+            if !old_summary.sensitive_calls.contains_key(api) {
+                change(
+                    name,
+                    ChangeKind::SensitiveCallAdded { permission },
+                    Some(api),
+                );
+            }
+        }
+    }
+    let mut packages: BTreeMap<String, Vec<Change>> = BTreeMap::new();
+    for change in changes {
+        packages
+            .entry(deps::package(&change.class).to_string())
+            .or_default()
+            .push(change);
+    }
+    debug!(target: "diff", "changes in {} packages", packages.len());
+    Ok(ApkReport {
+        packages,
+        old: old.iter().map(Dex::provenance).collect(),
+        new: new.iter().map(Dex::provenance).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apk_report, ChangeKind};
+    use crate::Dex;
+
+    #[test]
+    fn test_apk_report() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let report = apk_report(std::slice::from_ref(&dex), std::slice::from_ref(&dex)).unwrap();
+        assert!(report.is_empty());
+        assert_eq!(report.old().len(), 1);
+        assert!(report.to_markdown().contains("No changes."));
+
+        let none: &[Dex<memmap2::Mmap>] = &[];
+        let report = apk_report(none, std::slice::from_ref(&dex)).unwrap();
+        let added = report
+            .changes()
+            .filter(|change| change.kind() == ChangeKind::ClassAdded)
+            .count();
+        assert_eq!(added, dex.header().class_defs_size() as usize);
+        assert!(report
+            .changes()
+            .all(|change| change.kind() != ChangeKind::MethodAdded));
+        let launcher = &report.packages()["org.adw.launcher"];
+        assert!(launcher
+            .iter()
+            .any(|change| change.class() == "org.adw.launcher.Launcher"));
+        assert!(report.to_json().contains("\"kind\":\"class-added\""));
+        assert!(report
+            .to_markdown()
+            .contains("Added class `org.adw.launcher.Launcher`"));
+
+        let report = apk_report(std::slice::from_ref(&dex), none).unwrap();
+        assert!(report
+            .changes()
+            .all(|change| change.kind() == ChangeKind::ClassRemoved));
+    }
+}
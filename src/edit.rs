@@ -0,0 +1,604 @@
+//! Targeted edits of the bytes of a `Dex`, which move the items after the edited ones and
+//! fix the offsets pointing to them.
+//!
+//! An edit is a list of `Splice`s, each replacing a range of the file with other bytes.
+//! The aligned items after a splice must be moved by a multiple of 4 bytes to stay aligned.
+//! The offsets to the moved items are fixed in the header, the map list, the ids sections,
+//! the class data, code, annotations directory, annotation set and annotation set ref list
+//! items, which are all the items holding offsets.
+use std::collections::{BTreeMap, BTreeSet};
+
+use cesu8::to_java_cesu8;
+use scroll::{Pread, Pwrite, Uleb128};
+
+use crate::{
+    code::DebugInfoItem, error::Error, string::StringId, uint, ushort, writer, Dex, ItemType,
+    Result,
+};
+
+/// Largest size of an uleb128 encoded `uint`.
+pub(crate) const MAX_ULEB128_SIZE: usize = 5;
+
+/// An offset in the file, as found in an item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OffsetField {
+    /// A `uint`.
+    Uint,
+    /// An uleb128 value of `size` bytes, which keeps its size when it is moved.
+    Uleb128 { size: usize },
+}
+
+/// Replaces the contents of the string `string_id` with `value` and returns the bytes of the
+/// edited file, with its checksum and signature. Changing the contents of a string doesn't
+/// change its id, so the new contents must keep the string ids sorted.
+pub fn replace_string<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    string_id: StringId,
+    value: &str,
+) -> Result<Vec<u8>> {
+    writer::check_writable(dex)?;
+    check_string_order(dex, string_id, value)?;
+    let source = dex.source.as_ref();
+    let endian = dex.get_endian();
+
+    let start: uint = source.pread_with(
+        (dex.inner.strings_offset() + string_id * 4) as usize,
+        endian,
+    )?;
+    let start = start as usize;
+    let mut offset = start;
+    Uleb128::read(source, &mut offset)?;
+    let terminator = source[offset..]
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or_else(|| Error::BadOffset(start, "Unterminated string data".to_string()))?;
+    let end = offset + terminator + 1;
+
+    // the size is padded so that the item grows or shrinks by a multiple of 4 bytes.
+    let mutf8 = to_java_cesu8(value);
+    let utf16_size = value.encode_utf16().count() as uint;
+    let unpadded = uleb128_size(utf16_size) + mutf8.len() + 1;
+    let padding = (end - start + 4 - unpadded % 4) % 4;
+    let size_len = uleb128_size(utf16_size) + padding;
+    if size_len > MAX_ULEB128_SIZE {
+        return Err(Error::MalFormed(format!(
+            "The size of \"{}\" can't be padded to align the items after it",
+            value
+        )));
+    }
+    let mut item = vec![0; size_len];
+    write_uleb128(&mut item, 0, utf16_size, size_len)?;
+    item.extend_from_slice(&mutf8);
+    item.push(0);
+
+    let mut splices = Splices::default();
+    splices.push(Splice::replace(start, end, item))?;
+    let mut bytes = splices.apply(source);
+    relocate_offsets(dex, &splices, &mut bytes)?;
+    finish(dex, &splices, &mut bytes)?;
+    debug!(
+        target: "edit",
+        "replaced string {}, moved the items after it by {}",
+        string_id,
+        splices.delta()
+    );
+    Ok(bytes)
+}
+
+/// Bytes replacing the range `start..end` of a file.
+#[derive(Debug)]
+pub(crate) struct Splice {
+    start: usize,
+    end: usize,
+    bytes: Vec<u8>,
+    /// Whether the items in the range are removed, the offsets to them become 0.
+    removed: bool,
+}
+
+impl Splice {
+    /// Replaces `start..end` with `bytes`, the offsets to `start` point to the new bytes.
+    /// An empty range inserts `bytes` before the item at `start`.
+    pub(crate) fn replace(start: usize, end: usize, bytes: Vec<u8>) -> Self {
+        Self {
+            start,
+            end,
+            bytes,
+            removed: false,
+        }
+    }
+
+    /// Removes the items in `start..end`, leaving the `padding` bytes in their place.
+    pub(crate) fn remove(start: usize, end: usize, padding: Vec<u8>) -> Self {
+        Self {
+            start,
+            end,
+            bytes: padding,
+            removed: true,
+        }
+    }
+}
+
+/// Splices of a file, sorted by their ranges, with how far they move the bytes after them.
+#[derive(Debug, Default)]
+pub(crate) struct Splices {
+    splices: Vec<Splice>,
+    /// How far the bytes after each splice are moved.
+    deltas: Vec<i64>,
+}
+
+impl Splices {
+    /// Adds `splice`, which must start after the end of the previous ones, and returns its
+    /// index.
+    pub(crate) fn push(&mut self, splice: Splice) -> Result<usize> {
+        if splice.start > splice.end
+            || self.splices.last().map_or(0, |last| last.end) > splice.start
+        {
+            return Err(Error::BadOffset(
+                splice.start,
+                "Splice overlaps the previous one".to_string(),
+            ));
+        }
+        let delta = self.delta() + splice.bytes.len() as i64 - (splice.end - splice.start) as i64;
+        self.splices.push(splice);
+        self.deltas.push(delta);
+        Ok(self.splices.len() - 1)
+    }
+
+    /// How far the bytes after the last splice are moved.
+    pub(crate) fn delta(&self) -> i64 {
+        self.deltas.last().copied().unwrap_or(0)
+    }
+
+    /// Offset in the spliced file of the byte at `offset`. `None` if the byte is removed or
+    /// replaced, except for the first byte of a replaced range, which becomes the first of
+    /// the new bytes.
+    pub(crate) fn relocate(&self, offset: usize) -> Option<usize> {
+        let index = self.splices.partition_point(|splice| splice.end <= offset);
+        let delta = if index == 0 {
+            0
+        } else {
+            self.deltas[index - 1]
+        };
+        match self.splices.get(index) {
+            Some(splice) if splice.start < offset || splice.removed && splice.start == offset => {
+                None
+            }
+            _ => Some((offset as i64 + delta) as usize),
+        }
+    }
+
+    /// Whether the byte at `position` is in the range of a splice.
+    pub(crate) fn contains(&self, position: usize) -> bool {
+        let index = self
+            .splices
+            .partition_point(|splice| splice.end <= position);
+        self.splices
+            .get(index)
+            .is_some_and(|splice| splice.start <= position)
+    }
+
+    /// Offset in the spliced file of the bytes of the splice `index`.
+    pub(crate) fn new_start(&self, index: usize) -> usize {
+        let delta = if index == 0 {
+            0
+        } else {
+            self.deltas[index - 1]
+        };
+        (self.splices[index].start as i64 + delta) as usize
+    }
+
+    /// The bytes of `source` with the splices applied.
+    pub(crate) fn apply(&self, source: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity((source.len() as i64 + self.delta()) as usize);
+        let mut position = 0;
+        for splice in &self.splices {
+            bytes.extend_from_slice(&source[position..splice.start]);
+            bytes.extend_from_slice(&splice.bytes);
+            position = splice.end;
+        }
+        bytes.extend_from_slice(&source[position..]);
+        bytes
+    }
+}
+
+/// Writes the offsets of `dex` outside of the splices to their moved positions in `bytes`,
+/// the spliced file, pointing to the moved items. The offsets to removed items and into the
+/// replaced bytes become 0, the items replacing them are written with their own offsets.
+pub(crate) fn relocate_offsets<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    splices: &Splices,
+    bytes: &mut [u8],
+) -> Result<()> {
+    let source = dex.source.as_ref();
+    let endian = dex.get_endian();
+    let relocate = |offset: uint| splices.relocate(offset as usize).unwrap_or(0) as uint;
+    for (position, field) in offset_fields(dex)? {
+        let moved = match splices.relocate(position) {
+            Some(moved) if !splices.contains(position) => moved,
+            _ => continue,
+        };
+        match field {
+            OffsetField::Uint => {
+                let offset: uint = source.pread_with(position, endian)?;
+                bytes.pwrite_with(relocate(offset), moved, endian)?;
+            }
+            OffsetField::Uleb128 { size } => {
+                let offset = Uleb128::read(source, &mut { position })? as uint;
+                write_uleb128(bytes, moved, relocate(offset), size)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Updates the sizes and the offset of the data section in the header of `bytes`, the
+/// spliced file of `dex`, and writes its checksum and signature.
+pub(crate) fn finish<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    splices: &Splices,
+    bytes: &mut [u8],
+) -> Result<()> {
+    let header = dex.header();
+    let endian = dex.get_endian();
+    let relocate = |offset: uint| {
+        splices
+            .relocate(offset as usize)
+            .map(|offset| offset as uint)
+            .ok_or_else(|| Error::BadOffset(offset as usize, "Data section removed".to_string()))
+    };
+    let data_off = relocate(header.data_off())?;
+    let data_end = relocate(header.data_off() + header.data_size())?;
+    let file_size = bytes.len() as uint;
+    bytes.pwrite_with(file_size, 0x20, endian)?;
+    bytes.pwrite_with(data_end - data_off, 0x68, endian)?;
+    bytes.pwrite_with(data_off, 0x6c, endian)?;
+    if dex.container_size().is_some() {
+        bytes.pwrite_with(file_size, 0x70, endian)?;
+    }
+    writer::write_checksum_and_signature(bytes, endian)
+}
+
+/// Removes the `debug_info_item`s of `dex` and returns the bytes of the smaller file, with
+/// its checksum and signature. The `debug_info_off` of the code items become 0 and the map
+/// list loses the entry of the debug info section, the other items are kept as they are.
+pub fn strip_debug_info<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<Vec<u8>> {
+    writer::check_writable(dex)?;
+    let source = dex.source.as_ref();
+    let endian = dex.get_endian();
+    let map_off = dex.header().map_off() as usize;
+    let mut splices = Vec::new();
+    let mut entries = Vec::new();
+    let size: uint = source.pread_with(map_off, endian)?;
+    for index in 0..size as usize {
+        let entry = map_off + 4 + index * 12;
+        let type_code: ushort = source.pread_with(entry, endian)?;
+        let item_size: uint = source.pread_with(entry + 4, endian)?;
+        let offset: uint = source.pread_with(entry + 8, endian)?;
+        if type_code != ItemType::DebugInfoItem as ushort {
+            entries.push((type_code, item_size, offset));
+            continue;
+        }
+        let (start, end) = (offset as usize, &mut (offset as usize));
+        for _ in 0..item_size {
+            source.gread_with::<DebugInfoItem>(end, dex)?;
+        }
+        // the padding keeps the items after the section aligned.
+        splices.push(Splice::remove(start, *end, vec![0; (*end - start) % 4]));
+    }
+    // the map list is written once the items are moved.
+    let map_end = map_off + 4 + size as usize * 12;
+    let map_list = vec![0; 4 + entries.len() * 12];
+    splices.push(Splice::replace(map_off, map_end, map_list));
+    splices.sort_by_key(|splice| splice.start);
+    let splices = splices
+        .into_iter()
+        .try_fold(Splices::default(), |mut splices, splice| {
+            splices.push(splice)?;
+            Ok::<_, Error>(splices)
+        })?;
+
+    let mut bytes = splices.apply(source);
+    relocate_offsets(dex, &splices, &mut bytes)?;
+    let offset = &mut splices
+        .relocate(map_off)
+        .ok_or_else(|| Error::BadOffset(map_off, "Map list removed".to_string()))?;
+    bytes.gwrite_with(entries.len() as uint, offset, endian)?;
+    for (type_code, item_size, item_offset) in entries {
+        let item_offset = splices.relocate(item_offset as usize).unwrap_or(0) as uint;
+        bytes.gwrite_with(type_code, offset, endian)?;
+        bytes.gwrite_with(0 as ushort, offset, endian)?;
+        bytes.gwrite_with(item_size, offset, endian)?;
+        bytes.gwrite_with(item_offset, offset, endian)?;
+    }
+    finish(dex, &splices, &mut bytes)?;
+    debug!(target: "edit", "stripped {} bytes of debug info", -splices.delta());
+    Ok(bytes)
+}
+
+/// Returns an error if the string `string_id` can't be replaced with `value` without
+/// breaking the order of the string ids, which are sorted by their UTF-16 code units.
+fn check_string_order<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    string_id: StringId,
+    value: &str,
+) -> Result<()> {
+    let utf16 = |string: &str| string.encode_utf16().collect::<Vec<_>>();
+    let value_utf16 = utf16(value);
+    dex.get_string(string_id)?;
+    if string_id > 0 {
+        let previous = dex.get_string(string_id - 1)?;
+        if utf16(&previous) >= value_utf16 {
+            return Err(Error::MalFormed(format!(
+                "\"{}\" sorts before the previous string \"{}\"",
+                value, &*previous
+            )));
+        }
+    }
+    if string_id + 1 < dex.header().string_ids_size() {
+        let next = dex.get_string(string_id + 1)?;
+        if utf16(&next) <= value_utf16 {
+            return Err(Error::MalFormed(format!(
+                "\"{}\" sorts after the next string \"{}\"",
+                value, &*next
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Positions of all the offsets in the file, with how they are encoded.
+pub(crate) fn offset_fields<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<BTreeMap<usize, OffsetField>> {
+    let source = dex.source.as_ref();
+    let endian = dex.get_endian();
+    let header = dex.header();
+    let mut fields = BTreeMap::new();
+    let mut add = |position: uint| {
+        fields.insert(position as usize, OffsetField::Uint);
+    };
+    let read = |position: usize| -> Result<uint> { Ok(source.pread_with(position, endian)?) };
+
+    if header.link_size() != 0 {
+        add(0x30);
+    }
+    add(0x34);
+    for index in 0..header.string_ids_size() {
+        add(dex.inner.strings_offset() + index * 4);
+    }
+    for index in 0..header.proto_ids_size() {
+        add(dex.inner.proto_ids_offset() + index * 12 + 8);
+    }
+    let call_site_ids = dex.inner.call_site_ids_offset().unwrap_or(0);
+    for index in 0..dex.inner.call_site_ids_len().unwrap_or(0) {
+        add(call_site_ids + index * 4);
+    }
+    let map_list = dex.map_list();
+    let entries = map_list.items().len() + map_list.unknown_items().len();
+    for index in 0..entries as uint {
+        add(header.map_off() + 4 + index * 12 + 8);
+    }
+
+    let mut class_data_items = BTreeSet::new();
+    let mut annotations_directories = BTreeSet::new();
+    for index in 0..header.class_defs_size() {
+        let class_def = dex.inner.class_defs_offset() + index * 32;
+        for field in [12, 20, 24, 28] {
+            add(class_def + field);
+        }
+        class_data_items.insert(read((class_def + 24) as usize)?);
+        annotations_directories.insert(read((class_def + 20) as usize)?);
+    }
+
+    let mut code_items = BTreeSet::new();
+    for class_data in class_data_items.into_iter().filter(|offset| *offset != 0) {
+        let offset = &mut (class_data as usize);
+        let mut sizes = [0; 4];
+        for size in &mut sizes {
+            *size = Uleb128::read(source, offset)?;
+        }
+        for _ in 0..sizes[0] + sizes[1] {
+            Uleb128::read(source, offset)?;
+            Uleb128::read(source, offset)?;
+        }
+        for _ in 0..sizes[2] + sizes[3] {
+            Uleb128::read(source, offset)?;
+            Uleb128::read(source, offset)?;
+            let position = *offset;
+            let code_off = Uleb128::read(source, offset)?;
+            if code_off != 0 {
+                let size = *offset - position;
+                fields.insert(position, OffsetField::Uleb128 { size });
+                code_items.insert(code_off as usize);
+            }
+        }
+    }
+    // the debug_info_off of the code items, compact code items don't have one.
+    for code_item in code_items {
+        fields.insert(code_item + 8, OffsetField::Uint);
+    }
+
+    let mut annotation_sets = BTreeSet::new();
+    let mut annotation_set_ref_lists = BTreeSet::new();
+    for directory in annotations_directories
+        .into_iter()
+        .filter(|offset| *offset != 0)
+    {
+        let directory = directory as usize;
+        fields.insert(directory, OffsetField::Uint);
+        annotation_sets.insert(read(directory)?);
+        let fields_size = read(directory + 4)? as usize;
+        let methods_size = read(directory + 8)? as usize;
+        let parameters_size = read(directory + 12)? as usize;
+        let entries = directory + 16;
+        for index in 0..fields_size + methods_size + parameters_size {
+            let position = entries + index * 8 + 4;
+            fields.insert(position, OffsetField::Uint);
+            if index < fields_size + methods_size {
+                annotation_sets.insert(read(position)?);
+            } else {
+                annotation_set_ref_lists.insert(read(position)?);
+            }
+        }
+    }
+    for ref_list in annotation_set_ref_lists
+        .into_iter()
+        .filter(|offset| *offset != 0)
+    {
+        let ref_list = ref_list as usize;
+        for index in 0..read(ref_list)? as usize {
+            let position = ref_list + 4 + index * 4;
+            fields.insert(position, OffsetField::Uint);
+            annotation_sets.insert(read(position)?);
+        }
+    }
+    for set in annotation_sets.into_iter().filter(|offset| *offset != 0) {
+        let set = set as usize;
+        for index in 0..read(set)? as usize {
+            fields.insert(set + 4 + index * 4, OffsetField::Uint);
+        }
+    }
+    Ok(fields)
+}
+
+/// Number of bytes of the shortest uleb128 encoding of `value`.
+pub(crate) fn uleb128_size(value: uint) -> usize {
+    let bits = 32 - value.leading_zeros() as usize;
+    std::cmp::max(1, bits.div_ceil(7))
+}
+
+/// Writes `value` at `position` as an uleb128 value of exactly `size` bytes, padded with
+/// continuation bytes if needed.
+pub(crate) fn write_uleb128(
+    bytes: &mut [u8],
+    position: usize,
+    value: uint,
+    size: usize,
+) -> Result<()> {
+    if size < uleb128_size(value) {
+        return Err(Error::MalFormed(format!(
+            "Offset {:#x} doesn't fit in the {} bytes of the offset it replaces",
+            value, size
+        )));
+    }
+    let encoded = bytes
+        .get_mut(position..position + size)
+        .ok_or_else(|| Error::BadOffset(position, "Uleb128 value out of bounds".to_string()))?;
+    let mut value = u64::from(value);
+    for (index, byte) in encoded.iter_mut().enumerate() {
+        *byte = (value & 0x7f) as u8;
+        if index + 1 < size {
+            *byte |= 0x80;
+        }
+        value >>= 7;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{smali, string::StringId, verifier, DexReader, ItemType};
+
+    #[test]
+    fn test_replace_string() {
+        let dex = DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let strings: Vec<String> = dex
+            .strings_uncached()
+            .map(|string| string.unwrap().to_string())
+            .collect();
+        let sorted_between = |string_id: usize, value: &str| {
+            let utf16 = |string: &str| string.encode_utf16().collect::<Vec<_>>();
+            utf16(&strings[string_id - 1]) < utf16(value)
+                && utf16(value) < utf16(&strings[string_id + 1])
+        };
+
+        // growing by 1 to 5 bytes and shrinking, which need different paddings.
+        for suffix in ["a", "ab", "abc", "abcd", "abcde", "\u{20ac}"] {
+            let (string_id, value) = (1..strings.len() - 1)
+                .map(|string_id| (string_id, format!("{}{}", strings[string_id], suffix)))
+                .find(|(string_id, value)| sorted_between(*string_id, value))
+                .unwrap();
+            let bytes = dex.replace_string(string_id as StringId, &value).unwrap();
+            let edited = DexReader::from_vec(bytes).expect("failed to load edited dex");
+            assert!(edited.is_signature_valid());
+            assert_eq!(verifier::verify_structure(&edited).unwrap(), &[]);
+            assert_eq!(*edited.get_string(string_id as StringId).unwrap(), *value);
+
+            let edited_strings: Vec<String> = edited
+                .strings_uncached()
+                .map(|string| string.unwrap().to_string())
+                .collect();
+            for (index, (old, new)) in strings.iter().zip(&edited_strings).enumerate() {
+                if index != string_id {
+                    assert_eq!(old, new);
+                }
+            }
+            assert_eq!(edited.classes().count(), dex.classes().count());
+            for (class, edited_class) in dex.classes().zip(edited.classes()) {
+                let (class, edited_class) = (class.unwrap(), edited_class.unwrap());
+                assert_eq!(class.jtype(), edited_class.jtype());
+                let debug = |value: &dyn std::fmt::Debug| format!("{:?}", value);
+                assert_eq!(
+                    debug(class.annotations()),
+                    debug(edited_class.annotations())
+                );
+                for (method, edited_method) in class.methods().zip(edited_class.methods()) {
+                    assert_eq!(debug(&method.code()), debug(&edited_method.code()));
+                }
+            }
+        }
+
+        let shrunk = strings
+            .iter()
+            .enumerate()
+            .skip(1)
+            .find(|(string_id, string)| {
+                string.len() > 2 && sorted_between(*string_id, &string[..string.len() - 1])
+            })
+            .map(|(string_id, string)| (string_id, string[..string.len() - 1].to_string()))
+            .unwrap();
+        let bytes = dex.replace_string(shrunk.0 as StringId, &shrunk.1).unwrap();
+        let edited = DexReader::from_vec(bytes).expect("failed to load edited dex");
+        assert_eq!(verifier::verify_structure(&edited).unwrap(), &[]);
+        assert_eq!(*edited.get_string(shrunk.0 as StringId).unwrap(), *shrunk.1);
+
+        assert!(dex.replace_string(1, &strings[0]).is_err());
+    }
+
+    #[test]
+    fn test_strip_debug_info() {
+        let dex = DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let bytes = dex.strip_debug_info().unwrap();
+        let stripped = DexReader::from_vec(bytes).expect("failed to load stripped dex");
+        assert!(stripped.is_signature_valid());
+        assert_eq!(verifier::verify_structure(&stripped).unwrap(), &[]);
+        assert!(stripped.map_list().get(ItemType::DebugInfoItem).is_none());
+        let debug_info = dex.map_list().get(ItemType::DebugInfoItem).unwrap();
+        let next = dex
+            .map_list()
+            .items()
+            .iter()
+            .map(|item| item.offset())
+            .filter(|offset| *offset > debug_info.offset())
+            .min()
+            .unwrap();
+        let saved = dex.header().file_size() - stripped.header().file_size();
+        assert!(saved > next - debug_info.offset() - 4 + 12);
+
+        assert_eq!(stripped.classes().count(), dex.classes().count());
+        for (class, stripped_class) in dex.classes().zip(stripped.classes()) {
+            let (class, stripped_class) = (class.unwrap(), stripped_class.unwrap());
+            for (method, stripped_method) in class.methods().zip(stripped_class.methods()) {
+                if let Some(code) = stripped_method.code() {
+                    assert!(code.debug_info_item().is_none());
+                }
+                assert_eq!(
+                    smali::disassemble(&dex, method).unwrap(),
+                    smali::disassemble(&stripped, stripped_method).unwrap()
+                );
+            }
+        }
+
+        // stripping a file without debug info only recomputes its checksum and signature.
+        let bytes = stripped.strip_debug_info().unwrap();
+        assert!(bytes[..] == stripped.source.as_ref()[..]);
+    }
+}
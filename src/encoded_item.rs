@@ -15,7 +15,7 @@ pub trait EncodedItem {
     fn id(&self) -> ulong;
 }
 
-#[derive(Getters)]
+#[derive(Debug, Getters)]
 #[get = "pub"]
 pub struct EncodedItemArray<T> {
     inner: Vec<T>,
@@ -72,7 +72,7 @@ where
         let len = ctx.len;
         let mut prev = 0;
         let offset = &mut 0;
-        let mut inner = Vec::with_capacity(len);
+        let mut inner = Vec::with_capacity(len.min(source.len()));
         for _ in 0..len {
             let encoded_item: T = source.gread_with(offset, prev)?;
             prev = encoded_item.id();
@@ -152,7 +152,8 @@ where
     fn try_from_ctx(source: &'a [u8], dex: &super::Dex<S>) -> super::Result<(Self, Self::Size)> {
         let offset = &mut 0;
         let encoded_handler_size = Uleb128::read(source, offset)?;
-        let mut encoded_catch_handlers = Vec::with_capacity(encoded_handler_size as usize);
+        let mut encoded_catch_handlers =
+            Vec::with_capacity((encoded_handler_size as usize).min(source.len()));
         for _ in 0..encoded_handler_size {
             let off = *offset;
             let encoded_catch_handler = source.gread_with(offset, dex)?;
@@ -1,4 +1,6 @@
 //! Contains structures defining values in a `Dex`.
+use std::convert::TryFrom;
+
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use scroll::{self, ctx, Pread, Uleb128, LE};
@@ -20,6 +22,7 @@ use crate::{
 /// Used to represent values of fields, annotations etc.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#encoding)
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum EncodedValue {
     Byte(byte),
     Short(short),
@@ -50,6 +53,17 @@ impl PartialEq<ushort> for EncodedValue {
     }
 }
 
+/// A char value equals a `char` if it is the UTF-16 code unit of the `char`. Chars outside
+/// the basic multilingual plane need two code units, so they never equal a char value.
+impl PartialEq<char> for EncodedValue {
+    fn eq(&self, other: &char) -> bool {
+        match self {
+            EncodedValue::Char(us) => u32::from(*us) == u32::from(*other),
+            _ => false,
+        }
+    }
+}
+
 impl PartialEq<int> for EncodedValue {
     fn eq(&self, other: &int) -> bool {
         match self {
@@ -245,6 +259,126 @@ impl EncodedValue {
     );
 }
 
+macro_rules! gen_as_method {
+    ($name: ident, $ty: ty, [$($variant: ident),+], $doc: literal) => {
+        #[doc = $doc]
+        pub fn $name(&self) -> Option<$ty> {
+            match *self {
+                $(EncodedValue::$variant(v) => Some(v.into()),)+
+                _ => None,
+            }
+        }
+    };
+}
+
+/// Conversions following the widening primitive conversions of Java, a `short` can be read
+/// as an `int` but not as a `byte`.
+impl EncodedValue {
+    gen_as_method!(as_byte, byte, [Byte], "Returns the value if it is a byte");
+    gen_as_method!(
+        as_short,
+        short,
+        [Byte, Short],
+        "Returns the value if it is a byte or a short"
+    );
+    gen_as_method!(as_char, ushort, [Char], "Returns the value if it is a char");
+    gen_as_method!(
+        as_int,
+        int,
+        [Byte, Short, Char, Int],
+        "Returns the value if it is a byte, short, char or int"
+    );
+    gen_as_method!(
+        as_long,
+        long,
+        [Byte, Short, Char, Int, Long],
+        "Returns the value if it is a byte, short, char, int or long"
+    );
+    gen_as_method!(
+        as_bool,
+        bool,
+        [Boolean],
+        "Returns the value if it is a bool"
+    );
+
+    /// Returns the value if it is a byte, short, char, int, long or float. Ints and longs
+    /// may lose precision, as in Java.
+    pub fn as_float(&self) -> Option<f32> {
+        match *self {
+            EncodedValue::Int(v) => Some(v as f32),
+            EncodedValue::Long(v) => Some(v as f32),
+            EncodedValue::Char(v) => Some(v.into()),
+            EncodedValue::Float(v) => Some(v),
+            _ => self.as_short().map(f32::from),
+        }
+    }
+
+    /// Returns the value if it is of a numeric type. Longs may lose precision, as in Java.
+    pub fn as_double(&self) -> Option<f64> {
+        match *self {
+            EncodedValue::Long(v) => Some(v as f64),
+            EncodedValue::Float(v) => Some(v.into()),
+            EncodedValue::Double(v) => Some(v),
+            _ => self.as_int().map(f64::from),
+        }
+    }
+
+    /// Returns the value as a `char` if it is a char. `None` for surrogates, which are only
+    /// valid in pairs, use `char::decode_utf16` to decode those.
+    pub fn to_char(&self) -> Option<char> {
+        self.as_char()
+            .and_then(|unit| char::from_u32(u32::from(unit)))
+    }
+
+    /// Returns the value if it is a string.
+    pub fn as_string(&self) -> Option<&DexString> {
+        match self {
+            EncodedValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a `Type`.
+    pub fn as_type(&self) -> Option<&Type> {
+        match self {
+            EncodedValue::Type(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements if the value is an array.
+    pub fn as_array(&self) -> Option<&[EncodedValue]> {
+        match self {
+            EncodedValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! gen_try_from {
+    ($ty: ty, $method: ident, $name: literal) => {
+        impl TryFrom<&EncodedValue> for $ty {
+            type Error = Error;
+
+            fn try_from(value: &EncodedValue) -> Result<Self> {
+                value.$method().ok_or_else(|| {
+                    Error::MalFormed(format!("Expected {}, found: {:?}", $name, value))
+                })
+            }
+        }
+    };
+}
+
+gen_try_from!(byte, as_byte, "byte");
+gen_try_from!(short, as_short, "short");
+gen_try_from!(ushort, as_char, "char");
+gen_try_from!(int, as_int, "int");
+gen_try_from!(long, as_long, "long");
+gen_try_from!(f32, as_float, "float");
+gen_try_from!(f64, as_double, "double");
+gen_try_from!(bool, as_bool, "bool");
+gen_try_from!(char, to_char, "char");
+
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#value-formats)
 #[derive(FromPrimitive, Debug)]
 enum ValueType {
@@ -295,6 +429,7 @@ macro_rules! try_extended_gread {
             }
         }
         debug!(target: "encoded-value", "bytes: {:?}", bytes);
+        // encoded values are byte sequences, little endian whatever the endian tag is.
         let value = bytes.pread_with(0, LE)?;
         *$offset += 1 + $value_arg;
         value
@@ -327,85 +462,82 @@ where
         let value_type = ValueType::from_u8(value_type)
             .ok_or_else(|| Error::InvalidId(format!("Invalid value type {}", value_type)))?;
         debug!(target: "encoded-value", "encoded value type: {:?}, value_arg: {}", value_type, value_arg);
+        // value_arg is the size of the value minus one, or the value of a boolean.
+        let max_value_arg = match value_type {
+            ValueType::Byte | ValueType::Array | ValueType::Annotation | ValueType::Null => 0,
+            ValueType::Short | ValueType::Char | ValueType::Boolean => 1,
+            ValueType::Long | ValueType::Double => 7,
+            _ => 3,
+        };
+        if value_arg > max_value_arg {
+            return Err(Error::MalFormed(format!(
+                "Invalid value_arg {} for {:?} value",
+                value_arg, value_type
+            )));
+        }
         let value = match value_type {
             ValueType::Byte => {
-                debug_assert_eq!(value_arg, 0);
                 EncodedValue::Byte(try_extended_gread!(source, offset, value_arg, 1))
             }
             ValueType::Short => {
-                debug_assert!(value_arg < 2);
                 EncodedValue::Short(try_extended_gread!(source, offset, value_arg, 2, SIGN))
             }
             ValueType::Char => {
-                debug_assert!(value_arg < 2);
                 EncodedValue::Char(try_extended_gread!(source, offset, value_arg, 2))
             }
             ValueType::Int => {
-                debug_assert!(value_arg < 4);
                 EncodedValue::Int(try_extended_gread!(source, offset, value_arg, 4, SIGN))
             }
             ValueType::Long => {
-                debug_assert!(value_arg < 8);
                 EncodedValue::Long(try_extended_gread!(source, offset, value_arg, 8, SIGN))
             }
             ValueType::Float => {
-                debug_assert!(value_arg < 4);
                 EncodedValue::Float(try_extended_gread!(source, offset, value_arg, 4))
             }
             ValueType::Double => {
-                debug_assert!(value_arg < 8);
                 EncodedValue::Double(try_extended_gread!(source, offset, value_arg, 8))
             }
             ValueType::MethodType => {
-                debug_assert!(value_arg < 4);
+                dex.check_version("Method type values", 39)?;
                 let proto_id: uint = try_extended_gread!(source, offset, value_arg, 4);
                 EncodedValue::MethodType(dex.get_proto_item(ProtoId::from(proto_id))?)
             }
             ValueType::MethodHandle => {
-                debug_assert!(value_arg < 4);
+                dex.check_version("Method handle values", 39)?;
                 let index: MethodHandleId = try_extended_gread!(source, offset, value_arg, 4);
                 EncodedValue::MethodHandle(dex.get_method_handle_item(index)?)
             }
             ValueType::String => {
-                debug_assert!(value_arg < 4);
                 let string_id: StringId = try_extended_gread!(source, offset, value_arg, 4);
                 EncodedValue::String(dex.get_string(string_id)?)
             }
             ValueType::Type => {
-                debug_assert!(value_arg < 4);
                 let type_id: TypeId = try_extended_gread!(source, offset, value_arg, 4);
                 EncodedValue::Type(dex.get_type(type_id)?)
             }
             ValueType::Field => {
-                debug_assert!(value_arg < 4);
                 let index: uint = try_extended_gread!(source, offset, value_arg, 4);
                 EncodedValue::Field(dex.get_field_item(FieldId::from(index))?)
             }
             ValueType::Method => {
-                debug_assert!(value_arg < 4);
                 let index: uint = try_extended_gread!(source, offset, value_arg, 4);
                 EncodedValue::Method(dex.get_method_item(MethodId::from(index))?)
             }
             ValueType::Enum => {
-                debug_assert!(value_arg < 4);
                 let index: uint = try_extended_gread!(source, offset, value_arg, 4);
                 EncodedValue::Enum(dex.get_field_item(FieldId::from(index))?)
             }
             ValueType::Array => {
-                debug_assert!(value_arg == 0);
                 let encoded_array: EncodedArray = source.gread_with(offset, dex)?;
                 EncodedValue::Array(encoded_array.into_inner())
             }
             ValueType::Annotation => {
-                debug_assert!(value_arg == 0);
                 EncodedValue::Annotation(source.gread_with(offset, dex)?)
             }
             ValueType::Null => {
-                debug_assert!(value_arg == 0);
                 EncodedValue::Null
             }
             ValueType::Boolean => {
-                debug_assert!(value_arg < 2);
                 EncodedValue::Boolean(value_arg == 1)
             }
         };
@@ -437,10 +569,58 @@ where
         let size = Uleb128::read(source, offset)?;
         // TODO: find out why try_gread_vec_with! doesn't work here: fails in scroll
         debug!(target: "encoded-array", "encoded array size: {}", size);
-        let mut values = Vec::with_capacity(size as usize);
+        let mut values = Vec::with_capacity((size as usize).min(source.len()));
         for _ in 0..size {
             values.push(source.gread_with(offset, ctx)?);
         }
         Ok((Self { values }, *offset))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::EncodedValue;
+
+    #[test]
+    fn test_conversions() {
+        let value = EncodedValue::Short(-3);
+        assert_eq!(value.as_byte(), None);
+        assert_eq!(value.as_short(), Some(-3));
+        assert_eq!(value.as_int(), Some(-3));
+        assert_eq!(value.as_long(), Some(-3));
+        assert_eq!(value.as_double(), Some(-3.0));
+        assert_eq!(value.as_char(), None);
+        assert_eq!(EncodedValue::Char(0xffff).as_int(), Some(0xffff));
+        assert_eq!(
+            EncodedValue::Int(16_777_217).as_double(),
+            Some(16_777_217.0)
+        );
+        assert_eq!(EncodedValue::Long(1).as_int(), None);
+        assert_eq!(EncodedValue::Double(1.0).as_float(), None);
+        assert_eq!(EncodedValue::Boolean(true).as_bool(), Some(true));
+        assert_eq!(EncodedValue::Null.as_string(), None);
+
+        assert_eq!(i64::try_from(&EncodedValue::Int(7)).unwrap(), 7);
+        assert_eq!(f32::try_from(&EncodedValue::Byte(2)).unwrap(), 2.0);
+        assert!(i32::try_from(&EncodedValue::Long(7)).is_err());
+        assert!(bool::try_from(&EncodedValue::Int(1)).is_err());
+    }
+
+    #[test]
+    fn test_char() {
+        let value = EncodedValue::Char(0xe9);
+        assert_eq!(value.to_char(), Some('é'));
+        assert_eq!(value, 'é');
+        assert_ne!(value, 'e');
+        assert_ne!(EncodedValue::Int(0xe9), 'é');
+        assert_eq!(char::try_from(&EncodedValue::Char(0x20ac)).unwrap(), '€');
+
+        // the high surrogate of U+1F600
+        let value = EncodedValue::Char(0xd83d);
+        assert_eq!(value.to_char(), None);
+        assert!(char::try_from(&value).is_err());
+        assert_ne!(value, '😀');
+    }
+}
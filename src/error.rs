@@ -4,39 +4,195 @@ use std::{
     io,
 };
 
+use getset::CopyGetters;
 use scroll;
 
+use crate::{dex::ItemType, uint};
+
+/// The errors of the crate. New kinds of errors may be added, so matches need a wildcard
+/// arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
+    /// The file doesn't start with the magic value of a dex file.
+    BadMagic {
+        /// The first bytes of the file.
+        found: Vec<u8>,
+    },
+    /// The endian tag of the header is neither of the known values.
+    BadEndianTag {
+        /// The endian tag of the header.
+        found: [u8; 4],
+    },
+    /// The checksum of the file isn't the one in the header.
+    ChecksumMismatch {
+        /// The checksum in the header.
+        expected: uint,
+        /// The checksum of the file.
+        found: uint,
+    },
+    /// An id or index past the end of its section, like a `StringId` greater than the
+    /// number of strings.
+    IndexOutOfBounds {
+        /// The section the index refers to, like `ItemType::StringIdItem`.
+        section: ItemType,
+        /// The index.
+        index: u64,
+    },
+    /// An entity which isn't valid, for errors without a kind of their own.
     MalFormed(String),
     IO(io::Error),
+    /// A value which doesn't map to anything, like an unknown opcode.
     InvalidId(String),
     Scroll(scroll::Error),
+    /// An offset which is out of bounds or doesn't point to where it should.
     BadOffset(usize, String),
+    /// An error along with where it happened in the file.
+    Context(Box<Error>, Context),
+    /// A section or a value which the version of the file doesn't support, like method
+    /// handles in a version `035` file.
+    UnsupportedVersion {
+        /// What isn't supported, like `"method handles"`.
+        feature: &'static str,
+        /// The first version supporting it.
+        required: uint,
+        /// The version of the file.
+        found: uint,
+    },
 }
 
-impl error::Error for Error {
-    fn description(&self) -> &str {
-        match *self {
-            Error::IO(_) => "IO error",
-            Error::MalFormed(_) => "Entity is malformed in some way",
-            Error::Scroll(_) => "Scroll error",
-            Error::InvalidId(_) => "Invalid index",
-            Error::BadOffset(_, _) => "Invalid offset",
+/// Where an error happened: the item being read and the id being resolved, like
+/// `method_id 1234 in ClassDataItem at 0x4f00`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct Context {
+    /// Absolute offset in the file of the item being read.
+    offset: Option<usize>,
+    /// Type of the item being read.
+    item_type: Option<ItemType>,
+    /// Kind and value of the id being resolved, like `("method_id", 1234)`.
+    id: Option<(&'static str, u64)>,
+}
+
+impl Context {
+    /// Sets the fields of `self` which aren't set from `outer`, the innermost context is
+    /// the most precise one.
+    fn merge(&mut self, outer: Context) {
+        self.offset = self.offset.or(outer.offset);
+        self.item_type = self.item_type.or(outer.item_type);
+        self.id = self.id.or(outer.id);
+    }
+}
+
+impl Display for Context {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some((name, id)) = self.id {
+            parts.push(format!("{} {}", name, id));
+        }
+        if let Some(item_type) = self.item_type {
+            parts.push(format!("in {:?}", item_type));
+        }
+        if let Some(offset) = self.offset {
+            parts.push(format!("at {:#x}", offset));
+        }
+        write!(fmt, "{}", parts.join(" "))
+    }
+}
+
+impl Error {
+    /// Where the error happened, `None` if it isn't known.
+    pub fn context(&self) -> Option<&Context> {
+        match self {
+            Error::Context(_, context) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context(error, _) => error,
+            error => error,
         }
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    /// Absolute offset in the file where the error happened, if it's known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::Context(error, context) => context.offset.or_else(|| error.offset()),
+            Error::BadOffset(offset, _) => Some(*offset),
+            _ => None,
+        }
+    }
+
+    fn with_context(self, context: Context) -> Self {
+        match self {
+            Error::Context(error, mut inner) => {
+                inner.merge(context);
+                Error::Context(error, inner)
+            }
+            error => Error::Context(Box::new(error), context),
+        }
+    }
+}
+
+/// Adds the context of an error when it's propagated, see `Context`.
+pub(crate) trait ResultExt<T> {
+    /// Records the id being resolved.
+    fn with_id<I: Into<u64>>(self, name: &'static str, id: I) -> Result<T, Error>;
+    /// Records the item being read and its offset.
+    fn with_item(self, item_type: ItemType, offset: usize) -> Result<T, Error>;
+}
+
+impl<T> ResultExt<T> for Result<T, Error> {
+    fn with_id<I: Into<u64>>(self, name: &'static str, id: I) -> Result<T, Error> {
+        let id = Some((name, id.into()));
+        self.map_err(|error| {
+            error.with_context(Context {
+                id,
+                ..Default::default()
+            })
+        })
+    }
+
+    fn with_item(self, item_type: ItemType, offset: usize) -> Result<T, Error> {
+        self.map_err(|error| {
+            error.with_context(Context {
+                offset: Some(offset),
+                item_type: Some(item_type),
+                id: None,
+            })
+        })
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
-            Error::IO(ref io) => io.source(),
-            Error::Scroll(ref err) => err.source(),
-            Error::MalFormed(_) => None,
-            Error::InvalidId(_) => None,
-            Error::BadOffset(_, _) => None,
+            Error::IO(ref io) => Some(io),
+            Error::Scroll(ref err) => Some(err),
+            Error::Context(ref inner, _) => Some(inner.as_ref()),
+            _ => None,
         }
     }
 }
 
+/// Name of the ids of `section` in messages.
+fn index_name(section: ItemType) -> &'static str {
+    match section {
+        ItemType::StringIdItem => "string id",
+        ItemType::TypeIdItem => "type id",
+        ItemType::ProtoIdItem => "proto id",
+        ItemType::FieldIdItem => "field id",
+        ItemType::MethodIdItem => "method id",
+        ItemType::ClassDefItem => "class def index",
+        ItemType::CallSiteIdItem => "call site id",
+        ItemType::MethodHandleItem => "method handle id",
+        _ => "index",
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Error::IO(err)
@@ -52,11 +208,33 @@ impl From<scroll::Error> for Error {
 impl Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Error::BadMagic { ref found } => {
+                write!(fmt, "Not a dex file, magic value: {:?}", found)
+            }
+            Error::BadEndianTag { found } => write!(fmt, "Bad endian tag: {:02x?}", found),
+            Error::ChecksumMismatch { expected, found } => write!(
+                fmt,
+                "File corrupted, adler32 checksum {:#010x} doesn't match the header's {:#010x}",
+                found, expected
+            ),
+            Error::IndexOutOfBounds { section, index } => {
+                write!(fmt, "Invalid {}: {}", index_name(section), index)
+            }
             Error::IO(ref err) => write!(fmt, "{}", err),
             Error::Scroll(ref err) => write!(fmt, "{}", err),
             Error::MalFormed(ref msg) => write!(fmt, "Malformed entity: {}", msg),
             Error::InvalidId(ref msg) => write!(fmt, "{}", msg),
             Error::BadOffset(offset, ref msg) => write!(fmt, "{}: {}", msg, offset),
+            Error::Context(ref error, ref context) => write!(fmt, "{} ({})", error, context),
+            Error::UnsupportedVersion {
+                feature,
+                required,
+                found,
+            } => write!(
+                fmt,
+                "{} require dex version {:03}, the file is version {:03}",
+                feature, required, found
+            ),
         }
     }
 }
@@ -9,7 +9,8 @@ use crate::{
     error::Error,
     jtype::{Type, TypeId},
     string::{DexString, StringId},
-    ulong, ushort, utils,
+    ulong, ushort,
+    utils::{self, Lazy},
 };
 use getset::{CopyGetters, Getters};
 
@@ -30,7 +31,7 @@ bitflags! {
 
 /// Represents the field of a class
 #[derive(Debug, Getters, CopyGetters)]
-pub struct Field {
+pub struct Field<'a> {
     /// Name of the field.
     #[get = "pub"]
     name: DexString,
@@ -40,6 +41,8 @@ pub struct Field {
     /// Class which this field belongs to.
     #[get_copy = "pub"]
     class: ClassId,
+    /// Type of the class which this field belongs to.
+    class_type: Type,
     /// Access flags for the field.
     #[get_copy = "pub"]
     access_flags: AccessFlags,
@@ -47,15 +50,19 @@ pub struct Field {
     /// If the value is `None`, it is not guaranteed that initial_value is `null`
     /// at runtime. The field might be initialized in `<clinit>` method.
     initial_value: Option<EncodedValue>,
-    /// Annotations of the field.
-    #[get = "pub"]
-    annotations: AnnotationSetItem,
+    /// Annotations of the field, parsed on first access.
+    annotations: Lazy<'a, AnnotationSetItem>,
     /// `FieldId` of the field.
     #[get_copy = "pub"]
     id: FieldId,
+    /// Position of the field in `Class::static_fields` or `Class::instance_fields`, which
+    /// is the order of the class data. Fields are sorted by `FieldId` in a `Dex`, so the
+    /// order of declaration in the source isn't available.
+    #[get_copy = "pub"]
+    pub(crate) slot: usize,
 }
 
-impl Field {
+impl<'a> Field<'a> {
     /// Initial value of the field. Always `None` for non-static fields.
     /// If the value is `None`, it is not guaranteed that initial_value is `null`
     /// at runtime. The field might be initialized in `<clinit>` method.
@@ -63,6 +70,17 @@ impl Field {
         self.initial_value.as_ref()
     }
 
+    /// Annotations of the field, parsed on first access. Empty if they are malformed,
+    /// `try_annotations` returns the error instead.
+    pub fn annotations(&self) -> &AnnotationSetItem {
+        self.annotations.get_or_empty()
+    }
+
+    /// Annotations of the field, parsed on first access.
+    pub fn try_annotations(&self) -> super::Result<&AnnotationSetItem> {
+        self.annotations.get()
+    }
+
     gen_is_flag_set!(is_public, PUBLIC);
     gen_is_flag_set!(is_private, PRIVATE);
     gen_is_flag_set!(is_protected, PROTECTED);
@@ -75,14 +93,25 @@ impl Field {
 
     /// Returns the value of `dalvik.annotation.Signature`.
     pub fn signature(&self) -> super::Result<Option<String>> {
-        utils::get_signature(self.annotations())
+        utils::get_signature(self.try_annotations()?)
+    }
+
+    /// Reference to the field in smali notation, like `Lcom/foo/Bar;->baz:Ljava/lang/String;`,
+    /// the same as `FieldIdItem::descriptor`.
+    pub fn descriptor(&self) -> String {
+        format!(
+            "{}->{}:{}",
+            self.class_type.type_descriptor(),
+            self.name,
+            self.jtype.type_descriptor()
+        )
     }
 
     pub(crate) fn try_from_dex<S: AsRef<[u8]>>(
         dex: &super::Dex<S>,
         encoded_field: &EncodedField,
         initial_value: Option<EncodedValue>,
-        annotations: AnnotationSetItem,
+        annotations: Lazy<'a, AnnotationSetItem>,
     ) -> super::Result<Self> {
         debug!(target: "field", "encoded field: {:?}", encoded_field);
         let field_item = dex.get_field_item(encoded_field.field_id)?;
@@ -91,6 +120,7 @@ impl Field {
             name: dex.get_string(field_item.name_idx)?,
             jtype: dex.get_type(TypeId::from(field_item.type_idx))?,
             class: ClassId::from(field_item.class_idx),
+            class_type: dex.get_type(TypeId::from(field_item.class_idx))?,
             access_flags: AccessFlags::from_bits(encoded_field.access_flags).ok_or_else(|| {
                 Error::InvalidId(format!(
                     "Invalid access flags when loading field {}",
@@ -100,6 +130,22 @@ impl Field {
             initial_value,
             annotations,
             id: encoded_field.field_id,
+            slot: 0,
+        })
+    }
+
+    /// The field with its annotations parsed, see `Class::into_owned`.
+    pub(crate) fn into_owned(self) -> super::Result<Field<'static>> {
+        Ok(Field {
+            name: self.name,
+            jtype: self.jtype,
+            class: self.class,
+            class_type: self.class_type,
+            access_flags: self.access_flags,
+            initial_value: self.initial_value,
+            annotations: self.annotations.into_loaded()?,
+            id: self.id,
+            slot: self.slot,
         })
     }
 }
@@ -107,16 +153,17 @@ impl Field {
 /// List of `EncodedField`s
 pub type EncodedFieldArray = EncodedItemArray<EncodedField>;
 
-#[derive(Pread)]
-struct FieldIdData {
-    class_idx: ushort,
-    type_idx: ushort,
-    name_idx: StringId,
+#[derive(Pread, Debug)]
+pub(crate) struct FieldIdData {
+    pub(crate) class_idx: ushort,
+    pub(crate) type_idx: ushort,
+    pub(crate) name_idx: StringId,
 }
 
 /// Defines a `Field`
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#field-id-item)
-#[derive(Debug, CopyGetters, PartialEq)]
+#[derive(Debug, Clone, CopyGetters, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[get_copy = "pub"]
 pub struct FieldIdItem {
     /// Index into `TypeId`s list which contains the defining class's `Type`.
@@ -144,6 +191,37 @@ impl FieldIdItem {
             id: field_id,
         })
     }
+
+    /// Reference to the field in smali notation, like `Lcom/foo/Bar;->baz:Ljava/lang/String;`.
+    pub fn descriptor<T: AsRef<[u8]>>(&self, dex: &super::Dex<T>) -> super::Result<String> {
+        Ok(format!(
+            "{}->{}:{}",
+            dex.get_type(TypeId::from(self.class_idx))?
+                .type_descriptor(),
+            dex.get_string(self.name_idx)?,
+            dex.get_type(TypeId::from(self.type_idx))?.type_descriptor()
+        ))
+    }
+}
+
+/// A field found by `Dex::find_field`.
+#[derive(Debug)]
+pub enum ResolvedField<'a> {
+    /// The field is defined by a class of the `Dex`.
+    Defined(Field<'a>),
+    /// The field is only referenced, its class is defined in another `Dex` or the field
+    /// is inherited.
+    Referenced(FieldIdItem),
+}
+
+impl ResolvedField<'_> {
+    /// `FieldId` of the field.
+    pub fn id(&self) -> FieldId {
+        match self {
+            ResolvedField::Defined(field) => field.id(),
+            ResolvedField::Referenced(item) => item.id(),
+        }
+    }
 }
 
 /// Index into the `FieldId`s list.
@@ -184,3 +262,60 @@ impl<'a> ctx::TryFromCtx<'a, ulong> for EncodedField {
         ))
     }
 }
+
+/// Serialized as the bits of the flags.
+#[cfg(feature = "serde")]
+impl serde::Serialize for AccessFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.bits(), serializer)
+    }
+}
+
+/// Any combination of the defined flags.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for AccessFlags {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::from_bits_truncate(u.arbitrary()?))
+    }
+}
+/// Serialized with its annotations. Malformed annotations are serialized as empty.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Field<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Field", 6)?;
+        state.serialize_field("class", &self.class_type)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("type", &self.jtype)?;
+        state.serialize_field("access_flags", &self.access_flags)?;
+        state.serialize_field("initial_value", &self.initial_value)?;
+        state.serialize_field("annotations", self.annotations())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_descriptor() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let mut fields = 0;
+        for class in dex.classes() {
+            let class = class.expect("failed to load class");
+            for field in class.fields() {
+                let descriptor = field.descriptor();
+                assert!(descriptor.starts_with(&format!(
+                    "{}->{}:",
+                    class.jtype().type_descriptor(),
+                    field.name()
+                )));
+                assert!(descriptor.ends_with(&**field.jtype().type_descriptor()));
+                let item = dex.get_field_item(field.id()).unwrap();
+                assert_eq!(item.descriptor(&dex).unwrap(), descriptor);
+                fields += 1;
+            }
+        }
+        assert!(fields > 0);
+    }
+}
@@ -0,0 +1,253 @@
+//! Name independent fingerprints of classes, used to match classes across obfuscated builds.
+use std::collections::{BTreeMap, HashMap};
+
+use getset::CopyGetters;
+
+use crate::{class::Class, jtype::Type, Dex, Result};
+
+/// Packages whose names are kept by obfuscators, so their types are part of the fingerprint.
+const FRAMEWORK_PACKAGES: [&str; 4] = ["Ljava/", "Ljavax/", "Landroid/", "Ldalvik/"];
+
+/// Structural fingerprint of a `Class`. It is built from the access flags, the shapes of the
+/// members and the instructions of the class, while the names of the class, its members and
+/// of the non-framework types they refer to are ignored.
+#[derive(Debug, Clone, PartialEq, CopyGetters)]
+pub struct Fingerprint {
+    /// Hash of all the features. Classes with equal hashes are structurally identical.
+    #[get_copy = "pub"]
+    hash: u64,
+    /// Number of occurrences of each feature, keyed by the hash of the feature.
+    features: BTreeMap<u64, u32>,
+}
+
+impl Fingerprint {
+    pub(crate) fn from_class(class: &Class) -> Result<Self> {
+        let mut features = Vec::new();
+        features.push(format!("class:{:x}", class.access_flags().bits()));
+        for interface in class.interfaces() {
+            features.push(format!("interface:{}", normalize(interface)));
+        }
+        for field in class.fields() {
+            features.push(format!(
+                "field:{:x}:{}",
+                field.access_flags().bits(),
+                normalize(field.jtype())
+            ));
+        }
+        for method in class.methods() {
+            let params: Vec<String> = method.params().iter().map(normalize).collect();
+            features.push(format!(
+                "method:{:x}:({}){}",
+                method.access_flags().bits(),
+                params.join(""),
+                normalize(method.return_type())
+            ));
+            if let Some(code) = method.try_code()? {
+                features.push(format!("registers:{}", code.registers_size()));
+                features.push(format!("tries:{}", code.tries().len()));
+                // stops at the first malformed instruction, like the decoder.
+                for instruction in code.instructions().map_while(|i| i.ok()) {
+                    features.push(format!("op:{}", instruction.opcode().mnemonic()));
+                }
+            }
+        }
+
+        let mut counts = BTreeMap::new();
+        for feature in &features {
+            *counts.entry(fnv1a(feature.as_bytes())).or_insert(0) += 1;
+        }
+        let mut bytes = Vec::with_capacity(counts.len() * 12);
+        for (feature, count) in &counts {
+            bytes.extend_from_slice(&feature.to_le_bytes());
+            bytes.extend_from_slice(&u32::to_le_bytes(*count));
+        }
+        Ok(Self {
+            hash: fnv1a(&bytes),
+            features: counts,
+        })
+    }
+
+    /// Similarity of two fingerprints, between `0.0` for nothing in common and `1.0` for
+    /// structurally identical classes.
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        if self.hash == other.hash {
+            return 1.0;
+        }
+        let (mut common, mut total) = (0u64, 0u64);
+        let mut others = other.features.clone();
+        for (feature, count) in &self.features {
+            let other_count = others.remove(feature).unwrap_or(0);
+            common += u64::from((*count).min(other_count));
+            total += u64::from((*count).max(other_count));
+        }
+        total += others.values().map(|c| u64::from(*c)).sum::<u64>();
+        if total == 0 {
+            1.0
+        } else {
+            common as f64 / total as f64
+        }
+    }
+}
+
+/// A pair of classes matched by `match_classes`.
+#[derive(Debug, Clone, PartialEq, CopyGetters)]
+pub struct ClassMatch {
+    /// Type descriptor of the class in the first `Dex`.
+    left: String,
+    /// Type descriptor of the class in the second `Dex`.
+    right: String,
+    /// Similarity of the fingerprints of the classes.
+    #[get_copy = "pub"]
+    similarity: f64,
+}
+
+impl ClassMatch {
+    /// Type descriptor of the class in the first `Dex`.
+    pub fn left(&self) -> &str {
+        &self.left
+    }
+
+    /// Type descriptor of the class in the second `Dex`.
+    pub fn right(&self) -> &str {
+        &self.right
+    }
+}
+
+/// Pairs the classes of `left` with the classes of `right` by the similarity of their
+/// fingerprints. Structurally identical classes are paired first, then the remaining
+/// classes are paired greedily by decreasing similarity. Each class is matched at most
+/// once, and only pairs with a similarity of at least `min_similarity` are returned.
+pub fn match_classes<T, U>(
+    left: &Dex<T>,
+    right: &Dex<U>,
+    min_similarity: f64,
+) -> Result<Vec<ClassMatch>>
+where
+    T: AsRef<[u8]>,
+    U: AsRef<[u8]>,
+{
+    let fingerprints = |classes: Vec<Class>| {
+        classes
+            .into_iter()
+            .map(|c| {
+                Ok((
+                    c.jtype().type_descriptor().to_string(),
+                    c.structural_fingerprint()?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+    let left = fingerprints(left.classes().collect::<Result<_>>()?)?;
+    let right = fingerprints(right.classes().collect::<Result<_>>()?)?;
+    let mut matches = Vec::new();
+
+    // classes with unique identical hashes on both sides.
+    let mut hashes: HashMap<u64, (Vec<usize>, Vec<usize>)> = HashMap::new();
+    for (index, (_, fingerprint)) in left.iter().enumerate() {
+        hashes.entry(fingerprint.hash).or_default().0.push(index);
+    }
+    for (index, (_, fingerprint)) in right.iter().enumerate() {
+        hashes.entry(fingerprint.hash).or_default().1.push(index);
+    }
+    let (mut left_matched, mut right_matched) = (vec![false; left.len()], vec![false; right.len()]);
+    for (lefts, rights) in hashes.values() {
+        if let ([l], [r]) = (lefts.as_slice(), rights.as_slice()) {
+            left_matched[*l] = true;
+            right_matched[*r] = true;
+            matches.push(ClassMatch {
+                left: left[*l].0.clone(),
+                right: right[*r].0.clone(),
+                similarity: 1.0,
+            });
+        }
+    }
+    let unmatched = |classes: Vec<(String, Fingerprint)>, matched: Vec<bool>| {
+        classes
+            .into_iter()
+            .zip(matched)
+            .filter_map(|(class, matched)| if matched { None } else { Some(class) })
+            .collect::<Vec<_>>()
+    };
+    let left = unmatched(left, left_matched);
+    let right = unmatched(right, right_matched);
+
+    let mut candidates = Vec::new();
+    for (l, (_, left_fingerprint)) in left.iter().enumerate() {
+        for (r, (_, right_fingerprint)) in right.iter().enumerate() {
+            let similarity = left_fingerprint.similarity(right_fingerprint);
+            if similarity >= min_similarity {
+                candidates.push((similarity, l, r));
+            }
+        }
+    }
+    // stable sort keeps the file order for equally similar pairs.
+    candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    let (mut left_matched, mut right_matched) = (vec![false; left.len()], vec![false; right.len()]);
+    for (similarity, l, r) in candidates {
+        if left_matched[l] || right_matched[r] {
+            continue;
+        }
+        left_matched[l] = true;
+        right_matched[r] = true;
+        matches.push(ClassMatch {
+            left: left[l].0.clone(),
+            right: right[r].0.clone(),
+            similarity,
+        });
+    }
+    debug!(target: "fingerprint", "matched {} classes", matches.len());
+    Ok(matches)
+}
+
+/// Keeps primitive and framework types, other class types are replaced by `L;`.
+fn normalize(jtype: &Type) -> String {
+    let descriptor: &str = jtype.type_descriptor();
+    let element = descriptor.trim_start_matches('[');
+    if !element.starts_with('L') || FRAMEWORK_PACKAGES.iter().any(|p| element.starts_with(p)) {
+        descriptor.to_string()
+    } else {
+        format!("{}L;", &descriptor[..descriptor.len() - element.len()])
+    }
+}
+
+/// 64-bit FNV-1a, used instead of `DefaultHasher` so that fingerprints are stable across
+/// builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::match_classes;
+
+    #[test]
+    fn test_match_classes_with_itself() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let classes = dex.classes().count();
+        let matches = match_classes(&dex, &dex, 0.5).expect("failed to match classes");
+        assert_eq!(matches.len(), classes);
+        assert!(matches.iter().all(|m| m.similarity() == 1.0));
+        // structurally identical classes, like small listeners, can be swapped.
+        let same = matches.iter().filter(|m| m.left() == m.right()).count();
+        assert!(same * 10 > classes * 9);
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_names() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let launcher = dex
+            .find_class_by_name("Lorg/adw/launcher/Launcher;")
+            .expect("failed to load class")
+            .expect("class not found");
+        let fingerprint = launcher.structural_fingerprint().unwrap();
+        assert_eq!(fingerprint, launcher.structural_fingerprint().unwrap());
+        assert_eq!(fingerprint.similarity(&fingerprint), 1.0);
+        assert!(dex
+            .classes()
+            .filter_map(|c| c.ok())
+            .filter(|c| c.jtype() != launcher.jtype())
+            .all(|c| c.structural_fingerprint().unwrap().similarity(&fingerprint) < 1.0));
+    }
+}
@@ -0,0 +1,173 @@
+//! Read-only snapshots of a `Dex` which can be shared between threads, see `Dex::freeze`.
+//!
+//! A `Dex` can be shared between threads too, but its caches evict items and its classes
+//! are parsed on each access. Freezing it parses everything once into unbounded caches,
+//! after which the snapshot is only read from.
+use std::{collections::HashMap, ops::Deref, sync::Arc};
+
+use crate::{
+    cache::{CacheConfig, CacheSize},
+    class::Class,
+    Dex, Result,
+};
+
+/// A `Dex` whose caches hold all its strings, types, prototypes and field and method ids,
+/// with all its classes parsed along with their code and annotations. It derefs to the
+/// `Dex`, whose lookups then only read the caches, and keeps the parsed classes, see
+/// `all_classes` and `class_by_name`. Cloning it is cheap.
+#[derive(Debug)]
+pub struct FrozenDex<T> {
+    dex: Dex<T>,
+    classes: Arc<Vec<Class<'static>>>,
+    /// Index of the classes by type descriptor.
+    class_index: Arc<HashMap<String, usize>>,
+}
+
+impl<T> Clone for FrozenDex<T> {
+    fn clone(&self) -> Self {
+        Self {
+            dex: self.dex.clone(),
+            classes: self.classes.clone(),
+            class_index: self.class_index.clone(),
+        }
+    }
+}
+
+impl<T> FrozenDex<T>
+where
+    T: AsRef<[u8]>,
+{
+    pub(crate) fn new(dex: &Dex<T>) -> Result<Self> {
+        let dex = dex
+            .clone()
+            .with_cache_config(CacheConfig::all(CacheSize::Unbounded));
+        dex.strings().try_for_each(|string| string.map(drop))?;
+        dex.types().try_for_each(|jtype| jtype.map(drop))?;
+        for proto_id in 0..dex.inner.proto_ids_len() {
+            dex.get_prototype(proto_id.into())?;
+        }
+        dex.field_ids().try_for_each(|field| field.map(drop))?;
+        dex.method_ids().try_for_each(|method| method.map(drop))?;
+        let classes = dex
+            .classes()
+            .map(|class| class?.into_owned())
+            .collect::<Result<Vec<_>>>()?;
+        let class_index = classes
+            .iter()
+            .enumerate()
+            .map(|(index, class)| (class.jtype().type_descriptor().to_string(), index))
+            .collect();
+        debug!(target: "frozen", "froze {} classes", classes.len());
+        Ok(Self {
+            dex,
+            classes: Arc::new(classes),
+            class_index: Arc::new(class_index),
+        })
+    }
+}
+
+impl<T> FrozenDex<T> {
+    /// The frozen `Dex`.
+    pub fn dex(&self) -> &Dex<T> {
+        &self.dex
+    }
+
+    /// All the classes, in the order of the class_defs section.
+    pub fn all_classes(&self) -> &[Class<'static>] {
+        &self.classes
+    }
+
+    /// The class with the type descriptor `type_descriptor`, like `Lcom/example/Main;`.
+    pub fn class_by_name(&self, type_descriptor: &str) -> Option<&Class<'static>> {
+        self.class_index
+            .get(type_descriptor)
+            .map(|index| &self.classes[*index])
+    }
+}
+
+impl<T> Deref for FrozenDex<T> {
+    type Target = Dex<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.dex
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrozenDex;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_freeze() {
+        assert_send_sync::<FrozenDex<Vec<u8>>>();
+        let dex = crate::DexReader::from_file("resources/classes.dex")
+            .expect("failed to open dex")
+            .with_container("classes.dex");
+        let frozen = dex.freeze().expect("failed to freeze dex");
+        assert_eq!(
+            frozen.all_classes().len(),
+            dex.header().class_defs_size() as usize
+        );
+        let provenance = frozen.provenance();
+        assert_eq!(provenance.container(), Some("classes.dex"));
+        assert_eq!(provenance.sha256(), dex.provenance().sha256());
+        assert_eq!(provenance.string_cache_size(), usize::MAX);
+
+        let launcher = "Lorg/adw/launcher/Launcher;";
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let frozen = frozen.clone();
+                thread::spawn(move || {
+                    let class = frozen.class_by_name(launcher).unwrap();
+                    assert_eq!(frozen.get_type(class.id()).unwrap(), launcher);
+                    class.methods().count()
+                })
+            })
+            .collect();
+        let class = dex.find_class_by_name(launcher).unwrap().unwrap();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), class.methods().count());
+        }
+
+        let frozen_class = frozen.class_by_name(launcher).unwrap();
+        assert_eq!(
+            frozen_class.superclass_type().unwrap(),
+            "Landroid/app/Activity;"
+        );
+        assert_eq!(frozen_class.source_file().unwrap(), "Launcher.java");
+        assert_eq!(
+            frozen_class.try_annotations().unwrap().len(),
+            class.try_annotations().unwrap().len()
+        );
+        for (frozen_method, method) in frozen_class.methods().zip(class.methods()) {
+            assert_eq!(frozen_method.name(), method.name());
+            assert_eq!(
+                frozen_method.try_code().unwrap().map(|code| code.insns()),
+                method.try_code().unwrap().map(|code| code.insns())
+            );
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_par_classes() {
+        use rayon::iter::ParallelIterator;
+
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let classes = dex
+            .par_classes()
+            .map(|class| Ok(class?.jtype().clone()))
+            .collect::<crate::Result<Vec<_>>>()
+            .expect("failed to load classes");
+        let frozen = dex.freeze().unwrap();
+        let frozen_classes: Vec<_> = frozen
+            .all_classes()
+            .iter()
+            .map(|class| class.jtype().clone())
+            .collect();
+        assert_eq!(classes, frozen_classes);
+    }
+}
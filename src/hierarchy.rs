@@ -0,0 +1,370 @@
+//! Class hierarchy built from the `ClassDefItem`s of a `Dex`.
+use std::collections::{HashMap, HashSet};
+
+use getset::{CopyGetters, Getters};
+
+use crate::{
+    error::Error,
+    method::{AccessFlags, MethodId, ProtoId},
+    Dex, Result, NO_INDEX,
+};
+
+/// A method declared by a class of the hierarchy.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct DeclaredMethod {
+    /// Type descriptor of the declaring class.
+    #[get = "pub"]
+    class: String,
+    /// Name of the method.
+    #[get = "pub"]
+    name: String,
+    /// Descriptor of the prototype of the method, e.g. `(ILjava/lang/String;)V`.
+    #[get = "pub"]
+    descriptor: String,
+    /// Access flags of the method.
+    #[get_copy = "pub"]
+    access_flags: AccessFlags,
+    /// `MethodId` of the method, if it is defined in the `Dex`.
+    #[get_copy = "pub"]
+    id: Option<MethodId>,
+}
+
+impl DeclaredMethod {
+    gen_is_flag_set!(is_static, STATIC);
+    gen_is_flag_set!(is_private, PRIVATE);
+    gen_is_flag_set!(is_abstract, ABSTRACT);
+
+    fn overrides(&self, name: &str, descriptor: &str) -> bool {
+        !self.is_static()
+            && !self.is_private()
+            && self.name == name
+            && self.descriptor == descriptor
+    }
+}
+
+/// Outcome of resolving a virtual or interface call with `ClassHierarchy::resolve_virtual`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution<'a> {
+    /// The method which is invoked.
+    Method(&'a DeclaredMethod),
+    /// Only an abstract declaration was selected, the call throws `AbstractMethodError`.
+    Abstract(&'a DeclaredMethod),
+    /// Several maximally specific default methods apply, the call throws
+    /// `IncompatibleClassChangeError`.
+    Conflict(Vec<&'a DeclaredMethod>),
+}
+
+/// Super classes and interfaces of classes, keyed by type descriptor. Classes that are
+/// not defined in the `Dex` (for example, framework classes) can be added with `insert` so
+/// that queries can walk through them.
+#[derive(Debug, Default, Clone)]
+pub struct ClassHierarchy {
+    super_classes: HashMap<String, String>,
+    interfaces: HashMap<String, Vec<String>>,
+    methods: HashMap<String, Vec<DeclaredMethod>>,
+}
+
+impl ClassHierarchy {
+    /// Builds the hierarchy of the classes defined in `dex`. Only the class_defs section,
+    /// the class data and the referenced ids are read, no class is fully loaded.
+    pub fn from_dex<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<Self> {
+        let mut hierarchy = Self::default();
+        for class_def in dex.class_defs() {
+            let class_def = class_def?;
+            let jtype = dex.get_type(class_def.class_idx())?;
+            let super_class = if class_def.superclass_idx() != NO_INDEX {
+                Some(dex.get_type(class_def.superclass_idx())?)
+            } else {
+                None
+            };
+            let interfaces = dex.get_interfaces(class_def.interfaces_off())?;
+            hierarchy.insert(
+                jtype.type_descriptor(),
+                super_class.as_ref().map(|s| &**s.type_descriptor()),
+                interfaces.iter().map(|i| &**i.type_descriptor()),
+            );
+            let class_data = match dex.get_class_data(class_def.class_data_off())? {
+                Some(class_data) => class_data,
+                None => continue,
+            };
+            let encoded_methods = class_data
+                .direct_methods()
+                .into_iter()
+                .chain(class_data.virtual_methods())
+                .flat_map(|methods| methods.inner());
+            for encoded_method in encoded_methods {
+                let method_item = dex.get_method_item(encoded_method.method_id())?;
+                let prototype = dex.get_prototype(ProtoId::from(method_item.proto_idx()))?;
+                let descriptor = crate::smali::signature(&prototype.params, &prototype.return_type);
+                let access_flags = AccessFlags::from_bits(*encoded_method.access_flags())
+                    .ok_or_else(|| {
+                        Error::InvalidId(format!(
+                            "Invalid access flags for method {}",
+                            method_item.name_idx()
+                        ))
+                    })?;
+                hierarchy
+                    .methods
+                    .entry(jtype.to_string())
+                    .or_default()
+                    .push(DeclaredMethod {
+                        class: jtype.to_string(),
+                        name: dex.get_string(method_item.name_idx())?.to_string(),
+                        descriptor,
+                        access_flags,
+                        id: Some(encoded_method.method_id()),
+                    });
+            }
+        }
+        debug!(target: "hierarchy", "loaded hierarchy of {} classes", hierarchy.super_classes.len());
+        Ok(hierarchy)
+    }
+
+    /// Adds a class, or replaces it if it is already present.
+    pub fn insert<'a, I>(&mut self, class: &str, super_class: Option<&str>, interfaces: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        match super_class {
+            Some(super_class) => {
+                self.super_classes
+                    .insert(class.to_string(), super_class.to_string());
+            }
+            None => {
+                self.super_classes.remove(class);
+            }
+        }
+        let interfaces: Vec<_> = interfaces.into_iter().map(str::to_string).collect();
+        if interfaces.is_empty() {
+            self.interfaces.remove(class);
+        } else {
+            self.interfaces.insert(class.to_string(), interfaces);
+        }
+    }
+
+    /// Adds a method declared by `class`, for example a default method of a framework
+    /// interface. `descriptor` is the descriptor of its prototype, e.g. `(I)V`.
+    pub fn insert_method(
+        &mut self,
+        class: &str,
+        name: &str,
+        descriptor: &str,
+        access_flags: AccessFlags,
+    ) {
+        self.methods
+            .entry(class.to_string())
+            .or_default()
+            .push(DeclaredMethod {
+                class: class.to_string(),
+                name: name.to_string(),
+                descriptor: descriptor.to_string(),
+                access_flags,
+                id: None,
+            });
+    }
+
+    /// Returns the known methods declared by `class`.
+    pub fn methods(&self, class: &str) -> &[DeclaredMethod] {
+        self.methods.get(class).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Returns the super class of `class`, if it is known.
+    pub fn super_class(&self, class: &str) -> Option<&str> {
+        self.super_classes.get(class).map(String::as_str)
+    }
+
+    /// Returns the interfaces directly implemented by `class`.
+    pub fn interfaces(&self, class: &str) -> &[String] {
+        self.interfaces.get(class).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Iterator over the known super classes of `class`, starting with its direct super class.
+    pub fn super_classes<'a>(&'a self, class: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let mut current = Some(class);
+        // guards against cycles in malformed files.
+        let mut remaining = self.super_classes.len();
+        std::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            current = self.super_class(current?);
+            current
+        })
+    }
+
+    /// Returns `true` if `class` is `ancestor` or one of its known sub classes.
+    pub fn is_subclass_of(&self, class: &str, ancestor: &str) -> bool {
+        class == ancestor || self.super_classes(class).any(|c| c == ancestor)
+    }
+
+    /// All the known interfaces implemented by `class`, directly or through its super
+    /// classes and super interfaces.
+    pub fn all_interfaces<'a>(&'a self, class: &'a str) -> Vec<&'a str> {
+        let mut seen = HashSet::new();
+        let mut pending: Vec<&str> = std::iter::once(class)
+            .chain(self.super_classes(class))
+            .collect();
+        pending.reverse();
+        let mut all = Vec::new();
+        while let Some(current) = pending.pop() {
+            for interface in self.interfaces(current).iter().rev() {
+                if seen.insert(interface.as_str()) {
+                    pending.push(interface);
+                }
+            }
+            if current != class && seen.contains(current) {
+                all.push(current);
+            }
+        }
+        all
+    }
+
+    /// Returns `true` if `interface` is `ancestor` or one of its known sub interfaces.
+    pub fn is_subinterface_of(&self, interface: &str, ancestor: &str) -> bool {
+        interface == ancestor || self.all_interfaces(interface).contains(&ancestor)
+    }
+
+    /// Selects the method invoked by a virtual or interface call of `name` with prototype
+    /// `descriptor` on a receiver of type `class`, following the rules used by ART. The super
+    /// class chain is searched first, then the maximally specific methods of the super
+    /// interfaces, where a default method wins over abstract declarations. Package private
+    /// access isn't taken into account. Returns `None` if no method is known.
+    pub fn resolve_virtual(
+        &self,
+        class: &str,
+        name: &str,
+        descriptor: &str,
+    ) -> Option<Resolution<'_>> {
+        let declared = std::iter::once(class)
+            .chain(self.super_classes(class))
+            .find_map(|c| {
+                self.methods(c)
+                    .iter()
+                    .find(|m| m.overrides(name, descriptor))
+            });
+        if let Some(method) = declared {
+            return Some(if method.is_abstract() {
+                Resolution::Abstract(method)
+            } else {
+                Resolution::Method(method)
+            });
+        }
+
+        let candidates: Vec<&DeclaredMethod> = self
+            .all_interfaces(class)
+            .into_iter()
+            .filter_map(|i| {
+                self.methods(i)
+                    .iter()
+                    .find(|m| m.overrides(name, descriptor))
+            })
+            .collect();
+        let maximally_specific: Vec<&DeclaredMethod> = candidates
+            .iter()
+            .filter(|m| {
+                !candidates.iter().any(|other| {
+                    other.class != m.class && self.is_subinterface_of(&other.class, &m.class)
+                })
+            })
+            .cloned()
+            .collect();
+        let mut defaults: Vec<&DeclaredMethod> = maximally_specific
+            .iter()
+            .filter(|m| !m.is_abstract())
+            .cloned()
+            .collect();
+        match defaults.len() {
+            0 => maximally_specific.first().map(|m| Resolution::Abstract(m)),
+            1 => defaults.pop().map(Resolution::Method),
+            _ => Some(Resolution::Conflict(defaults)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClassHierarchy, Resolution};
+    use crate::method::AccessFlags;
+
+    #[test]
+    fn test_hierarchy_from_dex() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let mut hierarchy = ClassHierarchy::from_dex(&dex).expect("failed to load hierarchy");
+        let launcher = "Lorg/adw/launcher/Launcher;";
+        assert_eq!(
+            hierarchy.super_class(launcher),
+            Some("Landroid/app/Activity;")
+        );
+        assert!(hierarchy.is_subclass_of(launcher, launcher));
+        assert!(!hierarchy.is_subclass_of(launcher, "Ljava/lang/Object;"));
+
+        hierarchy.insert("Landroid/app/Activity;", Some("Ljava/lang/Object;"), vec![]);
+        assert!(hierarchy.is_subclass_of(launcher, "Ljava/lang/Object;"));
+        assert_eq!(
+            hierarchy.super_classes(launcher).collect::<Vec<_>>(),
+            vec!["Landroid/app/Activity;", "Ljava/lang/Object;"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_virtual_from_dex() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let hierarchy = ClassHierarchy::from_dex(&dex).expect("failed to load hierarchy");
+        let launcher = "Lorg/adw/launcher/Launcher;";
+        match hierarchy.resolve_virtual(launcher, "onCreate", "(Landroid/os/Bundle;)V") {
+            Some(Resolution::Method(method)) => {
+                assert_eq!(method.class(), launcher);
+                assert!(method.id().is_some());
+            }
+            resolution => panic!("unexpected resolution: {:?}", resolution),
+        }
+        // framework methods are unknown unless inserted.
+        assert!(hierarchy
+            .resolve_virtual(launcher, "finish", "()V")
+            .is_none());
+    }
+
+    #[test]
+    fn test_resolve_default_methods() {
+        let public = AccessFlags::PUBLIC;
+        let abstract_ = AccessFlags::PUBLIC | AccessFlags::ABSTRACT;
+        let mut hierarchy = ClassHierarchy::default();
+        hierarchy.insert("LA;", None, vec![]);
+        hierarchy.insert("LB;", None, vec!["LA;"]);
+        hierarchy.insert("LC;", None, vec![]);
+        hierarchy.insert("LBase;", Some("Ljava/lang/Object;"), vec!["LB;"]);
+        hierarchy.insert("LImpl;", Some("LBase;"), vec!["LA;"]);
+        hierarchy.insert("LBoth;", Some("Ljava/lang/Object;"), vec!["LB;", "LC;"]);
+        hierarchy.insert_method("LA;", "run", "()V", public);
+        hierarchy.insert_method("LB;", "run", "()V", public);
+        hierarchy.insert_method("LC;", "run", "()V", public);
+        hierarchy.insert_method("LA;", "stop", "()V", abstract_);
+
+        let class_of = |resolution: Option<Resolution<'_>>| match resolution {
+            Some(Resolution::Method(m)) => m.class().clone(),
+            resolution => panic!("unexpected resolution: {:?}", resolution),
+        };
+        // B is more specific than A, even though A is also implemented directly.
+        assert_eq!(
+            class_of(hierarchy.resolve_virtual("LImpl;", "run", "()V")),
+            "LB;"
+        );
+        match hierarchy.resolve_virtual("LBoth;", "run", "()V") {
+            Some(Resolution::Conflict(methods)) => assert_eq!(methods.len(), 2),
+            resolution => panic!("unexpected resolution: {:?}", resolution),
+        }
+        match hierarchy.resolve_virtual("LImpl;", "stop", "()V") {
+            Some(Resolution::Abstract(method)) => assert_eq!(method.class(), "LA;"),
+            resolution => panic!("unexpected resolution: {:?}", resolution),
+        }
+
+        // methods of the class chain win over default methods.
+        hierarchy.insert_method("LBase;", "run", "()V", public);
+        assert_eq!(
+            class_of(hierarchy.resolve_virtual("LImpl;", "run", "()V")),
+            "LBase;"
+        );
+        assert!(hierarchy.resolve_virtual("LImpl;", "run", "(I)V").is_none());
+    }
+}
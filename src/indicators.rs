@@ -0,0 +1,214 @@
+//! Network indicators, like URLs, IP addresses and domains, in the strings used by the code
+//! of a `Dex`, see `extract_network_indicators`.
+use std::collections::{BTreeMap, BTreeSet};
+
+use getset::{CopyGetters, Getters};
+
+use crate::{method::MethodId, strings::string_references, Dex, Result};
+
+/// Top level domains accepted for bare domain names. Without a scheme, names like
+/// `android.intent.action.MAIN` can't be told apart from domains, so only common top
+/// level domains are accepted.
+const TOP_LEVEL_DOMAINS: [&str; 24] = [
+    "com", "net", "org", "info", "biz", "io", "co", "me", "ru", "cn", "de", "uk", "fr", "br", "in",
+    "jp", "kr", "tk", "top", "xyz", "online", "site", "club", "pw",
+];
+
+/// Schemes of URLs which refer to network resources, unlike `content://` or `file://`.
+const NETWORK_SCHEMES: [&str; 10] = [
+    "http", "https", "ftp", "ftps", "ws", "wss", "rtsp", "rtmp", "tcp", "udp",
+];
+
+/// Kind of a `NetworkIndicator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IndicatorKind {
+    /// A URL with a scheme, like `https://example.com/path`.
+    Url,
+    /// A domain name, either bare or the host of a URL.
+    Domain,
+    /// An IPv4 address, either bare or the host of a URL.
+    Ip,
+}
+
+/// A URL, domain or IP address found in the strings of a `Dex`.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct NetworkIndicator {
+    /// Kind of the indicator.
+    #[get_copy = "pub"]
+    kind: IndicatorKind,
+    /// The URL, domain or IP address.
+    #[get = "pub"]
+    value: String,
+    /// Methods which use a string containing the indicator.
+    #[get = "pub"]
+    methods: Vec<MethodId>,
+}
+
+/// Finds the URLs, domains and IP addresses in the strings used by the methods of `dex`,
+/// including strings built by constant concatenations (see `string_references`). The hosts
+/// of URLs are reported as domains or IP addresses too. Results are sorted by kind and value.
+pub fn extract_network_indicators<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<Vec<NetworkIndicator>> {
+    let mut indicators: BTreeMap<(IndicatorKind, String), BTreeSet<MethodId>> = BTreeMap::new();
+    for reference in string_references(dex)? {
+        for indicator in network_indicators(reference.value()) {
+            indicators
+                .entry(indicator)
+                .or_default()
+                .insert(reference.method());
+        }
+    }
+    Ok(indicators
+        .into_iter()
+        .map(|((kind, value), methods)| NetworkIndicator {
+            kind,
+            value,
+            methods: methods.into_iter().collect(),
+        })
+        .collect())
+}
+
+/// Indicators in a single string.
+fn network_indicators(value: &str) -> Vec<(IndicatorKind, String)> {
+    let mut indicators = Vec::new();
+    let separator = |c: char| c.is_whitespace() || "\"'<>()[]{},;|\\^`".contains(c);
+    for token in value.split(separator).filter(|token| !token.is_empty()) {
+        if let Some(start) = token.find("://") {
+            let scheme = &token[..start];
+            let scheme_start = scheme
+                .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '+' && c != '-' && c != '.')
+                .map_or(0, |index| index + 1);
+            let scheme = &scheme[scheme_start..];
+            if !NETWORK_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()) {
+                continue;
+            }
+            let rest = &token[start + 3..];
+            let authority = &rest[..rest.find(['/', '?', '#']).unwrap_or(rest.len())];
+            let host = authority.rsplit('@').next().unwrap_or_default();
+            let host = host.split(':').next().unwrap_or_default();
+            let kind = if is_ipv4(host) {
+                IndicatorKind::Ip
+            } else if is_domain(host) || host == "localhost" {
+                IndicatorKind::Domain
+            } else {
+                continue;
+            };
+            indicators.push((IndicatorKind::Url, token[scheme_start..].to_string()));
+            indicators.push((kind, host.to_ascii_lowercase()));
+        } else {
+            let host = token.trim_end_matches('.');
+            let host = match host.rsplit_once(':') {
+                Some((host, port))
+                    if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) =>
+                {
+                    host
+                }
+                _ => host,
+            };
+            if is_ipv4(host) {
+                indicators.push((IndicatorKind::Ip, host.to_string()));
+            } else if is_domain(host) && !is_package_name(host) {
+                let tld = host
+                    .rsplit('.')
+                    .next()
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+                if TOP_LEVEL_DOMAINS.contains(&tld.as_str()) {
+                    indicators.push((IndicatorKind::Domain, host.to_ascii_lowercase()));
+                }
+            }
+        }
+    }
+    indicators
+}
+
+/// Dotted quad IPv4 address, without leading zeros.
+fn is_ipv4(host: &str) -> bool {
+    let octets: Vec<&str> = host.split('.').collect();
+    octets.len() == 4
+        && octets.iter().all(|octet| {
+            !octet.is_empty()
+                && octet.len() <= 3
+                && octet.bytes().all(|b| b.is_ascii_digit())
+                && (octet.len() == 1 || !octet.starts_with('0'))
+                && octet.parse::<u8>().is_ok()
+        })
+}
+
+/// At least two labels of letters, digits and hyphens, and a top level domain of letters.
+fn is_domain(host: &str) -> bool {
+    let labels: Vec<&str> = host.split('.').collect();
+    let tld = labels[labels.len() - 1];
+    labels.len() >= 2
+        && host.len() <= 253
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+        && tld.len() >= 2
+        && tld.bytes().all(|b| b.is_ascii_alphabetic())
+}
+
+/// Java package and class names in reverse domain notation, like `com.example.app`.
+fn is_package_name(name: &str) -> bool {
+    let first = name.split('.').next().unwrap_or_default();
+    [
+        "android", "androidx", "com", "dalvik", "io", "java", "javax", "kotlin", "net", "org",
+    ]
+    .contains(&first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_network_indicators, network_indicators, IndicatorKind};
+
+    #[test]
+    fn test_network_indicators() {
+        let indicators = network_indicators;
+        assert_eq!(
+            indicators("see https://user@Example.com:8443/a?b=c, or 10.0.0.1:80"),
+            vec![
+                (
+                    IndicatorKind::Url,
+                    "https://user@Example.com:8443/a?b=c".to_string()
+                ),
+                (IndicatorKind::Domain, "example.com".to_string()),
+                (IndicatorKind::Ip, "10.0.0.1".to_string()),
+            ]
+        );
+        assert_eq!(
+            indicators("url=\"ws://192.168.1.20/socket\""),
+            vec![
+                (IndicatorKind::Url, "ws://192.168.1.20/socket".to_string()),
+                (IndicatorKind::Ip, "192.168.1.20".to_string()),
+            ]
+        );
+        assert_eq!(
+            indicators("evil-c2.xyz"),
+            vec![(IndicatorKind::Domain, "evil-c2.xyz".to_string())]
+        );
+        assert!(indicators("content://org.adw.launcher.settings/favorites").is_empty());
+        assert!(indicators("com.example.app").is_empty());
+        assert!(indicators("android.intent.action.MAIN").is_empty());
+        assert!(indicators("version 1.02.3.4 or 256.1.1.1").is_empty());
+    }
+
+    #[test]
+    fn test_extract_network_indicators() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let indicators = extract_network_indicators(&dex).expect("failed to find indicators");
+        let xmlpull = indicators
+            .iter()
+            .find(|i| i.kind() == IndicatorKind::Domain && i.value() == "xmlpull.org")
+            .expect("domain not found");
+        assert_eq!(xmlpull.methods().len(), 3);
+        assert!(indicators
+            .iter()
+            .any(|i| i.value() == "http://schemas.android.com/apk/res/android"));
+        assert!(indicators.iter().all(|i| !i.value().contains("content://")));
+    }
+}
@@ -0,0 +1,1427 @@
+//! Decoding of Dalvik bytecode found in a `CodeItem`.
+//! [Android docs](https://source.android.com/devices/tech/dalvik/dalvik-bytecode)
+use getset::CopyGetters;
+
+use crate::{error::Error, field::FieldId, method::MethodId, ubyte, uint, ushort, Result};
+
+/// Format of an instruction. The name of a format describes its size in code units,
+/// the number of registers it takes and the kind of additional data it carries.
+/// [Android docs](https://source.android.com/devices/tech/dalvik/instruction-formats)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    F10x,
+    F12x,
+    F11n,
+    F11x,
+    F10t,
+    F20t,
+    F20bc,
+    F22x,
+    F21t,
+    F21s,
+    F21h,
+    F21c,
+    F23x,
+    F22b,
+    F22t,
+    F22s,
+    F22c,
+    F22cs,
+    F30t,
+    F32x,
+    F31i,
+    F31t,
+    F31c,
+    F35c,
+    F35ms,
+    F35mi,
+    F3rc,
+    F3rms,
+    F3rmi,
+    F45cc,
+    F4rcc,
+    F51l,
+    /// Data of a `packed-switch` instruction.
+    PackedSwitchPayload,
+    /// Data of a `sparse-switch` instruction.
+    SparseSwitchPayload,
+    /// Data of a `fill-array-data` instruction.
+    FillArrayDataPayload,
+}
+
+impl Format {
+    /// Size of the instruction in code units, or `None` for the variable sized payloads.
+    pub fn size(self) -> Option<usize> {
+        use Format::*;
+        Some(match self {
+            F10x | F12x | F11n | F11x | F10t => 1,
+            F20t | F20bc | F22x | F21t | F21s | F21h | F21c | F23x | F22b | F22t | F22s | F22c
+            | F22cs => 2,
+            F30t | F32x | F31i | F31t | F31c | F35c | F35ms | F35mi | F3rc | F3rms | F3rmi => 3,
+            F45cc | F4rcc => 4,
+            F51l => 5,
+            PackedSwitchPayload | SparseSwitchPayload | FillArrayDataPayload => return None,
+        })
+    }
+}
+
+/// The kind of item the index operand of an instruction refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndexType {
+    /// The instruction has no index operand.
+    None,
+    /// Index into the `StringId`s list.
+    String,
+    /// Index into the `TypeId`s list.
+    Type,
+    /// Index into the `FieldId`s list.
+    Field,
+    /// Index into the `MethodId`s list.
+    Method,
+    /// Index into the `MethodHandleItem`s list.
+    MethodHandle,
+    /// Index into the `ProtoId`s list.
+    Proto,
+    /// Index into the `CallSiteId`s list.
+    CallSite,
+    /// Byte offset of a field in an object. Used by quickened field accessors.
+    FieldOffset,
+    /// Index into the vtable of the receiver. Used by quickened invokes.
+    VtableOffset,
+    /// Index into the VM's table of inlined methods.
+    InlineMethod,
+    /// Kind of a verification error, used by `throw-verification-error`.
+    VerificationError,
+}
+
+macro_rules! opcodes {
+    (
+        $($name:ident = $value:literal => ($mnemonic:literal, $format:ident, $index:ident)),+;
+        $($ename:ident => ($emnemonic:literal, $eformat:ident, $eindex:ident)),+
+    ) => {
+        /// Operation performed by an instruction. Besides the standard opcodes, this also
+        /// contains the implementation specific (quickened) opcodes and the pseudo-opcodes
+        /// for the payloads of switch and array instructions.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Opcode {
+            $($name,)+
+            $($ename,)+
+        }
+
+        impl Opcode {
+            /// Name of the opcode as used in the Android docs and smali.
+            pub fn mnemonic(self) -> &'static str {
+                match self {
+                    $(Opcode::$name => $mnemonic,)+
+                    $(Opcode::$ename => $emnemonic,)+
+                }
+            }
+
+            /// Format of instructions with this opcode.
+            pub fn format(self) -> Format {
+                match self {
+                    $(Opcode::$name => Format::$format,)+
+                    $(Opcode::$ename => Format::$eformat,)+
+                }
+            }
+
+            /// The kind of item the index operand of this opcode refers to.
+            pub fn index_type(self) -> IndexType {
+                match self {
+                    $(Opcode::$name => IndexType::$index,)+
+                    $(Opcode::$ename => IndexType::$eindex,)+
+                }
+            }
+
+            /// Returns the standard opcode represented by `value`.
+            pub fn from_standard(value: ubyte) -> Option<Opcode> {
+                match value {
+                    $($value => Some(Opcode::$name),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+opcodes! {
+    Nop = 0x00 => ("nop", F10x, None),
+    Move = 0x01 => ("move", F12x, None),
+    MoveFrom16 = 0x02 => ("move/from16", F22x, None),
+    Move16 = 0x03 => ("move/16", F32x, None),
+    MoveWide = 0x04 => ("move-wide", F12x, None),
+    MoveWideFrom16 = 0x05 => ("move-wide/from16", F22x, None),
+    MoveWide16 = 0x06 => ("move-wide/16", F32x, None),
+    MoveObject = 0x07 => ("move-object", F12x, None),
+    MoveObjectFrom16 = 0x08 => ("move-object/from16", F22x, None),
+    MoveObject16 = 0x09 => ("move-object/16", F32x, None),
+    MoveResult = 0x0a => ("move-result", F11x, None),
+    MoveResultWide = 0x0b => ("move-result-wide", F11x, None),
+    MoveResultObject = 0x0c => ("move-result-object", F11x, None),
+    MoveException = 0x0d => ("move-exception", F11x, None),
+    ReturnVoid = 0x0e => ("return-void", F10x, None),
+    Return = 0x0f => ("return", F11x, None),
+    ReturnWide = 0x10 => ("return-wide", F11x, None),
+    ReturnObject = 0x11 => ("return-object", F11x, None),
+    Const4 = 0x12 => ("const/4", F11n, None),
+    Const16 = 0x13 => ("const/16", F21s, None),
+    Const = 0x14 => ("const", F31i, None),
+    ConstHigh16 = 0x15 => ("const/high16", F21h, None),
+    ConstWide16 = 0x16 => ("const-wide/16", F21s, None),
+    ConstWide32 = 0x17 => ("const-wide/32", F31i, None),
+    ConstWide = 0x18 => ("const-wide", F51l, None),
+    ConstWideHigh16 = 0x19 => ("const-wide/high16", F21h, None),
+    ConstString = 0x1a => ("const-string", F21c, String),
+    ConstStringJumbo = 0x1b => ("const-string/jumbo", F31c, String),
+    ConstClass = 0x1c => ("const-class", F21c, Type),
+    MonitorEnter = 0x1d => ("monitor-enter", F11x, None),
+    MonitorExit = 0x1e => ("monitor-exit", F11x, None),
+    CheckCast = 0x1f => ("check-cast", F21c, Type),
+    InstanceOf = 0x20 => ("instance-of", F22c, Type),
+    ArrayLength = 0x21 => ("array-length", F12x, None),
+    NewInstance = 0x22 => ("new-instance", F21c, Type),
+    NewArray = 0x23 => ("new-array", F22c, Type),
+    FilledNewArray = 0x24 => ("filled-new-array", F35c, Type),
+    FilledNewArrayRange = 0x25 => ("filled-new-array/range", F3rc, Type),
+    FillArrayData = 0x26 => ("fill-array-data", F31t, None),
+    Throw = 0x27 => ("throw", F11x, None),
+    Goto = 0x28 => ("goto", F10t, None),
+    Goto16 = 0x29 => ("goto/16", F20t, None),
+    Goto32 = 0x2a => ("goto/32", F30t, None),
+    PackedSwitch = 0x2b => ("packed-switch", F31t, None),
+    SparseSwitch = 0x2c => ("sparse-switch", F31t, None),
+    CmplFloat = 0x2d => ("cmpl-float", F23x, None),
+    CmpgFloat = 0x2e => ("cmpg-float", F23x, None),
+    CmplDouble = 0x2f => ("cmpl-double", F23x, None),
+    CmpgDouble = 0x30 => ("cmpg-double", F23x, None),
+    CmpLong = 0x31 => ("cmp-long", F23x, None),
+    IfEq = 0x32 => ("if-eq", F22t, None),
+    IfNe = 0x33 => ("if-ne", F22t, None),
+    IfLt = 0x34 => ("if-lt", F22t, None),
+    IfGe = 0x35 => ("if-ge", F22t, None),
+    IfGt = 0x36 => ("if-gt", F22t, None),
+    IfLe = 0x37 => ("if-le", F22t, None),
+    IfEqz = 0x38 => ("if-eqz", F21t, None),
+    IfNez = 0x39 => ("if-nez", F21t, None),
+    IfLtz = 0x3a => ("if-ltz", F21t, None),
+    IfGez = 0x3b => ("if-gez", F21t, None),
+    IfGtz = 0x3c => ("if-gtz", F21t, None),
+    IfLez = 0x3d => ("if-lez", F21t, None),
+    Aget = 0x44 => ("aget", F23x, None),
+    AgetWide = 0x45 => ("aget-wide", F23x, None),
+    AgetObject = 0x46 => ("aget-object", F23x, None),
+    AgetBoolean = 0x47 => ("aget-boolean", F23x, None),
+    AgetByte = 0x48 => ("aget-byte", F23x, None),
+    AgetChar = 0x49 => ("aget-char", F23x, None),
+    AgetShort = 0x4a => ("aget-short", F23x, None),
+    Aput = 0x4b => ("aput", F23x, None),
+    AputWide = 0x4c => ("aput-wide", F23x, None),
+    AputObject = 0x4d => ("aput-object", F23x, None),
+    AputBoolean = 0x4e => ("aput-boolean", F23x, None),
+    AputByte = 0x4f => ("aput-byte", F23x, None),
+    AputChar = 0x50 => ("aput-char", F23x, None),
+    AputShort = 0x51 => ("aput-short", F23x, None),
+    Iget = 0x52 => ("iget", F22c, Field),
+    IgetWide = 0x53 => ("iget-wide", F22c, Field),
+    IgetObject = 0x54 => ("iget-object", F22c, Field),
+    IgetBoolean = 0x55 => ("iget-boolean", F22c, Field),
+    IgetByte = 0x56 => ("iget-byte", F22c, Field),
+    IgetChar = 0x57 => ("iget-char", F22c, Field),
+    IgetShort = 0x58 => ("iget-short", F22c, Field),
+    Iput = 0x59 => ("iput", F22c, Field),
+    IputWide = 0x5a => ("iput-wide", F22c, Field),
+    IputObject = 0x5b => ("iput-object", F22c, Field),
+    IputBoolean = 0x5c => ("iput-boolean", F22c, Field),
+    IputByte = 0x5d => ("iput-byte", F22c, Field),
+    IputChar = 0x5e => ("iput-char", F22c, Field),
+    IputShort = 0x5f => ("iput-short", F22c, Field),
+    Sget = 0x60 => ("sget", F21c, Field),
+    SgetWide = 0x61 => ("sget-wide", F21c, Field),
+    SgetObject = 0x62 => ("sget-object", F21c, Field),
+    SgetBoolean = 0x63 => ("sget-boolean", F21c, Field),
+    SgetByte = 0x64 => ("sget-byte", F21c, Field),
+    SgetChar = 0x65 => ("sget-char", F21c, Field),
+    SgetShort = 0x66 => ("sget-short", F21c, Field),
+    Sput = 0x67 => ("sput", F21c, Field),
+    SputWide = 0x68 => ("sput-wide", F21c, Field),
+    SputObject = 0x69 => ("sput-object", F21c, Field),
+    SputBoolean = 0x6a => ("sput-boolean", F21c, Field),
+    SputByte = 0x6b => ("sput-byte", F21c, Field),
+    SputChar = 0x6c => ("sput-char", F21c, Field),
+    SputShort = 0x6d => ("sput-short", F21c, Field),
+    InvokeVirtual = 0x6e => ("invoke-virtual", F35c, Method),
+    InvokeSuper = 0x6f => ("invoke-super", F35c, Method),
+    InvokeDirect = 0x70 => ("invoke-direct", F35c, Method),
+    InvokeStatic = 0x71 => ("invoke-static", F35c, Method),
+    InvokeInterface = 0x72 => ("invoke-interface", F35c, Method),
+    InvokeVirtualRange = 0x74 => ("invoke-virtual/range", F3rc, Method),
+    InvokeSuperRange = 0x75 => ("invoke-super/range", F3rc, Method),
+    InvokeDirectRange = 0x76 => ("invoke-direct/range", F3rc, Method),
+    InvokeStaticRange = 0x77 => ("invoke-static/range", F3rc, Method),
+    InvokeInterfaceRange = 0x78 => ("invoke-interface/range", F3rc, Method),
+    NegInt = 0x7b => ("neg-int", F12x, None),
+    NotInt = 0x7c => ("not-int", F12x, None),
+    NegLong = 0x7d => ("neg-long", F12x, None),
+    NotLong = 0x7e => ("not-long", F12x, None),
+    NegFloat = 0x7f => ("neg-float", F12x, None),
+    NegDouble = 0x80 => ("neg-double", F12x, None),
+    IntToLong = 0x81 => ("int-to-long", F12x, None),
+    IntToFloat = 0x82 => ("int-to-float", F12x, None),
+    IntToDouble = 0x83 => ("int-to-double", F12x, None),
+    LongToInt = 0x84 => ("long-to-int", F12x, None),
+    LongToFloat = 0x85 => ("long-to-float", F12x, None),
+    LongToDouble = 0x86 => ("long-to-double", F12x, None),
+    FloatToInt = 0x87 => ("float-to-int", F12x, None),
+    FloatToLong = 0x88 => ("float-to-long", F12x, None),
+    FloatToDouble = 0x89 => ("float-to-double", F12x, None),
+    DoubleToInt = 0x8a => ("double-to-int", F12x, None),
+    DoubleToLong = 0x8b => ("double-to-long", F12x, None),
+    DoubleToFloat = 0x8c => ("double-to-float", F12x, None),
+    IntToByte = 0x8d => ("int-to-byte", F12x, None),
+    IntToChar = 0x8e => ("int-to-char", F12x, None),
+    IntToShort = 0x8f => ("int-to-short", F12x, None),
+    AddInt = 0x90 => ("add-int", F23x, None),
+    SubInt = 0x91 => ("sub-int", F23x, None),
+    MulInt = 0x92 => ("mul-int", F23x, None),
+    DivInt = 0x93 => ("div-int", F23x, None),
+    RemInt = 0x94 => ("rem-int", F23x, None),
+    AndInt = 0x95 => ("and-int", F23x, None),
+    OrInt = 0x96 => ("or-int", F23x, None),
+    XorInt = 0x97 => ("xor-int", F23x, None),
+    ShlInt = 0x98 => ("shl-int", F23x, None),
+    ShrInt = 0x99 => ("shr-int", F23x, None),
+    UshrInt = 0x9a => ("ushr-int", F23x, None),
+    AddLong = 0x9b => ("add-long", F23x, None),
+    SubLong = 0x9c => ("sub-long", F23x, None),
+    MulLong = 0x9d => ("mul-long", F23x, None),
+    DivLong = 0x9e => ("div-long", F23x, None),
+    RemLong = 0x9f => ("rem-long", F23x, None),
+    AndLong = 0xa0 => ("and-long", F23x, None),
+    OrLong = 0xa1 => ("or-long", F23x, None),
+    XorLong = 0xa2 => ("xor-long", F23x, None),
+    ShlLong = 0xa3 => ("shl-long", F23x, None),
+    ShrLong = 0xa4 => ("shr-long", F23x, None),
+    UshrLong = 0xa5 => ("ushr-long", F23x, None),
+    AddFloat = 0xa6 => ("add-float", F23x, None),
+    SubFloat = 0xa7 => ("sub-float", F23x, None),
+    MulFloat = 0xa8 => ("mul-float", F23x, None),
+    DivFloat = 0xa9 => ("div-float", F23x, None),
+    RemFloat = 0xaa => ("rem-float", F23x, None),
+    AddDouble = 0xab => ("add-double", F23x, None),
+    SubDouble = 0xac => ("sub-double", F23x, None),
+    MulDouble = 0xad => ("mul-double", F23x, None),
+    DivDouble = 0xae => ("div-double", F23x, None),
+    RemDouble = 0xaf => ("rem-double", F23x, None),
+    AddInt2Addr = 0xb0 => ("add-int/2addr", F12x, None),
+    SubInt2Addr = 0xb1 => ("sub-int/2addr", F12x, None),
+    MulInt2Addr = 0xb2 => ("mul-int/2addr", F12x, None),
+    DivInt2Addr = 0xb3 => ("div-int/2addr", F12x, None),
+    RemInt2Addr = 0xb4 => ("rem-int/2addr", F12x, None),
+    AndInt2Addr = 0xb5 => ("and-int/2addr", F12x, None),
+    OrInt2Addr = 0xb6 => ("or-int/2addr", F12x, None),
+    XorInt2Addr = 0xb7 => ("xor-int/2addr", F12x, None),
+    ShlInt2Addr = 0xb8 => ("shl-int/2addr", F12x, None),
+    ShrInt2Addr = 0xb9 => ("shr-int/2addr", F12x, None),
+    UshrInt2Addr = 0xba => ("ushr-int/2addr", F12x, None),
+    AddLong2Addr = 0xbb => ("add-long/2addr", F12x, None),
+    SubLong2Addr = 0xbc => ("sub-long/2addr", F12x, None),
+    MulLong2Addr = 0xbd => ("mul-long/2addr", F12x, None),
+    DivLong2Addr = 0xbe => ("div-long/2addr", F12x, None),
+    RemLong2Addr = 0xbf => ("rem-long/2addr", F12x, None),
+    AndLong2Addr = 0xc0 => ("and-long/2addr", F12x, None),
+    OrLong2Addr = 0xc1 => ("or-long/2addr", F12x, None),
+    XorLong2Addr = 0xc2 => ("xor-long/2addr", F12x, None),
+    ShlLong2Addr = 0xc3 => ("shl-long/2addr", F12x, None),
+    ShrLong2Addr = 0xc4 => ("shr-long/2addr", F12x, None),
+    UshrLong2Addr = 0xc5 => ("ushr-long/2addr", F12x, None),
+    AddFloat2Addr = 0xc6 => ("add-float/2addr", F12x, None),
+    SubFloat2Addr = 0xc7 => ("sub-float/2addr", F12x, None),
+    MulFloat2Addr = 0xc8 => ("mul-float/2addr", F12x, None),
+    DivFloat2Addr = 0xc9 => ("div-float/2addr", F12x, None),
+    RemFloat2Addr = 0xca => ("rem-float/2addr", F12x, None),
+    AddDouble2Addr = 0xcb => ("add-double/2addr", F12x, None),
+    SubDouble2Addr = 0xcc => ("sub-double/2addr", F12x, None),
+    MulDouble2Addr = 0xcd => ("mul-double/2addr", F12x, None),
+    DivDouble2Addr = 0xce => ("div-double/2addr", F12x, None),
+    RemDouble2Addr = 0xcf => ("rem-double/2addr", F12x, None),
+    AddIntLit16 = 0xd0 => ("add-int/lit16", F22s, None),
+    RsubInt = 0xd1 => ("rsub-int", F22s, None),
+    MulIntLit16 = 0xd2 => ("mul-int/lit16", F22s, None),
+    DivIntLit16 = 0xd3 => ("div-int/lit16", F22s, None),
+    RemIntLit16 = 0xd4 => ("rem-int/lit16", F22s, None),
+    AndIntLit16 = 0xd5 => ("and-int/lit16", F22s, None),
+    OrIntLit16 = 0xd6 => ("or-int/lit16", F22s, None),
+    XorIntLit16 = 0xd7 => ("xor-int/lit16", F22s, None),
+    AddIntLit8 = 0xd8 => ("add-int/lit8", F22b, None),
+    RsubIntLit8 = 0xd9 => ("rsub-int/lit8", F22b, None),
+    MulIntLit8 = 0xda => ("mul-int/lit8", F22b, None),
+    DivIntLit8 = 0xdb => ("div-int/lit8", F22b, None),
+    RemIntLit8 = 0xdc => ("rem-int/lit8", F22b, None),
+    AndIntLit8 = 0xdd => ("and-int/lit8", F22b, None),
+    OrIntLit8 = 0xde => ("or-int/lit8", F22b, None),
+    XorIntLit8 = 0xdf => ("xor-int/lit8", F22b, None),
+    ShlIntLit8 = 0xe0 => ("shl-int/lit8", F22b, None),
+    ShrIntLit8 = 0xe1 => ("shr-int/lit8", F22b, None),
+    UshrIntLit8 = 0xe2 => ("ushr-int/lit8", F22b, None),
+    InvokePolymorphic = 0xfa => ("invoke-polymorphic", F45cc, Method),
+    InvokePolymorphicRange = 0xfb => ("invoke-polymorphic/range", F4rcc, Method),
+    InvokeCustom = 0xfc => ("invoke-custom", F35c, CallSite),
+    InvokeCustomRange = 0xfd => ("invoke-custom/range", F3rc, CallSite),
+    ConstMethodHandle = 0xfe => ("const-method-handle", F21c, MethodHandle),
+    ConstMethodType = 0xff => ("const-method-type", F21c, Proto);
+
+    PackedSwitchPayload => ("packed-switch-payload", PackedSwitchPayload, None),
+    SparseSwitchPayload => ("sparse-switch-payload", SparseSwitchPayload, None),
+    FillArrayDataPayload => ("fill-array-data-payload", FillArrayDataPayload, None),
+    IgetQuick => ("iget-quick", F22cs, FieldOffset),
+    IgetWideQuick => ("iget-wide-quick", F22cs, FieldOffset),
+    IgetObjectQuick => ("iget-object-quick", F22cs, FieldOffset),
+    IgetBooleanQuick => ("iget-boolean-quick", F22cs, FieldOffset),
+    IgetByteQuick => ("iget-byte-quick", F22cs, FieldOffset),
+    IgetCharQuick => ("iget-char-quick", F22cs, FieldOffset),
+    IgetShortQuick => ("iget-short-quick", F22cs, FieldOffset),
+    IputQuick => ("iput-quick", F22cs, FieldOffset),
+    IputWideQuick => ("iput-wide-quick", F22cs, FieldOffset),
+    IputObjectQuick => ("iput-object-quick", F22cs, FieldOffset),
+    IputBooleanQuick => ("iput-boolean-quick", F22cs, FieldOffset),
+    IputByteQuick => ("iput-byte-quick", F22cs, FieldOffset),
+    IputCharQuick => ("iput-char-quick", F22cs, FieldOffset),
+    IputShortQuick => ("iput-short-quick", F22cs, FieldOffset),
+    InvokeVirtualQuick => ("invoke-virtual-quick", F35ms, VtableOffset),
+    InvokeVirtualQuickRange => ("invoke-virtual-quick/range", F3rms, VtableOffset),
+    InvokeSuperQuick => ("invoke-super-quick", F35ms, VtableOffset),
+    InvokeSuperQuickRange => ("invoke-super-quick/range", F3rms, VtableOffset),
+    ReturnVoidNoBarrier => ("return-void-no-barrier", F10x, None),
+    ReturnVoidBarrier => ("return-void-barrier", F10x, None),
+    IgetVolatile => ("iget-volatile", F22c, Field),
+    IgetWideVolatile => ("iget-wide-volatile", F22c, Field),
+    IgetObjectVolatile => ("iget-object-volatile", F22c, Field),
+    IputVolatile => ("iput-volatile", F22c, Field),
+    IputWideVolatile => ("iput-wide-volatile", F22c, Field),
+    IputObjectVolatile => ("iput-object-volatile", F22c, Field),
+    SgetVolatile => ("sget-volatile", F21c, Field),
+    SgetWideVolatile => ("sget-wide-volatile", F21c, Field),
+    SgetObjectVolatile => ("sget-object-volatile", F21c, Field),
+    SputVolatile => ("sput-volatile", F21c, Field),
+    SputWideVolatile => ("sput-wide-volatile", F21c, Field),
+    SputObjectVolatile => ("sput-object-volatile", F21c, Field),
+    ThrowVerificationError => ("throw-verification-error", F20bc, VerificationError),
+    ExecuteInline => ("execute-inline", F35mi, InlineMethod),
+    ExecuteInlineRange => ("execute-inline/range", F3rmi, InlineMethod),
+    InvokeObjectInitRange => ("invoke-object-init/range", F3rc, Method)
+}
+
+impl Opcode {
+    /// Returns `true` if the opcode is not part of the standard instruction set but
+    /// was produced by an optimizer (dexopt or dex2oat).
+    pub fn is_quickened(self) -> bool {
+        self.unquickened().is_some()
+            || matches!(
+                self,
+                Opcode::ThrowVerificationError | Opcode::ExecuteInline | Opcode::ExecuteInlineRange
+            )
+    }
+
+    /// Minimum version of the dex format in which this opcode is legal, like `38` for
+    /// `invoke-custom`. `None` for the quickened opcodes, which are never legal in a dex
+    /// file.
+    pub fn min_dex_version(self) -> Option<uint> {
+        use Opcode::*;
+        if self.is_quickened() {
+            return None;
+        }
+        Some(match self {
+            InvokePolymorphic | InvokePolymorphicRange | InvokeCustom | InvokeCustomRange => 38,
+            ConstMethodHandle | ConstMethodType => 39,
+            _ => 35,
+        })
+    }
+
+    /// Returns `true` if instructions with this opcode may throw an exception, like field
+    /// accesses, invocations and integer divisions.
+    pub fn can_throw(self) -> bool {
+        use Opcode::*;
+        if let Some(opcode) = self.unquickened() {
+            return opcode != ReturnVoid;
+        }
+        matches!(
+            self,
+            ConstString
+                | ConstStringJumbo
+                | ConstClass
+                | ConstMethodHandle
+                | ConstMethodType
+                | MonitorEnter
+                | MonitorExit
+                | CheckCast
+                | InstanceOf
+                | ArrayLength
+                | NewInstance
+                | NewArray
+                | FilledNewArray
+                | FilledNewArrayRange
+                | FillArrayData
+                | Throw
+                | ThrowVerificationError
+                | ExecuteInline
+                | ExecuteInlineRange
+                | DivInt
+                | RemInt
+                | DivLong
+                | RemLong
+                | DivInt2Addr
+                | RemInt2Addr
+                | DivLong2Addr
+                | RemLong2Addr
+                | DivIntLit16
+                | RemIntLit16
+                | DivIntLit8
+                | RemIntLit8
+                | Aget
+                | AgetWide
+                | AgetObject
+                | AgetBoolean
+                | AgetByte
+                | AgetChar
+                | AgetShort
+                | Aput
+                | AputWide
+                | AputObject
+                | AputBoolean
+                | AputByte
+                | AputChar
+                | AputShort
+        ) || matches!(
+            self.index_type(),
+            IndexType::Field | IndexType::Method | IndexType::CallSite
+        )
+    }
+
+    /// Returns `false` if execution never continues with the next instruction, after
+    /// returns, throws and `goto`s. Payloads aren't executed and don't continue either.
+    pub fn can_continue(self) -> bool {
+        use Opcode::*;
+        !matches!(
+            self,
+            ReturnVoid
+                | Return
+                | ReturnWide
+                | ReturnObject
+                | ReturnVoidNoBarrier
+                | ReturnVoidBarrier
+                | Throw
+                | ThrowVerificationError
+                | Goto
+                | Goto16
+                | Goto32
+                | PackedSwitchPayload
+                | SparseSwitchPayload
+                | FillArrayDataPayload
+        )
+    }
+
+    /// Returns the standard opcode which was replaced by this quickened opcode, if it
+    /// can be recovered.
+    pub fn unquickened(self) -> Option<Opcode> {
+        use Opcode::*;
+        Some(match self {
+            IgetQuick | IgetVolatile => Iget,
+            IgetWideQuick | IgetWideVolatile => IgetWide,
+            IgetObjectQuick | IgetObjectVolatile => IgetObject,
+            IgetBooleanQuick => IgetBoolean,
+            IgetByteQuick => IgetByte,
+            IgetCharQuick => IgetChar,
+            IgetShortQuick => IgetShort,
+            IputQuick | IputVolatile => Iput,
+            IputWideQuick | IputWideVolatile => IputWide,
+            IputObjectQuick | IputObjectVolatile => IputObject,
+            IputBooleanQuick => IputBoolean,
+            IputByteQuick => IputByte,
+            IputCharQuick => IputChar,
+            IputShortQuick => IputShort,
+            SgetVolatile => Sget,
+            SgetWideVolatile => SgetWide,
+            SgetObjectVolatile => SgetObject,
+            SputVolatile => Sput,
+            SputWideVolatile => SputWide,
+            SputObjectVolatile => SputObject,
+            InvokeVirtualQuick => InvokeVirtual,
+            InvokeVirtualQuickRange => InvokeVirtualRange,
+            InvokeSuperQuick => InvokeSuper,
+            InvokeSuperQuickRange => InvokeSuperRange,
+            InvokeObjectInitRange => InvokeDirectRange,
+            ReturnVoidNoBarrier | ReturnVoidBarrier => ReturnVoid,
+            _ => return None,
+        })
+    }
+}
+
+/// The set of quickened opcodes to recognize when decoding. Quickened instructions reuse
+/// opcode values that are unused in the standard instruction set, so they can only be decoded
+/// correctly if it is known which optimizer produced the code.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Quickening {
+    /// Only the standard instruction set.
+    #[default]
+    None,
+    /// Instructions produced by `dexopt` in odex files (Dalvik VM).
+    Dalvik,
+    /// Instructions produced by `dex2oat` quickening in vdex/oat files (ART, Android 5-11).
+    Art,
+}
+
+impl Quickening {
+    /// Returns the opcode represented by `value` in this instruction set.
+    pub fn opcode(self, value: ubyte) -> Option<Opcode> {
+        let quickened = match self {
+            Quickening::None => None,
+            Quickening::Dalvik => dalvik_opcode(value),
+            Quickening::Art => art_opcode(value),
+        };
+        quickened.or_else(|| Opcode::from_standard(value))
+    }
+}
+
+fn dalvik_opcode(value: ubyte) -> Option<Opcode> {
+    use Opcode::*;
+    Some(match value {
+        0xe3 => IgetVolatile,
+        0xe4 => IputVolatile,
+        0xe5 => SgetVolatile,
+        0xe6 => SputVolatile,
+        0xe7 => IgetObjectVolatile,
+        0xe8 => IgetWideVolatile,
+        0xe9 => IputWideVolatile,
+        0xea => SgetWideVolatile,
+        0xeb => SputWideVolatile,
+        0xed => ThrowVerificationError,
+        0xee => ExecuteInline,
+        0xef => ExecuteInlineRange,
+        0xf0 => InvokeObjectInitRange,
+        0xf1 => ReturnVoidBarrier,
+        0xf2 => IgetQuick,
+        0xf3 => IgetWideQuick,
+        0xf4 => IgetObjectQuick,
+        0xf5 => IputQuick,
+        0xf6 => IputWideQuick,
+        0xf7 => IputObjectQuick,
+        0xf8 => InvokeVirtualQuick,
+        0xf9 => InvokeVirtualQuickRange,
+        0xfa => InvokeSuperQuick,
+        0xfb => InvokeSuperQuickRange,
+        0xfc => IputObjectVolatile,
+        0xfd => SgetObjectVolatile,
+        0xfe => SputObjectVolatile,
+        _ => return None,
+    })
+}
+
+fn art_opcode(value: ubyte) -> Option<Opcode> {
+    use Opcode::*;
+    Some(match value {
+        0x73 => ReturnVoidNoBarrier,
+        0xe3 => IgetQuick,
+        0xe4 => IgetWideQuick,
+        0xe5 => IgetObjectQuick,
+        0xe6 => IputQuick,
+        0xe7 => IputWideQuick,
+        0xe8 => IputObjectQuick,
+        0xe9 => InvokeVirtualQuick,
+        0xea => InvokeVirtualQuickRange,
+        0xeb => IputBooleanQuick,
+        0xec => IputByteQuick,
+        0xed => IputCharQuick,
+        0xee => IputShortQuick,
+        0xef => IgetBooleanQuick,
+        0xf0 => IgetByteQuick,
+        0xf1 => IgetCharQuick,
+        0xf2 => IgetShortQuick,
+        _ => return None,
+    })
+}
+
+/// Supplies the information that was removed from quickened instructions, so that they can be
+/// mapped back to the field and method references they replaced. This is usually recovered
+/// from the quickening info in a vdex file or from the vtables and field layouts of a running VM.
+pub trait QuickeningInfo {
+    /// Returns the `FieldId` accessed by the quickened field accessor at `offset` (in code units
+    /// from the start of the method) which reads or writes the field at `field_offset` in the object.
+    fn field_id(&self, _offset: usize, _field_offset: ushort) -> Option<FieldId> {
+        None
+    }
+
+    /// Returns the `MethodId` called by the quickened invoke at `offset` (in code units from
+    /// the start of the method) through the vtable entry `vtable_index`.
+    fn method_id(&self, _offset: usize, _vtable_index: ushort) -> Option<MethodId> {
+        None
+    }
+}
+
+/// A quickened instruction mapped back to its standard opcode and index operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct Dequickened {
+    /// The standard opcode.
+    opcode: Opcode,
+    /// The `FieldId` or `MethodId` the instruction refers to, if the standard opcode takes one.
+    index: Option<u64>,
+}
+
+/// Payload of a `packed-switch`, `sparse-switch` or `fill-array-data` instruction.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Payload {
+    /// Jump table of a `packed-switch`. `targets[i]` is the branch offset for `first_key + i`.
+    PackedSwitch { first_key: i32, targets: Vec<i32> },
+    /// Jump table of a `sparse-switch`. `targets[i]` is the branch offset for `keys[i]`.
+    SparseSwitch { keys: Vec<i32>, targets: Vec<i32> },
+    /// Array contents of a `fill-array-data` in little endian byte order.
+    FillArrayData {
+        element_width: ushort,
+        data: Vec<ubyte>,
+    },
+}
+
+/// A single decoded instruction.
+#[derive(Debug, Clone, Copy, CopyGetters)]
+pub struct Instruction<'a> {
+    /// The operation performed by this instruction.
+    #[get_copy = "pub"]
+    opcode: Opcode,
+    /// Code units of this instruction.
+    #[get_copy = "pub"]
+    insns: &'a [ushort],
+}
+
+const PACKED_SWITCH_IDENT: ushort = 0x0100;
+const SPARSE_SWITCH_IDENT: ushort = 0x0200;
+const FILL_ARRAY_DATA_IDENT: ushort = 0x0300;
+
+impl<'a> Instruction<'a> {
+    /// Decodes the instruction at the start of `insns`.
+    pub fn decode(insns: &'a [ushort], quickening: Quickening) -> Result<Self> {
+        let first = *insns
+            .first()
+            .ok_or_else(|| Error::MalFormed("Expected an instruction, found none".to_string()))?;
+        let opcode = match first {
+            PACKED_SWITCH_IDENT => Opcode::PackedSwitchPayload,
+            SPARSE_SWITCH_IDENT => Opcode::SparseSwitchPayload,
+            FILL_ARRAY_DATA_IDENT => Opcode::FillArrayDataPayload,
+            _ => {
+                let value = (first & 0xff) as ubyte;
+                quickening
+                    .opcode(value)
+                    .ok_or_else(|| Error::InvalidId(format!("Invalid opcode: {:#04x}", value)))?
+            }
+        };
+        let len = match opcode.format().size() {
+            Some(size) => size as u64,
+            None => payload_size(opcode, insns)?,
+        };
+        if (insns.len() as u64) < len {
+            return Err(Error::MalFormed(format!(
+                "Truncated instruction {}: expected {} code units, found {}",
+                opcode.mnemonic(),
+                len,
+                insns.len()
+            )));
+        }
+        Ok(Self {
+            opcode,
+            insns: &insns[..len as usize],
+        })
+    }
+
+    /// Format of this instruction.
+    pub fn format(&self) -> Format {
+        self.opcode.format()
+    }
+
+    /// Size of this instruction in code units.
+    pub fn len(&self) -> usize {
+        self.insns.len()
+    }
+
+    /// Always `false`, instructions are at least one code unit long.
+    pub fn is_empty(&self) -> bool {
+        self.insns.is_empty()
+    }
+
+    // `decode` checks the length of the instruction against its format, missing code
+    // units read as zero so that no accessor can panic.
+    fn unit(&self, index: usize) -> u64 {
+        self.insns.get(index).map_or(0, |unit| u64::from(*unit))
+    }
+
+    fn wide(&self, index: usize) -> u64 {
+        self.unit(index) | self.unit(index + 1) << 16
+    }
+
+    /// The `A` operand as named in the instruction format, if the format has one.
+    pub fn a(&self) -> Option<u64> {
+        use Format::*;
+        Some(match self.format() {
+            F12x | F11n | F22t | F22s | F22c | F22cs => (self.unit(0) >> 8) & 0xf,
+            F11x | F10t | F20bc | F22x | F21t | F21s | F21h | F21c | F23x | F22b | F31i | F31t
+            | F31c | F3rc | F3rms | F3rmi | F4rcc | F51l => self.unit(0) >> 8,
+            F20t | F32x => self.unit(1),
+            F30t => self.wide(1),
+            F35c | F35ms | F35mi | F45cc => self.unit(0) >> 12,
+            _ => return None,
+        })
+    }
+
+    /// The `B` operand as named in the instruction format, if the format has one.
+    pub fn b(&self) -> Option<u64> {
+        use Format::*;
+        Some(match self.format() {
+            F12x | F11n | F22t | F22s | F22c | F22cs => self.unit(0) >> 12,
+            F20bc | F22x | F21t | F21s | F21h | F21c | F35c | F35ms | F35mi | F3rc | F3rms
+            | F3rmi | F45cc | F4rcc => self.unit(1),
+            F23x | F22b => self.unit(1) & 0xff,
+            F32x => self.unit(2),
+            F31i | F31t | F31c => self.wide(1),
+            F51l => self.wide(1) | self.wide(3) << 32,
+            _ => return None,
+        })
+    }
+
+    /// The `C` operand as named in the instruction format, if the format has one.
+    pub fn c(&self) -> Option<u64> {
+        use Format::*;
+        Some(match self.format() {
+            F23x | F22b => self.unit(1) >> 8,
+            F22t | F22s | F22c | F22cs => self.unit(1),
+            F3rc | F3rms | F3rmi | F4rcc => self.unit(2),
+            F35c | F35ms | F35mi | F45cc => self.unit(2) & 0xf,
+            _ => return None,
+        })
+    }
+
+    /// The `D` operand as named in the instruction format, if the format has one.
+    pub fn d(&self) -> Option<u64> {
+        match self.format() {
+            Format::F35c | Format::F35ms | Format::F35mi | Format::F45cc => {
+                Some((self.unit(2) >> 4) & 0xf)
+            }
+            _ => None,
+        }
+    }
+
+    /// The `E` operand as named in the instruction format, if the format has one.
+    pub fn e(&self) -> Option<u64> {
+        match self.format() {
+            Format::F35c | Format::F35ms | Format::F35mi | Format::F45cc => {
+                Some((self.unit(2) >> 8) & 0xf)
+            }
+            _ => None,
+        }
+    }
+
+    /// The `F` operand as named in the instruction format, if the format has one.
+    pub fn f(&self) -> Option<u64> {
+        match self.format() {
+            Format::F35c | Format::F35ms | Format::F35mi | Format::F45cc => {
+                Some(self.unit(2) >> 12)
+            }
+            _ => None,
+        }
+    }
+
+    /// The `G` operand as named in the instruction format, if the format has one.
+    pub fn g(&self) -> Option<u64> {
+        match self.format() {
+            Format::F35c | Format::F35ms | Format::F35mi | Format::F45cc => {
+                Some((self.unit(0) >> 8) & 0xf)
+            }
+            _ => None,
+        }
+    }
+
+    /// The `H` operand as named in the instruction format, if the format has one.
+    pub fn h(&self) -> Option<u64> {
+        match self.format() {
+            Format::F45cc | Format::F4rcc => Some(self.unit(3)),
+            _ => None,
+        }
+    }
+
+    /// Registers used by this instruction, in the order of the operands.
+    pub fn registers(&self) -> Vec<ushort> {
+        use Format::*;
+        let reg = |operand: Option<u64>| operand.unwrap_or_default() as ushort;
+        match self.format() {
+            F12x | F22x | F22b | F22t | F22s | F22c | F22cs | F32x => {
+                vec![reg(self.a()), reg(self.b())]
+            }
+            F11n | F11x | F21t | F21s | F21h | F21c | F31i | F31t | F31c | F51l => {
+                vec![reg(self.a())]
+            }
+            F23x => vec![reg(self.a()), reg(self.b()), reg(self.c())],
+            F35c | F35ms | F35mi | F45cc => {
+                let count = reg(self.a()) as usize;
+                [self.c(), self.d(), self.e(), self.f(), self.g()]
+                    .iter()
+                    .take(count)
+                    .map(|r| reg(*r))
+                    .collect()
+            }
+            F3rc | F3rms | F3rmi | F4rcc => {
+                let (first, count) = (reg(self.c()), reg(self.a()));
+                (0..count).map(|i| first.wrapping_add(i)).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The index operand of this instruction. What the index refers to is given by
+    /// `Opcode::index_type`.
+    pub fn index(&self) -> Option<u64> {
+        use Format::*;
+        match self.format() {
+            F20bc | F21c | F31c | F35c | F35ms | F35mi | F3rc | F3rms | F3rmi | F45cc | F4rcc => {
+                self.b()
+            }
+            F22c | F22cs => self.c(),
+            _ => None,
+        }
+    }
+
+    /// The `ProtoId` operand of `invoke-polymorphic` and `invoke-polymorphic/range`, which
+    /// also have a `MethodId` as `index`.
+    pub fn proto_index(&self) -> Option<u64> {
+        self.h()
+    }
+
+    /// The literal value loaded or used by this instruction, sign extended where
+    /// the instruction format requires it.
+    pub fn literal(&self) -> Option<i64> {
+        use Format::*;
+        Some(match self.format() {
+            F11n => i64::from(((self.b()? as i8) << 4) >> 4),
+            F21s => i64::from(self.b()? as i16),
+            F21h if self.opcode == Opcode::ConstWideHigh16 => (self.b()? as i64) << 48,
+            F21h => i64::from((self.b()? as i32) << 16),
+            F22b => i64::from(self.c()? as i8),
+            F22s => i64::from(self.c()? as i16),
+            F31i => i64::from(self.b()? as i32),
+            F51l => self.b()? as i64,
+            _ => return None,
+        })
+    }
+
+    /// The branch offset of this instruction in code units, relative to the start of the
+    /// instruction. For `packed-switch`, `sparse-switch` and `fill-array-data`, this is the
+    /// offset of the payload.
+    pub fn branch_offset(&self) -> Option<i32> {
+        use Format::*;
+        Some(match self.format() {
+            F10t => i32::from(self.a()? as i8),
+            F20t => i32::from(self.a()? as i16),
+            F30t => self.a()? as i32,
+            F21t => i32::from(self.b()? as i16),
+            F22t => i32::from(self.c()? as i16),
+            F31t => self.b()? as i32,
+            _ => return None,
+        })
+    }
+
+    /// Decodes the contents of a payload pseudo-instruction.
+    pub fn payload(&self) -> Option<Payload> {
+        let signed = |index: usize| self.wide(index) as i32;
+        match self.opcode {
+            Opcode::PackedSwitchPayload => {
+                let size = self.unit(1) as usize;
+                Some(Payload::PackedSwitch {
+                    first_key: signed(2),
+                    targets: (0..size).map(|i| signed(4 + i * 2)).collect(),
+                })
+            }
+            Opcode::SparseSwitchPayload => {
+                let size = self.unit(1) as usize;
+                Some(Payload::SparseSwitch {
+                    keys: (0..size).map(|i| signed(2 + i * 2)).collect(),
+                    targets: (0..size).map(|i| signed(2 + (size + i) * 2)).collect(),
+                })
+            }
+            Opcode::FillArrayDataPayload => {
+                let element_width = self.unit(1) as ushort;
+                let size = self.wide(2) as usize * element_width as usize;
+                Some(Payload::FillArrayData {
+                    element_width,
+                    data: self
+                        .insns
+                        .iter()
+                        .skip(4)
+                        .flat_map(|unit| unit.to_le_bytes().to_vec())
+                        .take(size)
+                        .collect(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Maps a quickened instruction back to the standard opcode and reference it replaced.
+    /// `offset` is the offset of this instruction in code units from the start of the method.
+    /// Returns `None` if the instruction is not quickened, or if `info` can't resolve it.
+    pub fn dequicken(&self, offset: usize, info: &dyn QuickeningInfo) -> Option<Dequickened> {
+        let opcode = self.opcode.unquickened()?;
+        let index = match self.opcode.index_type() {
+            IndexType::FieldOffset => Some(info.field_id(offset, self.index()? as ushort)?),
+            IndexType::VtableOffset => Some(info.method_id(offset, self.index()? as ushort)?),
+            IndexType::None => None,
+            _ => Some(self.index()?),
+        };
+        Some(Dequickened { opcode, index })
+    }
+}
+
+fn payload_size(opcode: Opcode, insns: &[ushort]) -> Result<u64> {
+    let unit = |index: usize| {
+        insns
+            .get(index)
+            .map(|u| u64::from(*u))
+            .ok_or_else(|| Error::MalFormed(format!("Truncated {} header", opcode.mnemonic())))
+    };
+    Ok(match opcode {
+        Opcode::PackedSwitchPayload => 4 + unit(1)? * 2,
+        Opcode::SparseSwitchPayload => 2 + unit(1)? * 4,
+        Opcode::FillArrayDataPayload => {
+            let size = unit(2)? | unit(3)? << 16;
+            4 + (unit(1)? * size).div_ceil(2)
+        }
+        _ => unreachable!("{:?} is not a payload", opcode),
+    })
+}
+
+impl<'a> std::fmt::Display for Instruction<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())
+    }
+}
+
+/// Iterator over the instructions in a list of code units.
+pub struct InstructionIter<'a> {
+    insns: &'a [ushort],
+    offset: usize,
+    quickening: Quickening,
+    /// Version of the dex format the opcodes are checked against, if any.
+    version: Option<uint>,
+}
+
+impl<'a> InstructionIter<'a> {
+    /// Iterator over the standard instructions in `insns`.
+    pub fn new(insns: &'a [ushort]) -> Self {
+        Self::with_quickening(insns, Quickening::None)
+    }
+
+    /// Iterator over the instructions in `insns`, also recognizing the quickened opcodes
+    /// of the given instruction set.
+    pub fn with_quickening(insns: &'a [ushort], quickening: Quickening) -> Self {
+        Self {
+            insns,
+            offset: 0,
+            quickening,
+            version: None,
+        }
+    }
+
+    /// Fails on the instructions whose opcode isn't legal in `version` of the dex format,
+    /// see `Opcode::min_dex_version`, instead of decoding them. Quickened opcodes are
+    /// still accepted as requested by the `Quickening`.
+    pub fn with_version(mut self, version: uint) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Offset in code units of the next instruction.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Continues decoding at `offset` code units from the start. `offset` should be the
+    /// start of an instruction, such as a branch target. The iterator ends if `offset` is
+    /// past the end of the code.
+    pub fn seek(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    fn check_version(&self, instruction: Instruction<'a>) -> Result<Instruction<'a>> {
+        let opcode = instruction.opcode();
+        match (self.version, opcode.min_dex_version()) {
+            (Some(version), Some(required)) if required > version => {
+                Err(Error::MalFormed(format!(
+                    "{} requires dex version {:03}, found {:03}",
+                    opcode.mnemonic(),
+                    required,
+                    version
+                )))
+            }
+            _ => Ok(instruction),
+        }
+    }
+
+    /// Iterator which also yields the offset of each instruction.
+    pub fn with_offsets(self) -> InstructionOffsets<'a> {
+        InstructionOffsets { inner: self }
+    }
+}
+
+impl<'a> Iterator for InstructionIter<'a> {
+    type Item = Result<Instruction<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.insns.len() {
+            return None;
+        }
+        let instruction = Instruction::decode(&self.insns[self.offset..], self.quickening)
+            .and_then(|instruction| self.check_version(instruction));
+        match instruction {
+            Ok(ref instruction) => self.offset += instruction.len(),
+            // decoding can't continue after a malformed instruction.
+            Err(_) => self.offset = self.insns.len(),
+        }
+        debug!(target: "instruction", "decoded instruction: {:?}", instruction);
+        Some(instruction)
+    }
+}
+
+/// Iterator over the instructions in a list of code units along with their offsets in
+/// code units from the start of the list.
+pub struct InstructionOffsets<'a> {
+    inner: InstructionIter<'a>,
+}
+
+impl<'a> InstructionOffsets<'a> {
+    /// Offset in code units of the next instruction.
+    pub fn offset(&self) -> usize {
+        self.inner.offset()
+    }
+
+    /// Continues decoding at `offset` code units from the start.
+    /// See `InstructionIter::seek`.
+    pub fn seek(&mut self, offset: usize) {
+        self.inner.seek(offset)
+    }
+}
+
+impl<'a> Iterator for InstructionOffsets<'a> {
+    type Item = Result<(usize, Instruction<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.inner.offset();
+        self.inner
+            .next()
+            .map(|instruction| instruction.map(|instruction| (offset, instruction)))
+    }
+}
+
+/// Serialized as its mnemonic.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Opcode {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.mnemonic())
+    }
+}
+
+/// Serialized with its decoded operands rather than its code units.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Instruction<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Instruction", 6)?;
+        state.serialize_field("opcode", &self.opcode)?;
+        state.serialize_field("registers", &self.registers())?;
+        state.serialize_field("index", &self.index())?;
+        state.serialize_field("literal", &self.literal())?;
+        state.serialize_field("branch_offset", &self.branch_offset())?;
+        state.serialize_field("payload", &self.payload())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Dequickened, IndexType, Instruction, InstructionIter, Opcode, Payload, Quickening,
+        QuickeningInfo, FILL_ARRAY_DATA_IDENT, PACKED_SWITCH_IDENT, SPARSE_SWITCH_IDENT,
+    };
+
+    struct NoInfo;
+    impl QuickeningInfo for NoInfo {}
+
+    #[test]
+    fn test_decode_operands() {
+        // invoke-virtual {v1, v2}, method@0x1234
+        let insns = [0x206e, 0x1234, 0x0021];
+        let instruction = Instruction::decode(&insns, Quickening::None).expect("decode failed");
+        assert_eq!(instruction.opcode(), Opcode::InvokeVirtual);
+        assert_eq!(instruction.len(), 3);
+        assert_eq!(instruction.index(), Some(0x1234));
+        assert_eq!(instruction.registers(), vec![1, 2]);
+
+        // const/4 v0, #-1
+        let instruction = Instruction::decode(&[0xf012], Quickening::None).unwrap();
+        assert_eq!(instruction.literal(), Some(-1));
+        assert_eq!(instruction.registers(), vec![0]);
+
+        // const-wide/high16 v0, #0x4000000000000000
+        let instruction = Instruction::decode(&[0x0019, 0x4000], Quickening::None).unwrap();
+        assert_eq!(instruction.literal(), Some(0x4000_0000_0000_0000));
+
+        // if-nez v3, -4
+        let instruction = Instruction::decode(&[0x0339, 0xfffc], Quickening::None).unwrap();
+        assert_eq!(instruction.branch_offset(), Some(-4));
+
+        // invoke-static/range {v4 .. v6}, method@0x2
+        let instruction = Instruction::decode(&[0x0377, 0x0002, 0x0004], Quickening::None).unwrap();
+        assert_eq!(instruction.registers(), vec![4, 5, 6]);
+        assert!(instruction.literal().is_none());
+        assert!(instruction.h().is_none());
+    }
+
+    #[test]
+    fn test_decode_payloads() {
+        let insns = [
+            0x0100, 0x0002, 0x000a, 0x0000, 0x0005, 0x0000, 0x0007, 0x0000,
+        ];
+        let instruction = Instruction::decode(&insns, Quickening::None).unwrap();
+        assert_eq!(instruction.opcode(), Opcode::PackedSwitchPayload);
+        assert_eq!(
+            instruction.payload(),
+            Some(Payload::PackedSwitch {
+                first_key: 10,
+                targets: vec![5, 7]
+            })
+        );
+
+        let insns = [0x0300, 0x0001, 0x0003, 0x0000, 0x0201, 0x0003];
+        let instruction = Instruction::decode(&insns, Quickening::None).unwrap();
+        assert_eq!(
+            instruction.payload(),
+            Some(Payload::FillArrayData {
+                element_width: 1,
+                data: vec![1, 2, 3]
+            })
+        );
+
+        // truncated payloads are errors, not panics.
+        assert!(Instruction::decode(&[0x0100, 0x0010, 0x0000], Quickening::None).is_err());
+        assert!(Instruction::decode(&[0x0300], Quickening::None).is_err());
+    }
+
+    #[test]
+    fn test_decode_dex_038_opcodes() {
+        // invoke-polymorphic {v1, v2}, method@0x3, proto@0x4
+        let insns = [0x20fa, 0x0003, 0x0021, 0x0004];
+        let instruction = Instruction::decode(&insns, Quickening::None).unwrap();
+        assert_eq!(instruction.opcode(), Opcode::InvokePolymorphic);
+        assert_eq!(instruction.registers(), vec![1, 2]);
+        assert_eq!(instruction.index(), Some(3));
+        assert_eq!(instruction.proto_index(), Some(4));
+
+        // invoke-polymorphic/range {v5 .. v6}, method@0x3, proto@0x4
+        let insns = [0x02fb, 0x0003, 0x0005, 0x0004];
+        let instruction = Instruction::decode(&insns, Quickening::None).unwrap();
+        assert_eq!(instruction.registers(), vec![5, 6]);
+        assert_eq!(instruction.proto_index(), Some(4));
+
+        // invoke-custom/range {v0 .. v1}, call_site@0x1
+        let insns = [0x02fd, 0x0001, 0x0000];
+        let instruction = Instruction::decode(&insns, Quickening::None).unwrap();
+        assert_eq!(instruction.opcode().index_type(), IndexType::CallSite);
+        assert_eq!(instruction.index(), Some(1));
+        assert!(instruction.proto_index().is_none());
+
+        // const-method-handle v0, method_handle@0x2; const-method-type v1, proto@0x7
+        let instruction = Instruction::decode(&[0x00fe, 0x0002], Quickening::None).unwrap();
+        assert_eq!(instruction.opcode().index_type(), IndexType::MethodHandle);
+        let instruction = Instruction::decode(&[0x01ff, 0x0007], Quickening::None).unwrap();
+        assert_eq!(instruction.opcode(), Opcode::ConstMethodType);
+        assert_eq!(
+            (instruction.registers(), instruction.index()),
+            (vec![1], Some(7))
+        );
+    }
+
+    #[test]
+    fn test_control_flow_flags() {
+        assert!(Opcode::DivInt.can_throw());
+        assert!(Opcode::InvokeVirtual.can_throw());
+        assert!(Opcode::IgetQuick.can_throw());
+        assert!(!Opcode::AddInt.can_throw());
+        assert!(!Opcode::DivFloat.can_throw());
+        assert!(!Opcode::ReturnVoidBarrier.can_throw());
+        assert!(!Opcode::Throw.can_continue());
+        assert!(!Opcode::Goto16.can_continue());
+        assert!(Opcode::IfEqz.can_continue());
+        assert!(Opcode::PackedSwitch.can_continue());
+    }
+
+    #[test]
+    fn test_version_check() {
+        // const-method-type v1, proto@0x7; nop
+        let insns = [0x01ff, 0x0007, 0x0000];
+        assert_eq!(Opcode::ConstMethodType.min_dex_version(), Some(39));
+        assert_eq!(Opcode::InvokeCustom.min_dex_version(), Some(38));
+        assert_eq!(Opcode::Nop.min_dex_version(), Some(35));
+        assert_eq!(Opcode::IgetQuick.min_dex_version(), None);
+        assert!(InstructionIter::new(&insns).all(|i| i.is_ok()));
+        assert!(InstructionIter::new(&insns)
+            .with_version(39)
+            .all(|i| i.is_ok()));
+        let mut instructions = InstructionIter::new(&insns).with_version(38);
+        assert!(instructions.next().unwrap().is_err());
+        assert!(instructions.next().is_none());
+    }
+
+    #[test]
+    fn test_offsets_and_seek() {
+        // const/4 v0, #1; if-eqz v0, +3; nop; return-void
+        let insns = [0x1012, 0x0038, 0x0003, 0x0000, 0x000e];
+        let offsets = InstructionIter::new(&insns)
+            .with_offsets()
+            .map(|i| i.map(|(offset, i)| (offset, i.opcode())))
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            offsets,
+            vec![
+                (0, Opcode::Const4),
+                (1, Opcode::IfEqz),
+                (3, Opcode::Nop),
+                (4, Opcode::ReturnVoid)
+            ]
+        );
+
+        let mut iter = InstructionIter::new(&insns).with_offsets();
+        let (offset, branch) = iter.next().unwrap().and(iter.next().unwrap()).unwrap();
+        iter.seek(offset + branch.branch_offset().unwrap() as usize);
+        assert_eq!(iter.offset(), 4);
+        let (offset, instruction) = iter.next().unwrap().unwrap();
+        assert_eq!((offset, instruction.opcode()), (4, Opcode::ReturnVoid));
+        assert!(iter.next().is_none());
+
+        iter.seek(insns.len() + 10);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_quickened_opcodes() {
+        struct Info;
+        impl QuickeningInfo for Info {
+            fn field_id(&self, offset: usize, field_offset: u16) -> Option<u64> {
+                assert_eq!(offset, 3);
+                Some(u64::from(field_offset) + 100)
+            }
+        }
+
+        // iget-quick v0, v1, [obj+0x8]
+        let insns = [0x10e3, 0x0008];
+        assert!(Instruction::decode(&insns, Quickening::None).is_err());
+        let instruction = Instruction::decode(&insns, Quickening::Art).unwrap();
+        assert_eq!(instruction.opcode(), Opcode::IgetQuick);
+        assert!(instruction.opcode().is_quickened());
+        assert_eq!(
+            instruction.dequicken(3, &Info),
+            Some(Dequickened {
+                opcode: Opcode::Iget,
+                index: Some(108)
+            })
+        );
+
+        // same opcode value means something else in odex files.
+        let instruction = Instruction::decode(&insns, Quickening::Dalvik).unwrap();
+        assert_eq!(instruction.opcode(), Opcode::IgetVolatile);
+        assert_eq!(
+            instruction.dequicken(3, &Info),
+            Some(Dequickened {
+                opcode: Opcode::Iget,
+                index: Some(8)
+            })
+        );
+
+        // no vtable information supplied.
+        let instruction = Instruction::decode(&[0x10e9, 0x0001, 0x0000], Quickening::Art).unwrap();
+        assert_eq!(instruction.opcode(), Opcode::InvokeVirtualQuick);
+        assert_eq!(instruction.dequicken(0, &Info), None);
+    }
+
+    #[test]
+    fn test_malformed_instructions() {
+        // every opcode, with the high byte and the following code units all ones so the
+        // operands are out of range, truncated at every length. Decoding may fail but
+        // accessors must never panic.
+        let quickenings = [Quickening::None, Quickening::Dalvik, Quickening::Art];
+        let payloads = [
+            PACKED_SWITCH_IDENT,
+            SPARSE_SWITCH_IDENT,
+            FILL_ARRAY_DATA_IDENT,
+        ];
+        let opcodes = (0..=0xff_u16).map(|opcode| opcode | 0xff00);
+        for first in opcodes.chain(payloads.iter().copied()) {
+            let mut insns = [0xffff; 6];
+            insns[0] = first;
+            for len in 0..=insns.len() {
+                for quickening in &quickenings {
+                    let instruction = match Instruction::decode(&insns[..len], *quickening) {
+                        Ok(instruction) => instruction,
+                        Err(_) => continue,
+                    };
+                    let _ = (instruction.a(), instruction.b(), instruction.c());
+                    let _ = (instruction.d(), instruction.e(), instruction.f());
+                    let _ = (instruction.g(), instruction.h(), instruction.index());
+                    let _ = (instruction.literal(), instruction.branch_offset());
+                    let _ = (instruction.registers(), instruction.payload());
+                    let _ = instruction.dequicken(0, &NoInfo);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_all_methods() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let mut count = 0;
+        for class in dex.classes() {
+            let class = class.expect("can't load class");
+            for method in class.methods() {
+                if let Some(code) = method.code() {
+                    for instruction in code.instructions() {
+                        let instruction = instruction.expect("decode failed");
+                        assert!(!instruction.opcode().is_quickened());
+                        count += 1;
+                    }
+                }
+            }
+        }
+        assert!(count > 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        // invoke-virtual {v1, v2}, method@0x1234
+        let insns = [0x206e, 0x1234, 0x0021];
+        let instruction = Instruction::decode(&insns, Quickening::None).unwrap();
+        assert_eq!(
+            serde_json::to_value(instruction).unwrap(),
+            serde_json::json!({
+                "opcode": "invoke-virtual",
+                "registers": [1, 2],
+                "index": 0x1234,
+                "literal": null,
+                "branch_offset": null,
+                "payload": null,
+            })
+        );
+    }
+}
@@ -2,8 +2,9 @@
 use std::{clone::Clone, fmt};
 
 use getset::{CopyGetters, Getters};
+use scroll::{ctx, Pread};
 
-use crate::{string::DexString, uint};
+use crate::{error::Error, string::DexString, uint, ushort, Endian};
 
 /// Dex representation of a boolean type
 pub const BOOLEAN: &'static str = "Z";
@@ -24,6 +25,41 @@ pub const DOUBLE: &'static str = "D";
 /// Dex representation of a void type
 pub const VOID: &'static str = "V";
 
+/// How `Type::to_java_type_with` renders the names of nested classes and arrays. The
+/// default renders them like `to_java_type`, the way the VM names them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct JavaNameOptions {
+    dotted_nested: bool,
+    describe_anonymous: bool,
+    c_style_arrays: bool,
+}
+
+impl JavaNameOptions {
+    /// Separates nested classes from their enclosing class with a `.` like in source code,
+    /// `Outer.Inner` instead of `Outer$Inner`.
+    pub fn dotted_nested(mut self) -> Self {
+        self.dotted_nested = true;
+        self
+    }
+
+    /// Renders anonymous classes like javac, `<anonymous Outer$1>` instead of `Outer$1`.
+    pub fn describe_anonymous(mut self) -> Self {
+        self.describe_anonymous = true;
+        self
+    }
+
+    /// Places the dimensions of arrays after the name in declarations, `int values[]`
+    /// instead of `int[] values`.
+    pub fn c_style_arrays(mut self) -> Self {
+        self.c_style_arrays = true;
+        self
+    }
+
+    pub(crate) fn is_describe_anonymous(&self) -> bool {
+        self.describe_anonymous
+    }
+}
+
 /// Offset into the `TypeId`s section.
 pub type TypeId = uint;
 
@@ -44,7 +80,7 @@ macro_rules! gen_is_type_method {
         pub fn $func_name(&self) -> bool {
             self.type_descriptor == $descriptor
         }
-    }
+    };
 }
 
 impl Type {
@@ -96,6 +132,64 @@ impl Type {
         to_java_type(&*self.type_descriptor)
     }
 
+    /// Package of the type in Java notation, like `java.lang` for `Ljava/lang/String;` or
+    /// `[Ljava/lang/String;`. Empty for primitive types and classes of the default package.
+    pub fn package_name(&self) -> String {
+        match self
+            .type_descriptor
+            .trim_start_matches('[')
+            .strip_prefix('L')
+        {
+            Some(name) => name
+                .rfind('/')
+                .map_or_else(String::new, |index| name[..index].replace('/', ".")),
+            None => String::new(),
+        }
+    }
+
+    /// Name of the type without its package, like `String[]` for `[Ljava/lang/String;`. For
+    /// nested classes it is the name after the last `$`, like `Inner` for `a.Outer$Inner`,
+    /// `Class::simple_name` uses the `InnerClass` annotations instead.
+    pub fn simple_name(&self) -> String {
+        let dimensions = self.array_dimensions().unwrap_or(0);
+        let element = &self.type_descriptor[dimensions..];
+        let mut name = match element.strip_prefix('L') {
+            Some(name) => {
+                let name = name.trim_end_matches(';');
+                let name = &name[name.rfind('/').map_or(0, |index| index + 1)..];
+                let parts: Vec<&str> = name.split('$').collect();
+                if is_generated(&parts) {
+                    name.to_string()
+                } else {
+                    parts[parts.len() - 1].to_string()
+                }
+            }
+            None => to_java_type(element),
+        };
+        name.push_str(&"[]".repeat(dimensions));
+        name
+    }
+
+    /// Returns the Java representation of the `Type`, rendered with `options`. Classes are
+    /// taken to be nested if their name contains `$`, `Class::to_java_name` uses the
+    /// `InnerClass` annotations instead.
+    pub fn to_java_type_with(&self, options: JavaNameOptions) -> String {
+        to_java_type_with(&self.type_descriptor, options)
+    }
+
+    /// Returns the Java declaration of a variable of this `Type`, like `int[] values`.
+    pub fn to_java_declaration(&self, name: &str, options: JavaNameOptions) -> String {
+        match self.array_dimensions() {
+            Some(d) if options.c_style_arrays => format!(
+                "{} {}{}",
+                to_java_type_with(&self.type_descriptor[d..], options),
+                name,
+                "[]".repeat(d)
+            ),
+            _ => format!("{} {}", self.to_java_type_with(options), name),
+        }
+    }
+
     gen_is_type_method!(is_bool, BOOLEAN, "Returns `true` if the type is a boolean");
     gen_is_type_method!(is_byte, BYTE, "Returns `true` if the type is a byte");
     gen_is_type_method!(is_short, SHORT, "Returns `true` if the type is a short");
@@ -125,10 +219,122 @@ fn to_java_type(s: &str) -> String {
             base_type.push_str(&"[]".repeat(d));
             base_type
         }
-        _ => unreachable!(),
+        // a malformed descriptor is kept as it is.
+        s => s.to_string(),
     }
 }
 
+fn to_java_type_with(s: &str, options: JavaNameOptions) -> String {
+    if s.starts_with('[') {
+        let d = s.chars().take_while(|c| *c == '[').count();
+        let mut base_type = to_java_type_with(&s[d..], options);
+        base_type.push_str(&"[]".repeat(d));
+        return base_type;
+    }
+    let binary_name = to_java_type(s);
+    if !s.starts_with('L') {
+        return binary_name;
+    }
+    let simple_name_start = binary_name.rfind('.').map_or(0, |i| i + 1);
+    let parts: Vec<&str> = binary_name[simple_name_start..].split('$').collect();
+    if is_generated(&parts) {
+        return binary_name;
+    }
+    if options.describe_anonymous && parts.len() > 1 && is_anonymous(parts[parts.len() - 1]) {
+        return format!("<anonymous {}>", binary_name);
+    }
+    nested_name(&binary_name[..simple_name_start], &parts, options)
+}
+
+/// Joins the simple names of a class and the classes enclosing it, `$` is kept before
+/// anonymous and local classes like `Outer$1` or `Outer$1Local`.
+pub(crate) fn nested_name(package: &str, parts: &[&str], options: JavaNameOptions) -> String {
+    let mut name = package.to_string();
+    for (index, part) in parts.iter().enumerate() {
+        if index > 0 {
+            name.push(if options.dotted_nested && !is_anonymous(part) {
+                '.'
+            } else {
+                '$'
+            });
+        }
+        name.push_str(part);
+    }
+    name
+}
+
+/// Returns `true` for names like `Foo$$Lambda$1` or `Foo$-CC`, which are generated rather
+/// than nested classes, given the parts of the name between `$`s.
+fn is_generated(parts: &[&str]) -> bool {
+    parts
+        .iter()
+        .any(|part| part.is_empty() || part.starts_with('-'))
+}
+
+fn is_anonymous(simple_name: &str) -> bool {
+    simple_name.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Converts a Java type name, like `java.lang.String[]` or `com.example.Outer$Inner`, to a
+/// type descriptor. The inverse of `to_java_type`.
+pub(crate) fn to_descriptor(java_name: &str) -> String {
+    if let Some(element) = java_name.strip_suffix("[]") {
+        return format!("[{}", to_descriptor(element));
+    }
+    match java_name {
+        "boolean" => BOOLEAN.to_string(),
+        "byte" => BYTE.to_string(),
+        "short" => SHORT.to_string(),
+        "char" => CHAR.to_string(),
+        "int" => INT.to_string(),
+        "long" => LONG.to_string(),
+        "float" => FLOAT.to_string(),
+        "double" => DOUBLE.to_string(),
+        "void" => VOID.to_string(),
+        name => format!("L{};", name.replace('.', "/")),
+    }
+}
+
+/// List of types, like the interfaces of a class or the parameters of a prototype. Use
+/// `Dex::get_type_list` to read and resolve one.
+/// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#type-list)
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct TypeList {
+    /// Indices into the `TypeId`s of the types.
+    #[get = "pub"]
+    type_ids: Vec<ushort>,
+}
+
+impl<'a> ctx::TryFromCtx<'a, Endian> for TypeList {
+    type Error = Error;
+    type Size = usize;
+
+    fn try_from_ctx(source: &'a [u8], endian: Endian) -> super::Result<(Self, Self::Size)> {
+        let offset = &mut 0;
+        let len: uint = source.gread_with(offset, endian)?;
+        let type_ids = try_gread_vec_with!(source, offset, len, endian);
+        Ok((Self { type_ids }, *offset))
+    }
+}
+
+/// Splits concatenated type descriptors, like the parameters of a method descriptor
+/// `ILjava/lang/String;[J`. Returns `None` if they aren't valid descriptors.
+pub(crate) fn split_descriptors(descriptors: &str) -> Option<Vec<&str>> {
+    let mut split = Vec::new();
+    let mut rest = descriptors;
+    while !rest.is_empty() {
+        let dimensions = rest.chars().take_while(|c| *c == '[').count();
+        let len = match rest[dimensions..].chars().next()? {
+            'L' => rest[dimensions..].find(';')? + 1,
+            'Z' | 'B' | 'S' | 'C' | 'I' | 'J' | 'F' | 'D' | 'V' => 1,
+            _ => return None,
+        };
+        split.push(&rest[..dimensions + len]);
+        rest = &rest[dimensions + len..];
+    }
+    Some(split)
+}
+
 impl Clone for Type {
     fn clone(&self) -> Self {
         Type {
@@ -168,6 +374,14 @@ impl fmt::Display for Type {
     }
 }
 
+/// Serialized as its type descriptor.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Type {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.type_descriptor, serializer)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -186,4 +400,100 @@ mod tests {
         assert_eq!(to_java_type("[Ljava/lang/String;"), "java.lang.String[]");
         assert_eq!(to_java_type("[[Ljava/lang/String;"), "java.lang.String[][]");
     }
+
+    #[test]
+    fn test_to_java_type_with() {
+        use super::{to_java_type_with, JavaNameOptions};
+        let dotted = JavaNameOptions::default().dotted_nested();
+        let described = dotted.describe_anonymous();
+        assert_eq!(
+            to_java_type_with("La/Outer$Inner;", Default::default()),
+            "a.Outer$Inner"
+        );
+        assert_eq!(
+            to_java_type_with("La/Outer$Inner;", dotted),
+            "a.Outer.Inner"
+        );
+        assert_eq!(
+            to_java_type_with("[[La/Outer$Inner;", dotted),
+            "a.Outer.Inner[][]"
+        );
+        assert_eq!(to_java_type_with("La/Outer$1;", dotted), "a.Outer$1");
+        assert_eq!(
+            to_java_type_with("La/Outer$1;", described),
+            "<anonymous a.Outer$1>"
+        );
+        assert_eq!(to_java_type_with("La/Outer$1$A;", dotted), "a.Outer$1.A");
+        assert_eq!(
+            to_java_type_with("La/Foo$$Lambda$1;", described),
+            "a.Foo$$Lambda$1"
+        );
+        assert_eq!(to_java_type_with("La/I$-CC;", dotted), "a.I$-CC");
+        assert_eq!(to_java_type_with(super::INT, described), "int");
+    }
+
+    #[test]
+    fn test_package_and_simple_names() {
+        use super::Type;
+        let names = |descriptor: &str| {
+            let jtype = Type {
+                id: 0,
+                type_descriptor: descriptor.to_string().into(),
+            };
+            (jtype.package_name(), jtype.simple_name())
+        };
+        let names_of = |package: &str, simple: &str| (package.to_string(), simple.to_string());
+        assert_eq!(names("Ljava/lang/String;"), names_of("java.lang", "String"));
+        assert_eq!(
+            names("[[Ljava/lang/String;"),
+            names_of("java.lang", "String[][]")
+        );
+        assert_eq!(names("La/Outer$Inner;"), names_of("a", "Inner"));
+        assert_eq!(names("La/Outer$1;"), names_of("a", "1"));
+        assert_eq!(names("La/I$-CC;"), names_of("a", "I$-CC"));
+        assert_eq!(names("LMain;"), names_of("", "Main"));
+        assert_eq!(names("[I"), names_of("", "int[]"));
+    }
+
+    #[test]
+    fn test_to_java_declaration() {
+        use super::{JavaNameOptions, Type};
+        let jtype = Type {
+            id: 0,
+            type_descriptor: "[[La/Outer$Inner;".to_string().into(),
+        };
+        let options = JavaNameOptions::default().dotted_nested();
+        assert_eq!(
+            jtype.to_java_declaration("grid", options),
+            "a.Outer.Inner[][] grid"
+        );
+        assert_eq!(
+            jtype.to_java_declaration("grid", options.c_style_arrays()),
+            "a.Outer.Inner grid[][]"
+        );
+    }
+
+    #[test]
+    fn test_split_descriptors() {
+        use super::split_descriptors;
+        assert_eq!(
+            split_descriptors("ILjava/lang/String;[[J[La;"),
+            Some(vec!["I", "Ljava/lang/String;", "[[J", "[La;"])
+        );
+        assert_eq!(split_descriptors(""), Some(vec![]));
+        assert_eq!(split_descriptors("Ljava/lang/String"), None);
+        assert_eq!(split_descriptors("[Q"), None);
+    }
+
+    #[test]
+    fn test_to_descriptor() {
+        use super::{to_descriptor, to_java_type};
+        assert_eq!(to_descriptor("int"), super::INT);
+        assert_eq!(to_descriptor("java.lang.String"), "Ljava/lang/String;");
+        assert_eq!(to_descriptor("a.Outer$Inner"), "La/Outer$Inner;");
+        assert_eq!(to_descriptor("Main"), "LMain;");
+        for descriptor in &["[[J", "[Ljava/lang/Object;", "V"] {
+            assert_eq!(to_descriptor(&to_java_type(descriptor)), *descriptor);
+        }
+    }
 }
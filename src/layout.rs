@@ -0,0 +1,410 @@
+//! Layout of a `Dex`: the bytes taken by the sections of its map list, the item containing
+//! an offset and the number of items of each kind against the limits of the format.
+use getset::{CopyGetters, Getters};
+use scroll::Pread;
+
+use crate::{provenance::Provenance, uint, ushort, utils::quote, Dex, ItemType, Result};
+
+/// Bytes of the file taken by a section of the map list.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct SectionSize {
+    /// Type of the items, `None` if the parser doesn't know it.
+    item_type: Option<ItemType>,
+    /// Type code of the items in the map list.
+    type_code: ushort,
+    /// Number of items.
+    count: uint,
+    /// Offset of the section from the start of the file.
+    offset: uint,
+    /// Bytes from the start of the section to the start of the next one, or to the end of
+    /// the file for the last section.
+    size: usize,
+    /// Share of the file taken by the section, in percent.
+    percentage: f64,
+}
+
+/// Bytes taken by each section of a `Dex`, see `Dex::section_sizes`.
+#[derive(Debug, Clone, Default, PartialEq, Getters, CopyGetters)]
+pub struct SectionSizes {
+    /// Size of the file.
+    #[get_copy = "pub"]
+    file_size: usize,
+    /// The sections, in the order of their offsets.
+    #[get = "pub"]
+    sections: Vec<SectionSize>,
+    /// Provenance of the `Dex` the sizes are about.
+    provenance: Option<Provenance>,
+}
+
+impl SectionSizes {
+    /// Provenance of the `Dex` the sizes are about.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    /// The section of the items of `item_type`, if there is one.
+    pub fn get(&self, item_type: ItemType) -> Option<SectionSize> {
+        self.sections
+            .iter()
+            .find(|section| section.item_type == Some(item_type))
+            .copied()
+    }
+
+    /// The sizes as a JSON object with a `sections` array, and a `provenance` object if
+    /// the provenance is known. Sections of unknown types are named by their type code.
+    pub fn to_json(&self) -> String {
+        let sections: Vec<String> = self
+            .sections
+            .iter()
+            .map(|section| {
+                let name = match section.item_type {
+                    Some(item_type) => format!("{:?}", item_type),
+                    None => format!("{:#06x}", section.type_code),
+                };
+                format!(
+                    "{{\"type\":{},\"count\":{},\"offset\":{},\"size\":{},\"percentage\":{}}}",
+                    quote(&name),
+                    section.count,
+                    section.offset,
+                    section.size,
+                    section.percentage
+                )
+            })
+            .collect();
+        let provenance = self
+            .provenance
+            .as_ref()
+            .map(|provenance| format!(",\"provenance\":{}", provenance.to_json()))
+            .unwrap_or_default();
+        format!(
+            "{{\"file_size\":{},\"sections\":[{}]{}}}",
+            self.file_size,
+            sections.join(","),
+            provenance
+        )
+    }
+}
+
+/// The item of a `Dex` containing a byte, see `Dex::item_at_offset`.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct ItemLocation {
+    /// The section containing the byte.
+    section: SectionSize,
+    /// Index of the item: its position in the section for the items of a fixed size and
+    /// for the entries of the map list, its `StringId` for string data items. `None` if the
+    /// items of the section can't be told apart, or if the byte is in the padding after
+    /// them.
+    index: Option<usize>,
+    /// Offset of the start of the item, if it is known.
+    item_offset: Option<uint>,
+}
+
+/// The section and the item of `dex` containing the byte at `offset`.
+pub(crate) fn item_at_offset<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    offset: uint,
+) -> Result<Option<ItemLocation>> {
+    let section = match section_sizes(dex)
+        .sections
+        .into_iter()
+        .rev()
+        .find(|section| section.offset <= offset)
+    {
+        Some(section) if ((offset - section.offset) as usize) < section.size => section,
+        _ => return Ok(None),
+    };
+    let relative = offset - section.offset;
+    let item = match section.item_type {
+        Some(ItemType::Header) if relative < dex.header().header_size() => Some((0, 0)),
+        Some(ItemType::MapList) if relative >= 4 => {
+            let index = (relative - 4) / 12;
+            Some((index, 4 + index * 12)).filter(|(index, _)| *index < section.count)
+        }
+        Some(ItemType::StringDataItem) => {
+            // the closest string starting before the byte.
+            let mut closest = None;
+            for string_id in 0..dex.inner.strings_len() {
+                let string_id_off = dex.inner.strings_offset() + string_id * 4;
+                let data_off: uint = dex
+                    .source
+                    .as_ref()
+                    .pread_with(string_id_off as usize, dex.get_endian())?;
+                if data_off <= offset && Some(data_off) > closest.map(|(_, closest)| closest) {
+                    closest = Some((string_id, data_off));
+                }
+            }
+            closest.map(|(string_id, data_off)| (string_id, data_off - section.offset))
+        }
+        Some(item_type) => item_type.item_size().and_then(|item_size| {
+            let index = relative / item_size;
+            Some((index, index * item_size)).filter(|(index, _)| *index < section.count)
+        }),
+        None => None,
+    };
+    Ok(Some(ItemLocation {
+        section,
+        index: item.map(|(index, _)| index as usize),
+        item_offset: item.map(|(_, item_offset)| section.offset + item_offset),
+    }))
+}
+
+/// Bytes taken by the sections of the map list of `dex`. Sections don't have a size in the
+/// map list, so each one is assumed to extend to the start of the next one.
+pub(crate) fn section_sizes<T: AsRef<[u8]>>(dex: &Dex<T>) -> SectionSizes {
+    let file_size = dex.header().file_size() as usize;
+    let map_list = dex.map_list();
+    let mut sections: Vec<SectionSize> = map_list
+        .items()
+        .iter()
+        .map(|item| {
+            (
+                Some(item.item_type()),
+                item.item_type() as ushort,
+                item.size(),
+                item.offset(),
+            )
+        })
+        .chain(
+            map_list
+                .unknown_items()
+                .iter()
+                .map(|item| (None, item.type_code(), item.size(), item.offset())),
+        )
+        .map(|(item_type, type_code, count, offset)| SectionSize {
+            item_type,
+            type_code,
+            count,
+            offset,
+            size: 0,
+            percentage: 0.0,
+        })
+        .collect();
+    sections.sort_by_key(|section| section.offset);
+    let ends: Vec<usize> = sections
+        .iter()
+        .skip(1)
+        .map(|section| section.offset as usize)
+        .chain(Some(file_size))
+        .collect();
+    for (section, end) in sections.iter_mut().zip(ends) {
+        section.size = end.min(file_size).saturating_sub(section.offset as usize);
+        if file_size > 0 {
+            section.percentage = section.size as f64 * 100.0 / file_size as f64;
+        }
+    }
+    SectionSizes {
+        file_size,
+        sections,
+        provenance: Some(dex.provenance()),
+    }
+}
+
+/// Number of items which can be referenced by the 16 bit indices of instructions. A `Dex`
+/// with more methods, fields or types than this has to be split, see `multidex`.
+pub const REFERENCE_LIMIT: usize = 65536;
+
+/// Number of items of a kind and how close it is to `REFERENCE_LIMIT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct ReferenceCount {
+    /// Number of items.
+    count: usize,
+    /// Number of items which can be referenced.
+    limit: usize,
+}
+
+impl ReferenceCount {
+    fn new(count: uint) -> Self {
+        Self {
+            count: count as usize,
+            limit: REFERENCE_LIMIT,
+        }
+    }
+
+    /// Share of the limit which is used, more than `1` if it is exceeded.
+    pub fn usage(self) -> f64 {
+        self.count as f64 / self.limit as f64
+    }
+
+    /// Number of items which can still be added.
+    pub fn remaining(self) -> usize {
+        self.limit.saturating_sub(self.count)
+    }
+
+    /// Whether there are more items than can be referenced.
+    pub fn is_over_limit(self) -> bool {
+        self.count > self.limit
+    }
+
+    fn to_json(self) -> String {
+        format!(
+            "{{\"count\":{},\"limit\":{},\"usage\":{}}}",
+            self.count,
+            self.limit,
+            self.usage()
+        )
+    }
+}
+
+/// Numbers of the referenceable items of a `Dex`, see `Dex::stats`. Only the methods,
+/// fields and types are really limited: strings past the limit are loaded with
+/// `const-string/jumbo`, and there can't be more protos or classes than types.
+#[derive(Debug, Clone, PartialEq, CopyGetters)]
+pub struct DexStats {
+    /// Method references, the entries of the method_ids section.
+    #[get_copy = "pub"]
+    method_refs: ReferenceCount,
+    /// Field references, the entries of the field_ids section.
+    #[get_copy = "pub"]
+    field_refs: ReferenceCount,
+    /// Type references, the entries of the type_ids section.
+    #[get_copy = "pub"]
+    type_refs: ReferenceCount,
+    /// Strings.
+    #[get_copy = "pub"]
+    strings: ReferenceCount,
+    /// Method prototypes.
+    #[get_copy = "pub"]
+    protos: ReferenceCount,
+    /// Classes defined by the `Dex`.
+    #[get_copy = "pub"]
+    classes: ReferenceCount,
+    /// Provenance of the `Dex` the counts are about.
+    provenance: Option<Provenance>,
+}
+
+impl DexStats {
+    /// Provenance of the `Dex` the counts are about.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    /// Usage of the limited references which is the closest to the limit.
+    pub fn max_usage(&self) -> f64 {
+        [self.method_refs, self.field_refs, self.type_refs]
+            .iter()
+            .map(|count| count.usage())
+            .fold(0.0, f64::max)
+    }
+
+    /// The counts as a JSON object, with a `provenance` object if the provenance is known.
+    pub fn to_json(&self) -> String {
+        let provenance = self
+            .provenance
+            .as_ref()
+            .map(|provenance| format!(",\"provenance\":{}", provenance.to_json()))
+            .unwrap_or_default();
+        format!(
+            "{{\"method_refs\":{},\"field_refs\":{},\"type_refs\":{},\"strings\":{},\
+             \"protos\":{},\"classes\":{}{}}}",
+            self.method_refs.to_json(),
+            self.field_refs.to_json(),
+            self.type_refs.to_json(),
+            self.strings.to_json(),
+            self.protos.to_json(),
+            self.classes.to_json(),
+            provenance
+        )
+    }
+}
+
+/// Counts the referenceable items of `dex`.
+pub(crate) fn stats<T: AsRef<[u8]>>(dex: &Dex<T>) -> DexStats {
+    DexStats {
+        method_refs: ReferenceCount::new(dex.inner.method_ids_len()),
+        field_refs: ReferenceCount::new(dex.inner.field_ids_len()),
+        type_refs: ReferenceCount::new(dex.inner.type_ids_len()),
+        strings: ReferenceCount::new(dex.inner.strings_len()),
+        protos: ReferenceCount::new(dex.inner.proto_ids_len()),
+        classes: ReferenceCount::new(dex.inner.class_defs_len()),
+        provenance: Some(dex.provenance()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{uint, ItemType};
+
+    #[test]
+    fn test_section_sizes() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let sizes = dex.section_sizes();
+        assert_eq!(sizes.sections().len(), dex.map_list().items().len());
+        assert_eq!(
+            sizes.sections().iter().map(|s| s.size()).sum::<usize>(),
+            sizes.file_size()
+        );
+        let percentage: f64 = sizes.sections().iter().map(|s| s.percentage()).sum();
+        assert!((percentage - 100.0).abs() < 1e-6);
+        let header = sizes.get(ItemType::Header).unwrap();
+        assert_eq!(header.offset(), 0);
+        assert_eq!(header.size(), dex.header().header_size() as usize);
+        let string_ids = sizes.get(ItemType::StringIdItem).unwrap();
+        assert_eq!(
+            string_ids.size(),
+            4 * dex.header().string_ids_size() as usize
+        );
+        assert!(sizes.get(ItemType::CodeItem).unwrap().percentage() > 0.0);
+
+        let class_defs = dex.header().class_defs_off();
+        let location = dex
+            .item_at_offset(class_defs + 32 * 2 + 5)
+            .unwrap()
+            .unwrap();
+        assert_eq!(location.section().item_type(), Some(ItemType::ClassDefItem));
+        assert_eq!(location.index(), Some(2));
+        assert_eq!(location.item_offset(), Some(class_defs + 32 * 2));
+        let location = dex.item_at_offset(0x20).unwrap().unwrap();
+        assert_eq!(location.section().item_type(), Some(ItemType::Header));
+        assert_eq!(location.item_offset(), Some(0));
+        let code = sizes.get(ItemType::CodeItem).unwrap();
+        let location = dex.item_at_offset(code.offset() + 1).unwrap().unwrap();
+        assert_eq!(location.section(), code);
+        assert_eq!(location.index(), None);
+        assert!(dex
+            .item_at_offset(sizes.file_size() as uint)
+            .unwrap()
+            .is_none());
+
+        // the string data item of the 3rd string, past its size.
+        let strings = dex.header().string_ids_off() as usize;
+        let bytes = std::fs::read("resources/classes.dex").unwrap();
+        let data_off: uint =
+            scroll::Pread::pread_with(&bytes[..], strings + 8, scroll::LE).unwrap();
+        let location = dex.item_at_offset(data_off + 1).unwrap().unwrap();
+        assert_eq!(
+            location.section().item_type(),
+            Some(ItemType::StringDataItem)
+        );
+        assert_eq!(location.index(), Some(2));
+        assert_eq!(location.item_offset(), Some(data_off));
+        assert!(sizes
+            .to_json()
+            .contains("{\"type\":\"Header\",\"count\":1,\"offset\":0,"));
+    }
+
+    #[test]
+    fn test_stats() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let stats = dex.stats();
+        let header = dex.header();
+        assert_eq!(
+            stats.method_refs().count(),
+            header.method_ids_size() as usize
+        );
+        assert_eq!(stats.field_refs().count(), header.field_ids_size() as usize);
+        assert_eq!(stats.type_refs().count(), header.type_ids_size() as usize);
+        assert_eq!(stats.strings().count(), header.string_ids_size() as usize);
+        assert_eq!(stats.protos().count(), header.proto_ids_size() as usize);
+        assert_eq!(stats.classes().count(), header.class_defs_size() as usize);
+        let methods = stats.method_refs();
+        assert_eq!(methods.remaining(), 65536 - methods.count());
+        assert!(!methods.is_over_limit());
+        assert!(stats.max_usage() >= methods.usage() && stats.max_usage() < 1.0);
+        let json = stats.to_json();
+        assert!(json.starts_with("{\"method_refs\":{\"count\":"), "{}", json);
+        assert!(json.contains("\"provenance\":"));
+    }
+}
@@ -16,31 +16,74 @@ extern crate log;
 
 extern crate getset;
 
+pub use cache::{CacheConfig, CacheSize};
 pub use error::Error;
 
-pub use crate::dex::{Dex, DexReader, Header};
+pub use crate::dex::{
+    Dex, DexReader, Header, ItemType, MapItem, MapList, SectionItems, UnknownMapItem,
+};
 
+#[macro_use]
+mod trace;
 #[macro_use]
 mod utils;
+pub mod analysis;
 pub mod annotation;
+pub mod builder;
 mod cache;
+pub mod callgraph;
+pub mod cdex;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod cfg;
 pub mod class;
 pub mod code;
+pub mod conformance;
+pub mod custom;
+pub mod deps;
 mod dex;
+pub mod diff;
+pub mod edit;
 mod encoded_item;
 pub mod encoded_value;
 mod error;
 pub mod field;
+pub mod fingerprint;
+pub mod frozen;
+pub mod hierarchy;
+pub mod indicators;
+pub mod instruction;
 pub mod jtype;
+pub mod layout;
 pub mod method;
+pub mod multidex;
+pub mod odex;
+pub mod opcodes;
+pub mod provenance;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "raw")]
+pub mod raw;
+pub mod rename;
 mod search;
+pub mod smali;
 mod source;
+pub mod size;
+pub mod sourcemap;
 pub mod string;
+pub mod strings;
+pub mod system_annotations;
+pub mod verifier;
+pub mod visit;
+pub mod writer;
+pub mod xref;
 
 /// The constant NO_INDEX is used to indicate that an index value is absent.
 pub const NO_INDEX: uint = 0xffff_ffff;
-const ENDIAN_CONSTANT: (ubyte, ubyte, ubyte, ubyte) = (0x12, 0x34, 0x56, 0x78);
-const REVERSE_ENDIAN_CONSTANT: (ubyte, ubyte, ubyte, ubyte) = (0x78, 0x56, 0x34, 0x12);
+/// The `endian_tag` 0x12345678 as stored in a little endian dex file.
+const ENDIAN_CONSTANT: [ubyte; 4] = [0x78, 0x56, 0x34, 0x12];
+/// The `endian_tag` 0x12345678 as stored in a byte-swapped, big endian dex file.
+const REVERSE_ENDIAN_CONSTANT: [ubyte; 4] = [0x12, 0x34, 0x56, 0x78];
 
 /// 8-bit signed int
 #[allow(non_camel_case_types)]
@@ -1,4 +1,6 @@
 //! Dex `Method` and supporting structures
+use std::sync::Arc;
+
 use getset::{CopyGetters, Getters};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
@@ -6,13 +8,17 @@ use scroll::{ctx, Pread, Uleb128};
 
 use crate::{
     annotation::{AnnotationSetItem, AnnotationSetRefList},
-    code::CodeItem,
+    code::{CodeItem, DebugInfoItem},
     encoded_item::{EncodedItem, EncodedItemArray},
-    error::Error,
+    encoded_value::EncodedValue,
+    error::{Error, ResultExt},
     field::FieldId,
     jtype::{Type, TypeId},
+    opcodes::OpcodeHistogram,
+    smali,
     string::{DexString, StringId},
-    uint, ulong, ushort, utils,
+    uint, ulong, ushort,
+    utils::{self, Lazy},
 };
 
 bitflags! {
@@ -37,7 +43,7 @@ bitflags! {
 
 /// Represents a `Class` method.
 #[derive(Debug, Getters, CopyGetters)]
-pub struct Method {
+pub struct Method<'a> {
     /// Parent class of the method.
     #[get = "pub"]
     class: Type,
@@ -57,20 +63,23 @@ pub struct Method {
     /// Return type of the method.
     #[get = "pub"]
     return_type: Type,
-    /// Code and DebugInfo of the method.
-    code: Option<CodeItem>,
-    /// Annotations of the method.
-    #[get = "pub"]
-    annotations: AnnotationSetItem,
-    /// Annotations of the params.
-    #[get = "pub"]
-    param_annotations: AnnotationSetRefList,
+    /// Code and DebugInfo of the method, parsed on first access.
+    code: Lazy<'a, Option<CodeItem<'a>>>,
+    /// Annotations of the method, parsed on first access.
+    annotations: Lazy<'a, AnnotationSetItem>,
+    /// Annotations of the params, parsed on first access.
+    param_annotations: Lazy<'a, AnnotationSetRefList>,
     /// `MethodId` of the method.
     #[get_copy = "pub"]
     id: MethodId,
+    /// Position of the method in `Class::direct_methods` or `Class::virtual_methods`, which
+    /// is the order of the class data. Methods are sorted by `MethodId` in a `Dex`, so the
+    /// order of declaration in the source isn't available.
+    #[get_copy = "pub"]
+    pub(crate) slot: usize,
 }
 
-impl Method {
+impl<'a> Method<'a> {
     gen_is_flag_set!(is_public, PUBLIC);
     gen_is_flag_set!(is_private, PRIVATE);
     gen_is_flag_set!(is_protected, PROTECTED);
@@ -88,12 +97,108 @@ impl Method {
 
     /// Returns the value of `dalvik.annotation.Signature`.
     pub fn signature(&self) -> super::Result<Option<String>> {
-        utils::get_signature(self.annotations())
+        utils::get_signature(self.try_annotations()?)
+    }
+
+    /// Returns the exceptions declared by the method, the value of `dalvik.annotation.Throws`.
+    /// Empty if the method doesn't declare any.
+    pub fn throws(&self) -> super::Result<Vec<Type>> {
+        utils::get_throws(self.try_annotations()?)
+    }
+
+    /// Annotations of the method, parsed on first access. Empty if they are malformed,
+    /// `try_annotations` returns the error instead.
+    pub fn annotations(&self) -> &AnnotationSetItem {
+        self.annotations.get_or_empty()
+    }
+
+    /// Annotations of the method, parsed on first access.
+    pub fn try_annotations(&self) -> super::Result<&AnnotationSetItem> {
+        self.annotations.get()
+    }
+
+    /// Annotations of the params, parsed on first access. Empty if they are malformed,
+    /// `try_param_annotations` returns the error instead.
+    pub fn param_annotations(&self) -> &AnnotationSetRefList {
+        self.param_annotations.get_or_empty()
     }
 
-    /// Code and DebugInfo of the method.
-    pub fn code(&self) -> Option<&CodeItem> {
-        self.code.as_ref()
+    /// Annotations of the params, parsed on first access.
+    pub fn try_param_annotations(&self) -> super::Result<&AnnotationSetRefList> {
+        self.param_annotations.get()
+    }
+
+    /// Offset of the `CodeItem` of the method, `0` if it has none.
+    pub fn code_off(&self) -> ulong {
+        self.code.offset()
+    }
+
+    /// Code and DebugInfo of the method, parsed on first access. This is the lossy
+    /// convenience accessor: it returns `None` if the method has no code and also if its
+    /// code is malformed, so code which can report errors should use `try_code` instead.
+    pub fn code(&self) -> Option<&CodeItem<'a>> {
+        self.try_code().unwrap_or_else(|e| {
+            debug!(target: "method", "failed to load code of {}: {:?}", self.name, e);
+            None
+        })
+    }
+
+    /// Code and DebugInfo of the method, parsed on first access.
+    pub fn try_code(&self) -> super::Result<Option<&CodeItem<'a>>> {
+        Ok(self.code.get()?.as_ref())
+    }
+
+    /// Number of times each opcode occurs in the code of the method. Empty if the method
+    /// has no code.
+    pub fn opcode_histogram(&self) -> super::Result<OpcodeHistogram> {
+        match self.try_code()? {
+            Some(code) => OpcodeHistogram::from_code(code),
+            None => Ok(Default::default()),
+        }
+    }
+
+    /// Source line of the instruction at `address`, given in code units from the start of
+    /// the method's code. Returns `None` if the method has no debug info or `address` is
+    /// before the first position entry.
+    pub fn line_for_address(&self, address: uint) -> Option<usize> {
+        self.debug_info_item()?.line_for_address(address)
+    }
+
+    /// Start addresses, in code units, of the instructions mapped to `line`.
+    pub fn addresses_for_line(&self, line: usize) -> Vec<uint> {
+        self.debug_info_item()
+            .map(|debug_info| debug_info.addresses_for_line(line))
+            .unwrap_or_default()
+    }
+
+    /// Reference to the method in smali notation, like `Lcom/foo/Bar;->baz(ILjava/lang/String;)V`,
+    /// the same as `MethodIdItem::descriptor`.
+    pub fn descriptor(&self) -> String {
+        format!(
+            "{}->{}{}",
+            self.class.type_descriptor(),
+            self.name,
+            smali::signature(&self.params, &self.return_type)
+        )
+    }
+
+    /// Names and types of the parameters of the method. Names come from the debug info and
+    /// are `None` if the method has none or the compiler didn't keep them. The implicit
+    /// `this` parameter of instance methods isn't included, like in `params`.
+    pub fn parameters(&self) -> Vec<(Option<DexString>, Type)> {
+        let names = self
+            .debug_info_item()
+            .map(|debug_info| debug_info.parameter_names().as_slice())
+            .unwrap_or_default();
+        self.params
+            .iter()
+            .enumerate()
+            .map(|(index, jtype)| (names.get(index).cloned().flatten(), jtype.clone()))
+            .collect()
+    }
+
+    fn debug_info_item(&self) -> Option<&DebugInfoItem> {
+        self.code()?.debug_info_item()
     }
 }
 
@@ -103,6 +208,7 @@ pub type ProtoId = ulong;
 /// Method Prototypes.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#proto-id-item)
 #[derive(Pread, Debug, CopyGetters, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[get_copy = "pub"]
 pub struct ProtoIdItem {
     /// Index into the string_ids list for the short-form descriptor string of this prototype
@@ -124,42 +230,43 @@ impl ProtoIdItem {
     }
 }
 
-impl Method {
+/// A `ProtoIdItem` with its shorty, return type and params resolved, shared by the
+/// methods with the same prototype. See `Dex::get_prototype`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Prototype {
+    pub(crate) shorty: DexString,
+    pub(crate) return_type: Type,
+    pub(crate) params: Vec<Type>,
+}
+
+impl Prototype {
     pub(crate) fn try_from_dex<S: AsRef<[u8]>>(
         dex: &super::Dex<S>,
+        proto_item: &ProtoIdItem,
+    ) -> super::Result<Self> {
+        let params = dex.get_type_list(proto_item.params_off)?;
+        Ok(Self {
+            shorty: dex.get_string(proto_item.shorty)?,
+            return_type: dex.get_type(proto_item.return_type)?,
+            params,
+        })
+    }
+}
+
+impl<'a> Method<'a> {
+    pub(crate) fn try_from_dex<S: AsRef<[u8]>>(
+        dex: &'a super::Dex<S>,
         encoded_method: &EncodedMethod,
-        annotations: AnnotationSetItem,
-        param_annotations: AnnotationSetRefList,
-    ) -> super::Result<Method> {
+        annotations: Lazy<'a, AnnotationSetItem>,
+        param_annotations: Lazy<'a, AnnotationSetRefList>,
+    ) -> super::Result<Self> {
         debug!(target: "method", "encoded method: {:?}", encoded_method);
-        let source = &dex.source;
         let method_item = dex.get_method_item(encoded_method.method_id)?;
         let name = dex.get_string(method_item.name_idx)?;
         debug!(target: "method", "name: {}, method id item: {:?}", name, method_item);
-        let proto_item = dex.get_proto_item(ProtoId::from(method_item.proto_idx))?;
-        debug!(target: "method", "method proto_item: {:?}", proto_item);
-        let shorty = dex.get_string(proto_item.shorty)?;
-        let return_type = dex.get_type(proto_item.return_type)?;
-        let params = if proto_item.params_off != 0 {
-            if !dex.is_offset_in_data_section(proto_item.params_off) {
-                return Err(Error::BadOffset(
-                    proto_item.params_off as usize,
-                    format!(
-                        "Params offset not in data section for proto_item: {:?}",
-                        proto_item
-                    ),
-                ));
-            }
-            let offset = &mut (proto_item.params_off as usize);
-            let endian = dex.get_endian();
-            let len = source.gread_with::<uint>(offset, endian)?;
-            let type_ids: Vec<ushort> = try_gread_vec_with!(source, offset, len, endian);
-            utils::get_types(dex, &type_ids)?
-        } else {
-            Default::default()
-        };
+        let prototype = dex.get_prototype(ProtoId::from(method_item.proto_idx))?;
+        debug!(target: "method", "method prototype: {:?}", prototype);
         debug!(target: "method", "code item offset: {}", encoded_method.code_offset);
-        let code = dex.get_code_item(encoded_method.code_offset)?;
         Ok(Self {
             name,
             class: dex.get_type(TypeId::from(method_item.class_idx))?,
@@ -169,27 +276,56 @@ impl Method {
                     method_item.name_idx
                 ))
             })?,
-            shorty,
-            return_type,
-            params,
-            code,
+            shorty: prototype.shorty.clone(),
+            return_type: prototype.return_type.clone(),
+            params: prototype.params.clone(),
+            code: Lazy::new(encoded_method.code_offset, {
+                let source = dex.source.as_ref();
+                let dex = dex.borrowed();
+                let method_id = encoded_method.method_id;
+                Arc::new(move |code_off| {
+                    dex.read_code_item(source, code_off, Some(method_id))
+                        .with_id("method_id", method_id)
+                })
+            }),
             annotations,
             param_annotations,
             id: encoded_method.method_id,
+            slot: 0,
+        })
+    }
+
+    /// The method with its code and annotations parsed, see `Class::into_owned`.
+    pub(crate) fn into_owned(self) -> super::Result<Method<'static>> {
+        Ok(Method {
+            class: self.class,
+            name: self.name,
+            access_flags: self.access_flags,
+            params: self.params,
+            shorty: self.shorty,
+            return_type: self.return_type,
+            code: self
+                .code
+                .into_loaded_with(|code| code.map(CodeItem::into_owned))?,
+            annotations: self.annotations.into_loaded()?,
+            param_annotations: self.param_annotations.into_loaded()?,
+            id: self.id,
+            slot: self.slot,
         })
     }
 }
 
-#[derive(Pread)]
-struct MethodIdData {
-    class_idx: ushort,
-    proto_idx: ushort,
-    name_idx: StringId,
+#[derive(Pread, Debug)]
+pub(crate) struct MethodIdData {
+    pub(crate) class_idx: ushort,
+    pub(crate) proto_idx: ushort,
+    pub(crate) name_idx: StringId,
 }
 
 /// Method identifier.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#method-id-item)
-#[derive(Debug, CopyGetters, PartialEq)]
+#[derive(Debug, Clone, CopyGetters, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[get_copy = "pub"]
 pub struct MethodIdItem {
     /// Index into the `TypeId`s list for the definer of this method.
@@ -217,6 +353,18 @@ impl MethodIdItem {
             id: method_id,
         })
     }
+
+    /// Reference to the method in smali notation, like `Lcom/foo/Bar;->baz(ILjava/lang/String;)V`.
+    pub fn descriptor<S: AsRef<[u8]>>(&self, dex: &super::Dex<S>) -> super::Result<String> {
+        let prototype = dex.get_prototype(ProtoId::from(self.proto_idx))?;
+        Ok(format!(
+            "{}->{}{}",
+            dex.get_type(TypeId::from(self.class_idx))?
+                .type_descriptor(),
+            dex.get_string(self.name_idx)?,
+            smali::signature(&prototype.params, &prototype.return_type)
+        ))
+    }
 }
 
 /// Index into the `MethodId`s list.
@@ -225,6 +373,9 @@ pub type MethodId = ulong;
 /// Index into the `MethodHandleItem`s list.
 pub type MethodHandleId = uint;
 
+/// Index into the `CallSiteId`s list.
+pub type CallSiteId = uint;
+
 /// Contains a `MethodId` along with its access flags and code.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#encoded-method)
 #[derive(Debug, Getters, CopyGetters)]
@@ -271,9 +422,57 @@ impl<'a> ctx::TryFromCtx<'a, ulong> for EncodedMethod {
     }
 }
 
+/// A call site of `invoke-custom` instructions: the bootstrap method which links it, and
+/// the arguments it is given.
+/// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#call-site-item)
+#[derive(Debug, PartialEq, Getters, CopyGetters)]
+pub struct CallSiteItem {
+    /// `CallSiteId` of the call site.
+    #[get_copy = "pub"]
+    id: CallSiteId,
+    /// The bootstrap method.
+    #[get = "pub"]
+    bootstrap_method: MethodHandleItem,
+    /// Name of the method the call site is linked to.
+    #[get = "pub"]
+    method_name: DexString,
+    /// Prototype of the method the call site is linked to.
+    #[get = "pub"]
+    method_type: ProtoIdItem,
+    /// The constant arguments given to the bootstrap method after the first three.
+    #[get = "pub"]
+    extra_arguments: Vec<EncodedValue>,
+}
+
+impl CallSiteItem {
+    /// Call site from the values of its encoded array, which start with the bootstrap
+    /// method, the method name and the method type.
+    pub(crate) fn from_values(id: CallSiteId, values: Vec<EncodedValue>) -> super::Result<Self> {
+        let mut values = values.into_iter();
+        match (values.next(), values.next(), values.next()) {
+            (
+                Some(EncodedValue::MethodHandle(bootstrap_method)),
+                Some(EncodedValue::String(method_name)),
+                Some(EncodedValue::MethodType(method_type)),
+            ) => Ok(Self {
+                id,
+                bootstrap_method,
+                method_name,
+                method_type,
+                extra_arguments: values.collect(),
+            }),
+            values => Err(Error::MalFormed(format!(
+                "Invalid call site {}: {:?}",
+                id, values
+            ))),
+        }
+    }
+}
+
 /// Type of the method handle.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#method-handle-type-codes)
 #[derive(FromPrimitive, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum MethodHandleType {
     StaticPut = 0x00,
     StaticGet = 0x01,
@@ -286,7 +485,28 @@ pub enum MethodHandleType {
     InvokeInterface = 0x08,
 }
 
+/// A method found by `Dex::find_method`.
+#[derive(Debug)]
+pub enum ResolvedMethod<'a> {
+    /// The method is defined by a class of the `Dex`.
+    Defined(Box<Method<'a>>),
+    /// The method is only referenced, its class is defined in another `Dex` or the
+    /// method is inherited.
+    Referenced(MethodIdItem),
+}
+
+impl ResolvedMethod<'_> {
+    /// `MethodId` of the method.
+    pub fn id(&self) -> MethodId {
+        match self {
+            ResolvedMethod::Defined(method) => method.id(),
+            ResolvedMethod::Referenced(item) => item.id(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum FieldOrMethodId {
     Field(FieldId),
     Method(MethodId),
@@ -295,6 +515,7 @@ pub enum FieldOrMethodId {
 /// A method handle.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#method-handle-item)
 #[derive(Debug, CopyGetters, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[get_copy = "pub"]
 pub struct MethodHandleItem {
     ///  The type of this MethodHandleItem.
@@ -328,3 +549,126 @@ impl<'a, S: AsRef<[u8]>> ctx::TryFromCtx<'a, &super::Dex<S>> for MethodHandleIte
         Ok((Self { handle_type, id }, *offset))
     }
 }
+
+/// Serialized as the bits of the flags.
+#[cfg(feature = "serde")]
+impl serde::Serialize for AccessFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.bits(), serializer)
+    }
+}
+
+/// Any combination of the defined flags.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for AccessFlags {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::from_bits_truncate(u.arbitrary()?))
+    }
+}
+
+/// Serialized with its annotations but without its code, use `CodeItem::instructions` to
+/// serialize the instructions. Malformed annotations are serialized as empty.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Method<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Method", 8)?;
+        state.serialize_field("class", &self.class)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("access_flags", &self.access_flags)?;
+        state.serialize_field("params", &self.params)?;
+        state.serialize_field("return_type", &self.return_type)?;
+        state.serialize_field("shorty", &self.shorty)?;
+        state.serialize_field("annotations", self.annotations())?;
+        state.serialize_field("param_annotations", self.param_annotations())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallSiteItem, FieldOrMethodId, MethodHandleItem, MethodHandleType, ProtoIdItem};
+    use crate::{encoded_value::EncodedValue, string::DexString};
+
+    #[test]
+    fn test_call_site_item() {
+        let bootstrap_method = || MethodHandleItem {
+            handle_type: MethodHandleType::InvokeStatic,
+            id: FieldOrMethodId::Method(3),
+        };
+        let method_type = || ProtoIdItem {
+            shorty: 1,
+            return_type: 2,
+            params_off: 0,
+        };
+        let values = vec![
+            EncodedValue::MethodHandle(bootstrap_method()),
+            EncodedValue::String(DexString::from("apply".to_string())),
+            EncodedValue::MethodType(method_type()),
+            EncodedValue::Int(42),
+        ];
+        let call_site = CallSiteItem::from_values(7, values).unwrap();
+        assert_eq!(call_site.id(), 7);
+        assert_eq!(call_site.bootstrap_method(), &bootstrap_method());
+        assert_eq!(call_site.method_name(), "apply");
+        assert_eq!(call_site.method_type(), &method_type());
+        assert_eq!(call_site.extra_arguments(), &[EncodedValue::Int(42)]);
+
+        let values = vec![EncodedValue::MethodHandle(bootstrap_method())];
+        let error = CallSiteItem::from_values(7, values).unwrap_err();
+        assert!(
+            error.to_string().contains("Invalid call site 7"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_descriptor() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let class = dex
+            .find_class_by_name("Lorg/adw/launcher/Launcher;")
+            .unwrap()
+            .expect("class not found");
+        let method = class
+            .method("onCreate", &["Landroid/os/Bundle;"])
+            .expect("method not found");
+        assert_eq!(
+            method.descriptor(),
+            "Lorg/adw/launcher/Launcher;->onCreate(Landroid/os/Bundle;)V"
+        );
+        for method in class.methods() {
+            let item = dex.get_method_item(method.id()).unwrap();
+            assert_eq!(item.descriptor(&dex).unwrap(), method.descriptor());
+        }
+    }
+
+    #[test]
+    fn test_throws() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let class = dex
+            .find_class_by_name("Lmobi/intuitit/android/internal/utils/FastXmlSerializer;")
+            .unwrap()
+            .expect("class not found");
+        let throws = |name, params: &[&str]| -> Vec<String> {
+            let method = class.method(name, params).expect("method not found");
+            method
+                .throws()
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+        assert_eq!(throws("flushBytes", &[]), vec!["Ljava/io/IOException;"]);
+        assert_eq!(
+            throws("comment", &["Ljava/lang/String;"]),
+            vec![
+                "Ljava/io/IOException;",
+                "Ljava/lang/IllegalArgumentException;",
+                "Ljava/lang/IllegalStateException;"
+            ]
+        );
+        assert!(throws("<init>", &[]).is_empty());
+    }
+}
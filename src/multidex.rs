@@ -0,0 +1,205 @@
+//! The dex files of an app, like `classes.dex`, `classes2.dex`, ... of an APK, whose classes
+//! refer to the classes of each other.
+use getset::Getters;
+
+use crate::{
+    class::Class,
+    field::{FieldId, ResolvedField},
+    jtype::TypeId,
+    method::{MethodId, ResolvedMethod},
+    Dex, Result,
+};
+
+/// Several `Dex` files which are loaded together. A class is defined by the first `Dex`
+/// which defines it, as Android ignores the definitions of later ones.
+#[derive(Debug, Getters)]
+pub struct MultiDex<T> {
+    /// The dex files in the order they are loaded.
+    #[get = "pub"]
+    dexes: Vec<Dex<T>>,
+}
+
+impl<T: AsRef<[u8]>> MultiDex<T> {
+    /// Groups `dexes`, in the order they are loaded.
+    pub fn new(dexes: Vec<Dex<T>>) -> Self {
+        Self { dexes }
+    }
+
+    /// Index of the `Dex` which defines the class with the type descriptor `descriptor`,
+    /// `None` if none of them do.
+    pub fn defining_dex(&self, descriptor: &str) -> Result<Option<usize>> {
+        for (index, dex) in self.dexes.iter().enumerate() {
+            if let Some(type_id) = dex.find_type_id_by_name(descriptor)? {
+                if dex.get_class_def(type_id)?.is_some() {
+                    return Ok(Some(index));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<T: AsRef<[u8]>> MultiDex<T> {
+    /// Iterator over the classes of all the dex files, in the order of the files. A class
+    /// defined by several files is returned once for each of them.
+    pub fn classes(&self) -> impl Iterator<Item = Result<Class<'_>>> + '_ {
+        self.dexes.iter().flat_map(Dex::classes)
+    }
+
+    /// Finds a class by its type descriptor, see `Dex::find_class_by_name`.
+    pub fn find_class_by_name(&self, descriptor: &str) -> Result<Option<Class<'_>>> {
+        match self.defining_dex(descriptor)? {
+            Some(index) => self.dexes[index].find_class_by_name(descriptor),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds a method by its smali reference, see `Dex::find_method`. The method is
+    /// `Defined` if the dex file which defines its class defines it, otherwise the first
+    /// `MethodIdItem` which refers to it is returned.
+    pub fn find_method(&self, reference: &str) -> Result<Option<ResolvedMethod<'_>>> {
+        let mut referenced = None;
+        for dex in &self.dexes {
+            match dex.find_method(reference)? {
+                Some(ResolvedMethod::Defined(method)) => {
+                    return Ok(Some(ResolvedMethod::Defined(method)))
+                }
+                Some(method) => {
+                    referenced.get_or_insert(method);
+                }
+                None => {}
+            }
+        }
+        Ok(referenced)
+    }
+
+    /// Finds a field by the type descriptor of its class and its name, see `Dex::find_field`
+    /// and `find_method`.
+    pub fn find_field(
+        &self,
+        class_descriptor: &str,
+        field_name: &str,
+    ) -> Result<Option<ResolvedField<'_>>> {
+        let mut referenced = None;
+        for dex in &self.dexes {
+            match dex.find_field(class_descriptor, field_name)? {
+                Some(ResolvedField::Defined(field)) => {
+                    return Ok(Some(ResolvedField::Defined(field)))
+                }
+                Some(field) => {
+                    referenced.get_or_insert(field);
+                }
+                None => {}
+            }
+        }
+        Ok(referenced)
+    }
+
+    /// Resolves the type `type_id` of the `Dex` at `dex_index` to the class which defines
+    /// it, in any of the dex files. `None` if the class isn't defined by any of them, like
+    /// the classes of the framework.
+    pub fn resolve_class(&self, dex_index: usize, type_id: TypeId) -> Result<Option<Class<'_>>> {
+        let descriptor = self.dex(dex_index)?.get_type(type_id)?.to_string();
+        self.find_class_by_name(&descriptor)
+    }
+
+    /// Resolves the method `method_id` of the `Dex` at `dex_index`, like the target of an
+    /// `invoke` instruction, to its definition in any of the dex files.
+    pub fn resolve_method(
+        &self,
+        dex_index: usize,
+        method_id: MethodId,
+    ) -> Result<ResolvedMethod<'_>> {
+        let dex = self.dex(dex_index)?;
+        let item = dex.get_method_item(method_id)?;
+        match self.find_method(&item.descriptor(dex)?)? {
+            Some(ResolvedMethod::Defined(method)) => Ok(ResolvedMethod::Defined(method)),
+            _ => Ok(ResolvedMethod::Referenced(item)),
+        }
+    }
+
+    /// Resolves the field `field_id` of the `Dex` at `dex_index`, like the operand of an
+    /// `iget` instruction, to its definition in any of the dex files.
+    pub fn resolve_field(&self, dex_index: usize, field_id: FieldId) -> Result<ResolvedField<'_>> {
+        let dex = self.dex(dex_index)?;
+        let item = dex.get_field_item(field_id)?;
+        let class = dex.get_type(item.class_idx().into())?.to_string();
+        let name = dex.get_string(item.name_idx())?.to_string();
+        match self.find_field(&class, &name)? {
+            Some(ResolvedField::Defined(field)) => Ok(ResolvedField::Defined(field)),
+            _ => Ok(ResolvedField::Referenced(item)),
+        }
+    }
+
+    fn dex(&self, dex_index: usize) -> Result<&Dex<T>> {
+        self.dexes.get(dex_index).ok_or_else(|| {
+            crate::Error::InvalidId(format!(
+                "Invalid dex index {}, there are {} dex files",
+                dex_index,
+                self.dexes.len()
+            ))
+        })
+    }
+}
+
+impl MultiDex<Vec<u8>> {
+    /// Reads the dex files of the APK at `path`, see `DexReader::from_apk`.
+    #[cfg(feature = "zip")]
+    pub fn from_apk<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(crate::DexReader::from_apk(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultiDex;
+    use crate::{field::ResolvedField, method::ResolvedMethod, DexReader};
+
+    #[test]
+    fn test_multidex() {
+        let open = || DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let multidex = MultiDex::new(vec![open(), open()]);
+        let launcher = "Lorg/adw/launcher/Launcher;";
+        assert_eq!(multidex.defining_dex(launcher).unwrap(), Some(0));
+        assert_eq!(
+            multidex.defining_dex("Landroid/app/Activity;").unwrap(),
+            None
+        );
+        assert_eq!(
+            multidex.defining_dex("Lnet/missing/Missing;").unwrap(),
+            None
+        );
+        assert_eq!(
+            multidex.classes().count(),
+            2 * multidex.dexes()[0].classes().count()
+        );
+
+        let reference = "Lorg/adw/launcher/Launcher;->onCreate(Landroid/os/Bundle;)V";
+        let method_id = match multidex.dexes()[1].find_method(reference).unwrap() {
+            Some(ResolvedMethod::Defined(method)) => method.id(),
+            _ => panic!("method not defined"),
+        };
+        match multidex.resolve_method(1, method_id).unwrap() {
+            ResolvedMethod::Defined(method) => assert_eq!(method.descriptor(), reference),
+            ResolvedMethod::Referenced(_) => panic!("method not resolved"),
+        }
+        let super_call = "Landroid/app/Activity;->onCreate(Landroid/os/Bundle;)V";
+        assert!(matches!(
+            multidex.find_method(super_call).unwrap(),
+            Some(ResolvedMethod::Referenced(_))
+        ));
+
+        let class = multidex.find_class_by_name(launcher).unwrap().unwrap();
+        let resolved = multidex
+            .resolve_class(1, class.id())
+            .unwrap()
+            .expect("class not resolved");
+        assert_eq!(resolved.jtype(), class.jtype());
+        let field = class.fields().next().expect("no fields");
+        assert!(matches!(
+            multidex.resolve_field(1, field.id()).unwrap(),
+            ResolvedField::Defined(_)
+        ));
+        assert!(multidex.resolve_class(2, class.id()).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! Files in which the runtime wraps the dex files it optimized: the ODEX files of Dalvik,
+//! the OAT files of ART, which are ELF files, and VDEX files. Their dex files are read with
+//! `DexReader::from_odex_file`.
+use std::fmt;
+
+use scroll::{Pread, LE};
+
+use crate::{cdex, error::Error, uint, Result};
+
+/// The kind of a file which holds dex files, given by its magic value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    /// A standard dex file.
+    Dex,
+    /// A compact dex file, see `cdex`.
+    CompactDex,
+    /// A dex file optimized by Dalvik, with the `dey\n` magic.
+    Odex,
+    /// An OAT file compiled by ART. The dex files of the ones written by Android 8.0 and
+    /// later are in the VDEX file next to them.
+    Oat,
+    /// A VDEX file written by ART, which holds the dex files and their verification data.
+    Vdex,
+}
+
+impl Container {
+    /// The kind of `bytes`, `None` if it isn't a dex file nor wraps one.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        let container = match bytes.get(..4)? {
+            b"dex\n" => Container::Dex,
+            b"cdex" => Container::CompactDex,
+            b"dey\n" => Container::Odex,
+            b"\x7fELF" => Container::Oat,
+            b"vdex" => Container::Vdex,
+            _ => return None,
+        };
+        Some(container)
+    }
+
+    /// Whether the dex files are wrapped by another format.
+    pub fn is_wrapper(self) -> bool {
+        !matches!(self, Container::Dex | Container::CompactDex)
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Container::Dex => "dex",
+            Container::CompactDex => "compact dex",
+            Container::Odex => "ODEX",
+            Container::Oat => "OAT",
+            Container::Vdex => "VDEX",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Kind of the section of a VDEX file which holds the dex files, since version `027`.
+const VDEX_DEX_FILE_SECTION: uint = 1;
+
+/// Offsets of the dex files embedded in `bytes`, which is a `container`.
+pub(crate) fn embedded_dex_offsets(bytes: &[u8], container: Container) -> Result<Vec<usize>> {
+    let offsets = match container {
+        Container::Dex | Container::CompactDex => vec![0],
+        Container::Odex => {
+            // dexOffset follows the magic value, the header is little endian.
+            let dex_offset: uint = bytes.pread_with(8, LE)?;
+            vec![dex_offset as usize]
+        }
+        // the layout of OAT files changes with every version of ART, so their dex files
+        // are found by their magic value.
+        Container::Oat => scan(bytes, 0..bytes.len()),
+        Container::Vdex => {
+            let version: uint = bytes
+                .get(4..7)
+                .and_then(|version| std::str::from_utf8(version).ok()?.parse().ok())
+                .ok_or_else(|| Error::MalFormed("Invalid VDEX version".to_string()))?;
+            if version >= 27 {
+                // the header is followed by a list of sections, each with a kind, an offset
+                // and a size.
+                let sections: uint = bytes.pread_with(8, LE)?;
+                let mut offsets = Vec::new();
+                for section in 0..sections as usize {
+                    let kind: uint = bytes.pread_with(12 + section * 12, LE)?;
+                    let offset: uint = bytes.pread_with(16 + section * 12, LE)?;
+                    let size: uint = bytes.pread_with(20 + section * 12, LE)?;
+                    if kind == VDEX_DEX_FILE_SECTION {
+                        let (offset, size) = (offset as usize, size as usize);
+                        offsets.extend(scan(bytes, offset..offset + size));
+                    }
+                }
+                offsets
+            } else {
+                scan(bytes, 12..bytes.len())
+            }
+        }
+    };
+    if offsets.is_empty() {
+        return Err(Error::MalFormed(format!(
+            "No dex file found in {} file",
+            container
+        )));
+    }
+    Ok(offsets)
+}
+
+/// Offsets of the headers of the dex files in `range`, which are 4 byte aligned. The
+/// dex files don't overlap, the search resumes after the end of each of them.
+fn scan(bytes: &[u8], range: std::ops::Range<usize>) -> Vec<usize> {
+    let end = range.end.min(bytes.len());
+    let mut offsets = Vec::new();
+    let mut offset = (range.start + 3) & !3;
+    while offset + 0x70 <= end {
+        match dex_file_size(&bytes[offset..end]) {
+            Some(file_size) => {
+                offsets.push(offset);
+                offset = (offset + file_size + 3) & !3;
+            }
+            None => offset += 4,
+        }
+    }
+    offsets
+}
+
+/// `file_size` of the dex file at the start of `bytes`, `None` if there is no dex file
+/// there.
+fn dex_file_size(bytes: &[u8]) -> Option<usize> {
+    let magic = bytes.get(..8)?;
+    let known_magic = (magic.starts_with(b"dex\n") || magic.starts_with(cdex::MAGIC))
+        && magic[4..7].iter().all(u8::is_ascii_digit)
+        && magic[7] == 0;
+    if !known_magic {
+        return None;
+    }
+    let endian = crate::dex::read_endian(bytes, 0).ok()?;
+    let file_size: uint = bytes.pread_with(32, endian).ok()?;
+    let header_size: uint = bytes.pread_with(36, endian).ok()?;
+    if header_size < 0x70 || file_size < header_size {
+        return None;
+    }
+    Some(file_size as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Container;
+    use crate::DexReader;
+
+    #[test]
+    fn test_odex() {
+        let dex = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let padded_len = (dex.len() + 3) & !3;
+        let class_defs = DexReader::from_vec(&dex)
+            .unwrap()
+            .header()
+            .class_defs_size();
+
+        // a VDEX file with a checksum section and a dex file section holding two dex files.
+        let mut vdex = b"vdex027\0".to_vec();
+        vdex.extend_from_slice(&2u32.to_le_bytes());
+        let dex_section = 12 + 2 * 12 + 8;
+        for (kind, offset, size) in [(0u32, 36, 8), (1, dex_section, 2 * padded_len)] {
+            for value in [kind, offset as u32, size as u32] {
+                vdex.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        vdex.resize(dex_section, 0);
+        for _ in 0..2 {
+            vdex.extend_from_slice(&dex);
+            vdex.resize((vdex.len() + 3) & !3, 0);
+        }
+        assert_eq!(Container::detect(&vdex), Some(Container::Vdex));
+        let dexes = DexReader::from_odex_vec(&vdex).expect("failed to read VDEX file");
+        assert_eq!(dexes.len(), 2);
+        assert_eq!(dexes[1].header().class_defs_size(), class_defs);
+        let container = format!("VDEX@{:#x}", dex_section + padded_len);
+        assert_eq!(dexes[1].provenance().container(), Some(container.as_str()));
+        let error = DexReader::from_vec(&vdex).unwrap_err().to_string();
+        assert!(error.contains("VDEX"), "{}", error);
+
+        // an older VDEX file without sections, and a Dalvik ODEX file.
+        let mut old_vdex = vdex.clone();
+        old_vdex[4..7].copy_from_slice(b"019");
+        assert_eq!(DexReader::from_odex_vec(&old_vdex).unwrap().len(), 2);
+        let mut odex = b"dey\n036\0".to_vec();
+        odex.extend_from_slice(&40u32.to_le_bytes());
+        odex.extend_from_slice(&(dex.len() as u32).to_le_bytes());
+        odex.resize(40, 0);
+        odex.extend_from_slice(&dex);
+        let dexes = DexReader::from_odex_vec(&odex).expect("failed to read ODEX file");
+        assert_eq!(dexes[0].provenance().container(), Some("ODEX@0x28"));
+        let mut truncated = odex[..64].to_vec();
+        truncated[8..12].copy_from_slice(&0xffff_0000u32.to_le_bytes());
+        let error = DexReader::from_odex_vec(&truncated).unwrap_err();
+        assert!(error.to_string().contains("out of bounds"), "{}", error);
+
+        let error = DexReader::from_odex_vec(b"\x7fELF\x02\x01\x01\0").unwrap_err();
+        assert!(error.to_string().contains("No dex file found in OAT file"));
+        assert_eq!(DexReader::from_odex_vec(&dex).unwrap().len(), 1);
+        assert!(DexReader::from_odex_vec(b"not a dex file").is_err());
+    }
+}
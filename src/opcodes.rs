@@ -0,0 +1,239 @@
+//! Histograms of the opcodes of the code of a `Dex` or of a method, and the methods whose
+//! opcodes are unusual for their `Dex`.
+use std::collections::HashMap;
+
+use getset::CopyGetters;
+
+use crate::{code::CodeItem, instruction::Opcode, method::MethodId, utils::quote, Dex, Result};
+
+/// Number of times each opcode occurs in some code, see `Dex::opcode_histogram` and
+/// `Method::opcode_histogram`. Payloads of switch and array instructions are counted under
+/// their pseudo-opcodes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpcodeHistogram {
+    counts: HashMap<Opcode, usize>,
+    total: usize,
+}
+
+impl OpcodeHistogram {
+    /// Histogram of the instructions of `code`.
+    pub(crate) fn from_code(code: &CodeItem) -> Result<Self> {
+        let mut histogram = Self::default();
+        for instruction in code.instructions() {
+            *histogram.counts.entry(instruction?.opcode()).or_insert(0) += 1;
+            histogram.total += 1;
+        }
+        Ok(histogram)
+    }
+
+    /// Number of instructions.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Number of instructions with `opcode`.
+    pub fn count(&self, opcode: Opcode) -> usize {
+        self.counts.get(&opcode).copied().unwrap_or(0)
+    }
+
+    /// Share of the instructions with `opcode`, `0` if there are no instructions.
+    pub fn frequency(&self, opcode: Opcode) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.count(opcode) as f64 / self.total as f64
+    }
+
+    /// Opcodes which occur along with their counts, the most frequent first.
+    pub fn opcodes(&self) -> Vec<(Opcode, usize)> {
+        let mut opcodes: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(opcode, count)| (*opcode, *count))
+            .collect();
+        opcodes.sort_by(|(a, a_count), (b, b_count)| {
+            b_count
+                .cmp(a_count)
+                .then_with(|| a.mnemonic().cmp(b.mnemonic()))
+        });
+        opcodes
+    }
+
+    /// Shannon entropy of the distribution, in bits. Packed or generated code tends to
+    /// have a lower entropy than code written by hand.
+    pub fn entropy(&self) -> f64 {
+        self.counts
+            .values()
+            .map(|count| {
+                let p = *count as f64 / self.total as f64;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// Adds the counts of `other` to this histogram.
+    pub fn merge(&mut self, other: &OpcodeHistogram) {
+        for (opcode, count) in &other.counts {
+            *self.counts.entry(*opcode).or_insert(0) += count;
+        }
+        self.total += other.total;
+    }
+
+    /// Opcodes which are used more than expected from the frequencies of `baseline`, like
+    /// the histogram of the whole `Dex`, with a score of at least `threshold`. The score is
+    /// the number of standard deviations the count is above the expected count, taking
+    /// each instruction as an independent draw from `baseline`. The highest scores come
+    /// first.
+    pub fn anomalies(&self, baseline: &OpcodeHistogram, threshold: f64) -> Vec<OpcodeAnomaly> {
+        if self.total == 0 {
+            return Vec::new();
+        }
+        let total = self.total as f64;
+        let mut anomalies: Vec<_> = self
+            .counts
+            .iter()
+            .filter_map(|(opcode, count)| {
+                // smoothed, so that opcodes missing from the baseline are merely rare.
+                let p = (baseline.count(*opcode) as f64 + 1.0) / (baseline.total as f64 + 2.0);
+                let expected = total * p;
+                let score = (*count as f64 - expected) / (total * p * (1.0 - p)).sqrt();
+                if score >= threshold {
+                    Some(OpcodeAnomaly {
+                        opcode: *opcode,
+                        count: *count,
+                        expected,
+                        score,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+        anomalies.sort_by(|a, b| b.score.total_cmp(&a.score));
+        anomalies
+    }
+
+    /// The histogram as a JSON object with the `total` number of instructions and the
+    /// `opcodes` counts by mnemonic.
+    pub fn to_json(&self) -> String {
+        let opcodes: Vec<String> = self
+            .opcodes()
+            .into_iter()
+            .map(|(opcode, count)| format!("{}:{}", quote(opcode.mnemonic()), count))
+            .collect();
+        format!(
+            "{{\"total\":{},\"opcodes\":{{{}}}}}",
+            self.total,
+            opcodes.join(",")
+        )
+    }
+}
+
+/// An opcode used more than expected, see `OpcodeHistogram::anomalies`.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct OpcodeAnomaly {
+    /// The opcode.
+    opcode: Opcode,
+    /// Number of instructions with the opcode.
+    count: usize,
+    /// Number of instructions expected from the baseline.
+    expected: f64,
+    /// Number of standard deviations `count` is above `expected`.
+    score: f64,
+}
+
+impl OpcodeAnomaly {
+    /// The anomaly as a JSON object.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"opcode\":{},\"count\":{},\"expected\":{},\"score\":{}}}",
+            quote(self.opcode.mnemonic()),
+            self.count,
+            self.expected,
+            self.score
+        )
+    }
+}
+
+/// Histograms of the code of each method of `dex` which has code.
+fn method_histograms<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<Vec<(MethodId, OpcodeHistogram)>> {
+    dex.method_code_items()?
+        .iter()
+        .map(|(method_id, code)| Ok((*method_id, OpcodeHistogram::from_code(code)?)))
+        .collect()
+}
+
+/// Histogram of the instructions of all the methods of `dex`.
+pub(crate) fn opcode_histogram<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<OpcodeHistogram> {
+    let mut histogram = OpcodeHistogram::default();
+    for (_, method) in method_histograms(dex)? {
+        histogram.merge(&method);
+    }
+    Ok(histogram)
+}
+
+/// Methods of `dex` with at least `min_instructions` instructions whose opcodes stand out
+/// from the histogram of the whole `Dex`, along with the opcodes that do, see
+/// `OpcodeHistogram::anomalies`. Small methods are skipped as a single rare instruction
+/// is enough to make them stand out.
+pub fn unusual_methods<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    threshold: f64,
+    min_instructions: usize,
+) -> Result<Vec<(MethodId, Vec<OpcodeAnomaly>)>> {
+    let histograms = method_histograms(dex)?;
+    let mut baseline = OpcodeHistogram::default();
+    for (_, histogram) in &histograms {
+        baseline.merge(histogram);
+    }
+    Ok(histograms
+        .into_iter()
+        .filter(|(_, histogram)| histogram.total >= min_instructions)
+        .filter_map(|(method_id, histogram)| {
+            let anomalies = histogram.anomalies(&baseline, threshold);
+            if anomalies.is_empty() {
+                None
+            } else {
+                Some((method_id, anomalies))
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unusual_methods, OpcodeHistogram};
+    use crate::instruction::Opcode;
+
+    #[test]
+    fn test_opcode_histogram() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let histogram = dex.opcode_histogram().expect("failed to compute histogram");
+        let mut methods = OpcodeHistogram::default();
+        for class in dex.classes() {
+            for method in class.unwrap().methods() {
+                methods.merge(&method.opcode_histogram().unwrap());
+            }
+        }
+        assert_eq!(methods, histogram);
+        let (most_used, count) = histogram.opcodes()[0];
+        assert_eq!(histogram.count(most_used), count);
+        assert!(histogram.frequency(most_used) > histogram.frequency(Opcode::Nop));
+        let entropy = histogram.entropy();
+        assert!(entropy > 0.0 && entropy <= (histogram.opcodes().len() as f64).log2());
+        assert!(histogram.anomalies(&histogram, 3.0).is_empty());
+        assert!(histogram
+            .to_json()
+            .starts_with(&format!("{{\"total\":{},\"opcodes\":{{", histogram.total())));
+
+        let unusual = unusual_methods(&dex, 3.0, 20).expect("failed to find unusual methods");
+        assert!(!unusual.is_empty());
+        for (_, anomalies) in unusual {
+            assert!(anomalies.windows(2).all(|w| w[0].score() >= w[1].score()));
+            assert!(anomalies
+                .iter()
+                .all(|a| a.score() >= 3.0 && a.count() as f64 > a.expected()));
+        }
+    }
+}
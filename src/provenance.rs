@@ -0,0 +1,117 @@
+//! Where a `Dex` comes from, so that pipelines handling many files can attribute findings.
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use getset::{CopyGetters, Getters};
+use sha2::{Digest, Sha256};
+
+/// Origin of a `Dex`, see `Dex::provenance`.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct Provenance {
+    /// Path of the file the `Dex` was read from. `None` if it was loaded from memory.
+    path: Option<PathBuf>,
+    /// Name of the entry of the container, like `classes2.dex` in an APK, set through
+    /// `Dex::with_container`.
+    container: Option<String>,
+    /// SHA-256 of the bytes of the `Dex`, in lower case hex.
+    #[get = "pub"]
+    sha256: String,
+    /// Time at which the `Dex` was parsed.
+    #[get_copy = "pub"]
+    parsed_at: SystemTime,
+    /// Whether the file was memory mapped by the reader.
+    #[get_copy = "pub"]
+    memory_mapped: bool,
+    /// Number of strings the `Dex` caches, `usize::MAX` if unbounded.
+    #[get_copy = "pub"]
+    string_cache_size: usize,
+}
+
+impl Provenance {
+    /// Path of the file the `Dex` was read from. `None` if it was loaded from memory.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Name of the entry of the container, like `classes2.dex` in an APK.
+    pub fn container(&self) -> Option<&str> {
+        self.container.as_deref()
+    }
+
+    /// The provenance as a JSON object. `parsed_at` is given in seconds since the Unix epoch.
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<&str>| value.map_or("null".to_string(), crate::utils::quote);
+        let parsed_at = self
+            .parsed_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        format!(
+            "{{\"path\":{},\"container\":{},\"sha256\":\"{}\",\"parsed_at\":{},\"memory_mapped\":{},\"string_cache_size\":{}}}",
+            optional(self.path.as_ref().map(|path| path.to_string_lossy()).as_deref()),
+            optional(self.container()),
+            self.sha256,
+            parsed_at,
+            self.memory_mapped,
+            self.string_cache_size
+        )
+    }
+}
+
+/// What the reader knows about a `Dex` when loading it, the hash is computed on first use.
+#[derive(Debug, Clone)]
+pub(crate) struct Origin {
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) container: Option<String>,
+    pub(crate) parsed_at: SystemTime,
+    pub(crate) memory_mapped: bool,
+    pub(crate) string_cache_size: usize,
+}
+
+impl Origin {
+    pub(crate) fn new(
+        path: Option<PathBuf>,
+        memory_mapped: bool,
+        string_cache_size: usize,
+    ) -> Self {
+        Self {
+            path,
+            container: None,
+            parsed_at: SystemTime::now(),
+            memory_mapped,
+            string_cache_size,
+        }
+    }
+
+    pub(crate) fn provenance(&self, sha256: String) -> Provenance {
+        Provenance {
+            path: self.path.clone(),
+            container: self.container.clone(),
+            sha256,
+            parsed_at: self.parsed_at,
+            memory_mapped: self.memory_mapped,
+            string_cache_size: self.string_cache_size,
+        }
+    }
+}
+
+impl From<&Provenance> for Origin {
+    fn from(provenance: &Provenance) -> Self {
+        Self {
+            path: provenance.path.clone(),
+            container: provenance.container.clone(),
+            parsed_at: provenance.parsed_at,
+            memory_mapped: provenance.memory_mapped,
+            string_cache_size: provenance.string_cache_size,
+        }
+    }
+}
+
+/// Lower case hex SHA-256 of `bytes`.
+pub(crate) fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,320 @@
+//! Python bindings of the library, built with the `python` feature. Build the crate as a
+//! `cdylib` named `dex` with PyO3's `extension-module` feature to import it from Python:
+//!
+//! ```python
+//! import dex
+//! for cls in dex.Dex("classes.dex").classes():
+//!     for method in cls.methods():
+//!         print(method.descriptor, [i.opcode for i in method.instructions()])
+//! ```
+// the `#[pymethods]` expansion converts the errors of `PyResult`s, which are `PyErr`s already.
+#![allow(clippy::useless_conversion)]
+
+use std::rc::Rc;
+
+use memmap2::Mmap;
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+};
+
+use crate::{class::Class, field::Field, method::Method, Dex, DexReader, Error};
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::IO(_) => PyIOError::new_err(error.to_string()),
+            _ => PyValueError::new_err(error.to_string()),
+        }
+    }
+}
+
+/// A dex file, see `Dex`.
+#[pyclass(name = "Dex", unsendable)]
+pub struct PyDex(Dex<Mmap>);
+
+#[pymethods]
+impl PyDex {
+    /// Opens the dex file at `path`.
+    #[new]
+    fn new(path: &str) -> PyResult<Self> {
+        Ok(Self(DexReader::from_file(path)?))
+    }
+
+    /// Version of the dex format, like `35`.
+    #[getter]
+    fn version(&self) -> Option<u32> {
+        self.0.header().version()
+    }
+
+    /// The classes defined in the file.
+    fn classes(&self) -> PyResult<Vec<PyClassDef>> {
+        self.0
+            .classes()
+            .map(|class| Ok(PyClassDef(Rc::new(class?.into_owned()?))))
+            .collect()
+    }
+
+    /// The class with the type descriptor `descriptor`, like `Lcom/foo/Bar;`.
+    fn find_class(&self, descriptor: &str) -> PyResult<Option<PyClassDef>> {
+        match self.0.find_class_by_name(descriptor)? {
+            Some(class) => Ok(Some(PyClassDef(Rc::new(class.into_owned()?)))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A class, see `Class`. Its members are parsed along with it, so it doesn't borrow its
+/// `Dex`.
+#[pyclass(name = "Class", unsendable)]
+pub struct PyClassDef(Rc<Class<'static>>);
+
+#[pymethods]
+impl PyClassDef {
+    /// Type descriptor of the class.
+    #[getter]
+    fn descriptor(&self) -> String {
+        self.0.jtype().to_string()
+    }
+
+    #[getter]
+    fn access_flags(&self) -> u32 {
+        self.0.access_flags().bits()
+    }
+
+    /// Type descriptor of the super class, `None` for `java.lang.Object`.
+    #[getter]
+    fn superclass(&self) -> Option<String> {
+        self.0.superclass_type().map(ToString::to_string)
+    }
+
+    #[getter]
+    fn interfaces(&self) -> Vec<String> {
+        self.0
+            .interfaces()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[getter]
+    fn source_file(&self) -> Option<String> {
+        self.0.source_file().map(ToString::to_string)
+    }
+
+    /// Direct methods, then virtual methods.
+    fn methods(&self) -> Vec<PyMethodDef> {
+        (0..self.0.methods().count())
+            .map(|index| PyMethodDef {
+                class: self.0.clone(),
+                index,
+            })
+            .collect()
+    }
+
+    /// Static fields, then instance fields.
+    fn fields(&self) -> Vec<PyFieldDef> {
+        (0..self.0.fields().count())
+            .map(|index| PyFieldDef {
+                class: self.0.clone(),
+                index,
+            })
+            .collect()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Class({})", self.0.jtype())
+    }
+}
+
+/// A method, see `Method`.
+#[pyclass(name = "Method", unsendable)]
+pub struct PyMethodDef {
+    class: Rc<Class<'static>>,
+    index: usize,
+}
+
+impl PyMethodDef {
+    fn method(&self) -> &Method<'static> {
+        self.class
+            .methods()
+            .nth(self.index)
+            .expect("index is less than the number of methods")
+    }
+}
+
+#[pymethods]
+impl PyMethodDef {
+    #[getter]
+    fn name(&self) -> String {
+        self.method().name().to_string()
+    }
+
+    /// Reference to the method in smali notation, like `Lcom/foo/Bar;->baz(I)V`.
+    #[getter]
+    fn descriptor(&self) -> String {
+        self.method().descriptor()
+    }
+
+    #[getter]
+    fn access_flags(&self) -> u64 {
+        self.method().access_flags().bits()
+    }
+
+    #[getter]
+    fn params(&self) -> Vec<String> {
+        self.method()
+            .params()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[getter]
+    fn return_type(&self) -> String {
+        self.method().return_type().to_string()
+    }
+
+    /// The decoded instructions of the method, empty if it is abstract or native.
+    fn instructions(&self) -> PyResult<Vec<PyInstruction>> {
+        let code = match self.method().try_code()? {
+            Some(code) => code,
+            None => return Ok(Vec::new()),
+        };
+        let instructions = code
+            .instructions()
+            .with_offsets()
+            .map(|instruction| {
+                let (offset, instruction) = instruction?;
+                Ok(PyInstruction {
+                    offset,
+                    opcode: instruction.opcode().mnemonic(),
+                    registers: instruction.registers(),
+                    index: instruction.index(),
+                    literal: instruction.literal(),
+                    branch_offset: instruction.branch_offset(),
+                })
+            })
+            .collect::<crate::Result<_>>()?;
+        Ok(instructions)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Method({})", self.method().descriptor())
+    }
+}
+
+/// A field, see `Field`.
+#[pyclass(name = "Field", unsendable)]
+pub struct PyFieldDef {
+    class: Rc<Class<'static>>,
+    index: usize,
+}
+
+impl PyFieldDef {
+    fn field(&self) -> &Field<'static> {
+        self.class
+            .fields()
+            .nth(self.index)
+            .expect("index is less than the number of fields")
+    }
+}
+
+#[pymethods]
+impl PyFieldDef {
+    #[getter]
+    fn name(&self) -> String {
+        self.field().name().to_string()
+    }
+
+    /// Type descriptor of the field.
+    #[getter]
+    fn r#type(&self) -> String {
+        self.field().jtype().to_string()
+    }
+
+    /// Reference to the field in smali notation, like `Lcom/foo/Bar;->baz:I`.
+    #[getter]
+    fn descriptor(&self) -> String {
+        self.field().descriptor()
+    }
+
+    #[getter]
+    fn access_flags(&self) -> u64 {
+        self.field().access_flags().bits()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Field({})", self.field().descriptor())
+    }
+}
+
+/// A decoded instruction, see `Instruction`.
+#[pyclass(name = "Instruction", get_all)]
+pub struct PyInstruction {
+    /// Offset in code units from the start of the code.
+    offset: usize,
+    opcode: &'static str,
+    registers: Vec<u16>,
+    index: Option<u64>,
+    literal: Option<i64>,
+    branch_offset: Option<i32>,
+}
+
+#[pymethods]
+impl PyInstruction {
+    fn __repr__(&self) -> String {
+        format!("Instruction({:#x}, {})", self.offset, self.opcode)
+    }
+}
+
+/// The `dex` Python module.
+#[pymodule]
+fn dex(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyDex>()?;
+    m.add_class::<PyClassDef>()?;
+    m.add_class::<PyMethodDef>()?;
+    m.add_class::<PyFieldDef>()?;
+    m.add_class::<PyInstruction>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::{prelude::*, types::PyDict};
+
+    #[test]
+    fn test_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new_bound(py, "dex").unwrap();
+            super::dex(&module).unwrap();
+            let locals = PyDict::new_bound(py);
+            locals.set_item("dex", module).unwrap();
+            py.run_bound(
+                r#"
+d = dex.Dex("resources/classes.dex")
+assert d.version == 35
+cls = d.find_class("Lorg/adw/launcher/Launcher;")
+assert cls.descriptor == "Lorg/adw/launcher/Launcher;"
+assert cls.source_file == "Launcher.java"
+assert d.find_class("Lnet/missing/Missing;") is None
+method = next(m for m in cls.methods() if m.name == "onCreate")
+assert method.descriptor == "Lorg/adw/launcher/Launcher;->onCreate(Landroid/os/Bundle;)V"
+instructions = method.instructions()
+assert instructions[0].offset == 0
+assert "return-void" in [i.opcode for i in instructions]
+assert all(f.descriptor.startswith(cls.descriptor + "->") for f in cls.fields())
+try:
+    dex.Dex("resources/missing.dex")
+    assert False
+except IOError:
+    pass
+"#,
+                Some(&locals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
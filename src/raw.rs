@@ -0,0 +1,217 @@
+//! Raw id items along with their locations in the file, for tools which cross-check or patch
+//! a `Dex` at precise offsets. Enabled by the `raw` feature.
+use getset::CopyGetters;
+use scroll::Pread;
+
+use crate::{class::ClassDefItem, error::Error, string::StringId, uint, Dex, Result};
+
+/// Location of a table of fixed size entries, like the `string_ids` section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct Table {
+    /// Offset of the first entry from the start of the file.
+    offset: uint,
+    /// Number of entries.
+    len: uint,
+    /// Size of an entry in bytes.
+    stride: uint,
+}
+
+impl Table {
+    fn new(offset: Option<uint>, len: Option<uint>, stride: uint) -> Self {
+        Self {
+            offset: offset.unwrap_or(0),
+            len: len.unwrap_or(0),
+            stride,
+        }
+    }
+
+    /// Returns `true` if the table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Offset of the entry at `index`, or `None` if `index` is out of bounds.
+    pub fn entry_offset(&self, index: uint) -> Option<uint> {
+        if index < self.len {
+            Some(self.offset + index * self.stride)
+        } else {
+            None
+        }
+    }
+}
+
+/// Locations of the id sections of a `Dex`.
+/// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#file-layout)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct Layout {
+    /// The `string_ids` section.
+    string_ids: Table,
+    /// The `type_ids` section.
+    type_ids: Table,
+    /// The `proto_ids` section.
+    proto_ids: Table,
+    /// The `field_ids` section.
+    field_ids: Table,
+    /// The `method_ids` section.
+    method_ids: Table,
+    /// The `class_defs` section.
+    class_defs: Table,
+    /// The `call_site_ids` section, empty if the file has none.
+    call_site_ids: Table,
+    /// The `method_handles` section, empty if the file has none.
+    method_handles: Table,
+}
+
+/// Returns the locations of the id sections of `dex`.
+pub fn layout<T: AsRef<[u8]>>(dex: &Dex<T>) -> Layout {
+    let inner = &dex.inner;
+    Layout {
+        string_ids: Table::new(Some(inner.strings_offset()), Some(inner.strings_len()), 4),
+        type_ids: Table::new(Some(inner.type_ids_offset()), Some(inner.type_ids_len()), 4),
+        proto_ids: Table::new(
+            Some(inner.proto_ids_offset()),
+            Some(inner.proto_ids_len()),
+            12,
+        ),
+        field_ids: Table::new(
+            Some(inner.field_ids_offset()),
+            Some(inner.field_ids_len()),
+            8,
+        ),
+        method_ids: Table::new(
+            Some(inner.method_ids_offset()),
+            Some(inner.method_ids_len()),
+            8,
+        ),
+        class_defs: Table::new(
+            Some(inner.class_defs_offset()),
+            Some(inner.class_defs_len()),
+            32,
+        ),
+        call_site_ids: Table::new(inner.call_site_ids_offset(), inner.call_site_ids_len(), 4),
+        method_handles: Table::new(inner.method_handles_offset(), inner.method_handles_len(), 8),
+    }
+}
+
+/// An entry of the `string_ids` section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct RawStringId {
+    /// Offset of this entry from the start of the file.
+    offset: uint,
+    /// Offset of the string data from the start of the file.
+    string_data_off: uint,
+}
+
+/// An entry of the `type_ids` section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct RawTypeId {
+    /// Offset of this entry from the start of the file.
+    offset: uint,
+    /// `StringId` of the type descriptor.
+    descriptor_idx: StringId,
+}
+
+/// An entry of the `class_defs` section.
+#[derive(Debug, Clone, Copy, CopyGetters)]
+#[get_copy = "pub"]
+pub struct RawClassDef {
+    /// Offset of this entry from the start of the file.
+    offset: uint,
+    /// The entry.
+    item: ClassDefItem,
+}
+
+fn read_entry<T, S, F>(dex: &Dex<S>, table: Table, index: uint, read: F) -> Result<T>
+where
+    S: AsRef<[u8]>,
+    F: FnOnce(&[u8], uint) -> Result<T>,
+{
+    let offset = table
+        .entry_offset(index)
+        .ok_or_else(|| Error::InvalidId(format!("Invalid index: {}", index)))?;
+    read(dex.source.as_ref(), offset)
+}
+
+/// Iterator over the entries of the `string_ids` section.
+pub fn string_ids<T: AsRef<[u8]>>(dex: &Dex<T>) -> impl Iterator<Item = Result<RawStringId>> + '_ {
+    let table = layout(dex).string_ids;
+    (0..table.len).map(move |index| {
+        read_entry(dex, table, index, |source, offset| {
+            Ok(RawStringId {
+                offset,
+                string_data_off: source.pread_with(offset as usize, dex.get_endian())?,
+            })
+        })
+    })
+}
+
+/// Iterator over the entries of the `type_ids` section.
+pub fn type_ids<T: AsRef<[u8]>>(dex: &Dex<T>) -> impl Iterator<Item = Result<RawTypeId>> + '_ {
+    let table = layout(dex).type_ids;
+    (0..table.len).map(move |index| {
+        read_entry(dex, table, index, |source, offset| {
+            Ok(RawTypeId {
+                offset,
+                descriptor_idx: source.pread_with(offset as usize, dex.get_endian())?,
+            })
+        })
+    })
+}
+
+/// Iterator over the entries of the `class_defs` section.
+pub fn class_defs<T: AsRef<[u8]>>(dex: &Dex<T>) -> impl Iterator<Item = Result<RawClassDef>> + '_ {
+    let table = layout(dex).class_defs;
+    (0..table.len).map(move |index| {
+        read_entry(dex, table, index, |source, offset| {
+            Ok(RawClassDef {
+                offset,
+                item: source.pread_with(offset as usize, dex.get_endian())?,
+            })
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{class_defs, layout, string_ids, type_ids};
+
+    #[test]
+    fn test_raw_items() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let layout = layout(&dex);
+        assert_eq!(layout.string_ids().offset(), 0x70);
+        assert_eq!(
+            layout.string_ids().entry_offset(1),
+            Some(layout.string_ids().offset() + 4)
+        );
+        assert!(layout.call_site_ids().is_empty());
+        assert!(layout
+            .type_ids()
+            .entry_offset(layout.type_ids().len())
+            .is_none());
+
+        let strings: Vec<_> = string_ids(&dex).collect::<crate::Result<_>>().unwrap();
+        assert_eq!(strings.len() as u32, layout.string_ids().len());
+        for (type_id, raw) in type_ids(&dex).enumerate() {
+            let raw = raw.unwrap();
+            assert_eq!(
+                raw.offset(),
+                layout.type_ids().entry_offset(type_id as u32).unwrap()
+            );
+            assert_eq!(
+                dex.get_string(raw.descriptor_idx()).unwrap(),
+                *dex.get_type(type_id as u32).unwrap().type_descriptor()
+            );
+        }
+        for (raw, class_def) in class_defs(&dex).zip(dex.class_defs()) {
+            assert_eq!(
+                raw.unwrap().item().class_idx(),
+                class_def.unwrap().class_idx()
+            );
+        }
+    }
+}
@@ -0,0 +1,1316 @@
+//! Renaming of classes and their members, see `rename`.
+//!
+//! Renaming changes the contents of strings, and with them the sorted order of the strings,
+//! types, prototypes, fields and methods. The ids sections are written again in the new
+//! order, the indexes in the code, class data, debug info, annotation and encoded array items
+//! are mapped to the new ids, and the items which change size are spliced, see `edit`.
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+};
+
+use cesu8::to_java_cesu8;
+use scroll::{Endian, Pread, Pwrite, Sleb128, Uleb128};
+
+use crate::{
+    builder,
+    code::{
+        DBG_ADVANCE_LINE, DBG_ADVANCE_PC, DBG_END_LOCAL, DBG_END_SEQUENCE, DBG_RESTART_LOCAL,
+        DBG_SET_FILE, DBG_START_LOCAL, DBG_START_LOCAL_EXTENDED,
+    },
+    edit::{self, Splice, Splices},
+    error::Error,
+    instruction::{IndexType, InstructionIter},
+    string::StringId,
+    ubyte, uint, ushort, writer, Dex, ItemType, MapItem, Result, NO_INDEX,
+};
+
+/// The method handle types below this one access a field, the others invoke a method.
+const FIRST_METHOD_HANDLE_TYPE: ushort = 4;
+
+/// Renamed classes, fields and methods, see `rename`.
+#[derive(Debug, Clone, Default)]
+pub struct Renames {
+    /// New descriptors, by old descriptor.
+    classes: HashMap<String, String>,
+    /// New names of the fields, by descriptor of their class and old name.
+    fields: HashMap<(String, String), String>,
+    /// New names of the methods, by descriptor of their class and old name.
+    methods: HashMap<(String, String), String>,
+}
+
+impl Renames {
+    /// No renames.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renames the class of descriptor `old`, like `Lcom/example/Foo;`, to `new`.
+    pub fn with_class(mut self, old: &str, new: &str) -> Self {
+        self.classes.insert(old.to_string(), new.to_string());
+        self
+    }
+
+    /// Renames the field `name` of the class of descriptor `class`, given before the class
+    /// is renamed.
+    pub fn with_field(mut self, class: &str, name: &str, new_name: &str) -> Self {
+        self.fields
+            .insert((class.to_string(), name.to_string()), new_name.to_string());
+        self
+    }
+
+    /// Renames all the methods `name` of the class of descriptor `class`, given before the
+    /// class is renamed. The methods they override, or which override them, aren't renamed.
+    pub fn with_method(mut self, class: &str, name: &str, new_name: &str) -> Self {
+        self.methods
+            .insert((class.to_string(), name.to_string()), new_name.to_string());
+        self
+    }
+
+    fn check(&self) -> Result<()> {
+        for descriptor in self.classes.iter().flat_map(|(old, new)| vec![old, new]) {
+            let name = descriptor
+                .strip_prefix('L')
+                .and_then(|name| name.strip_suffix(';'));
+            if !name.is_some_and(|name| !name.is_empty() && !name.contains(';')) {
+                return Err(Error::MalFormed(format!(
+                    "\"{}\" isn't the descriptor of a class",
+                    descriptor
+                )));
+            }
+        }
+        if self
+            .fields
+            .values()
+            .chain(self.methods.values())
+            .any(String::is_empty)
+        {
+            return Err(Error::MalFormed(
+                "Members can't be renamed to \"\"".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The contents of `string` if it is the descriptor of a renamed class or of an array of
+    /// it. The `Lcom/example/Foo<` parts of the signature annotations of generic types are
+    /// renamed too.
+    fn rename_string(&self, string: &str) -> Option<String> {
+        let (dimensions, descriptor) =
+            string.split_at(string.len() - string.trim_start_matches('[').len());
+        if let Some(new) = self.classes.get(descriptor) {
+            return Some(format!("{}{}", dimensions, new));
+        }
+        let class = format!("{};", descriptor.strip_suffix('<')?);
+        let new = self.classes.get(&class)?;
+        Some(format!("{}{}<", dimensions, &new[..new.len() - 1]))
+    }
+
+    /// The new name of the member `name` of `class`, if it is renamed.
+    fn rename_member<'a>(
+        members: &'a HashMap<(String, String), String>,
+        class: &str,
+        name: &str,
+    ) -> Option<&'a String> {
+        if members.is_empty() {
+            return None;
+        }
+        members.get(&(class.to_string(), name.to_string()))
+    }
+}
+
+/// The bytes of `dex` with the classes, fields and methods of `renames` renamed, with their
+/// checksum and signature.
+///
+/// The descriptors of the renamed classes are replaced in all the strings which are exactly
+/// such a descriptor, or the descriptor of an array of such a class, and in the parts of the
+/// signature annotations naming a generic class. The shorties don't name classes and don't
+/// change. Renamed members get their name from a new string if needed, the other members
+/// with the same name keep it. The sections the parser doesn't know about, like the hidden
+/// API flags of the classes, can't be rewritten and are an error.
+pub fn rename<T: AsRef<[u8]>>(dex: &Dex<T>, renames: &Renames) -> Result<Vec<u8>> {
+    writer::check_writable(dex)?;
+    renames.check()?;
+    if let Some(item) = dex.map_list().unknown_items().first() {
+        return Err(Error::MalFormed(format!(
+            "Section of unknown type {:#x} can't be rewritten",
+            item.type_code()
+        )));
+    }
+    let mut sections = dex.map_list().items().to_vec();
+    sections.sort_by_key(|item| item.offset());
+    let mut rewriter = Rewriter {
+        dex,
+        ids: Ids::new(dex, renames)?,
+        splices: Splices::default(),
+        ids_sections: Vec::new(),
+        patches: Vec::new(),
+        code_offsets: Vec::new(),
+        added_strings: None,
+        annotation_sets: Vec::new(),
+        annotations_directories: Vec::new(),
+    };
+    rewriter.splice_ids_sections(&sections)?;
+    for section in sections {
+        rewriter.rewrite_section(section)?;
+    }
+    rewriter.write()
+}
+
+/// A string of the renamed file.
+#[derive(Debug, Clone, Copy)]
+enum NewString {
+    /// The string of this id in the original file.
+    Old(StringId),
+    /// A new string, index into `Ids::added`.
+    Added(usize),
+}
+
+/// The new ids of the items of an ids section, indexed by their old ids.
+struct IdMap {
+    section: ItemType,
+    ids: Vec<uint>,
+}
+
+impl IdMap {
+    fn new(section: ItemType, ids: Vec<uint>) -> Self {
+        Self { section, ids }
+    }
+
+    /// The new id of the item `id`.
+    fn get(&self, id: u64) -> Result<uint> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|index| self.ids.get(index))
+            .copied()
+            .ok_or(Error::IndexOutOfBounds {
+                section: self.section,
+                index: id,
+            })
+    }
+
+    /// The new value of an `uleb128p1` encoded index, where 0 is `NO_INDEX`.
+    fn get_p1(&self, value: u64) -> Result<uint> {
+        match value {
+            0 => Ok(0),
+            value => Ok(self.get(value - 1)? + 1),
+        }
+    }
+}
+
+/// The new ids of the items of the renamed file, indexed by their old ids, and the items of
+/// the ids sections in their new order.
+struct Ids {
+    strings: IdMap,
+    types: IdMap,
+    protos: IdMap,
+    fields: IdMap,
+    methods: IdMap,
+    /// The strings, in their new order.
+    string_ids: Vec<NewString>,
+    /// New contents of the renamed strings, by old id.
+    renamed: BTreeMap<StringId, String>,
+    /// The names of renamed members which aren't strings of the original file.
+    added: Vec<String>,
+    /// Descriptor of the types, in their new order.
+    type_ids: Vec<uint>,
+    /// Shorty, return type and old parameters offset of the prototypes, in their new order.
+    proto_ids: Vec<(uint, uint, uint)>,
+    /// Class, type and name of the fields, in their new order.
+    field_ids: Vec<(uint, uint, uint)>,
+    /// Class, prototype and name of the methods, in their new order.
+    method_ids: Vec<(uint, uint, uint)>,
+}
+
+impl Ids {
+    fn new<T: AsRef<[u8]>>(dex: &Dex<T>, renames: &Renames) -> Result<Self> {
+        let source = dex.source.as_ref();
+        let endian = dex.get_endian();
+        let strings = dex.strings_uncached().collect::<Result<Vec<_>>>()?;
+        let mut renamed = BTreeMap::new();
+        let mut by_contents = HashMap::with_capacity(strings.len());
+        for (string_id, string) in strings.iter().enumerate() {
+            let contents = renames.rename_string(string);
+            let key = contents.as_deref().unwrap_or(string).to_string();
+            if let Some(other) = by_contents.insert(key, string_id as StringId) {
+                return Err(Error::MalFormed(format!(
+                    "Renaming makes strings {} and {} the same",
+                    other, string_id
+                )));
+            }
+            if let Some(contents) = contents {
+                renamed.insert(string_id as StringId, contents);
+            }
+        }
+
+        let type_descriptors = (0..dex.header().type_ids_size())
+            .map(|type_id| {
+                source.pread_with((dex.inner.type_ids_offset() + type_id * 4) as usize, endian)
+            })
+            .collect::<std::result::Result<Vec<uint>, _>>()?;
+        let name = |string_id: StringId| {
+            strings
+                .get(string_id as usize)
+                .ok_or(Error::IndexOutOfBounds {
+                    section: ItemType::StringIdItem,
+                    index: string_id.into(),
+                })
+        };
+        let descriptor = |type_id: ushort| {
+            let string_id =
+                type_descriptors
+                    .get(type_id as usize)
+                    .ok_or(Error::IndexOutOfBounds {
+                        section: ItemType::TypeIdItem,
+                        index: type_id.into(),
+                    })?;
+            name(*string_id)
+        };
+        let field_items = dex.field_ids().collect::<Result<Vec<_>>>()?;
+        let method_items = dex.method_ids().collect::<Result<Vec<_>>>()?;
+        let mut field_names = HashMap::new();
+        for field in &field_items {
+            let class = descriptor(field.class_idx())?;
+            if let Some(new) =
+                Renames::rename_member(&renames.fields, class, name(field.name_idx())?)
+            {
+                field_names.insert(field.id(), new.clone());
+            }
+        }
+        let mut method_names = HashMap::new();
+        for method in &method_items {
+            let class = descriptor(method.class_idx())?;
+            if let Some(new) =
+                Renames::rename_member(&renames.methods, class, name(method.name_idx())?)
+            {
+                method_names.insert(method.id(), new.clone());
+            }
+        }
+        let mut added: Vec<String> = field_names
+            .values()
+            .chain(method_names.values())
+            .filter(|name| !by_contents.contains_key(*name))
+            .cloned()
+            .collect();
+        added.sort();
+        added.dedup();
+
+        let utf16 = |string: &str| string.encode_utf16().collect::<Vec<_>>();
+        let keys = strings
+            .iter()
+            .enumerate()
+            .map(|(string_id, string)| {
+                utf16(
+                    renamed
+                        .get(&(string_id as StringId))
+                        .map_or(string, String::as_str),
+                )
+            })
+            .chain(added.iter().map(|string| utf16(string)))
+            .collect();
+        let (mut string_map, order) = sort_ids(keys, "strings")?;
+        let added_ids = string_map.split_off(strings.len());
+        let string_map = IdMap::new(ItemType::StringIdItem, string_map);
+        let string_ids = order
+            .into_iter()
+            .map(|index| match index.checked_sub(strings.len()) {
+                Some(added) => NewString::Added(added),
+                None => NewString::Old(index as StringId),
+            })
+            .collect();
+        let new_name = |old: StringId, new: Option<&String>| match new {
+            Some(new) => match by_contents.get(new) {
+                Some(string_id) => string_map.ids[*string_id as usize],
+                None => added_ids[added.binary_search(new).unwrap_or_default()],
+            },
+            None => string_map.ids[old as usize],
+        };
+
+        let keys = type_descriptors
+            .iter()
+            .map(|string_id| string_map.get(u64::from(*string_id)))
+            .collect::<Result<Vec<_>>>()?;
+        let (types, order) = sort_ids(keys.clone(), "types")?;
+        let types = IdMap::new(ItemType::TypeIdItem, types);
+        let type_ids = order.into_iter().map(|type_id| keys[type_id]).collect();
+
+        let proto_items = dex.proto_ids().collect::<Result<Vec<_>>>()?;
+        let mut keys = Vec::with_capacity(proto_items.len());
+        for proto in &proto_items {
+            let mut params = Vec::new();
+            if proto.params_off() != 0 {
+                let offset = proto.params_off() as usize;
+                let size: uint = source.pread_with(offset, endian)?;
+                for index in 0..size as usize {
+                    let type_id: ushort = source.pread_with(offset + 4 + index * 2, endian)?;
+                    params.push(types.get(type_id.into())?);
+                }
+            }
+            keys.push((types.get(proto.return_type().into())?, params));
+        }
+        let (protos, order) = sort_ids(keys, "prototypes")?;
+        let protos = IdMap::new(ItemType::ProtoIdItem, protos);
+        let proto_ids = order
+            .into_iter()
+            .map(|proto_id| {
+                let proto = &proto_items[proto_id];
+                Ok((
+                    string_map.get(proto.shorty().into())?,
+                    types.get(proto.return_type().into())?,
+                    proto.params_off(),
+                ))
+            })
+            .collect::<Result<_>>()?;
+
+        let keys = field_items
+            .iter()
+            .map(|field| {
+                Ok((
+                    types.get(field.class_idx().into())?,
+                    new_name(field.name_idx(), field_names.get(&field.id())),
+                    types.get(field.type_idx().into())?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (fields, order) = sort_ids(keys.clone(), "fields")?;
+        let fields = IdMap::new(ItemType::FieldIdItem, fields);
+        let field_ids = order
+            .into_iter()
+            .map(|field_id| {
+                let (class, name, field_type) = keys[field_id];
+                (class, field_type, name)
+            })
+            .collect();
+
+        let keys = method_items
+            .iter()
+            .map(|method| {
+                Ok((
+                    types.get(method.class_idx().into())?,
+                    new_name(method.name_idx(), method_names.get(&method.id())),
+                    protos.get(method.proto_idx().into())?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (methods, order) = sort_ids(keys.clone(), "methods")?;
+        let methods = IdMap::new(ItemType::MethodIdItem, methods);
+        let method_ids = order
+            .into_iter()
+            .map(|method_id| {
+                let (class, name, proto) = keys[method_id];
+                (class, proto, name)
+            })
+            .collect();
+
+        Ok(Self {
+            strings: string_map,
+            types,
+            protos,
+            fields,
+            methods,
+            string_ids,
+            renamed,
+            added,
+            type_ids,
+            proto_ids,
+            field_ids,
+            method_ids,
+        })
+    }
+
+    /// Encodes the `encoded_value` at `offset` of `source` with the new ids into `out`.
+    fn encoded_value(&self, source: &[u8], offset: &mut usize, out: &mut Vec<u8>) -> Result<()> {
+        let header: ubyte = source.gread(offset)?;
+        let (value_type, size) = (header & 0x1f, (header >> 5) as usize + 1);
+        let ids = match value_type {
+            0x15 => &self.protos,
+            0x17 => &self.strings,
+            0x18 => &self.types,
+            0x19 | 0x1b => &self.fields,
+            0x1a => &self.methods,
+            // byte, short, char, int, long, float, double and method handle values.
+            0x00 | 0x02 | 0x03 | 0x04 | 0x06 | 0x10 | 0x11 | 0x16 => {
+                out.push(header);
+                copy(source, offset, size, out)?;
+                return Ok(());
+            }
+            0x1c => {
+                out.push(header);
+                return self.encoded_array(source, offset, out);
+            }
+            0x1d => {
+                out.push(header);
+                return self.encoded_annotation(source, offset, out);
+            }
+            // null and boolean values.
+            0x1e | 0x1f => {
+                out.push(header);
+                return Ok(());
+            }
+            _ => {
+                return Err(Error::MalFormed(format!(
+                    "Invalid encoded value type {:#x}",
+                    value_type
+                )))
+            }
+        };
+        let bytes = source
+            .get(*offset..*offset + size)
+            .ok_or_else(|| Error::BadOffset(*offset, "Encoded value out of bounds".to_string()))?;
+        *offset += size;
+        let index = bytes
+            .iter()
+            .rev()
+            .fold(0, |index, byte| index << 8 | u64::from(*byte));
+        let index = ids.get(index)?.to_le_bytes();
+        let size = index.len() - index.iter().rev().take_while(|byte| **byte == 0).count();
+        let size = size.max(1);
+        out.push(value_type | ((size - 1) as ubyte) << 5);
+        out.extend_from_slice(&index[..size]);
+        Ok(())
+    }
+
+    fn encoded_array(&self, source: &[u8], offset: &mut usize, out: &mut Vec<u8>) -> Result<()> {
+        let size = Uleb128::read(source, offset)?;
+        push_uleb128(out, size as uint);
+        for _ in 0..size {
+            self.encoded_value(source, offset, out)?;
+        }
+        Ok(())
+    }
+
+    fn encoded_annotation(
+        &self,
+        source: &[u8],
+        offset: &mut usize,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        let type_id = self.types.get(Uleb128::read(source, offset)?)?;
+        push_uleb128(out, type_id);
+        let size = Uleb128::read(source, offset)?;
+        push_uleb128(out, size as uint);
+        let mut elements = Vec::new();
+        for _ in 0..size {
+            let name = self.strings.get(Uleb128::read(source, offset)?)?;
+            let mut value = Vec::new();
+            self.encoded_value(source, offset, &mut value)?;
+            elements.push((name, value));
+        }
+        // the elements are sorted by the ids of their names.
+        elements.sort_by_key(|(name, _)| *name);
+        for (name, value) in elements {
+            push_uleb128(out, name);
+            out.extend_from_slice(&value);
+        }
+        Ok(())
+    }
+
+    fn annotation_item(&self, source: &[u8], offset: &mut usize, out: &mut Vec<u8>) -> Result<()> {
+        copy(source, offset, 1, out)?;
+        self.encoded_annotation(source, offset, out)
+    }
+
+    fn debug_info_item(&self, source: &[u8], offset: &mut usize, out: &mut Vec<u8>) -> Result<()> {
+        copy_leb128(source, offset, out)?;
+        let parameters_size = Uleb128::read(source, offset)?;
+        push_uleb128(out, parameters_size as uint);
+        for _ in 0..parameters_size {
+            push_uleb128(out, self.strings.get_p1(Uleb128::read(source, offset)?)?);
+        }
+        loop {
+            let opcode: ubyte = source.gread(offset)?;
+            out.push(opcode);
+            match opcode {
+                DBG_END_SEQUENCE => return Ok(()),
+                DBG_ADVANCE_PC | DBG_ADVANCE_LINE | DBG_END_LOCAL | DBG_RESTART_LOCAL => {
+                    copy_leb128(source, offset, out)?
+                }
+                DBG_START_LOCAL | DBG_START_LOCAL_EXTENDED => {
+                    copy_leb128(source, offset, out)?;
+                    let name = Uleb128::read(source, offset)?;
+                    push_uleb128(out, self.strings.get_p1(name)?);
+                    let local_type = Uleb128::read(source, offset)?;
+                    push_uleb128(out, self.types.get_p1(local_type)?);
+                    if opcode == DBG_START_LOCAL_EXTENDED {
+                        let signature = Uleb128::read(source, offset)?;
+                        push_uleb128(out, self.strings.get_p1(signature)?);
+                    }
+                }
+                DBG_SET_FILE => {
+                    let name = Uleb128::read(source, offset)?;
+                    push_uleb128(out, self.strings.get_p1(name)?);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Encodes the `encoded_catch_handler_list` at `offset` with the new ids. Returns the
+    /// new offsets of the handlers, by old offset from the start of the list.
+    fn handlers(
+        &self,
+        source: &[u8],
+        offset: &mut usize,
+        out: &mut Vec<u8>,
+    ) -> Result<HashMap<usize, usize>> {
+        let start = *offset;
+        let mut handler_offsets = HashMap::new();
+        let size = Uleb128::read(source, offset)?;
+        push_uleb128(out, size as uint);
+        for _ in 0..size {
+            handler_offsets.insert(*offset - start, out.len());
+            let position = *offset;
+            let size = Sleb128::read(source, offset)?;
+            out.extend_from_slice(&source[position..*offset]);
+            for _ in 0..size.unsigned_abs() {
+                push_uleb128(out, self.types.get(Uleb128::read(source, offset)?)?);
+                copy_leb128(source, offset, out)?;
+            }
+            if size <= 0 {
+                copy_leb128(source, offset, out)?;
+            }
+        }
+        Ok(handler_offsets)
+    }
+
+    /// Maps the indexes of the instructions in `insns` to the new ids. Returns whether any
+    /// of them changed.
+    fn instructions(&self, insns: &mut [ushort]) -> Result<bool> {
+        let mut references = Vec::new();
+        for instruction in InstructionIter::new(insns).with_offsets() {
+            let (address, instruction) = instruction?;
+            let index_type = instruction.opcode().index_type();
+            let ids = match index_type {
+                IndexType::String => &self.strings,
+                IndexType::Type => &self.types,
+                IndexType::Field => &self.fields,
+                IndexType::Method => &self.methods,
+                IndexType::Proto => &self.protos,
+                _ => continue,
+            };
+            let index = instruction.index().unwrap_or_default();
+            references.push((address, index_type, ids.get(index)?));
+            // invoke-polymorphic also has a prototype.
+            if let Some(proto) = instruction.proto_index() {
+                references.push((address + 3, IndexType::None, self.protos.get(proto)?));
+            }
+        }
+        let old = insns.to_vec();
+        for (address, index_type, index) in references {
+            match index_type {
+                IndexType::None => insns[address] = index as ushort,
+                _ => builder::patch_reference(insns, address, (index_type, index))?,
+            }
+        }
+        Ok(insns != &old[..])
+    }
+}
+
+/// The new ids of items of the given sort keys, by old id, and the old ids in their new
+/// order. Items with the same key are an error.
+fn sort_ids<K: Ord>(keys: Vec<K>, items: &str) -> Result<(Vec<uint>, Vec<usize>)> {
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+    if order.windows(2).any(|pair| keys[pair[0]] == keys[pair[1]]) {
+        return Err(Error::MalFormed(format!(
+            "Renaming makes two {} the same",
+            items
+        )));
+    }
+    let mut ids = vec![0; keys.len()];
+    for (new_id, old_id) in order.iter().enumerate() {
+        ids[*old_id] = new_id as uint;
+    }
+    Ok((ids, order))
+}
+
+/// Rewrites the sections of a renamed file.
+struct Rewriter<'a, T> {
+    dex: &'a Dex<T>,
+    ids: Ids,
+    splices: Splices,
+    /// Type, size and offset of the ids sections in the renamed file.
+    ids_sections: Vec<(ItemType, uint, uint)>,
+    /// Bytes written over the moved bytes of the items which keep their size, by position
+    /// in the original file.
+    patches: Vec<(usize, Vec<u8>)>,
+    /// Code offsets of the spliced class data items, which are written once all the items
+    /// are placed: splice, position in the splice and old code offset.
+    code_offsets: Vec<(usize, usize, uint)>,
+    /// The splice of the new strings and their positions in it.
+    added_strings: Option<(usize, Vec<usize>)>,
+    annotation_sets: Vec<usize>,
+    annotations_directories: Vec<usize>,
+}
+
+impl<'a, T: AsRef<[u8]>> Rewriter<'a, T> {
+    /// Places the ids sections in their new sizes one after the other from the end of the
+    /// header, up to the data section which follows them.
+    fn splice_ids_sections(&mut self, sections: &[MapItem]) -> Result<()> {
+        let header = self.dex.header();
+        let (start, end) = (header.header_size(), header.data_off());
+        for section in sections {
+            let is_ids = writer::IDS_SECTIONS.contains(&section.item_type());
+            let in_ids = (start..end).contains(&section.offset());
+            if section.size() > 0 && is_ids != in_ids {
+                return Err(Error::BadOffset(
+                    section.offset() as usize,
+                    format!("{:?} section out of place", section.item_type()),
+                ));
+            }
+        }
+        if end % 4 != 0 {
+            return Err(Error::BadOffset(
+                end as usize,
+                "Unaligned data section".to_string(),
+            ));
+        }
+        let mut offset = start;
+        for item_type in writer::IDS_SECTIONS {
+            let size = match item_type {
+                ItemType::StringIdItem => self.ids.string_ids.len() as uint,
+                _ => self.dex.map_list().get_len(item_type).unwrap_or(0),
+            };
+            if size > 0 {
+                self.ids_sections.push((item_type, size, offset));
+                offset += size * item_type.item_size().unwrap_or(0);
+            }
+        }
+        let bytes = vec![0; (offset - start) as usize];
+        self.splices
+            .push(Splice::replace(start as usize, end as usize, bytes))?;
+        Ok(())
+    }
+
+    fn rewrite_section(&mut self, section: MapItem) -> Result<()> {
+        let dex = self.dex;
+        let source = dex.source.as_ref();
+        let endian = dex.get_endian();
+        let mut offset = section.offset() as usize;
+        let ids = &self.ids;
+        match section.item_type() {
+            ItemType::StringDataItem => return self.rewrite_string_data(section),
+            ItemType::ClassDataItem => return self.rewrite_class_data(section),
+            ItemType::CodeItem => return self.rewrite_code_items(section),
+            ItemType::TypeList => {
+                for _ in 0..section.size() {
+                    offset = align(offset);
+                    let size: uint = source.pread_with(offset, endian)?;
+                    let mut bytes = vec![0; size as usize * 2];
+                    for index in 0..size as usize {
+                        let type_id: ushort = source.pread_with(offset + 4 + index * 2, endian)?;
+                        let type_id = ids.types.get(type_id.into())? as ushort;
+                        bytes.pwrite_with(type_id, index * 2, endian)?;
+                    }
+                    self.patches.push((offset + 4, bytes));
+                    offset += 4 + size as usize * 2;
+                }
+            }
+            ItemType::AnnotationSetItem => {
+                for _ in 0..section.size() {
+                    offset = align(offset);
+                    self.annotation_sets.push(offset);
+                    let size: uint = source.pread_with(offset, endian)?;
+                    offset += 4 + size as usize * 4;
+                }
+            }
+            ItemType::AnnotationsDirectoryItem => {
+                for _ in 0..section.size() {
+                    offset = align(offset);
+                    self.annotations_directories.push(offset);
+                    let mut size = 0;
+                    for count in 1..4 {
+                        size += source.pread_with::<uint>(offset + count * 4, endian)? as usize;
+                    }
+                    offset += 16 + size * 8;
+                }
+            }
+            ItemType::DebugInfoItem => self.reencode_items(section, Ids::debug_info_item)?,
+            ItemType::AnnotationItem => self.reencode_items(section, Ids::annotation_item)?,
+            ItemType::EncodedArrayItem => self.reencode_items(section, Ids::encoded_array)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Splices the items of the unaligned section `section` which `encode` changes.
+    fn reencode_items(
+        &mut self,
+        section: MapItem,
+        encode: fn(&Ids, &[u8], &mut usize, &mut Vec<u8>) -> Result<()>,
+    ) -> Result<()> {
+        let source = self.dex.source.as_ref();
+        let mut offset = section.offset() as usize;
+        for _ in 0..section.size() {
+            let start = offset;
+            let mut bytes = Vec::new();
+            encode(&self.ids, source, &mut offset, &mut bytes)?;
+            if bytes[..] != source[start..offset] {
+                self.splices.push(Splice::replace(start, offset, bytes))?;
+            }
+        }
+        self.pad(offset)
+    }
+
+    /// Pads the end of an unaligned section at `end` so that the items after it are moved
+    /// by a multiple of 4 bytes. The padding is between sections, where any number of zero
+    /// bytes is allowed.
+    fn pad(&mut self, end: usize) -> Result<()> {
+        let padding = (-self.splices.delta()).rem_euclid(4) as usize;
+        if padding > 0 {
+            self.splices
+                .push(Splice::replace(end, end, vec![0; padding]))?;
+        }
+        Ok(())
+    }
+
+    fn rewrite_string_data(&mut self, section: MapItem) -> Result<()> {
+        let dex = self.dex;
+        let source = dex.source.as_ref();
+        let endian = dex.get_endian();
+        let mut renamed = HashMap::new();
+        for (string_id, contents) in &self.ids.renamed {
+            let position = (dex.inner.strings_offset() + string_id * 4) as usize;
+            let offset: uint = source.pread_with(position, endian)?;
+            renamed.insert(offset as usize, contents);
+        }
+        let mut offset = section.offset() as usize;
+        for _ in 0..section.size() {
+            let start = offset;
+            Uleb128::read(source, &mut offset)?;
+            let terminator = source[offset..]
+                .iter()
+                .position(|byte| *byte == 0)
+                .ok_or_else(|| Error::BadOffset(start, "Unterminated string data".to_string()))?;
+            offset += terminator + 1;
+            if let Some(contents) = renamed.get(&start) {
+                self.splices
+                    .push(Splice::replace(start, offset, string_data(contents)))?;
+            }
+        }
+        // the new strings are added after the last one.
+        let mut bytes = Vec::new();
+        let mut positions = Vec::new();
+        for string in &self.ids.added {
+            positions.push(bytes.len());
+            bytes.extend_from_slice(&string_data(string));
+        }
+        if !bytes.is_empty() {
+            let splice = self.splices.push(Splice::replace(offset, offset, bytes))?;
+            self.added_strings = Some((splice, positions));
+        }
+        self.pad(offset)
+    }
+
+    fn rewrite_class_data(&mut self, section: MapItem) -> Result<()> {
+        let source = self.dex.source.as_ref();
+        let mut offset = section.offset() as usize;
+        for _ in 0..section.size() {
+            let start = offset;
+            let mut sizes = [0; 4];
+            for size in &mut sizes {
+                *size = Uleb128::read(source, &mut offset)?;
+            }
+            let mut bytes = Vec::new();
+            for size in sizes {
+                push_uleb128(&mut bytes, size as uint);
+            }
+            let mut code_offsets = Vec::new();
+            for (list, size) in sizes.iter().enumerate() {
+                let is_method = list >= 2;
+                let ids = if is_method {
+                    &self.ids.methods
+                } else {
+                    &self.ids.fields
+                };
+                let mut members = Vec::new();
+                let mut id = 0;
+                for _ in 0..*size {
+                    id += Uleb128::read(source, &mut offset)?;
+                    let access_flags = offset;
+                    Uleb128::read(source, &mut offset)?;
+                    let access_flags = &source[access_flags..offset];
+                    let code_off = if is_method {
+                        Uleb128::read(source, &mut offset)? as uint
+                    } else {
+                        0
+                    };
+                    members.push((ids.get(id)?, access_flags, code_off));
+                }
+                // the members are sorted by their new ids.
+                members.sort_by_key(|(id, _, _)| *id);
+                let mut previous = 0;
+                for (id, access_flags, code_off) in members {
+                    push_uleb128(&mut bytes, id - previous);
+                    previous = id;
+                    bytes.extend_from_slice(access_flags);
+                    if !is_method {
+                        continue;
+                    }
+                    match self.splices.relocate(code_off as usize) {
+                        // the code items before the class data item are already placed.
+                        Some(moved) if (code_off as usize) < start => {
+                            push_uleb128(&mut bytes, moved as uint)
+                        }
+                        _ => {
+                            code_offsets.push((bytes.len(), code_off));
+                            bytes.extend_from_slice(&[0; edit::MAX_ULEB128_SIZE]);
+                        }
+                    }
+                }
+            }
+            if bytes[..] != source[start..offset] {
+                let splice = self.splices.push(Splice::replace(start, offset, bytes))?;
+                self.code_offsets.extend(
+                    code_offsets
+                        .into_iter()
+                        .map(|(position, code_off)| (splice, position, code_off)),
+                );
+            }
+        }
+        self.pad(offset)
+    }
+
+    fn rewrite_code_items(&mut self, section: MapItem) -> Result<()> {
+        let dex = self.dex;
+        let source = dex.source.as_ref();
+        let endian = dex.get_endian();
+        let mut offset = section.offset() as usize;
+        for index in 0..section.size() {
+            let code = align(offset);
+            let tries_size: ushort = source.pread_with(code + 6, endian)?;
+            let insns_size: uint = source.pread_with(code + 12, endian)?;
+            let insns_start = code + 16;
+            let mut insns = (0..insns_size as usize)
+                .map(|index| source.pread_with(insns_start + index * 2, endian))
+                .collect::<std::result::Result<Vec<ushort>, _>>()?;
+            if self.ids.instructions(&mut insns)? {
+                let mut bytes = vec![0; insns.len() * 2];
+                for (index, insn) in insns.iter().enumerate() {
+                    bytes.pwrite_with(*insn, index * 2, endian)?;
+                }
+                self.patches.push((insns_start, bytes));
+            }
+            offset = insns_start + insns.len() * 2;
+            if tries_size == 0 {
+                continue;
+            }
+
+            let tries = align(offset);
+            let handlers = tries + tries_size as usize * 8;
+            offset = handlers;
+            let mut bytes = Vec::new();
+            let handler_offsets = self.ids.handlers(source, &mut offset, &mut bytes)?;
+            if bytes[..] == source[handlers..offset] {
+                continue;
+            }
+            for try_item in 0..tries_size as usize {
+                let position = tries + try_item * 8 + 6;
+                let handler_off: ushort = source.pread_with(position, endian)?;
+                let handler_off = handler_offsets
+                    .get(&(handler_off as usize))
+                    .and_then(|handler_off| ushort::try_from(*handler_off).ok())
+                    .ok_or_else(|| {
+                        Error::BadOffset(position, "Invalid handler offset".to_string())
+                    })?;
+                let mut patch = vec![0; 2];
+                patch.pwrite_with(handler_off, 0, endian)?;
+                self.patches.push((position, patch));
+            }
+            // the handlers are padded to the alignment of the next code item, or to the
+            // same alignment as before at the end of the section.
+            let end = if index + 1 < section.size() {
+                align(offset)
+            } else {
+                offset
+            };
+            let padding = ((end - handlers) as i64 - bytes.len() as i64).rem_euclid(4);
+            bytes.resize(bytes.len() + padding as usize, 0);
+            self.splices.push(Splice::replace(handlers, end, bytes))?;
+        }
+        Ok(())
+    }
+
+    /// Applies the splices to the file and writes the items and offsets which depend on the
+    /// new places of the items.
+    fn write(self) -> Result<Vec<u8>> {
+        let dex = self.dex;
+        let source = dex.source.as_ref();
+        let endian = dex.get_endian();
+        let mut bytes = self.splices.apply(source);
+        edit::relocate_offsets(dex, &self.splices, &mut bytes)?;
+        for (position, patch) in &self.patches {
+            let moved = self.moved(*position as uint)? as usize;
+            bytes[moved..moved + patch.len()].copy_from_slice(patch);
+        }
+        for (splice, position, code_off) in &self.code_offsets {
+            let position = self.splices.new_start(*splice) + position;
+            let code_off = self.moved(*code_off)?;
+            edit::write_uleb128(&mut bytes, position, code_off, edit::MAX_ULEB128_SIZE)?;
+        }
+        self.write_ids_sections(&mut bytes, endian)?;
+        self.write_annotation_sets(&mut bytes, endian)?;
+        self.write_annotations_directories(&mut bytes, endian)?;
+        self.write_map_list(&mut bytes, endian)?;
+
+        let offset = &mut 0x38;
+        for item_type in &writer::IDS_SECTIONS[..6] {
+            let (size, section_offset) = self
+                .ids_sections
+                .iter()
+                .find(|(section, _, _)| section == item_type)
+                .map_or((0, 0), |(_, size, offset)| (*size, *offset));
+            bytes.gwrite_with(size, offset, endian)?;
+            bytes.gwrite_with(section_offset, offset, endian)?;
+        }
+        edit::finish(dex, &self.splices, &mut bytes)?;
+        debug!(
+            target: "rename",
+            "renamed {} strings, added {}",
+            self.ids.renamed.len(),
+            self.ids.added.len()
+        );
+        Ok(bytes)
+    }
+
+    /// The offset in the renamed file of the item at `offset`, 0 stays 0.
+    fn moved(&self, offset: uint) -> Result<uint> {
+        if offset == 0 {
+            return Ok(0);
+        }
+        self.splices
+            .relocate(offset as usize)
+            .map(|offset| offset as uint)
+            .ok_or_else(|| {
+                Error::BadOffset(offset as usize, "Offset into a replaced item".to_string())
+            })
+    }
+
+    fn write_ids_sections(&self, bytes: &mut [u8], endian: Endian) -> Result<()> {
+        let dex = self.dex;
+        let source = dex.source.as_ref();
+        let ids = &self.ids;
+        let read =
+            |position: uint| -> Result<uint> { Ok(source.pread_with(position as usize, endian)?) };
+        for (item_type, _, offset) in &self.ids_sections {
+            let offset = &mut (*offset as usize);
+            match item_type {
+                ItemType::StringIdItem => {
+                    for string in &ids.string_ids {
+                        let string_data_off = match string {
+                            NewString::Old(string_id) => {
+                                self.moved(read(dex.inner.strings_offset() + string_id * 4)?)?
+                            }
+                            NewString::Added(index) => {
+                                let (splice, positions) =
+                                    self.added_strings.as_ref().ok_or_else(|| {
+                                        Error::MalFormed("No string data section".to_string())
+                                    })?;
+                                (self.splices.new_start(*splice) + positions[*index]) as uint
+                            }
+                        };
+                        bytes.gwrite_with(string_data_off, offset, endian)?;
+                    }
+                }
+                ItemType::TypeIdItem => {
+                    for descriptor in &ids.type_ids {
+                        bytes.gwrite_with(*descriptor, offset, endian)?;
+                    }
+                }
+                ItemType::ProtoIdItem => {
+                    for (shorty, return_type, params_off) in &ids.proto_ids {
+                        bytes.gwrite_with(*shorty, offset, endian)?;
+                        bytes.gwrite_with(*return_type, offset, endian)?;
+                        bytes.gwrite_with(self.moved(*params_off)?, offset, endian)?;
+                    }
+                }
+                ItemType::FieldIdItem | ItemType::MethodIdItem => {
+                    let items = if *item_type == ItemType::FieldIdItem {
+                        &ids.field_ids
+                    } else {
+                        &ids.method_ids
+                    };
+                    for (class, type_or_proto, name) in items {
+                        bytes.gwrite_with(*class as ushort, offset, endian)?;
+                        bytes.gwrite_with(*type_or_proto as ushort, offset, endian)?;
+                        bytes.gwrite_with(*name, offset, endian)?;
+                    }
+                }
+                ItemType::ClassDefItem => {
+                    for class_def in 0..dex.header().class_defs_size() {
+                        let position = dex.inner.class_defs_offset() + class_def * 32;
+                        let class_id = ids.types.get(read(position)?.into())?;
+                        bytes.gwrite_with(class_id, offset, endian)?;
+                        bytes.gwrite_with(read(position + 4)?, offset, endian)?;
+                        for (field, ids) in [(8, &ids.types), (16, &ids.strings)] {
+                            let id = match read(position + field)? {
+                                NO_INDEX => NO_INDEX,
+                                id => ids.get(id.into())?,
+                            };
+                            bytes.pwrite_with(id, *offset + field as usize - 8, endian)?;
+                        }
+                        for field in [12, 20, 24, 28] {
+                            let moved = self.moved(read(position + field)?)?;
+                            bytes.pwrite_with(moved, *offset + field as usize - 8, endian)?;
+                        }
+                        *offset += 24;
+                    }
+                }
+                ItemType::CallSiteIdItem => {
+                    let call_site_ids = dex.inner.call_site_ids_offset().unwrap_or(0);
+                    for index in 0..dex.inner.call_site_ids_len().unwrap_or(0) {
+                        let moved = self.moved(read(call_site_ids + index * 4)?)?;
+                        bytes.gwrite_with(moved, offset, endian)?;
+                    }
+                }
+                ItemType::MethodHandleItem => {
+                    let method_handles = dex.inner.method_handles_offset().unwrap_or(0) as usize;
+                    for index in 0..dex.inner.method_handles_len().unwrap_or(0) as usize {
+                        let position = method_handles + index * 8;
+                        let handle_type: ushort = source.pread_with(position, endian)?;
+                        let id: ushort = source.pread_with(position + 4, endian)?;
+                        let ids = if handle_type < FIRST_METHOD_HANDLE_TYPE {
+                            &ids.fields
+                        } else {
+                            &ids.methods
+                        };
+                        bytes.gwrite_with(handle_type, offset, endian)?;
+                        bytes.gwrite_with(0 as ushort, offset, endian)?;
+                        bytes.gwrite_with(ids.get(id.into())? as ushort, offset, endian)?;
+                        bytes.gwrite_with(0 as ushort, offset, endian)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the entries of the annotation sets sorted by the new ids of the types of their
+    /// annotations.
+    fn write_annotation_sets(&self, bytes: &mut [u8], endian: Endian) -> Result<()> {
+        let source = self.dex.source.as_ref();
+        for set in &self.annotation_sets {
+            let size: uint = source.pread_with(*set, endian)?;
+            let mut entries = Vec::with_capacity(size as usize);
+            for index in 0..size as usize {
+                let annotation: uint = source.pread_with(set + 4 + index * 4, endian)?;
+                // the type of the annotation follows its visibility.
+                let type_id = Uleb128::read(source, &mut (annotation as usize + 1))?;
+                entries.push((self.ids.types.get(type_id)?, self.moved(annotation)?));
+            }
+            entries.sort_by_key(|(type_id, _)| *type_id);
+            let moved = self.moved(*set as uint)? as usize;
+            for (index, (_, annotation)) in entries.into_iter().enumerate() {
+                bytes.pwrite_with(annotation, moved + 4 + index * 4, endian)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the field, method and parameter annotations of the annotations directories
+    /// with the new ids, sorted by them.
+    fn write_annotations_directories(&self, bytes: &mut [u8], endian: Endian) -> Result<()> {
+        let source = self.dex.source.as_ref();
+        let ids = &self.ids;
+        for directory in &self.annotations_directories {
+            let mut position = directory + 16;
+            let mut moved = self.moved(position as uint)? as usize;
+            for (count, ids) in [(4, &ids.fields), (8, &ids.methods), (12, &ids.methods)] {
+                let size: uint = source.pread_with(directory + count, endian)?;
+                let mut entries = Vec::with_capacity(size as usize);
+                for _ in 0..size {
+                    let id: uint = source.pread_with(position, endian)?;
+                    let annotations: uint = source.pread_with(position + 4, endian)?;
+                    entries.push((ids.get(id.into())?, self.moved(annotations)?));
+                    position += 8;
+                }
+                entries.sort_by_key(|(id, _)| *id);
+                for (id, annotations) in entries {
+                    bytes.pwrite_with(id, moved, endian)?;
+                    bytes.pwrite_with(annotations, moved + 4, endian)?;
+                    moved += 8;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_map_list(&self, bytes: &mut [u8], endian: Endian) -> Result<()> {
+        let source = self.dex.source.as_ref();
+        let map_off = self.dex.header().map_off() as usize;
+        let moved = self.moved(map_off as uint)? as usize;
+        let size: uint = source.pread_with(map_off, endian)?;
+        for index in 0..size as usize {
+            let entry = map_off + 4 + index * 12;
+            let type_code: ushort = source.pread_with(entry, endian)?;
+            let mut item_size: uint = source.pread_with(entry + 4, endian)?;
+            let mut offset = self.moved(source.pread_with(entry + 8, endian)?);
+            if let Some((_, size, ids_offset)) = self
+                .ids_sections
+                .iter()
+                .find(|(item_type, _, _)| *item_type as ushort == type_code)
+            {
+                item_size = *size;
+                offset = Ok(*ids_offset);
+            } else if type_code == ItemType::StringDataItem as ushort {
+                item_size += self.ids.added.len() as uint;
+            }
+            let entry = moved + 4 + index * 12;
+            bytes.pwrite_with(item_size, entry + 4, endian)?;
+            bytes.pwrite_with(offset?, entry + 8, endian)?;
+        }
+        Ok(())
+    }
+}
+
+/// The `string_data_item` of `string`.
+fn string_data(string: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    push_uleb128(&mut bytes, string.encode_utf16().count() as uint);
+    bytes.extend_from_slice(&to_java_cesu8(string));
+    bytes.push(0);
+    bytes
+}
+
+/// Offset of the first item aligned to 4 bytes at or after `offset`.
+fn align(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+fn push_uleb128(out: &mut Vec<u8>, value: uint) {
+    let position = out.len();
+    let size = edit::uleb128_size(value);
+    out.resize(position + size, 0);
+    // the bytes were resized to fit the value.
+    let _ = edit::write_uleb128(out, position, value, size);
+}
+
+/// Copies the `size` bytes at `offset` of `source` to `out`.
+fn copy(source: &[u8], offset: &mut usize, size: usize, out: &mut Vec<u8>) -> Result<()> {
+    let bytes = source
+        .get(*offset..*offset + size)
+        .ok_or_else(|| Error::BadOffset(*offset, "Value out of bounds".to_string()))?;
+    out.extend_from_slice(bytes);
+    *offset += size;
+    Ok(())
+}
+
+/// Copies the uleb128 or sleb128 value at `offset` of `source` to `out`.
+fn copy_leb128(source: &[u8], offset: &mut usize, out: &mut Vec<u8>) -> Result<()> {
+    let start = *offset;
+    Uleb128::read(source, offset)?;
+    out.extend_from_slice(&source[start..*offset]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Renames;
+    use crate::{annotation::AnnotationSetItem, smali, verifier, Dex, DexReader};
+
+    /// `value` formatted without the ids, which renaming changes.
+    fn without_ids(value: &dyn std::fmt::Debug) -> String {
+        let debug = format!("{:?}", value);
+        let mut formatted = String::new();
+        for (index, part) in debug.split(": ").enumerate() {
+            let is_id = formatted.ends_with("id") || formatted.ends_with("idx");
+            if index > 0 {
+                formatted.push_str(": ");
+            }
+            formatted.push_str(match is_id {
+                true => part.trim_start_matches(|c: char| c.is_ascii_digit()),
+                false => part,
+            });
+        }
+        formatted
+    }
+
+    /// Each class with its annotations and its disassembled methods, with their debug info
+    /// and annotations, sorted.
+    fn classes<T: AsRef<[u8]>>(dex: &Dex<T>) -> Vec<(String, String, Vec<String>)> {
+        let annotations = |annotations: &AnnotationSetItem| {
+            let mut annotations: Vec<String> = annotations.iter().map(|a| without_ids(a)).collect();
+            annotations.sort();
+            annotations.join("\n")
+        };
+        dex.classes()
+            .map(|class| {
+                let class = class.unwrap();
+                let mut methods: Vec<String> = class
+                    .methods()
+                    .map(|method| {
+                        let debug_info = method.code().map(|code| code.debug_info_item());
+                        format!(
+                            "{}{}\n{}",
+                            smali::disassemble(dex, method).unwrap(),
+                            without_ids(&debug_info),
+                            annotations(method.annotations())
+                        )
+                    })
+                    .collect();
+                methods.sort();
+                let descriptor = class.jtype().type_descriptor().to_string();
+                (descriptor, annotations(class.annotations()), methods)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rename() {
+        let dex = DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let (old, new) = ("Lorg/adw/launcher/ItemInfo;", "Lcom/example/Item;");
+        let renames = Renames::new()
+            .with_class(old, new)
+            .with_field(old, "cellX", "column")
+            .with_method(old, "assignFrom", "copyFrom");
+        let bytes = dex.rename(&renames).unwrap();
+        let renamed = DexReader::from_vec(bytes).expect("failed to load renamed dex");
+        assert!(renamed.is_signature_valid());
+        assert_eq!(verifier::verify_structure(&renamed).unwrap(), &[]);
+        assert!(renamed.find_class_by_name(old).unwrap().is_none());
+        let class = renamed.find_class_by_name(new).unwrap().unwrap();
+        assert!(class.fields().any(|field| field.name() == "column"));
+        assert!(class.methods().any(|method| method.name() == "copyFrom"));
+        // the members of other classes with the same names keep them.
+        let cell_info = renamed
+            .find_class_by_name("Lorg/adw/launcher/CellLayout$CellInfo;")
+            .unwrap()
+            .unwrap();
+        assert!(cell_info.fields().any(|field| field.name() == "cellX"));
+
+        let expected: Vec<_> = classes(&dex)
+            .into_iter()
+            .map(|(descriptor, annotations, methods)| {
+                let mut methods: Vec<String> = methods
+                    .into_iter()
+                    .map(|method| {
+                        let method = method
+                            .replace(old, new)
+                            .replace(&format!("{}->cellX:", new), &format!("{}->column:", new))
+                            .replace(
+                                &format!("{}->assignFrom(", new),
+                                &format!("{}->copyFrom(", new),
+                            );
+                        match method.split_once('\n') {
+                            Some((header, body)) if descriptor == old => format!(
+                                "{}\n{}",
+                                header.replace(" assignFrom(", " copyFrom("),
+                                body
+                            ),
+                            _ => method,
+                        }
+                    })
+                    .collect();
+                methods.sort();
+                let annotations = annotations.replace(old, new);
+                (descriptor.replace(old, new), annotations, methods)
+            })
+            .collect();
+        assert!(classes(&renamed) == expected);
+
+        let existing = Renames::new().with_class(old, "Lorg/adw/launcher/ApplicationInfo;");
+        assert!(dex.rename(&existing).is_err());
+        assert!(dex.rename(&Renames::new().with_class(old, "Item")).is_err());
+    }
+}
@@ -47,6 +47,7 @@ impl<'a> Section<'a> {
                     debug!(target: "binary-search", "binary search: success! index: {}", mid);
                     return Ok(Some(mid));
                 }
+                Ordering::Less if mid == 0 => break,
                 Ordering::Less => end = mid - 1,
                 Ordering::Greater => start = mid + 1,
             }
@@ -67,3 +68,46 @@ impl<'a> AsRef<[u8]> for Section<'a> {
         self.inner
     }
 }
+
+/// Matches a type descriptor against a glob pattern. `*` matches any characters except `/`,
+/// so a single package level, `**` matches any characters and `?` matches one character
+/// except `/`.
+pub(crate) fn glob_match(pattern: &str, descriptor: &str) -> bool {
+    fn matches(pattern: &[u8], text: &[u8]) -> bool {
+        match pattern {
+            [] => text.is_empty(),
+            [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+            [b'*', rest @ ..] => {
+                let level = text.iter().position(|c| *c == b'/').unwrap_or(text.len());
+                (0..=level).any(|i| matches(rest, &text[i..]))
+            }
+            [b'?', rest @ ..] => match text {
+                [c, text @ ..] if *c != b'/' => matches(rest, text),
+                _ => false,
+            },
+            [p, rest @ ..] => match text {
+                [c, text @ ..] if c == p => matches(rest, text),
+                _ => false,
+            },
+        }
+    }
+    matches(pattern.as_bytes(), descriptor.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("Lcom/example/**", "Lcom/example/Foo;"));
+        assert!(glob_match("Lcom/example/**", "Lcom/example/a/Foo$1;"));
+        assert!(!glob_match("Lcom/example/**", "Lcom/other/Foo;"));
+        assert!(glob_match("Lcom/example/*", "Lcom/example/Foo;"));
+        assert!(!glob_match("Lcom/example/*", "Lcom/example/a/Foo;"));
+        assert!(glob_match("L**/*Activity;", "Lcom/example/MainActivity;"));
+        assert!(glob_match("Lcom/?xample/Foo;", "Lcom/example/Foo;"));
+        assert!(!glob_match("Lcom/example/Foo", "Lcom/example/Foo;"));
+        assert!(glob_match("Lcom/example/Foo;", "Lcom/example/Foo;"));
+    }
+}
@@ -0,0 +1,301 @@
+//! Bytes of a `Dex` taken by debug information and metadata, by package, see `size_report`.
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use getset::{CopyGetters, Getters};
+use scroll::{Pread, Sleb128, Uleb128};
+
+use crate::{
+    annotation::AnnotationItem,
+    code::{
+        DBG_ADVANCE_LINE, DBG_ADVANCE_PC, DBG_END_LOCAL, DBG_END_SEQUENCE, DBG_RESTART_LOCAL,
+        DBG_SET_FILE, DBG_START_LOCAL, DBG_START_LOCAL_EXTENDED,
+    },
+    deps::package,
+    error::Error,
+    provenance::Provenance,
+    string::StringId,
+    ubyte, uint, Dex, Result, NO_INDEX,
+};
+
+/// Bytes of the file taken by debug information and metadata of the classes of a package.
+#[derive(Debug, Clone, Default, PartialEq, CopyGetters)]
+#[get_copy = "pub"]
+pub struct PackageSize {
+    /// Bytes of the `DebugInfoItem`s of the methods.
+    debug_info: usize,
+    /// Bytes of the annotations directories, annotation sets and annotations.
+    annotations: usize,
+}
+
+/// Report of the bytes of a `Dex` taken by data which can be stripped or shortened in
+/// release builds, see `size_report`. Items shared between classes are only counted once,
+/// and attributed to the package of the first class that uses them.
+#[derive(Debug, Clone, Default, PartialEq, Getters, CopyGetters)]
+pub struct SizeReport {
+    /// Size of the file.
+    #[get_copy = "pub"]
+    file_size: usize,
+    /// Bytes of the `DebugInfoItem`s.
+    #[get_copy = "pub"]
+    debug_info: usize,
+    /// Bytes of the strings used only as source file names.
+    #[get_copy = "pub"]
+    source_file_names: usize,
+    /// Bytes of the strings used only as parameter names in debug information.
+    #[get_copy = "pub"]
+    parameter_names: usize,
+    /// Bytes of the annotations directories, annotation sets and annotations.
+    #[get_copy = "pub"]
+    annotations: usize,
+    /// Number of strings longer than the threshold given to `size_report`.
+    #[get_copy = "pub"]
+    long_string_count: usize,
+    /// Bytes of the strings longer than the threshold given to `size_report`.
+    #[get_copy = "pub"]
+    long_strings: usize,
+    /// Sizes per package, in Java notation.
+    #[get = "pub"]
+    packages: BTreeMap<String, PackageSize>,
+    /// Provenance of the `Dex` the report is about.
+    provenance: Option<Provenance>,
+}
+
+impl SizeReport {
+    /// Provenance of the `Dex` the report is about.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+}
+
+/// Computes how many bytes of `dex` are taken by debug information, source file names,
+/// parameter names, annotations and strings of more than `long_string_len` bytes.
+pub fn size_report<T: AsRef<[u8]>>(dex: &Dex<T>, long_string_len: usize) -> Result<SizeReport> {
+    let source = dex.source.as_ref();
+    let endian = dex.get_endian();
+    let mut report = SizeReport {
+        file_size: dex.header().file_size() as usize,
+        provenance: Some(dex.provenance()),
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+    let mut source_files = BTreeSet::new();
+    let mut parameter_names = BTreeSet::new();
+    for class_def in dex.class_defs() {
+        let class_def = class_def?;
+        let package = package(&dex.get_type(class_def.class_idx())?.to_java_type()).to_string();
+        let mut sizes = PackageSize::default();
+        if class_def.source_file_idx() != NO_INDEX {
+            source_files.insert(class_def.source_file_idx());
+        }
+        if let Some(class_data) = dex.get_class_data(class_def.class_data_off())? {
+            let methods = class_data
+                .direct_methods()
+                .into_iter()
+                .chain(class_data.virtual_methods())
+                .flat_map(|methods| methods.inner());
+            for method in methods {
+                if *method.code_offset() == 0 {
+                    continue;
+                }
+                let debug_info_off =
+                    dex.debug_info_offset(method.method_id(), *method.code_offset())?;
+                if debug_info_off != 0 && seen.insert(debug_info_off) {
+                    let (size, names) = debug_info_size(source, debug_info_off as usize)?;
+                    sizes.debug_info += size;
+                    parameter_names.extend(names);
+                }
+            }
+        }
+        sizes.annotations +=
+            annotations_size(source, endian, class_def.annotations_off(), dex, &mut seen)?;
+        report.debug_info += sizes.debug_info;
+        report.annotations += sizes.annotations;
+        let total = report.packages.entry(package).or_default();
+        total.debug_info += sizes.debug_info;
+        total.annotations += sizes.annotations;
+    }
+
+    // strings used elsewhere, like a parameter name which is also a field name, aren't
+    // saved by stripping.
+    let mut used = HashSet::new();
+    for type_id in 0..dex.inner.type_ids_len() {
+        used.insert(
+            source
+                .pread_with::<uint>((dex.inner.type_ids_offset() + type_id * 4) as usize, endian)?,
+        );
+    }
+    for field in dex.field_ids() {
+        used.insert(field?.name_idx());
+    }
+    for method in dex.method_ids() {
+        used.insert(method?.name_idx());
+    }
+    for string_id in 0..dex.inner.strings_len() {
+        let size = string_data_size(dex, string_id)?;
+        if size > long_string_len {
+            report.long_string_count += 1;
+            report.long_strings += size;
+        }
+        if used.contains(&string_id) {
+            continue;
+        }
+        if source_files.contains(&string_id) {
+            report.source_file_names += size;
+        } else if parameter_names.contains(&string_id) {
+            report.parameter_names += size;
+        }
+    }
+    Ok(report)
+}
+
+/// Size of the string data item of `string_id`, including its length and terminator.
+fn string_data_size<T: AsRef<[u8]>>(dex: &Dex<T>, string_id: StringId) -> Result<usize> {
+    let source = dex.source.as_ref();
+    let data_off: uint = source.pread_with(
+        (dex.inner.strings_offset() + string_id * 4) as usize,
+        dex.get_endian(),
+    )?;
+    let offset = &mut (data_off as usize);
+    Uleb128::read(source, offset)?;
+    let len = source
+        .get(*offset..)
+        .and_then(|data| data.iter().position(|b| *b == 0))
+        .ok_or_else(|| Error::MalFormed(format!("Unterminated string: {}", string_id)))?;
+    Ok(*offset + len + 1 - data_off as usize)
+}
+
+/// Size of the debug info item at `offset` and the ids of its parameter names.
+fn debug_info_size(source: &[u8], start: usize) -> Result<(usize, Vec<StringId>)> {
+    let offset = &mut { start };
+    let uleb128p1 =
+        |offset: &mut usize| Ok::<_, Error>(Uleb128::read(source, offset)?.wrapping_sub(1));
+    Uleb128::read(source, offset)?;
+    let parameters_size = Uleb128::read(source, offset)?;
+    let mut names = Vec::new();
+    for _ in 0..parameters_size {
+        let name = uleb128p1(offset)?;
+        if name != u64::MAX {
+            names.push(name as StringId);
+        }
+    }
+    loop {
+        let opcode: ubyte = source.gread(offset)?;
+        match opcode {
+            DBG_END_SEQUENCE => break,
+            DBG_ADVANCE_PC | DBG_END_LOCAL | DBG_RESTART_LOCAL => {
+                Uleb128::read(source, offset)?;
+            }
+            DBG_ADVANCE_LINE => {
+                Sleb128::read(source, offset)?;
+            }
+            DBG_START_LOCAL => {
+                Uleb128::read(source, offset)?;
+                uleb128p1(offset)?;
+                uleb128p1(offset)?;
+            }
+            DBG_START_LOCAL_EXTENDED => {
+                Uleb128::read(source, offset)?;
+                uleb128p1(offset)?;
+                uleb128p1(offset)?;
+                uleb128p1(offset)?;
+            }
+            DBG_SET_FILE => {
+                uleb128p1(offset)?;
+            }
+            // prologue, epilogue and special opcodes have no arguments.
+            _ => {}
+        }
+    }
+    Ok((*offset - start, names))
+}
+
+/// Size of the annotations directory at `offset` and of the sets and annotations it
+/// refers to, skipping the ones in `seen`.
+fn annotations_size<T: AsRef<[u8]>>(
+    source: &[u8],
+    endian: crate::Endian,
+    directory_off: uint,
+    dex: &Dex<T>,
+    seen: &mut HashSet<uint>,
+) -> Result<usize> {
+    if directory_off == 0 || !seen.insert(directory_off) {
+        return Ok(0);
+    }
+    let offset = &mut (directory_off as usize);
+    let class_annotations_off: uint = source.gread_with(offset, endian)?;
+    let fields_size: uint = source.gread_with(offset, endian)?;
+    let methods_size: uint = source.gread_with(offset, endian)?;
+    let parameters_size: uint = source.gread_with(offset, endian)?;
+    let mut size = 16 + 8 * (fields_size + methods_size + parameters_size) as usize;
+    let mut sets = vec![class_annotations_off];
+    let mut ref_lists = Vec::new();
+    for index in 0..fields_size + methods_size + parameters_size {
+        let _: uint = source.gread_with(offset, endian)?;
+        let annotations_off: uint = source.gread_with(offset, endian)?;
+        if index < fields_size + methods_size {
+            sets.push(annotations_off);
+        } else {
+            ref_lists.push(annotations_off);
+        }
+    }
+    for ref_list in ref_lists {
+        if ref_list == 0 || !seen.insert(ref_list) {
+            continue;
+        }
+        let offset = &mut (ref_list as usize);
+        let len: uint = source.gread_with(offset, endian)?;
+        size += 4 + 4 * len as usize;
+        for _ in 0..len {
+            sets.push(source.gread_with(offset, endian)?);
+        }
+    }
+    for set in sets {
+        if set == 0 || !seen.insert(set) {
+            continue;
+        }
+        let offset = &mut (set as usize);
+        let len: uint = source.gread_with(offset, endian)?;
+        size += 4 + 4 * len as usize;
+        for _ in 0..len {
+            let annotation_off: uint = source.gread_with(offset, endian)?;
+            if seen.insert(annotation_off) {
+                let start = annotation_off as usize;
+                let end = &mut { start };
+                let _: AnnotationItem = source.gread_with(end, dex)?;
+                size += *end - start;
+            }
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::size_report;
+
+    #[test]
+    fn test_size_report() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let report = size_report(&dex, 100).expect("failed to compute report");
+        assert_eq!(
+            report.file_size(),
+            std::fs::metadata("resources/classes.dex").unwrap().len() as usize
+        );
+        assert!(report.debug_info() > 0);
+        assert!(report.annotations() > 0);
+        assert!(report.source_file_names() > 0);
+        assert!(report.debug_info() + report.annotations() < report.file_size());
+        let launcher = &report.packages()["org.adw.launcher"];
+        assert!(launcher.debug_info() > 0);
+        assert_eq!(
+            report
+                .packages()
+                .values()
+                .map(|p| p.debug_info())
+                .sum::<usize>(),
+            report.debug_info()
+        );
+        let all = size_report(&dex, 0).unwrap();
+        assert!(all.long_string_count() > report.long_string_count());
+    }
+}
@@ -0,0 +1,412 @@
+//! Smali-like textual disassembly of method code.
+//! [Smali syntax](https://github.com/JesusFreke/smali/wiki)
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::{
+    code::{CodeItem, ExceptionType},
+    field::FieldId,
+    instruction::{IndexType, Instruction, Opcode, Payload},
+    jtype::{Type, TypeId},
+    method::{
+        AccessFlags, CallSiteId, FieldOrMethodId, Method, MethodHandleId, MethodHandleItem,
+        MethodHandleType, MethodId, ProtoId, ProtoIdItem,
+    },
+    string::StringId,
+    Dex, Result,
+};
+
+const ACCESS_FLAGS: [(AccessFlags, &str); 14] = [
+    (AccessFlags::PUBLIC, "public"),
+    (AccessFlags::PRIVATE, "private"),
+    (AccessFlags::PROTECTED, "protected"),
+    (AccessFlags::STATIC, "static"),
+    (AccessFlags::FINAL, "final"),
+    (AccessFlags::SYNCHRONIZED, "synchronized"),
+    (AccessFlags::BRIDGE, "bridge"),
+    (AccessFlags::VARARGS, "varargs"),
+    (AccessFlags::NATIVE, "native"),
+    (AccessFlags::ABSTRACT, "abstract"),
+    (AccessFlags::STRICT, "strictfp"),
+    (AccessFlags::SYNTHETIC, "synthetic"),
+    (AccessFlags::CONSTRUCTOR, "constructor"),
+    (AccessFlags::DECLARED_SYNCHRONIZED, "declared-synchronized"),
+];
+
+/// Disassembles `method` into a smali-like `.method` block. Registers are named `vN`,
+/// branch targets, try blocks and handlers are labelled `:addr_<offset>` and string, type,
+/// field and method references are resolved through `dex`.
+pub fn disassemble<T: AsRef<[u8]>>(dex: &Dex<T>, method: &Method) -> Result<String> {
+    let mut out = String::new();
+    out.push_str(".method ");
+    for (flag, name) in ACCESS_FLAGS.iter() {
+        if method.access_flags().contains(*flag) {
+            let _ = write!(out, "{} ", name);
+        }
+    }
+    let _ = writeln!(
+        out,
+        "{}{}",
+        method.name(),
+        signature(method.params(), method.return_type())
+    );
+    if let Some(code) = method.try_code()? {
+        let _ = writeln!(out, "    .registers {}", code.registers_size());
+        let instructions = code
+            .instructions()
+            .with_offsets()
+            .collect::<Result<Vec<_>>>()?;
+
+        let (labels, switches) = jump_targets(code, &instructions);
+
+        for (offset, instruction) in &instructions {
+            if labels.contains(offset) {
+                let _ = writeln!(out, "    {}", label(*offset));
+            }
+            match instruction.payload() {
+                Some(payload) => {
+                    let base = switches.get(offset).copied().unwrap_or(*offset);
+                    for line in format_payload(&payload, base).lines() {
+                        let _ = writeln!(out, "    {}", line);
+                    }
+                }
+                None => {
+                    let _ = writeln!(
+                        out,
+                        "    {}",
+                        format_instruction(dex, instruction, *offset)?
+                    );
+                }
+            }
+        }
+        let end = code.insns().len();
+        if labels.contains(&end) {
+            let _ = writeln!(out, "    {}", label(end));
+        }
+
+        for try_block in code.tries().iter() {
+            let start = try_block.start_addr() as usize;
+            let end = start + try_block.insn_count() as usize;
+            for handler in try_block.catch_handlers() {
+                let range = format!("{{{} .. {}}}", label(start), label(end));
+                let target = label(handler.addr() as usize);
+                let _ = match handler.exception() {
+                    ExceptionType::BaseException => {
+                        writeln!(out, "    .catchall {} {}", range, target)
+                    }
+                    ExceptionType::Ty(jtype) => writeln!(
+                        out,
+                        "    .catch {} {} {}",
+                        jtype.type_descriptor(),
+                        range,
+                        target
+                    ),
+                };
+            }
+        }
+    }
+    out.push_str(".end method\n");
+    Ok(out)
+}
+
+/// Addresses that can be jumped to in `code`: branch and switch targets, try block
+/// boundaries and handlers. Also returns the address of the switch using each payload, as
+/// payloads are addressed relative to it.
+pub(crate) fn jump_targets(
+    code: &CodeItem,
+    instructions: &[(usize, Instruction<'_>)],
+) -> (BTreeSet<usize>, HashMap<usize, usize>) {
+    let mut labels = BTreeSet::new();
+    // payloads are addressed relative to the switch that uses them.
+    let mut switches = HashMap::new();
+    for (offset, instruction) in instructions {
+        if let Some(branch) = instruction.branch_offset() {
+            let target = (*offset as i64 + i64::from(branch)) as usize;
+            match instruction.opcode() {
+                Opcode::PackedSwitch | Opcode::SparseSwitch => {
+                    switches.insert(target, *offset);
+                }
+                _ => {}
+            }
+            labels.insert(target);
+        }
+    }
+    for (offset, instruction) in instructions {
+        if let (Some(base), Some(payload)) = (switches.get(offset), instruction.payload()) {
+            labels.extend(switch_targets(&payload, *base));
+        }
+    }
+    for try_block in code.tries().iter() {
+        labels.insert(try_block.start_addr() as usize);
+        labels.insert(try_block.start_addr() as usize + try_block.insn_count() as usize);
+        for handler in try_block.catch_handlers() {
+            labels.insert(handler.addr() as usize);
+        }
+    }
+    (labels, switches)
+}
+
+/// Formats a single instruction at `offset` code units from the start of its method.
+/// Payload pseudo-instructions are formatted relative to their own offset since the
+/// switch which uses them isn't known here; `disassemble` formats them correctly.
+pub fn format_instruction<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    instruction: &Instruction<'_>,
+    offset: usize,
+) -> Result<String> {
+    use crate::instruction::Format::*;
+
+    if let Some(payload) = instruction.payload() {
+        return Ok(format_payload(&payload, offset));
+    }
+    let opcode = instruction.opcode();
+    let mut operands = Vec::new();
+    let registers = instruction.registers();
+    match instruction.format() {
+        F35c | F35ms | F35mi | F45cc => operands.push(format!(
+            "{{{}}}",
+            registers
+                .iter()
+                .map(|r| format!("v{}", r))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        F3rc | F3rms | F3rmi | F4rcc => {
+            operands.push(match (registers.first(), registers.last()) {
+                (Some(first), Some(last)) => format!("{{v{} .. v{}}}", first, last),
+                _ => "{}".to_string(),
+            })
+        }
+        _ => operands.extend(registers.iter().map(|r| format!("v{}", r))),
+    }
+    if let Some(literal) = instruction.literal() {
+        let suffix = if opcode.mnemonic().starts_with("const-wide") {
+            "L"
+        } else {
+            ""
+        };
+        operands.push(format!("{}{}", hex(literal), suffix));
+    }
+    if let Some(branch) = instruction.branch_offset() {
+        operands.push(label((offset as i64 + i64::from(branch)) as usize));
+    }
+    if let Some(index) = instruction.index() {
+        operands.push(match opcode.index_type() {
+            IndexType::String => escape(&dex.get_string(index as StringId)?),
+            IndexType::Type => dex.get_type(index as TypeId)?.type_descriptor().to_string(),
+            IndexType::Field => field_reference(dex, index)?,
+            IndexType::Method => method_reference(dex, index)?,
+            IndexType::MethodHandle => {
+                method_handle(dex, &dex.get_method_handle_item(index as MethodHandleId)?)?
+            }
+            IndexType::Proto => prototype_descriptor(dex, index)?,
+            IndexType::CallSite => call_site(dex, index as CallSiteId)?,
+            IndexType::FieldOffset => format!("field@{:#x}", index),
+            IndexType::VtableOffset => format!("vtable@{:#x}", index),
+            IndexType::InlineMethod => format!("inline@{:#x}", index),
+            IndexType::VerificationError => format!("error@{:#x}", index),
+            IndexType::None => format!("{:#x}", index),
+        });
+    }
+    if let Some(proto) = instruction.proto_index() {
+        operands.push(prototype_descriptor(dex, proto)?);
+    }
+    if operands.is_empty() {
+        Ok(opcode.mnemonic().to_string())
+    } else {
+        Ok(format!("{} {}", opcode.mnemonic(), operands.join(", ")))
+    }
+}
+
+fn label(offset: usize) -> String {
+    format!(":addr_{:x}", offset)
+}
+
+fn hex(value: i64) -> String {
+    if value < 0 {
+        format!("-{:#x}", value.unsigned_abs())
+    } else {
+        format!("{:#x}", value)
+    }
+}
+
+fn switch_targets(payload: &Payload, base: usize) -> Vec<usize> {
+    let targets = match payload {
+        Payload::PackedSwitch { targets, .. } | Payload::SparseSwitch { targets, .. } => targets,
+        Payload::FillArrayData { .. } => return Vec::new(),
+    };
+    targets
+        .iter()
+        .map(|target| (base as i64 + i64::from(*target)) as usize)
+        .collect()
+}
+
+fn format_payload(payload: &Payload, base: usize) -> String {
+    let mut out = String::new();
+    let targets = switch_targets(payload, base);
+    match payload {
+        Payload::PackedSwitch { first_key, .. } => {
+            let _ = writeln!(out, ".packed-switch {}", hex(i64::from(*first_key)));
+            for target in targets {
+                let _ = writeln!(out, "    {}", label(target));
+            }
+            out.push_str(".end packed-switch");
+        }
+        Payload::SparseSwitch { keys, .. } => {
+            out.push_str(".sparse-switch\n");
+            for (key, target) in keys.iter().zip(targets) {
+                let _ = writeln!(out, "    {} -> {}", hex(i64::from(*key)), label(target));
+            }
+            out.push_str(".end sparse-switch");
+        }
+        Payload::FillArrayData {
+            element_width,
+            data,
+        } => {
+            let _ = writeln!(out, ".array-data {}", element_width);
+            let width = usize::from(*element_width).clamp(1, 8);
+            for element in data.chunks(width) {
+                let mut bytes = [0; 8];
+                bytes[..element.len()].copy_from_slice(element);
+                let (value, suffix) = match width {
+                    1 => (i64::from(bytes[0] as i8), "t"),
+                    2 => (i64::from(i16::from_le_bytes([bytes[0], bytes[1]])), "s"),
+                    4 => (
+                        i64::from(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+                        "",
+                    ),
+                    _ => (i64::from_le_bytes(bytes), "L"),
+                };
+                let _ = writeln!(out, "    {}{}", hex(value), suffix);
+            }
+            out.push_str(".end array-data");
+        }
+    }
+    out
+}
+
+pub(crate) fn signature(params: &[Type], return_type: &Type) -> String {
+    let params: String = params
+        .iter()
+        .map(|p| p.type_descriptor().to_string())
+        .collect();
+    format!("({}){}", params, return_type.type_descriptor())
+}
+
+fn field_reference<T: AsRef<[u8]>>(dex: &Dex<T>, field_id: FieldId) -> Result<String> {
+    dex.get_field_item(field_id)?.descriptor(dex)
+}
+
+fn proto_descriptor<T: AsRef<[u8]>>(dex: &Dex<T>, proto: &ProtoIdItem) -> Result<String> {
+    Ok(signature(
+        &dex.get_type_list(proto.params_off())?,
+        &dex.get_type(proto.return_type())?,
+    ))
+}
+
+fn prototype_descriptor<T: AsRef<[u8]>>(dex: &Dex<T>, proto_id: ProtoId) -> Result<String> {
+    let prototype = dex.get_prototype(proto_id)?;
+    Ok(signature(&prototype.params, &prototype.return_type))
+}
+
+pub(crate) fn method_reference<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    method_id: MethodId,
+) -> Result<String> {
+    dex.get_method_item(method_id)?.descriptor(dex)
+}
+
+fn method_handle<T: AsRef<[u8]>>(dex: &Dex<T>, handle: &MethodHandleItem) -> Result<String> {
+    let kind = match handle.handle_type() {
+        MethodHandleType::StaticPut => "static-put",
+        MethodHandleType::StaticGet => "static-get",
+        MethodHandleType::InstancePut => "instance-put",
+        MethodHandleType::InstanceGet => "instance-get",
+        MethodHandleType::InvokeStatic => "invoke-static",
+        MethodHandleType::InvokeInstance => "invoke-instance",
+        MethodHandleType::InvokeConstructor => "invoke-constructor",
+        MethodHandleType::InvokeDirect => "invoke-direct",
+        MethodHandleType::InvokeInterface => "invoke-interface",
+    };
+    let member = match handle.id() {
+        FieldOrMethodId::Field(field_id) => field_reference(dex, field_id)?,
+        FieldOrMethodId::Method(method_id) => method_reference(dex, method_id)?,
+    };
+    Ok(format!("{}@{}", kind, member))
+}
+
+fn call_site<T: AsRef<[u8]>>(dex: &Dex<T>, call_site_id: CallSiteId) -> Result<String> {
+    let call_site = dex.get_call_site_item(call_site_id)?;
+    Ok(format!(
+        "call_site_{}({}, {})@{}",
+        call_site_id,
+        escape(call_site.method_name()),
+        proto_descriptor(dex, call_site.method_type())?,
+        method_handle(dex, call_site.bootstrap_method())?
+    ))
+}
+
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() || !c.is_ascii() => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    let _ = write!(out, "\\u{:04x}", unit);
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, escape, format_instruction};
+    use crate::instruction::{Instruction, Quickening};
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\\n"), "\"a\\\"b\\\\\\n\"");
+        assert_eq!(escape("é"), "\"\\u00e9\"");
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let class = dex
+            .find_class_by_name("Lorg/adw/launcher/Launcher;")
+            .expect("failed to load class")
+            .expect("class not found");
+        for method in class.methods() {
+            let smali = disassemble(&dex, method).expect("failed to disassemble");
+            assert!(smali.starts_with(".method "));
+            assert!(smali.ends_with(".end method\n"));
+        }
+        let on_create = class
+            .methods()
+            .find(|m| m.name() == "onCreate")
+            .expect("onCreate not found");
+        let smali = disassemble(&dex, on_create).expect("failed to disassemble");
+        assert!(smali.starts_with(".method protected onCreate(Landroid/os/Bundle;)V\n"));
+        assert!(smali.contains(
+            "invoke-super {v3, v4}, Landroid/app/Activity;->onCreate(Landroid/os/Bundle;)V"
+        ));
+
+        let insns = [0x1012, 0x0e00];
+        let instruction = Instruction::decode(&insns, Quickening::None).unwrap();
+        assert_eq!(
+            format_instruction(&dex, &instruction, 0).unwrap(),
+            "const/4 v0, 0x1"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{clone::Clone, convert::AsRef, ops::Index, rc::Rc};
+use std::{clone::Clone, convert::AsRef, ops::Index, sync::Arc};
 
 use crate::ubyte;
 
@@ -7,7 +7,7 @@ use crate::ubyte;
 /// of the dex file's source.
 #[derive(Debug)]
 pub(crate) struct Source<T> {
-    inner: Rc<T>,
+    inner: Arc<T>,
 }
 
 impl<T> Source<T>
@@ -16,7 +16,7 @@ where
 {
     pub(crate) fn new(inner: T) -> Self {
         Self {
-            inner: Rc::new(inner),
+            inner: Arc::new(inner),
         }
     }
 }
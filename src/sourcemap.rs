@@ -0,0 +1,199 @@
+//! Mappings of the code of methods to source files and lines, built from the debug info of a
+//! `Dex`, for coverage and tracing tools which show bytecode offsets as source positions.
+use getset::{CopyGetters, Getters};
+
+use crate::{
+    code::DebugInfoItem, method::MethodId, provenance::Provenance, smali, uint, utils::quote, Dex,
+    Result,
+};
+
+/// Instructions of a method which come from the same source line.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct SourceRange {
+    /// Offset in code units of the first instruction.
+    #[get_copy = "pub"]
+    start: uint,
+    /// Offset in code units after the last instruction.
+    #[get_copy = "pub"]
+    end: uint,
+    /// Source file of the instructions, `None` if the debug info doesn't name one.
+    #[get = "pub"]
+    file: Option<String>,
+    /// Line of the instructions.
+    #[get_copy = "pub"]
+    line: usize,
+}
+
+impl SourceRange {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"start\":{},\"end\":{},\"file\":{},\"line\":{}}}",
+            self.start,
+            self.end,
+            self.file.as_deref().map_or("null".to_string(), quote),
+            self.line
+        )
+    }
+}
+
+/// Source ranges of the code of a method.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct MethodSourceMap {
+    /// The method.
+    #[get_copy = "pub"]
+    method: MethodId,
+    /// Reference to the method in smali notation, like `Lcom/foo/Bar;->baz(I)V`.
+    #[get = "pub"]
+    reference: String,
+    /// Ranges sorted by offset. Instructions before the first position have no range.
+    #[get = "pub"]
+    ranges: Vec<SourceRange>,
+}
+
+impl MethodSourceMap {
+    /// The range of the instruction at `address`.
+    pub fn range_for_address(&self, address: uint) -> Option<&SourceRange> {
+        let index = self.ranges.partition_point(|range| range.start <= address);
+        index
+            .checked_sub(1)
+            .map(|i| &self.ranges[i])
+            .filter(|range| address < range.end)
+    }
+
+    fn to_json(&self) -> String {
+        let ranges: Vec<String> = self.ranges.iter().map(SourceRange::to_json).collect();
+        format!(
+            "{{\"method\":{},\"ranges\":[{}]}}",
+            quote(&self.reference),
+            ranges.join(",")
+        )
+    }
+}
+
+/// Source ranges of the methods of a `Dex`, see `source_map`.
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct SourceMap {
+    /// Methods with debug info, in the order of their classes.
+    #[get = "pub"]
+    methods: Vec<MethodSourceMap>,
+    provenance: Option<Provenance>,
+}
+
+impl SourceMap {
+    /// The map of a method, `None` if it has no debug info.
+    pub fn method(&self, method: MethodId) -> Option<&MethodSourceMap> {
+        self.methods.iter().find(|map| map.method == method)
+    }
+
+    /// Provenance of the `Dex` the map was built from.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    /// The map as JSON, like
+    /// `{"methods":[{"method":"LFoo;->bar()V","ranges":[{"start":0,"end":3,"file":"Foo.java","line":12}]}]}`.
+    pub fn to_json(&self) -> String {
+        let methods: Vec<String> = self.methods.iter().map(MethodSourceMap::to_json).collect();
+        let provenance = self
+            .provenance
+            .as_ref()
+            .map(|provenance| format!(",\"provenance\":{}", provenance.to_json()))
+            .unwrap_or_default();
+        format!("{{\"methods\":[{}]{}}}", methods.join(","), provenance)
+    }
+}
+
+/// Builds the source map of the methods of `dex` which have debug info. An instruction is
+/// mapped to the last position at or before it, and to the source file of its class unless
+/// the debug info sets another one.
+pub fn source_map<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<SourceMap> {
+    let mut methods = Vec::new();
+    for class in dex.classes() {
+        let class = class?;
+        let class_file = class.source_file().map(ToString::to_string);
+        for method in class.methods() {
+            let code = match method.try_code()? {
+                Some(code) => code,
+                None => continue,
+            };
+            let debug_info = match code.debug_info_item() {
+                Some(debug_info) => debug_info,
+                None => continue,
+            };
+            methods.push(MethodSourceMap {
+                method: method.id(),
+                reference: smali::method_reference(dex, method.id())?,
+                ranges: ranges(debug_info, code.insns().len() as uint, &class_file),
+            });
+        }
+    }
+    debug!(target: "sourcemap", "mapped {} methods", methods.len());
+    Ok(SourceMap {
+        methods,
+        provenance: Some(dex.provenance()),
+    })
+}
+
+fn ranges(
+    debug_info: &DebugInfoItem,
+    code_len: uint,
+    class_file: &Option<String>,
+) -> Vec<SourceRange> {
+    let positions = debug_info.positions();
+    let mut ranges = Vec::with_capacity(positions.len());
+    for (index, position) in positions.iter().enumerate() {
+        let end = positions[index + 1..]
+            .iter()
+            .map(|next| next.address())
+            .find(|address| *address > position.address())
+            .unwrap_or(code_len);
+        // only the last of the positions at an address applies.
+        if end <= position.address()
+            || positions
+                .get(index + 1)
+                .is_some_and(|next| next.address() == position.address())
+        {
+            continue;
+        }
+        let file = debug_info
+            .source_file_for_address(position.address())
+            .map(ToString::to_string)
+            .or_else(|| class_file.clone());
+        ranges.push(SourceRange {
+            start: position.address(),
+            end,
+            file,
+            line: position.line(),
+        });
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::source_map;
+
+    #[test]
+    fn test_source_map() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let map = source_map(&dex).unwrap();
+        assert!(!map.methods().is_empty());
+        for method in map.methods() {
+            assert!(method
+                .ranges()
+                .windows(2)
+                .all(|w| w[0].end() <= w[1].start()));
+            for range in method.ranges() {
+                assert!(range.start() < range.end());
+                assert_eq!(method.range_for_address(range.start()), Some(range));
+                assert!(range
+                    .file()
+                    .as_deref()
+                    .is_some_and(|f| f.ends_with(".java")));
+            }
+        }
+        let json = map.to_json();
+        assert!(json.starts_with("{\"methods\":[{\"method\":\"L"));
+        assert!(json.contains("\"file\":\""));
+    }
+}
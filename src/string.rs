@@ -1,51 +1,175 @@
 //! Dex String utilities
 use std::{
+    borrow::Cow,
     convert::AsRef,
     fmt,
-    num::NonZeroUsize,
+    hash::{Hash, Hasher},
     ops::{Deref, Range},
 };
 
 use cesu8::{from_java_cesu8, to_java_cesu8};
 use scroll::{self, ctx, Pread, Uleb128};
 
-use crate::{cache::Cache, error, error::Error, source::Source, uint, Result};
-use std::rc::Rc;
+use crate::{
+    cache::{CacheSize, ShardedCache},
+    error,
+    error::{Error, ResultExt},
+    source::Source,
+    uint, ItemType, Result,
+};
+use std::sync::Arc;
 
 /// Index into the `StringId`s section.
 pub type StringId = uint;
 
 /// Strings in `Dex` file are encoded as MUTF-8 code units. DexString is a
-/// wrapper type for converting Dex strings into Rust strings.
+/// wrapper type for converting Dex strings into Rust strings. Strings which are valid
+/// UTF-8, like ASCII ones, are copied from the file without being converted. Invalid
+/// MUTF-8 strings are decoded lossily, their bytes are kept, see `mutf8`.
 /// [Android docs](https://source.android.com/devices/tech/dalvik/dex-format#mutf-8)
-#[derive(Debug, Hash, Eq, PartialEq, Clone, PartialOrd, Ord)]
+#[derive(Debug, Clone)]
 pub struct DexString {
-    string: Rc<String>,
+    repr: Repr,
+}
+
+#[derive(Debug, Clone)]
+enum Repr {
+    /// A string whose MUTF-8 bytes are its UTF-8 bytes, of the UTF-16 size they imply,
+    /// which is the case of most strings.
+    Plain(Arc<str>),
+    /// Any other string.
+    Raw(Arc<RawString>),
+}
+
+/// A string with the parts of its `string_data_item` which differ from the decoded string.
+#[derive(Debug)]
+struct RawString {
+    string: Box<str>,
+    /// The MUTF-8 bytes, `None` if they are the UTF-8 bytes of the string.
+    mutf8: Option<Box<[u8]>>,
+    /// The declared size in UTF-16 code units.
+    utf16_size: uint,
+}
+
+impl DexString {
+    /// A string decoded from `mutf8`, `converted` if its bytes differ from `mutf8`.
+    fn new(string: &str, converted: bool, mutf8: &[u8], utf16_size: uint) -> Self {
+        let repr = if !converted && utf16_size as usize == string.encode_utf16().count() {
+            Repr::Plain(Arc::from(string))
+        } else {
+            Repr::Raw(Arc::new(RawString {
+                string: Box::from(string),
+                mutf8: if converted {
+                    Some(Box::from(mutf8))
+                } else {
+                    None
+                },
+                utf16_size,
+            }))
+        };
+        DexString { repr }
+    }
+
+    /// The MUTF-8 bytes of the string as found in the file, without the terminating null.
+    pub fn mutf8(&self) -> &[u8] {
+        match &self.repr {
+            Repr::Raw(raw) => raw.mutf8.as_deref().unwrap_or(raw.string.as_bytes()),
+            Repr::Plain(string) => string.as_bytes(),
+        }
+    }
+
+    /// Size of the string in UTF-16 code units, as declared by its `string_data_item`. It
+    /// isn't checked against the string, obfuscators may set it to anything.
+    pub fn utf16_size(&self) -> uint {
+        match &self.repr {
+            Repr::Raw(raw) => raw.utf16_size,
+            Repr::Plain(string) => string.encode_utf16().count() as uint,
+        }
+    }
+
+    /// Whether the MUTF-8 bytes aren't valid and the string was decoded with replacement
+    /// characters.
+    pub fn is_lossy(&self) -> bool {
+        match &self.repr {
+            Repr::Raw(raw) => raw
+                .mutf8
+                .as_deref()
+                .is_some_and(|mutf8| from_java_cesu8(mutf8).is_err()),
+            Repr::Plain(_) => false,
+        }
+    }
+
+    /// What the strings are compared by: their text first, then the raw parts which differ.
+    fn key(&self) -> (&str, Option<(&[u8], uint)>) {
+        let raw = match &self.repr {
+            Repr::Raw(raw) => Some((self.mutf8(), raw.utf16_size)),
+            Repr::Plain(_) => None,
+        };
+        (self, raw)
+    }
+}
+
+impl PartialEq for DexString {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for DexString {}
+
+impl PartialOrd for DexString {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DexString {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl Hash for DexString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
 }
 
 impl PartialEq<str> for DexString {
     fn eq(&self, other: &str) -> bool {
-        *self.string == other
+        **self == *other
     }
 }
 
 impl<'a> PartialEq<&'a str> for DexString {
     fn eq(&self, other: &&'a str) -> bool {
-        *self.string == *other
+        **self == **other
     }
 }
 
 impl fmt::Display for DexString {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.string)
+        write!(f, "{}", &**self)
+    }
+}
+
+/// Serialized as a string.
+#[cfg(feature = "serde")]
+impl serde::Serialize for DexString {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
     }
 }
 
 impl From<String> for DexString {
     fn from(string: String) -> Self {
-        DexString {
-            string: Rc::new(string),
-        }
+        let mutf8 = to_java_cesu8(&string);
+        let converted = matches!(mutf8, Cow::Owned(_));
+        let utf16_size = string.encode_utf16().count() as uint;
+        DexString::new(&string, converted, &mutf8, utf16_size)
     }
 }
 
@@ -53,7 +177,10 @@ impl Deref for DexString {
     type Target = str;
 
     fn deref(&self) -> &Self::Target {
-        &self.string
+        match &self.repr {
+            Repr::Plain(string) => string,
+            Repr::Raw(raw) => &raw.string,
+        }
     }
 }
 
@@ -64,7 +191,7 @@ impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for DexString {
     // https://source.android.com/devices/tech/dalvik/dex-format#string-data-item
     fn try_from_ctx(source: &'a [u8], _: scroll::Endian) -> Result<(Self, Self::Size)> {
         let offset = &mut 0;
-        let _ = Uleb128::read(source, offset)?;
+        let utf16_size = Uleb128::read(source, offset)? as uint;
         let count = source
             .iter()
             .skip(*offset)
@@ -72,16 +199,14 @@ impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for DexString {
             .count();
         let bytes = &source[*offset..*offset + count];
         let size = *offset + bytes.len();
-        Ok((
-            DexString {
-                string: Rc::new(
-                    from_java_cesu8(bytes)
-                        .map_err(|e| Error::MalFormed(format!("Malformed string: {:?}", e)))?
-                        .into_owned(),
-                ),
-            },
-            size,
-        ))
+        // the string is borrowed from `source` unless it had to be converted, so it is
+        // allocated once, by the `Arc`.
+        let string = from_java_cesu8(bytes).unwrap_or_else(|e| {
+            debug!(target: "string", "invalid MUTF-8 string {:?}: {:?}", bytes, e);
+            String::from_utf8_lossy(bytes)
+        });
+        let converted = matches!(string, Cow::Owned(_));
+        Ok((DexString::new(&string, converted, bytes, utf16_size), size))
     }
 }
 
@@ -96,7 +221,7 @@ pub(crate) struct Strings<T> {
     endian: super::Endian,
     /// Length of the strings section.
     len: uint,
-    cache: Cache<StringId, DexString>,
+    cache: ShardedCache<StringId, DexString>,
     data_section: Range<uint>,
 }
 
@@ -110,7 +235,7 @@ where
         endian: super::Endian,
         offset: uint,
         len: uint,
-        cache_size: NonZeroUsize,
+        cache_size: CacheSize,
         data_section: Range<uint>,
     ) -> Self {
         Self {
@@ -118,11 +243,31 @@ where
             offset,
             endian,
             len,
-            cache: Cache::new(cache_size),
+            cache: ShardedCache::new(cache_size),
             data_section,
         }
     }
 
+    /// The same strings with an empty cache of `cache_size` strings.
+    pub(crate) fn with_cache_size(self, cache_size: CacheSize) -> Self {
+        Self {
+            cache: ShardedCache::new(cache_size),
+            ..self
+        }
+    }
+
+    /// The same strings, sharing the cache, read from `source`.
+    pub(crate) fn with_source<U>(&self, source: Source<U>) -> Strings<U> {
+        Strings {
+            source,
+            offset: self.offset,
+            endian: self.endian,
+            len: self.len,
+            cache: self.cache.clone(),
+            data_section: self.data_section.clone(),
+        }
+    }
+
     fn parse(&self, id: StringId) -> Result<DexString> {
         let source = &self.source;
         let offset = self.offset as usize + id as usize * 4;
@@ -133,27 +278,90 @@ where
                 format!("string_data_off not in data section for StringId: {}", id),
             ));
         }
-        source.pread(string_data_off as usize)
+        source
+            .pread(string_data_off as usize)
+            .with_item(ItemType::StringDataItem, string_data_off as usize)
     }
 
     /// Get the string at `id` updating the cache with the new item
     pub(crate) fn get(&self, id: StringId) -> Result<DexString> {
+        if let Some(string) = self.cached(id)? {
+            return Ok(string);
+        }
+        // another thread may evict the string as soon as it's cached.
+        let string = self.parse(id)?;
+        self.cache.put(id, string.clone());
+        Ok(string)
+    }
+
+    /// Get the string at `id` without adding it to the cache.
+    pub(crate) fn get_uncached(&self, id: StringId) -> Result<DexString> {
+        match self.cached(id)? {
+            Some(string) => Ok(string),
+            None => self.parse(id),
+        }
+    }
+
+    /// The string at `id` if it is cached, an error if `id` is out of bounds.
+    fn cached(&self, id: StringId) -> Result<Option<DexString>> {
         if id >= self.len {
-            return Err(Error::InvalidId(format!("Invalid string id: {}", id)));
+            return Err(Error::IndexOutOfBounds {
+                section: ItemType::StringIdItem,
+                index: id.into(),
+            });
         }
-        if let Some(string) = self.cache.get(&id) {
-            Ok(string)
-        } else {
-            self.cache.put(id, self.parse(id)?);
-            Ok(self.cache.get(&id).unwrap())
+        Ok(self.cache.get(&id))
+    }
+
+    /// Ids of the strings which start with `prefix`. The strings are sorted, so they are
+    /// found by binary search.
+    pub(crate) fn prefix_range(&self, prefix: &str) -> Result<Range<StringId>> {
+        let prefix = to_java_cesu8(prefix);
+        let start = self.partition_point(|string| string < &*prefix)?;
+        let end =
+            self.partition_point(|string| string < &*prefix || string.starts_with(&prefix))?;
+        Ok(start..end)
+    }
+
+    /// The first id for which `before` is false, which must be true for all the ids before
+    /// it and false for all the ids after it.
+    fn partition_point(&self, before: impl Fn(&[u8]) -> bool) -> Result<StringId> {
+        let (mut start, mut end) = (0, self.len);
+        while start < end {
+            let mid = start + (end - start) / 2;
+            if before(self.mutf8(mid)?) {
+                start = mid + 1;
+            } else {
+                end = mid;
+            }
         }
+        Ok(start)
+    }
+
+    /// MUTF-8 bytes of the string at `id`, without converting them.
+    fn mutf8(&self, id: StringId) -> Result<&[u8]> {
+        let source = self.source.as_ref();
+        let offset = self.offset as usize + id as usize * 4;
+        let mut data_offset = source.pread_with::<uint>(offset, self.endian)? as usize;
+        Uleb128::read(source, &mut data_offset)?;
+        let data = source.get(data_offset..).ok_or_else(|| {
+            Error::BadOffset(data_offset, "string_data_item out of bounds".to_string())
+        })?;
+        let len = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+        Ok(&data[..len])
     }
 
     pub(crate) fn get_id(&self, string: &str) -> Result<Option<StringId>> {
         use crate::search::Section;
         let java_string = to_java_cesu8(string);
         let (offset, len) = (self.offset as usize, self.len as usize);
-        let string_section = &self.source[offset..offset + len * std::mem::size_of::<StringId>()];
+        let string_section = self
+            .source
+            .as_ref()
+            .get(offset..offset + len * std::mem::size_of::<StringId>())
+            .ok_or_else(|| {
+                Error::BadOffset(offset, "string_ids section out of bounds".to_string())
+            })?;
         let section = Section::new(string_section);
         let source = self.source.clone();
         let index = section.binary_search(
@@ -163,8 +371,19 @@ where
                 let mut data_offset = *data_offset as usize;
                 let _ = Uleb128::read(source.as_ref(), &mut data_offset)
                     .map_err(crate::error::Error::from)?;
-                let value = &source[data_offset..data_offset + element.len()];
-                Ok((**element).cmp(value))
+                // strings are null terminated, a string which starts with `element` is after it.
+                let value = source
+                    .as_ref()
+                    .get(data_offset..data_offset + element.len() + 1)
+                    .ok_or_else(|| {
+                        Error::BadOffset(data_offset, "string_data_item out of bounds".to_string())
+                    })?;
+                Ok(match (**element).cmp(&value[..element.len()]) {
+                    std::cmp::Ordering::Equal if value[element.len()] != 0 => {
+                        std::cmp::Ordering::Less
+                    }
+                    ordering => ordering,
+                })
             },
         )?;
         Ok(index.map(|i| i as StringId))
@@ -190,6 +409,8 @@ pub struct StringsIter<T> {
     cache: Strings<T>,
     current: usize,
     len: usize,
+    /// Whether the strings are added to the cache.
+    cached: bool,
 }
 
 impl<T: AsRef<[u8]>> StringsIter<T> {
@@ -198,6 +419,16 @@ impl<T: AsRef<[u8]>> StringsIter<T> {
             cache,
             current: 0,
             len,
+            cached: true,
+        }
+    }
+
+    /// Iterator which only reads the strings which are already cached from the cache and
+    /// doesn't add the others to it.
+    pub(crate) fn uncached(cache: Strings<T>, len: usize) -> Self {
+        Self {
+            cached: false,
+            ..Self::new(cache, len)
         }
     }
 }
@@ -205,13 +436,16 @@ impl<T: AsRef<[u8]>> StringsIter<T> {
 impl<T: AsRef<[u8]>> Iterator for StringsIter<T> {
     type Item = super::Result<DexString>;
 
-    // NOTE: iteration may cause cache thrashing, introduce a new
-    // method to get but not update cache if needed
     fn next(&mut self) -> Option<Self::Item> {
         if self.current >= self.len {
             return None;
         }
-        let next = self.cache.get(self.current as uint);
+        let id = self.current as uint;
+        let next = if self.cached {
+            self.cache.get(id)
+        } else {
+            self.cache.get_uncached(id)
+        };
         self.current += 1;
         Some(next)
     }
@@ -234,4 +468,82 @@ mod tests {
             "Lorg/adw/launcher/Launcher;"
         );
     }
+
+    #[test]
+    fn test_strings_uncached() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let launcher = dex.get_string(1).unwrap();
+        let uncached = dex
+            .strings_uncached()
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(uncached.len(), dex.header().string_ids_size() as usize);
+        assert_eq!(uncached[1], launcher);
+        assert!(dex.strings.cache.get(&0).is_none());
+        assert!(dex.strings.cache.get(&1).is_some());
+        let cached = dex.strings().collect::<crate::Result<Vec<_>>>().unwrap();
+        assert_eq!(cached, uncached);
+        let last = dex.header().string_ids_size() - 1;
+        assert!(dex.strings.cache.get(&last).is_some());
+    }
+
+    #[test]
+    fn test_strings_with_prefix() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let strings = dex.strings().collect::<crate::Result<Vec<_>>>().unwrap();
+        for prefix in [
+            "Lorg/adw/launcher/",
+            "Landroid/",
+            "",
+            "L",
+            "zzz",
+            "Lorg/adw/launcher/Launcher;",
+        ] {
+            let found = dex
+                .strings_with_prefix(prefix)
+                .unwrap()
+                .collect::<crate::Result<Vec<_>>>()
+                .unwrap();
+            let expected: Vec<_> = strings
+                .iter()
+                .enumerate()
+                .filter(|(_, string)| string.starts_with(prefix))
+                .map(|(id, string)| (id as super::StringId, string.clone()))
+                .collect();
+            assert_eq!(found, expected, "prefix {:?}", prefix);
+        }
+        assert!(
+            dex.strings_with_prefix("Lorg/adw/launcher/")
+                .unwrap()
+                .count()
+                > 1
+        );
+    }
+
+    #[test]
+    fn test_mutf8() {
+        use scroll::Pread;
+
+        let ascii: super::DexString = b"\x03abc\0".pread(0).unwrap();
+        assert_eq!(ascii, "abc");
+        // a null character and U+1F600, encoded as a surrogate pair.
+        let bytes = b"\x04a\xc0\x80\xed\xa0\xbd\xed\xb8\x80\0";
+        let converted: super::DexString = bytes.pread(0).unwrap();
+        assert_eq!(converted, "a\0\u{1f600}");
+        assert_eq!(converted.mutf8(), &bytes[1..bytes.len() - 1]);
+        assert_eq!(converted.utf16_size(), 4);
+        assert_eq!(
+            converted,
+            super::DexString::from("a\0\u{1f600}".to_string())
+        );
+        assert_eq!(ascii.mutf8(), b"abc");
+        assert!(!converted.is_lossy());
+
+        // an unpaired continuation byte, and a declared size which doesn't match.
+        let invalid: super::DexString = b"\x09a\x80b\0".pread(0).unwrap();
+        assert_eq!(invalid, "a\u{fffd}b");
+        assert_eq!(invalid.mutf8(), b"a\x80b");
+        assert_eq!(invalid.utf16_size(), 9);
+        assert!(invalid.is_lossy());
+    }
 }
@@ -0,0 +1,347 @@
+//! Strings used by the code of a `Dex`, including the ones built by appending constants
+//! to a `StringBuilder`, see `string_references`.
+use std::collections::HashMap;
+
+use getset::{CopyGetters, Getters};
+
+use crate::{
+    code::CodeItem,
+    instruction::{IndexType, Opcode},
+    jtype::TypeId,
+    method::MethodId,
+    smali,
+    string::StringId,
+    ushort, Dex, Result,
+};
+
+/// Where a string found by `string_references` comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringSource {
+    /// A `const-string` or `const-string/jumbo` instruction.
+    Constant,
+    /// A `StringBuilder` or `StringBuffer` chain whose parts are all constants, folded at
+    /// the `toString()` call.
+    Concatenation,
+}
+
+/// A string used by the code of a method.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct StringReference {
+    /// The method using the string.
+    #[get_copy = "pub"]
+    method: MethodId,
+    /// Address of the instruction, in code units from the start of the method's code.
+    #[get_copy = "pub"]
+    address: usize,
+    /// The string.
+    #[get = "pub"]
+    value: String,
+    /// How the string is built.
+    #[get_copy = "pub"]
+    source: StringSource,
+}
+
+/// Strings used by the methods of `dex`: the `const-string` references and the constant
+/// concatenations reconstructed from `StringBuilder` and `StringBuffer` chains, like
+/// `new StringBuilder("http://").append(host).append(':').append(8080).toString()` when
+/// `host` is a constant. Values are only tracked within straight-line code, so parts which
+/// come from other branches aren't folded.
+pub fn string_references<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<Vec<StringReference>> {
+    let mut references = Vec::new();
+    for class in dex.classes() {
+        let class = class?;
+        for method in class.methods() {
+            if let Some(code) = method.try_code()? {
+                for (address, value, source) in method_strings(dex, code)? {
+                    references.push(StringReference {
+                        method: method.id(),
+                        address,
+                        value,
+                        source,
+                    });
+                }
+            }
+        }
+    }
+    Ok(references)
+}
+
+const STRING_BUILDERS: [&str; 2] = ["Ljava/lang/StringBuilder;", "Ljava/lang/StringBuffer;"];
+
+/// Value of a register, as far as `method_strings` knows.
+#[derive(Debug, Clone)]
+enum Value {
+    Unknown,
+    String(String),
+    Literal(i64),
+    /// Index of a string builder created by the method.
+    Builder(usize),
+}
+
+/// Strings used by `code`, with the address of the instruction using them.
+pub(crate) fn method_strings<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    code: &CodeItem,
+) -> Result<Vec<(usize, String, StringSource)>> {
+    let instructions = code
+        .instructions()
+        .with_offsets()
+        .collect::<Result<Vec<_>>>()?;
+    let (targets, _) = smali::jump_targets(code, &instructions);
+    let mut strings = Vec::new();
+    let mut registers: HashMap<ushort, Value> = HashMap::new();
+    // parts of each builder, `None` once a part isn't known.
+    let mut builders: Vec<Option<Vec<String>>> = Vec::new();
+    let mut result = Value::Unknown;
+    for (address, instruction) in &instructions {
+        if targets.contains(address) {
+            registers.clear();
+            result = Value::Unknown;
+        }
+        let previous = std::mem::replace(&mut result, Value::Unknown);
+        let regs = instruction.registers();
+        let value = |register: ushort| registers.get(&register).cloned().unwrap_or(Value::Unknown);
+        match instruction.opcode() {
+            Opcode::ConstString | Opcode::ConstStringJumbo => {
+                let string_id = instruction.index().unwrap_or_default() as StringId;
+                let string = dex.get_string(string_id)?.to_string();
+                strings.push((*address, string.clone(), StringSource::Constant));
+                registers.insert(regs[0], Value::String(string));
+            }
+            Opcode::Const4 | Opcode::Const16 | Opcode::Const | Opcode::ConstHigh16 => {
+                let literal = instruction.literal().unwrap_or_default();
+                registers.insert(regs[0], Value::Literal(literal));
+            }
+            Opcode::ConstWide16
+            | Opcode::ConstWide32
+            | Opcode::ConstWide
+            | Opcode::ConstWideHigh16 => {
+                let literal = instruction.literal().unwrap_or_default();
+                registers.insert(regs[0], Value::Literal(literal));
+                registers.remove(&regs[0].wrapping_add(1));
+            }
+            Opcode::NewInstance => {
+                let jtype = dex.get_type(instruction.index().unwrap_or_default() as TypeId)?;
+                let descriptor: &str = jtype.type_descriptor();
+                let value = if STRING_BUILDERS.contains(&descriptor) {
+                    builders.push(Some(Vec::new()));
+                    Value::Builder(builders.len() - 1)
+                } else {
+                    Value::Unknown
+                };
+                registers.insert(regs[0], value);
+            }
+            Opcode::MoveObject | Opcode::MoveObjectFrom16 | Opcode::MoveObject16 => {
+                let value = value(regs[1]);
+                registers.insert(regs[0], value);
+            }
+            Opcode::MoveResultObject => {
+                registers.insert(regs[0], previous);
+            }
+            Opcode::InvokeVirtual
+            | Opcode::InvokeVirtualRange
+            | Opcode::InvokeDirect
+            | Opcode::InvokeDirectRange => {
+                let method_id = instruction.index().unwrap_or_default() as MethodId;
+                let method = smali::method_reference(dex, method_id)?;
+                let args: Vec<Value> = regs.iter().map(|r| value(*r)).collect();
+                result = invoke_builder(&method, &args, &mut builders);
+                if let (Value::String(string), Some(Value::Builder(_))) = (&result, args.first()) {
+                    strings.push((*address, string.clone(), StringSource::Concatenation));
+                }
+            }
+            _ => {
+                // any other use of a builder can change it behind our back.
+                for register in &regs {
+                    if let Some(Value::Builder(builder)) = registers.get(register) {
+                        builders[*builder] = None;
+                    }
+                }
+                if instruction.opcode().index_type() != IndexType::Method {
+                    if let Some(register) = regs.first() {
+                        registers.remove(register);
+                        registers.remove(&register.wrapping_add(1));
+                    }
+                }
+            }
+        }
+    }
+    Ok(strings)
+}
+
+/// Applies a call of `method` (a method reference in smali syntax) with `args` to the
+/// builders, and returns the value of the call. A folded `toString()` is returned as
+/// `Value::String` only if the builder has more than one part, other strings are already
+/// reported as constants.
+fn invoke_builder(method: &str, args: &[Value], builders: &mut [Option<Vec<String>>]) -> Value {
+    let receiver = match args.first() {
+        Some(Value::Builder(builder)) => *builder,
+        _ => {
+            for arg in args {
+                if let Value::Builder(builder) = arg {
+                    builders[*builder] = None;
+                }
+            }
+            return Value::Unknown;
+        }
+    };
+    let name = method.split_once("->").map_or("", |(_, name)| name);
+    let param = name
+        .find('(')
+        .and_then(|start| {
+            let end = start + name[start..].find(')')?;
+            Some(&name[start + 1..end])
+        })
+        .unwrap_or_default();
+    let arg = args.get(1).cloned().unwrap_or(Value::Unknown);
+    let part = match (param, arg) {
+        ("Ljava/lang/String;", Value::String(string))
+        | ("Ljava/lang/CharSequence;", Value::String(string))
+        | ("Ljava/lang/Object;", Value::String(string)) => Some(string),
+        ("I", Value::Literal(literal))
+        | ("J", Value::Literal(literal))
+        | ("S", Value::Literal(literal))
+        | ("B", Value::Literal(literal)) => Some(literal.to_string()),
+        ("C", Value::Literal(literal)) => std::char::from_u32(literal as u32).map(String::from),
+        ("Z", Value::Literal(literal)) => Some((literal != 0).to_string()),
+        _ => None,
+    };
+    let parts = &mut builders[receiver];
+    if name.starts_with("<init>(") {
+        match param {
+            "" | "I" => {}
+            _ => *parts = part.map(|part| vec![part]),
+        }
+        Value::Unknown
+    } else if name.starts_with("append(") {
+        match (parts.as_mut(), part) {
+            (Some(parts), Some(part)) => parts.push(part),
+            _ => *parts = None,
+        }
+        Value::Builder(receiver)
+    } else if name == "toString()Ljava/lang/String;" {
+        match parts {
+            Some(parts) if parts.len() > 1 => Value::String(parts.concat()),
+            _ => Value::Unknown,
+        }
+    } else {
+        *parts = None;
+        Value::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{invoke_builder, string_references, StringSource, Value};
+
+    #[test]
+    fn test_invoke_builder() {
+        let sb = "Ljava/lang/StringBuilder;";
+        let mut builders = vec![Some(Vec::new())];
+        let builder = Value::Builder(0);
+        let string = |s: &str| Value::String(s.to_string());
+        let call = |builders: &mut Vec<_>, method: &str, args: &[Value]| {
+            invoke_builder(&format!("{}->{}", sb, method), args, builders)
+        };
+        let append = |param: &str| format!("append({}){}", param, sb);
+
+        call(
+            &mut builders,
+            "<init>(Ljava/lang/String;)V",
+            &[builder.clone(), string("http://")],
+        );
+        call(
+            &mut builders,
+            &append("Ljava/lang/String;"),
+            &[builder.clone(), string("host")],
+        );
+        call(
+            &mut builders,
+            &append("C"),
+            &[builder.clone(), Value::Literal(58)],
+        );
+        let result = call(
+            &mut builders,
+            &append("I"),
+            &[builder.clone(), Value::Literal(8080)],
+        );
+        assert!(matches!(result, Value::Builder(0)));
+        call(
+            &mut builders,
+            &append("Z"),
+            &[builder.clone(), Value::Literal(1)],
+        );
+        match call(
+            &mut builders,
+            "toString()Ljava/lang/String;",
+            std::slice::from_ref(&builder),
+        ) {
+            Value::String(value) => assert_eq!(value, "http://host:8080true"),
+            value => panic!("unexpected value: {:?}", value),
+        }
+
+        call(
+            &mut builders,
+            &append("Ljava/lang/String;"),
+            &[builder.clone(), Value::Unknown],
+        );
+        let result = call(
+            &mut builders,
+            "toString()Ljava/lang/String;",
+            std::slice::from_ref(&builder),
+        );
+        assert!(matches!(result, Value::Unknown));
+        assert_eq!(builders[0], None);
+
+        // a builder passed to another method may be changed by it.
+        let mut builders = vec![Some(vec!["a".to_string(), "b".to_string()])];
+        invoke_builder(
+            "LFoo;->bar(Ljava/lang/StringBuilder;)V",
+            &[Value::Unknown, builder.clone()],
+            &mut builders,
+        );
+        assert_eq!(builders[0], None);
+
+        // a malformed name, with the parameters closed before they are opened.
+        let mut builders = vec![Some(Vec::new())];
+        let result = call(&mut builders, "foo)(I", &[builder, Value::Literal(1)]);
+        assert!(matches!(result, Value::Unknown));
+        assert_eq!(builders[0], None);
+    }
+
+    #[test]
+    fn test_string_references() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let references = string_references(&dex).expect("failed to find strings");
+        let reference = references
+            .iter()
+            .find(|r| r.value() == "android.intent.action.MAIN")
+            .expect("string not found");
+        assert_eq!(reference.source(), StringSource::Constant);
+        let method = dex
+            .get_method_item(reference.method())
+            .expect("failed to load method");
+        assert!(dex.get_type(method.class_idx().into()).is_ok());
+
+        // an invoke-virtual without registers at the start of a method.
+        let code_off = dex
+            .classes()
+            .find_map(|class| {
+                class
+                    .unwrap()
+                    .methods()
+                    .find(|method| method.code().is_some_and(|code| code.insns().len() >= 3))
+                    .map(|method| method.code_off() as usize)
+            })
+            .expect("no method with code");
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let insns = code_off + 16;
+        for (index, unit) in [0x006eu16, 0, 0].iter().enumerate() {
+            bytes[insns + index * 2..insns + index * 2 + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        let checksum = adler32::adler32(&bytes[12..]).unwrap();
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+        let dex = crate::DexReader::from_vec(bytes).expect("failed to load dex");
+        assert!(string_references(&dex).is_ok());
+    }
+}
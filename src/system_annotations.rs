@@ -0,0 +1,233 @@
+//! Typed values of the system annotations which compilers attach to classes, methods and
+//! fields, as described
+//! [here](https://source.android.com/devices/tech/dalvik/dex-format#system-annotation).
+use getset::{CopyGetters, Getters};
+
+use crate::{
+    annotation::AnnotationItem, encoded_value::EncodedValue, int, jtype::Type,
+    method::MethodIdItem, string::DexString,
+};
+
+/// `dalvik.annotation.Signature`: the generic signature of a class, method or field.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[get = "pub"]
+pub struct Signature {
+    /// The signature, joined from the parts stored in the annotation.
+    value: String,
+}
+
+/// `dalvik.annotation.Throws`: the exceptions declared by a method.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[get = "pub"]
+pub struct Throws {
+    /// Types of the exceptions.
+    exceptions: Vec<Type>,
+}
+
+/// `dalvik.annotation.MemberClasses`: the member classes of a class.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[get = "pub"]
+pub struct MemberClasses {
+    /// Types of the member classes.
+    classes: Vec<Type>,
+}
+
+/// `dalvik.annotation.EnclosingClass`: the class a member class is declared in.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[get = "pub"]
+pub struct EnclosingClass {
+    /// Type of the enclosing class.
+    class: Type,
+}
+
+/// `dalvik.annotation.EnclosingMethod`: the method an anonymous or local class is declared in.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[get = "pub"]
+pub struct EnclosingMethod {
+    /// The enclosing method.
+    method: MethodIdItem,
+}
+
+/// `dalvik.annotation.InnerClass`: the name and flags of a nested class in the source code.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct InnerClass {
+    /// Name of the class, `None` for anonymous classes.
+    #[get = "pub"]
+    name: Option<DexString>,
+    /// Access flags of the class in the source code.
+    #[get_copy = "pub"]
+    access_flags: int,
+}
+
+/// `dalvik.annotation.SourceDebugExtension`: the SMAP of a class, like the ones emitted for
+/// Kotlin inline functions.
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[get = "pub"]
+pub struct SourceDebugExtension {
+    /// Contents of the extension.
+    value: DexString,
+}
+
+/// A system annotation, see `SystemAnnotation::from`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SystemAnnotation {
+    Signature(Signature),
+    Throws(Throws),
+    MemberClasses(MemberClasses),
+    EnclosingClass(EnclosingClass),
+    EnclosingMethod(EnclosingMethod),
+    InnerClass(InnerClass),
+    SourceDebugExtension(SourceDebugExtension),
+    /// Any other annotation, or a system annotation whose elements don't have the expected
+    /// types. Holds the type of the annotation.
+    Other(Type),
+}
+
+impl From<&AnnotationItem> for SystemAnnotation {
+    /// Converts `item` to the typed value of the system annotation it represents.
+    fn from(item: &AnnotationItem) -> Self {
+        parse(item).unwrap_or_else(|| SystemAnnotation::Other(item.jtype().clone()))
+    }
+}
+
+fn parse(item: &AnnotationItem) -> Option<SystemAnnotation> {
+    let element = |name: &str| item.find_element(name).map(|element| element.value());
+    let annotation = match item.jtype().type_descriptor().to_string().as_str() {
+        "Ldalvik/annotation/Signature;" => SystemAnnotation::Signature(Signature {
+            value: array(element("value")?, |value| match value {
+                EncodedValue::String(s) => Some(s.to_string()),
+                _ => None,
+            })?
+            .concat(),
+        }),
+        "Ldalvik/annotation/Throws;" => SystemAnnotation::Throws(Throws {
+            exceptions: array(element("value")?, as_type)?,
+        }),
+        "Ldalvik/annotation/MemberClasses;" => SystemAnnotation::MemberClasses(MemberClasses {
+            classes: array(element("value")?, as_type)?,
+        }),
+        "Ldalvik/annotation/EnclosingClass;" => SystemAnnotation::EnclosingClass(EnclosingClass {
+            class: as_type(element("value")?)?,
+        }),
+        "Ldalvik/annotation/EnclosingMethod;" => match element("value")? {
+            EncodedValue::Method(method) => SystemAnnotation::EnclosingMethod(EnclosingMethod {
+                method: method.clone(),
+            }),
+            _ => return None,
+        },
+        "Ldalvik/annotation/InnerClass;" => {
+            let name = match element("name")? {
+                EncodedValue::String(name) => Some(name.clone()),
+                EncodedValue::Null => None,
+                _ => return None,
+            };
+            match element("accessFlags")? {
+                EncodedValue::Int(access_flags) => SystemAnnotation::InnerClass(InnerClass {
+                    name,
+                    access_flags: *access_flags,
+                }),
+                _ => return None,
+            }
+        }
+        "Ldalvik/annotation/SourceDebugExtension;" => match element("value")? {
+            EncodedValue::String(value) => {
+                SystemAnnotation::SourceDebugExtension(SourceDebugExtension {
+                    value: value.clone(),
+                })
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(annotation)
+}
+
+fn as_type(value: &EncodedValue) -> Option<Type> {
+    match value {
+        EncodedValue::Type(jtype) => Some(jtype.clone()),
+        _ => None,
+    }
+}
+
+fn array<V>(value: &EncodedValue, f: impl Fn(&EncodedValue) -> Option<V>) -> Option<Vec<V>> {
+    match value {
+        EncodedValue::Array(values) => values.iter().map(f).collect(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SystemAnnotation;
+
+    #[test]
+    fn test_system_annotations() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let class = |name: &str| {
+            dex.find_class_by_name(name)
+                .unwrap()
+                .expect("class not found")
+        };
+        let outer = class("Lorg/adw/launcher/Launcher;");
+        let inner = class("Lorg/adw/launcher/Launcher$DesktopBinder;");
+        let anonymous = class("Lorg/adw/launcher/Launcher$1;");
+
+        let annotations: Vec<_> = outer
+            .annotations()
+            .iter()
+            .map(SystemAnnotation::from)
+            .collect();
+        assert!(annotations.iter().any(|annotation| match annotation {
+            SystemAnnotation::MemberClasses(members) => members.classes().contains(inner.jtype()),
+            _ => false,
+        }));
+
+        let annotations: Vec<_> = inner
+            .annotations()
+            .iter()
+            .map(SystemAnnotation::from)
+            .collect();
+        assert!(annotations.iter().any(|annotation| match annotation {
+            SystemAnnotation::EnclosingClass(enclosing) => enclosing.class() == outer.jtype(),
+            _ => false,
+        }));
+        assert!(annotations.iter().any(|annotation| match annotation {
+            SystemAnnotation::InnerClass(inner_class) => inner_class
+                .name()
+                .as_ref()
+                .is_some_and(|name| name == "DesktopBinder"),
+            _ => false,
+        }));
+
+        let annotations: Vec<_> = anonymous
+            .annotations()
+            .iter()
+            .map(SystemAnnotation::from)
+            .collect();
+        assert!(annotations.iter().any(|annotation| match annotation {
+            SystemAnnotation::EnclosingMethod(enclosing) =>
+                u32::from(enclosing.method().class_idx()) == outer.id(),
+            _ => false,
+        }));
+        assert!(annotations.iter().any(|annotation| match annotation {
+            SystemAnnotation::InnerClass(inner_class) => inner_class.name().is_none(),
+            _ => false,
+        }));
+
+        let serializer = class("Lmobi/intuitit/android/internal/utils/FastXmlSerializer;");
+        let method = serializer
+            .method("flushBytes", &[])
+            .expect("method not found");
+        let annotations: Vec<_> = method
+            .annotations()
+            .iter()
+            .map(SystemAnnotation::from)
+            .collect();
+        match &annotations[..] {
+            [SystemAnnotation::Throws(throws)] => {
+                assert_eq!(throws.exceptions()[0], "Ljava/io/IOException;")
+            }
+            annotations => panic!("unexpected annotations: {:?}", annotations),
+        }
+    }
+}
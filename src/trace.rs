@@ -0,0 +1,144 @@
+//! Spans around the phases of loading a `Dex`: reading the header and the map list, and
+//! loading classes, code items and annotations. With the `tracing` feature they are
+//! `tracing` spans with target `dex` and the sizes of what was read as fields, for
+//! profiling the loading of large files. Without it, the macros expand to nothing.
+
+/// Enters a span named `$name` with the fields, until the returned guard is dropped. The
+/// counters after `;` are recorded later with `record!`.
+#[cfg(feature = "tracing")]
+macro_rules! phase_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(; $($counter:ident),+)?) => {
+        tracing::debug_span!(
+            target: "dex",
+            $name
+            $(, $field = $value)*
+            $($(, $counter = tracing::field::Empty)+)?
+        )
+        .entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! phase_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(; $($counter:ident),+)?) => {{
+        $(let _ = &$value;)*
+        $crate::trace::NoSpan
+    }};
+}
+
+/// Records the counters of a span entered by `phase_span!`.
+#[cfg(feature = "tracing")]
+macro_rules! record {
+    ($span:expr, $($counter:ident = $value:expr),+) => {
+        $($span.record(stringify!($counter), $value);)+
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! record {
+    ($span:expr, $($counter:ident = $value:expr),+) => {{
+        let _ = &$span;
+        $(let _ = &$value;)+
+    }};
+}
+
+/// The guard of a span when the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+// ends the phase when dropped, like the guard of a span.
+#[cfg(not(feature = "tracing"))]
+impl Drop for NoSpan {
+    fn drop(&mut self) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    };
+
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    /// Collects the names of the spans and their counters.
+    #[derive(Default)]
+    struct Spans {
+        next_id: AtomicU64,
+        names: Mutex<Vec<&'static str>>,
+        counters: Mutex<Vec<(&'static str, u64)>>,
+    }
+
+    impl Visit for &Spans {
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            self.counters.lock().unwrap().push((field.name(), value));
+        }
+
+        fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+    }
+
+    /// The subscriber sharing the `Spans` with the test.
+    struct Collector(Arc<Spans>);
+
+    impl Subscriber for Collector {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.target() == "dex"
+        }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            self.0.names.lock().unwrap().push(span.metadata().name());
+            span::Id::from_u64(self.0.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &span::Id, values: &span::Record<'_>) {
+            values.record(&mut &*self.0);
+        }
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn test_phase_spans() {
+        let spans = Arc::new(Spans::default());
+        tracing::subscriber::with_default(Collector(spans.clone()), || {
+            let dex = crate::DexReader::from_file("resources/classes.dex").unwrap();
+            for class in dex.classes() {
+                let class = class.unwrap();
+                class.annotations();
+                for method in class.methods() {
+                    method.code();
+                }
+            }
+        });
+        let names = spans.names.lock().unwrap();
+        let expected = [
+            "header",
+            "map_list",
+            "class",
+            "code_item",
+            "annotations",
+            "annotation_set",
+        ];
+        for name in expected {
+            assert!(names.contains(&name), "no {} span in {:?}", name, names);
+        }
+        let counters = spans.counters.lock().unwrap();
+        for name in ["items", "fields", "methods", "insns", "annotations"] {
+            assert!(
+                counters.iter().any(|(counter, _)| *counter == name),
+                "{} not recorded in {:?}",
+                name,
+                counters
+            );
+        }
+    }
+}
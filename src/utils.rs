@@ -1,17 +1,24 @@
+use std::{
+    fmt,
+    fmt::Write,
+    sync::{Arc, OnceLock},
+};
+
 use crate::{
     annotation::AnnotationSetItem,
     encoded_item::{EncodedItem, EncodedItemArray},
     encoded_value::EncodedValue,
     error::Error,
     jtype::{Type, TypeId},
-    ushort,
+    uint, ulong, ushort,
 };
 
 macro_rules! try_gread_vec_with {
     ($source:ident,$offset:ident,$cap:expr,$ctx:expr) => {{
         let cap = $cap as usize;
         let ctx = $ctx;
-        let mut vec = Vec::with_capacity(cap);
+        // every item takes a byte at least, a bogus count can't allocate more than that.
+        let mut vec = Vec::with_capacity(cap.min($source.len()));
         // NOTE: gread_inout_with doesn't work when de-serializing encoded array
         // so using an explicit loop here.
         for _ in 0..cap {
@@ -63,13 +70,39 @@ where
         .collect()
 }
 
+/// Offset of an item which is `0` when the item is absent, like the code of an abstract
+/// method or the annotations of a class without any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OptionalOffset(pub(crate) uint);
+
+impl OptionalOffset {
+    /// `None` if the item is absent, the offset otherwise. Fails if the offset isn't in the
+    /// data section, `item` names the item in the error.
+    pub(crate) fn resolve<S>(self, dex: &super::Dex<S>, item: &str) -> super::Result<Option<usize>>
+    where
+        S: AsRef<[u8]>,
+    {
+        let OptionalOffset(offset) = self;
+        if offset == 0 {
+            return Ok(None);
+        }
+        if !dex.is_offset_in_data_section(offset) {
+            return Err(Error::BadOffset(
+                offset as usize,
+                format!("{} offset not in data section", item),
+            ));
+        }
+        Ok(Some(offset as usize))
+    }
+}
+
 macro_rules! gen_is_flag_set {
     ($name: ident, $flag: ident) => {
         /// Returns `true` if the access flag is set
         pub fn $name(&self) -> bool {
             self.access_flags().contains(AccessFlags::$flag)
         }
-    }
+    };
 }
 
 pub(crate) fn get_signature(annotations: &AnnotationSetItem) -> super::Result<Option<String>> {
@@ -106,3 +139,145 @@ pub(crate) fn get_signature(annotations: &AnnotationSetItem) -> super::Result<Op
         })
         .unwrap_or_else(|| Ok(None))
 }
+
+pub(crate) fn get_throws(annotations: &AnnotationSetItem) -> super::Result<Vec<Type>> {
+    let item = match annotations
+        .iter()
+        .find(|item| item.jtype() == "Ldalvik/annotation/Throws;")
+    {
+        Some(item) => item,
+        None => return Ok(Vec::new()),
+    };
+    let element = item.annotation().find_element("value").ok_or_else(|| {
+        Error::MalFormed("Expected element with name value, but not found".to_string())
+    })?;
+    match *element.value() {
+        EncodedValue::Array(ref v) => v
+            .iter()
+            .map(|t| {
+                if let EncodedValue::Type(ref t) = t {
+                    Ok(t.clone())
+                } else {
+                    Err(Error::MalFormed(format!(
+                        "Expected type element in throws, found: {:?}",
+                        t
+                    )))
+                }
+            })
+            .collect(),
+        ref e => Err(Error::MalFormed(format!("Expected array, found: {:?}", e))),
+    }
+}
+
+/// Quotes `value` as a JSON string, which DOT accepts too.
+pub(crate) fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Parses the item at an offset of a `Dex`, reading from a view of the `Dex` which borrows
+/// its bytes, see `Dex::borrowed`.
+pub(crate) type Loader<'a, T> = Arc<dyn Fn(ulong) -> super::Result<T> + Send + Sync + 'a>;
+
+/// A value of a `Dex` which is parsed on first access.
+pub(crate) struct Lazy<'a, T> {
+    offset: ulong,
+    value: OnceLock<T>,
+    load: Option<Loader<'a, T>>,
+}
+
+impl<'a, T> Lazy<'a, T> {
+    /// Value of the item at `offset`, parsed by `load` on first access.
+    pub(crate) fn new(offset: ulong, load: Loader<'a, T>) -> Self {
+        Self {
+            offset,
+            value: OnceLock::new(),
+            load: Some(load),
+        }
+    }
+
+    /// An already parsed value.
+    pub(crate) fn loaded(value: T) -> Self {
+        Self {
+            offset: 0,
+            value: OnceLock::from(value),
+            load: None,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> ulong {
+        self.offset
+    }
+
+    /// Parses the value if needed. Errors aren't cached, the next call tries again.
+    pub(crate) fn get(&self) -> super::Result<&T> {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        let load = self.load.as_ref().expect("lazy value without loader");
+        let value = load(self.offset)?;
+        Ok(self.value.get_or_init(|| value))
+    }
+
+    /// The value, parsed if needed, without the loader which borrows the `Dex`.
+    pub(crate) fn into_loaded(self) -> super::Result<Lazy<'static, T>> {
+        self.into_loaded_with(|value| value)
+    }
+
+    /// The value, parsed if needed and converted by `convert`, without the loader which
+    /// borrows the `Dex`. For values which borrow the `Dex` themselves.
+    pub(crate) fn into_loaded_with<U, F>(self, convert: F) -> super::Result<Lazy<'static, U>>
+    where
+        F: FnOnce(T) -> U,
+    {
+        let value = match self.value.into_inner() {
+            Some(value) => value,
+            None => (self.load.expect("lazy value without loader"))(self.offset)?,
+        };
+        Ok(Lazy {
+            offset: self.offset,
+            value: OnceLock::from(convert(value)),
+            load: None,
+        })
+    }
+}
+
+/// Values which have an empty instance, read in place of malformed ones.
+pub(crate) trait Empty: 'static {
+    const EMPTY: &'static Self;
+}
+
+impl<T: Empty> Lazy<'_, T> {
+    /// Parses the value if needed. A malformed value reads as the empty one, which isn't
+    /// cached so `get` still returns the error.
+    pub(crate) fn get_or_empty(&self) -> &T {
+        match self.get() {
+            Ok(value) => value,
+            Err(e) => {
+                debug!(target: "lazy", "failed to load item at {}: {:?}", self.offset, e);
+                T::EMPTY
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Lazy<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value.get() {
+            Some(value) => value.fmt(f),
+            None => write!(f, "<not loaded: {:#x}>", self.offset),
+        }
+    }
+}
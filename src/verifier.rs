@@ -0,0 +1,343 @@
+//! Checks of a `Dex` against rules of the format which the parser doesn't enforce, for tools
+//! which want anomalies of packed or obfuscated files flagged rather than silently accepted.
+//! The structure of the file is checked by `verify_structure`, against the rules of the
+//! `conformance` module.
+use std::convert::TryFrom;
+
+use getset::{CopyGetters, Getters};
+use scroll::{Pread, Uleb128};
+
+use crate::{
+    cfg::Cfg,
+    conformance::{self, Finding, Rule},
+    instruction::{Instruction, Opcode, Quickening},
+    method::MethodId,
+    uint, Dex, ItemType, Result,
+};
+
+/// What is wrong with an instruction, see `Violation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// The opcode was introduced in a later version of the format than the one declared in
+    /// the header, like `invoke-custom` in a version `035` file.
+    AnachronisticOpcode {
+        /// Version in which the opcode was introduced.
+        required: uint,
+        /// Version declared in the header.
+        declared: uint,
+    },
+    /// The instruction can't be decoded, the rest of the code isn't checked.
+    MalformedInstruction(String),
+    /// The instruction starts a block which can't be reached from the entry of the method,
+    /// like code after an unconditional `throw` or `return`. See `Cfg::unreachable_blocks`.
+    UnreachableCode {
+        /// Offset in code units after the last instruction of the block.
+        end: usize,
+    },
+}
+
+/// An instruction which breaks the rules of the format.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct Violation {
+    /// The method whose code contains the instruction.
+    #[get_copy = "pub"]
+    method: MethodId,
+    /// Address of the instruction in code units.
+    #[get_copy = "pub"]
+    address: usize,
+    /// Opcode of the instruction, `None` if it can't be decoded.
+    #[get_copy = "pub"]
+    opcode: Option<Opcode>,
+    /// What is wrong with the instruction.
+    #[get = "pub"]
+    kind: ViolationKind,
+}
+
+/// Runs the structural checks of the Android verifier on `dex`, like the order and the
+/// bounds of the sections, the sort order of the ids and the offsets into the data section.
+/// Unlike `conformance::check`, every offset of an item to the data section is checked.
+pub fn verify_structure<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<Vec<Finding>> {
+    let mut findings = conformance::check(dex)?.findings().clone();
+    let mut check_data_offset = |item_type, offset: uint, field_offset: uint| {
+        if offset != 0 && !dex.is_offset_in_data_section(offset) {
+            findings.push(Finding::new(
+                Rule::DataSection(item_type),
+                Some(field_offset),
+                format!(
+                    "{:?} offset {:#x} isn't in the data section",
+                    item_type, offset
+                ),
+            ));
+        }
+    };
+    let source = dex.source.as_ref();
+    let endian = dex.get_endian();
+    for index in 0..dex.header().string_ids_size() {
+        let field_offset = dex.inner.strings_offset() + index * 4;
+        let string_data_off = source.pread_with(field_offset as usize, endian)?;
+        check_data_offset(ItemType::StringDataItem, string_data_off, field_offset);
+    }
+    for (index, proto) in dex.proto_ids().enumerate() {
+        let field_offset = dex.inner.proto_ids_offset() + index as uint * 12 + 8;
+        check_data_offset(ItemType::TypeList, proto?.params_off(), field_offset);
+    }
+    for (index, class_def) in dex.class_defs().enumerate() {
+        let class_def = class_def?;
+        let class_def_offset = dex.inner.class_defs_offset() + index as uint * 32;
+        for (item_type, offset, field) in [
+            (ItemType::TypeList, class_def.interfaces_off(), 12),
+            (
+                ItemType::AnnotationsDirectoryItem,
+                class_def.annotations_off(),
+                20,
+            ),
+            (ItemType::ClassDataItem, class_def.class_data_off(), 24),
+            (
+                ItemType::EncodedArrayItem,
+                class_def.static_values_off(),
+                28,
+            ),
+        ] {
+            check_data_offset(item_type, offset, class_def_offset + field);
+        }
+        if !dex.is_offset_in_data_section(class_def.class_data_off()) {
+            continue;
+        }
+        // the code_off of each method is reported at its position in the class data.
+        let offset = &mut (class_def.class_data_off() as usize);
+        let mut sizes = [0; 4];
+        for size in &mut sizes {
+            *size = Uleb128::read(source, offset)?;
+        }
+        for _ in 0..sizes[0].saturating_add(sizes[1]) {
+            Uleb128::read(source, offset)?;
+            Uleb128::read(source, offset)?;
+        }
+        for _ in 0..sizes[2].saturating_add(sizes[3]) {
+            Uleb128::read(source, offset)?;
+            Uleb128::read(source, offset)?;
+            let field_offset = *offset as uint;
+            let code_off = uint::try_from(Uleb128::read(source, offset)?).unwrap_or(uint::MAX);
+            check_data_offset(ItemType::CodeItem, code_off, field_offset);
+        }
+    }
+    debug!(target: "verifier", "found {} structural violations", findings.len());
+    Ok(findings)
+}
+
+/// Checks the instructions of all the methods of `dex` against the version of the format
+/// declared in its header. Opcodes aren't checked if the version can't be read from the
+/// magic value.
+pub fn verify_opcodes<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<Vec<Violation>> {
+    let declared = dex.header().version();
+    let mut violations = Vec::new();
+    for (method, code) in dex.method_code_items()? {
+        let mut instructions = code.instructions();
+        loop {
+            let address = instructions.offset();
+            let instruction = match instructions.next() {
+                Some(Ok(instruction)) => instruction,
+                Some(Err(e)) => {
+                    violations.push(Violation {
+                        method,
+                        address,
+                        opcode: None,
+                        kind: ViolationKind::MalformedInstruction(e.to_string()),
+                    });
+                    break;
+                }
+                None => break,
+            };
+            let opcode = instruction.opcode();
+            if let (Some(declared), Some(required)) = (declared, opcode.min_dex_version()) {
+                if required > declared {
+                    violations.push(Violation {
+                        method,
+                        address,
+                        opcode: Some(opcode),
+                        kind: ViolationKind::AnachronisticOpcode { required, declared },
+                    });
+                }
+            }
+        }
+    }
+    debug!(target: "verifier", "found {} violations", violations.len());
+    Ok(violations)
+}
+
+/// Finds the code of the methods of `dex` which can't be reached from the entry of the
+/// method, a violation is reported for each unreachable block. Obfuscators insert junk code
+/// like this, compilers don't emit it.
+pub fn verify_reachability<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<Vec<Violation>> {
+    let mut violations = Vec::new();
+    for (method, code) in dex.method_code_items()? {
+        let cfg = match Cfg::new(&code) {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                violations.push(Violation {
+                    method,
+                    address: 0,
+                    opcode: None,
+                    kind: ViolationKind::MalformedInstruction(e.to_string()),
+                });
+                continue;
+            }
+        };
+        for block in cfg.unreachable_blocks() {
+            let (opcode, kind) =
+                match Instruction::decode(&code.insns()[block.start()..], Quickening::None) {
+                    Ok(instruction) => (
+                        Some(instruction.opcode()),
+                        ViolationKind::UnreachableCode { end: block.end() },
+                    ),
+                    Err(e) => (None, ViolationKind::MalformedInstruction(e.to_string())),
+                };
+            violations.push(Violation {
+                method,
+                address: block.start(),
+                opcode,
+                kind,
+            });
+        }
+    }
+    debug!(target: "verifier", "found {} unreachable blocks", violations.len());
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_opcodes, verify_reachability, verify_structure, Violation, ViolationKind};
+    use crate::{conformance::Rule, instruction::Opcode, method::MethodId, ushort, Dex, ItemType};
+
+    /// Replaces the code of a constructor which only calls its super constructor, the
+    /// `invoke-direct` and `return-void` of its 4 code units, with the result of `patch`.
+    fn patch_constructor(patch: impl Fn(&[ushort]) -> [ushort; 4]) -> (MethodId, Dex<Vec<u8>>) {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let (method, code_off, insns) = dex
+            .classes()
+            .find_map(|class| {
+                class
+                    .unwrap()
+                    .methods()
+                    .find(|method| {
+                        method.code().is_some_and(|code| {
+                            code.tries().is_empty()
+                                && code.insns().len() == 4
+                                && code.insns()[0] & 0xff == 0x70
+                                && code.insns()[3] == 0x0e
+                        })
+                    })
+                    .map(|method| {
+                        let insns = patch(method.code().unwrap().insns());
+                        (method.id(), method.code_off() as usize, insns)
+                    })
+            })
+            .expect("no constructor found");
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let start = code_off + 16;
+        for (index, unit) in insns.iter().enumerate() {
+            bytes[start + index * 2..start + index * 2 + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        let checksum = adler32::adler32(&bytes[12..]).unwrap();
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+        (
+            method,
+            crate::DexReader::from_vec(bytes).expect("failed to load dex"),
+        )
+    }
+
+    #[test]
+    fn test_verify_structure() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        assert_eq!(verify_structure(&dex).unwrap(), &[]);
+
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let class_defs_off = dex.header().class_defs_off() as usize;
+        // static_values_off of the first class points into the header.
+        bytes[class_defs_off + 28..class_defs_off + 32].copy_from_slice(&0x10u32.to_le_bytes());
+        // the map list entry of the type ids section points to the proto ids section.
+        let map_off = dex.header().map_off() as usize;
+        let entry = (0..dex.map_list().items().len())
+            .map(|index| map_off + 4 + index * 12)
+            .find(|entry| bytes[*entry] == ItemType::TypeIdItem as u8)
+            .unwrap();
+        let proto_ids_off = dex.header().proto_ids_off();
+        bytes[entry + 8..entry + 12].copy_from_slice(&proto_ids_off.to_le_bytes());
+        let checksum = adler32::adler32(&bytes[12..]).unwrap();
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+
+        let dex = crate::DexReader::from_vec(bytes).expect("failed to load dex");
+        let findings = verify_structure(&dex).unwrap();
+        let rules: Vec<_> = findings.iter().map(|finding| finding.rule()).collect();
+        assert!(rules.contains(&Rule::HeaderOffsets(ItemType::TypeIdItem)));
+        assert!(rules.contains(&Rule::MapListOrder));
+        let finding = findings
+            .iter()
+            .find(|finding| finding.rule() == Rule::DataSection(ItemType::EncodedArrayItem))
+            .expect("static values offset not reported");
+        assert_eq!(finding.offset(), Some(class_defs_off + 28));
+
+        // the code_off of a method points into the header, in as many bytes as before.
+        let mut bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let (position, size) = crate::edit::offset_fields(&dex)
+            .unwrap()
+            .into_iter()
+            .find_map(|(position, field)| match field {
+                crate::edit::OffsetField::Uleb128 { size } => Some((position, size)),
+                _ => None,
+            })
+            .unwrap();
+        bytes[position] = 0x90;
+        bytes[position + 1..position + size - 1].fill(0x80);
+        bytes[position + size - 1] = 0;
+        let checksum = adler32::adler32(&bytes[12..]).unwrap();
+        bytes[8..12].copy_from_slice(&checksum.to_le_bytes());
+        let dex = crate::DexReader::from_vec(bytes).expect("failed to load dex");
+        let findings = verify_structure(&dex).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule(), Rule::DataSection(ItemType::CodeItem));
+        assert_eq!(findings[0].offset(), Some(position));
+    }
+
+    #[test]
+    fn test_verify_opcodes() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        assert_eq!(dex.header().version(), Some(35));
+        assert!(verify_opcodes(&dex).unwrap().is_empty());
+
+        // an invoke-custom in place of the invoke-direct, with the same operands.
+        let (method, dex) =
+            patch_constructor(|insns| [insns[0] & 0xff00 | 0xfc, insns[1], insns[2], insns[3]]);
+        let violations = verify_opcodes(&dex).unwrap();
+        assert_eq!(
+            violations,
+            vec![Violation {
+                method,
+                address: 0,
+                opcode: Some(Opcode::InvokeCustom),
+                kind: ViolationKind::AnachronisticOpcode {
+                    required: 38,
+                    declared: 35
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_verify_reachability() {
+        let dex = crate::DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        assert_eq!(verify_reachability(&dex).unwrap(), vec![]);
+
+        // a return-void followed by a nop, a nop and the original return-void.
+        let (method, dex) = patch_constructor(|_| [0x0e, 0x00, 0x00, 0x0e]);
+        assert_eq!(
+            verify_reachability(&dex).unwrap(),
+            vec![Violation {
+                method,
+                address: 1,
+                opcode: Some(Opcode::Nop),
+                kind: ViolationKind::UnreachableCode { end: 4 },
+            }]
+        );
+    }
+}
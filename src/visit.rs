@@ -0,0 +1,331 @@
+//! Walking the classes of a `Dex` and their members, code and annotations with a `Visitor`.
+//! The classes are loaded one at a time, and the code and the annotations are only decoded
+//! if the visitor is interested in them, so an analysis only implements the visits it needs
+//! instead of its own nested loops over the classes.
+use crate::{
+    annotation::AnnotationItem, class::Class, field::Field, instruction::Instruction,
+    method::Method, Dex, Result,
+};
+
+bitflags! {
+    /// Items a `Visitor` needs, besides the classes and their members which are always
+    /// visited. The items it doesn't need aren't decoded.
+    pub struct Interests: u8 {
+        /// The instructions of the methods.
+        const CODE = 0x1;
+        /// The annotations of the classes, fields, methods and parameters.
+        const ANNOTATIONS = 0x2;
+    }
+}
+
+/// What the walk does after a visit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Visits the items of the visited one, then the next ones.
+    Continue,
+    /// Skips the items of the visited one, the members and annotations of a class or the
+    /// annotations and code of a member. After an instruction or an annotation, skips the
+    /// next ones of the method or of the annotated item.
+    Skip,
+    /// Ends the walk.
+    Stop,
+}
+
+/// The item an annotation is on, borrowed for `'a` from a `Dex` borrowed for `'d`.
+#[derive(Debug, Clone, Copy)]
+pub enum AnnotationTarget<'a, 'd> {
+    /// A class.
+    Class(&'a Class<'d>),
+    /// A field.
+    Field(&'a Field<'d>),
+    /// A method.
+    Method(&'a Method<'d>),
+    /// A parameter of a method.
+    Parameter {
+        /// The method.
+        method: &'a Method<'d>,
+        /// Index of the parameter, without `this`.
+        index: usize,
+    },
+}
+
+/// Callbacks of `walk`, which all continue the walk by default. The errors they return end
+/// the walk and are returned by `walk`.
+pub trait Visitor {
+    /// What the walk decodes for this visitor, all the items by default.
+    fn interests(&self) -> Interests {
+        Interests::all()
+    }
+
+    /// Visits a class, before its annotations and members.
+    fn visit_class(&mut self, _class: &Class) -> Result<Flow> {
+        Ok(Flow::Continue)
+    }
+
+    /// Visits a field of `class`, before its annotations.
+    fn visit_field(&mut self, _class: &Class, _field: &Field) -> Result<Flow> {
+        Ok(Flow::Continue)
+    }
+
+    /// Visits a method of `class`, before its annotations and its code.
+    fn visit_method(&mut self, _class: &Class, _method: &Method) -> Result<Flow> {
+        Ok(Flow::Continue)
+    }
+
+    /// Visits the instruction at `address`, in code units, of `method`.
+    fn visit_instruction(
+        &mut self,
+        _method: &Method,
+        _address: usize,
+        _instruction: &Instruction<'_>,
+    ) -> Result<Flow> {
+        Ok(Flow::Continue)
+    }
+
+    /// Visits an annotation of `target`.
+    fn visit_annotation(
+        &mut self,
+        _target: AnnotationTarget<'_, '_>,
+        _annotation: &AnnotationItem,
+    ) -> Result<Flow> {
+        Ok(Flow::Continue)
+    }
+}
+
+/// Walks the classes of `dex` in the order of their definitions, with the fields before
+/// the methods. Malformed code or annotations end the walk with their error.
+pub fn walk<T, V>(dex: &Dex<T>, visitor: &mut V) -> Result<()>
+where
+    T: AsRef<[u8]>,
+    V: Visitor + ?Sized,
+{
+    let interests = visitor.interests();
+    for class in dex.classes() {
+        if walk_class(&class?, visitor, interests)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Visits `class` and its annotations and members. Returns whether the walk ends.
+fn walk_class<V: Visitor + ?Sized>(
+    class: &Class,
+    visitor: &mut V,
+    interests: Interests,
+) -> Result<bool> {
+    match visitor.visit_class(class)? {
+        Flow::Continue => {}
+        Flow::Skip => return Ok(false),
+        Flow::Stop => return Ok(true),
+    }
+    if interests.contains(Interests::ANNOTATIONS) {
+        let annotations = class.try_annotations()?;
+        if walk_annotations(visitor, AnnotationTarget::Class(class), annotations)? {
+            return Ok(true);
+        }
+    }
+    for field in class.fields() {
+        match visitor.visit_field(class, field)? {
+            Flow::Continue => {}
+            Flow::Skip => continue,
+            Flow::Stop => return Ok(true),
+        }
+        if interests.contains(Interests::ANNOTATIONS) {
+            let annotations = field.try_annotations()?;
+            if walk_annotations(visitor, AnnotationTarget::Field(field), annotations)? {
+                return Ok(true);
+            }
+        }
+    }
+    for method in class.methods() {
+        match visitor.visit_method(class, method)? {
+            Flow::Continue => {}
+            Flow::Skip => continue,
+            Flow::Stop => return Ok(true),
+        }
+        if walk_method(method, visitor, interests)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Visits the annotations and the instructions of `method`. Returns whether the walk ends.
+fn walk_method<V: Visitor + ?Sized>(
+    method: &Method,
+    visitor: &mut V,
+    interests: Interests,
+) -> Result<bool> {
+    if interests.contains(Interests::ANNOTATIONS) {
+        let annotations = method.try_annotations()?;
+        if walk_annotations(visitor, AnnotationTarget::Method(method), annotations)? {
+            return Ok(true);
+        }
+        for (index, annotations) in method.try_param_annotations()?.iter().enumerate() {
+            let target = AnnotationTarget::Parameter { method, index };
+            if walk_annotations(visitor, target, annotations)? {
+                return Ok(true);
+            }
+        }
+    }
+    if !interests.contains(Interests::CODE) {
+        return Ok(false);
+    }
+    let code = match method.try_code()? {
+        Some(code) => code,
+        None => return Ok(false),
+    };
+    for instruction in code.instructions().with_offsets() {
+        let (address, instruction) = instruction?;
+        match visitor.visit_instruction(method, address, &instruction)? {
+            Flow::Continue => {}
+            Flow::Skip => break,
+            Flow::Stop => return Ok(true),
+        }
+    }
+    Ok(false)
+}
+
+/// Visits the annotations of `target`. Returns whether the walk ends.
+fn walk_annotations<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    target: AnnotationTarget<'_, '_>,
+    annotations: &[AnnotationItem],
+) -> Result<bool> {
+    for annotation in annotations {
+        match visitor.visit_annotation(target, annotation)? {
+            Flow::Continue => {}
+            Flow::Skip => break,
+            Flow::Stop => return Ok(true),
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnnotationTarget, Flow, Interests, Visitor};
+    use crate::{
+        annotation::AnnotationItem, class::Class, field::Field, instruction::Instruction,
+        method::Method, DexReader, Result,
+    };
+
+    #[derive(Default)]
+    struct Counter {
+        interests: Option<Interests>,
+        class_flow: Option<Flow>,
+        classes: usize,
+        fields: usize,
+        methods: usize,
+        instructions: usize,
+        annotations: usize,
+        parameter_annotations: usize,
+    }
+
+    impl Visitor for Counter {
+        fn interests(&self) -> Interests {
+            self.interests.unwrap_or_else(Interests::all)
+        }
+
+        fn visit_class(&mut self, _class: &Class) -> Result<Flow> {
+            self.classes += 1;
+            Ok(self.class_flow.unwrap_or(Flow::Continue))
+        }
+
+        fn visit_field(&mut self, _class: &Class, _field: &Field) -> Result<Flow> {
+            self.fields += 1;
+            Ok(Flow::Continue)
+        }
+
+        fn visit_method(&mut self, _class: &Class, _method: &Method) -> Result<Flow> {
+            self.methods += 1;
+            Ok(Flow::Continue)
+        }
+
+        fn visit_instruction(
+            &mut self,
+            _method: &Method,
+            _address: usize,
+            _instruction: &Instruction<'_>,
+        ) -> Result<Flow> {
+            self.instructions += 1;
+            Ok(Flow::Continue)
+        }
+
+        fn visit_annotation(
+            &mut self,
+            target: AnnotationTarget<'_, '_>,
+            _annotation: &AnnotationItem,
+        ) -> Result<Flow> {
+            self.annotations += 1;
+            if let AnnotationTarget::Parameter { .. } = target {
+                self.parameter_annotations += 1;
+            }
+            Ok(Flow::Continue)
+        }
+    }
+
+    #[test]
+    fn test_walk() {
+        let dex = DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let (mut classes, mut fields, mut methods, mut instructions) = (0, 0, 0, 0);
+        let (mut annotations, mut parameter_annotations) = (0, 0);
+        for class in dex.classes() {
+            let class = class.unwrap();
+            classes += 1;
+            annotations += class.annotations().len();
+            for field in class.fields() {
+                fields += 1;
+                annotations += field.annotations().len();
+            }
+            for method in class.methods() {
+                methods += 1;
+                annotations += method.annotations().len();
+                for set in method.param_annotations().iter() {
+                    parameter_annotations += set.len();
+                }
+                if let Some(code) = method.code() {
+                    instructions += code.instructions().count();
+                }
+            }
+        }
+        annotations += parameter_annotations;
+        assert!(instructions > 0 && annotations > 0);
+
+        let mut counter = Counter::default();
+        dex.walk(&mut counter).unwrap();
+        assert_eq!(
+            (counter.classes, counter.fields, counter.methods),
+            (classes, fields, methods)
+        );
+        assert_eq!(counter.instructions, instructions);
+        assert_eq!(counter.annotations, annotations);
+        assert_eq!(counter.parameter_annotations, parameter_annotations);
+
+        let mut counter = Counter {
+            interests: Some(Interests::empty()),
+            ..Counter::default()
+        };
+        dex.walk(&mut counter).unwrap();
+        assert_eq!((counter.methods, counter.instructions), (methods, 0));
+        assert_eq!(counter.annotations, 0);
+
+        let mut counter = Counter {
+            class_flow: Some(Flow::Skip),
+            ..Counter::default()
+        };
+        dex.walk(&mut counter).unwrap();
+        assert_eq!((counter.classes, counter.methods), (classes, 0));
+
+        let mut counter = Counter {
+            class_flow: Some(Flow::Stop),
+            ..Counter::default()
+        };
+        dex.walk(&mut counter).unwrap();
+        assert_eq!(
+            (counter.classes, counter.fields, counter.methods),
+            (1, 0, 0)
+        );
+    }
+}
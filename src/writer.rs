@@ -0,0 +1,329 @@
+//! Serialization of a `Dex` back to the bytes of a dex file.
+//!
+//! The header, the ids sections and the map list are written from the parsed items, one
+//! section after the other from the end of the header, and the checksum and the signature
+//! are computed from the written bytes. The items of the data section hold offsets to each
+//! other which aren't relocated, so the data section is copied as is and keeps its offset.
+//! The ids are written in the order of their ids, which is the sorted order in a valid file.
+use scroll::{Endian, Pread, Pwrite};
+use sha1::{Digest, Sha1};
+
+use crate::{error::Error, uint, ushort, Dex, ItemType, Result};
+
+/// Size of the header of the versions before `041`.
+const HEADER_SIZE: uint = 0x70;
+
+/// Size of the header of a version `041` file, which has the container fields.
+const CONTAINER_HEADER_SIZE: uint = 0x78;
+
+/// The ids sections, in the order they are written.
+pub(crate) const IDS_SECTIONS: [ItemType; 8] = [
+    ItemType::StringIdItem,
+    ItemType::TypeIdItem,
+    ItemType::ProtoIdItem,
+    ItemType::FieldIdItem,
+    ItemType::MethodIdItem,
+    ItemType::ClassDefItem,
+    ItemType::CallSiteIdItem,
+    ItemType::MethodHandleItem,
+];
+
+/// Offsets of the ids sections in the written file, in the order of `IDS_SECTIONS`.
+struct Layout {
+    offsets: [uint; 8],
+    counts: [uint; 8],
+}
+
+impl Layout {
+    /// Offset of the section of `item_type`, 0 if it is empty.
+    fn offset(&self, item_type: ItemType) -> uint {
+        self.index(item_type).map_or(0, |index| self.offsets[index])
+    }
+
+    fn count(&self, item_type: ItemType) -> uint {
+        self.index(item_type).map_or(0, |index| self.counts[index])
+    }
+
+    fn index(&self, item_type: ItemType) -> Option<usize> {
+        IDS_SECTIONS
+            .iter()
+            .position(|section| *section == item_type)
+    }
+}
+
+/// Writes a `Dex` to the bytes of a dex file. Compact dex files and the dex files of a
+/// version `041` container which share its data section can't be written.
+#[derive(Debug)]
+pub struct DexWriter<'a, T> {
+    dex: &'a Dex<T>,
+}
+
+impl<'a, T: AsRef<[u8]>> DexWriter<'a, T> {
+    /// Writer of `dex`.
+    pub fn new(dex: &'a Dex<T>) -> Self {
+        Self { dex }
+    }
+
+    /// The bytes of the dex file, with the checksum and the signature of the written bytes.
+    pub fn write(&self) -> Result<Vec<u8>> {
+        let dex = self.dex;
+        let header = dex.header();
+        check_writable(dex)?;
+        let layout = self.layout()?;
+        let source = dex.source.as_ref();
+        let endian = dex.get_endian();
+
+        let data_off = header.data_off() as usize;
+        let data_end = data_off + header.data_size() as usize;
+        let data = source
+            .get(data_off..data_end)
+            .ok_or_else(|| Error::BadOffset(data_off, "Data section out of bounds".to_string()))?;
+        let link = match dex.link_data() {
+            Some(link) => link,
+            None if header.link_size() == 0 => &[],
+            None => {
+                return Err(Error::BadOffset(
+                    header.link_off() as usize,
+                    "Link section out of bounds".to_string(),
+                ))
+            }
+        };
+        let link_off = header.link_off() as usize;
+        let file_size = data_end.max(link_off + link.len());
+        let mut bytes = vec![0; file_size];
+        bytes[data_off..data_end].copy_from_slice(data);
+        bytes[link_off..link_off + link.len()].copy_from_slice(link);
+
+        self.write_header(&mut bytes, &layout, endian)?;
+        self.write_ids_sections(&mut bytes, &layout, endian)?;
+        self.write_map_list(&mut bytes, &layout, endian)?;
+        write_checksum_and_signature(&mut bytes, endian)?;
+        debug!(target: "writer", "wrote {} bytes", bytes.len());
+        Ok(bytes)
+    }
+
+    /// Places the ids sections one after the other from the end of the header. They must
+    /// end before the data section, which isn't moved.
+    fn layout(&self) -> Result<Layout> {
+        let dex = self.dex;
+        let mut layout = Layout {
+            offsets: [0; 8],
+            counts: [0; 8],
+        };
+        let mut offset = dex.header().header_size();
+        for (index, item_type) in IDS_SECTIONS.iter().enumerate() {
+            let count = dex.map_list().get_len(*item_type).unwrap_or(0);
+            let item_size = item_type.item_size().unwrap_or(0);
+            if count == 0 {
+                continue;
+            }
+            layout.offsets[index] = offset;
+            layout.counts[index] = count;
+            offset = count
+                .checked_mul(item_size)
+                .and_then(|size| offset.checked_add(size))
+                .ok_or_else(|| Error::MalFormed(format!("{:?} section is too large", item_type)))?;
+        }
+        if offset > dex.header().data_off() {
+            return Err(Error::BadOffset(
+                dex.header().data_off() as usize,
+                format!(
+                    "The ids sections end at {:#x}, after the start of the data section",
+                    offset
+                ),
+            ));
+        }
+        Ok(layout)
+    }
+
+    fn write_header(&self, bytes: &mut [u8], layout: &Layout, endian: Endian) -> Result<()> {
+        let header = self.dex.header();
+        let file_size = bytes.len() as uint;
+        bytes[..8].copy_from_slice(&header.magic());
+        // the checksum and the signature are written last.
+        bytes.pwrite_with(file_size, 32, endian)?;
+        bytes.pwrite_with(header.header_size(), 36, endian)?;
+        bytes[40..44].copy_from_slice(&header.endian_tag());
+        let offset = &mut 44;
+        bytes.gwrite_with(header.link_size(), offset, endian)?;
+        bytes.gwrite_with(header.link_off(), offset, endian)?;
+        bytes.gwrite_with(header.map_off(), offset, endian)?;
+        for item_type in &IDS_SECTIONS[..6] {
+            bytes.gwrite_with(layout.count(*item_type), offset, endian)?;
+            bytes.gwrite_with(layout.offset(*item_type), offset, endian)?;
+        }
+        bytes.gwrite_with(header.data_size(), offset, endian)?;
+        bytes.gwrite_with(header.data_off(), offset, endian)?;
+        if header.header_size() >= CONTAINER_HEADER_SIZE && header.version() >= Some(41) {
+            bytes.gwrite_with(file_size, offset, endian)?;
+            bytes.gwrite_with(0 as uint, offset, endian)?;
+        } else if header.header_size() > HEADER_SIZE {
+            // unknown fields of a larger header are kept.
+            let end = header.header_size() as usize;
+            let source = self.dex.source.as_ref();
+            bytes[*offset..end].copy_from_slice(&source[*offset..end]);
+        }
+        Ok(())
+    }
+
+    fn write_ids_sections(&self, bytes: &mut [u8], layout: &Layout, endian: Endian) -> Result<()> {
+        let dex = self.dex;
+        let source = dex.source.as_ref();
+        // the string and type ids and the call site ids are offsets and indexes into other
+        // sections, they are copied.
+        let copied = [
+            (ItemType::StringIdItem, dex.inner.strings_offset()),
+            (ItemType::TypeIdItem, dex.inner.type_ids_offset()),
+            (
+                ItemType::CallSiteIdItem,
+                dex.inner.call_site_ids_offset().unwrap_or(0),
+            ),
+        ];
+        for (item_type, source_offset) in copied {
+            let offset = &mut (layout.offset(item_type) as usize);
+            for index in 0..layout.count(item_type) {
+                let value: uint =
+                    source.pread_with((source_offset + index * 4) as usize, endian)?;
+                bytes.gwrite_with(value, offset, endian)?;
+            }
+        }
+
+        let offset = &mut (layout.offset(ItemType::ProtoIdItem) as usize);
+        for proto in dex.proto_ids() {
+            let proto = proto?;
+            bytes.gwrite_with(proto.shorty(), offset, endian)?;
+            bytes.gwrite_with(proto.return_type(), offset, endian)?;
+            bytes.gwrite_with(proto.params_off(), offset, endian)?;
+        }
+        let offset = &mut (layout.offset(ItemType::FieldIdItem) as usize);
+        for field in dex.field_ids() {
+            let field = field?;
+            bytes.gwrite_with(field.class_idx(), offset, endian)?;
+            bytes.gwrite_with(field.type_idx(), offset, endian)?;
+            bytes.gwrite_with(field.name_idx(), offset, endian)?;
+        }
+        let offset = &mut (layout.offset(ItemType::MethodIdItem) as usize);
+        for method in dex.method_ids() {
+            let method = method?;
+            bytes.gwrite_with(method.class_idx(), offset, endian)?;
+            bytes.gwrite_with(method.proto_idx(), offset, endian)?;
+            bytes.gwrite_with(method.name_idx(), offset, endian)?;
+        }
+        let offset = &mut (layout.offset(ItemType::ClassDefItem) as usize);
+        for class_def in dex.class_defs() {
+            let class_def = class_def?;
+            for value in [
+                class_def.class_idx(),
+                class_def.access_flags(),
+                class_def.superclass_idx(),
+                class_def.interfaces_off(),
+                class_def.source_file_idx(),
+                class_def.annotations_off(),
+                class_def.class_data_off(),
+                class_def.static_values_off(),
+            ] {
+                bytes.gwrite_with(value, offset, endian)?;
+            }
+        }
+        let offset = &mut (layout.offset(ItemType::MethodHandleItem) as usize);
+        for method_handle in dex.method_handles() {
+            let method_handle = method_handle?;
+            let id = match method_handle.id() {
+                crate::method::FieldOrMethodId::Field(id) => id,
+                crate::method::FieldOrMethodId::Method(id) => id,
+            };
+            bytes.gwrite_with(method_handle.handle_type() as ushort, offset, endian)?;
+            bytes.gwrite_with(0 as ushort, offset, endian)?;
+            bytes.gwrite_with(id as ushort, offset, endian)?;
+            bytes.gwrite_with(0 as ushort, offset, endian)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the map list at its offset in the data section, with the entries of the ids
+    /// sections at their new offsets.
+    fn write_map_list(&self, bytes: &mut [u8], layout: &Layout, endian: Endian) -> Result<()> {
+        let map_list = self.dex.map_list();
+        let mut entries: Vec<(ushort, uint, uint)> = map_list
+            .items()
+            .iter()
+            .map(|item| {
+                let offset = match layout.index(item.item_type()) {
+                    Some(_) => layout.offset(item.item_type()),
+                    None => item.offset(),
+                };
+                (item.item_type() as ushort, item.size(), offset)
+            })
+            .chain(
+                map_list
+                    .unknown_items()
+                    .iter()
+                    .map(|item| (item.type_code(), item.size(), item.offset())),
+            )
+            .collect();
+        entries.sort_by_key(|(_, _, offset)| *offset);
+        let offset = &mut (self.dex.header().map_off() as usize);
+        bytes.gwrite_with(entries.len() as uint, offset, endian)?;
+        for (type_code, size, item_offset) in entries {
+            bytes.gwrite_with(type_code, offset, endian)?;
+            bytes.gwrite_with(0 as ushort, offset, endian)?;
+            bytes.gwrite_with(size, offset, endian)?;
+            bytes.gwrite_with(item_offset, offset, endian)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns an error if `dex` is a compact dex file or shares the data section of a
+/// container, which can't be written.
+pub(crate) fn check_writable<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<()> {
+    if dex.compact_header().is_some() {
+        return Err(Error::MalFormed(
+            "Compact dex files can't be written".to_string(),
+        ));
+    }
+    let shares_data = dex.container_size().is_some() && dex.header().data_size() == 0;
+    if dex.header_offset() != 0 || shares_data {
+        return Err(Error::MalFormed(
+            "Dex files sharing the data section of a container can't be written".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Writes the signature and then the checksum of the dex file `bytes` to its header.
+pub(crate) fn write_checksum_and_signature(bytes: &mut [u8], endian: Endian) -> Result<()> {
+    let signature: [u8; 20] = Sha1::digest(&bytes[32..]).into();
+    bytes[12..32].copy_from_slice(&signature);
+    let checksum = adler32::RollingAdler32::from_buffer(&bytes[12..]).hash();
+    bytes.pwrite_with(checksum, 8, endian)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DexWriter;
+    use crate::DexReader;
+
+    #[test]
+    fn test_write_unmodified() {
+        let bytes = std::fs::read("resources/classes.dex").expect("failed to read dex");
+        let dex = DexReader::from_vec(bytes.clone()).expect("failed to load dex");
+        let written = DexWriter::new(&dex).write().unwrap();
+        assert_eq!(written.len(), bytes.len());
+        assert!(written == bytes);
+
+        // a stale signature is recomputed.
+        let mut stale = bytes.clone();
+        stale[12..32].copy_from_slice(&[0; 20]);
+        let checksum = adler32::adler32(&stale[12..]).unwrap();
+        stale[8..12].copy_from_slice(&checksum.to_le_bytes());
+        let dex = DexReader::from_vec(stale).expect("failed to load dex");
+        assert!(!dex.is_signature_valid());
+        let written = dex.to_bytes().unwrap();
+        assert!(written == bytes);
+        let dex = DexReader::from_vec(written).expect("failed to load written dex");
+        assert!(dex.is_signature_valid());
+        assert!(dex.is_checksum_valid());
+    }
+}
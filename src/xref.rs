@@ -0,0 +1,321 @@
+//! Cross references from the code of the methods of a `Dex` to the items it uses, like the
+//! instructions loading a string. The code items are decoded on each query, an `XrefIndex`
+//! decodes them once for repeated queries.
+use std::collections::HashMap;
+
+use getset::CopyGetters;
+
+use crate::{
+    field::FieldId,
+    instruction::{IndexType, Instruction, Opcode},
+    jtype::TypeId,
+    method::MethodId,
+    string::StringId,
+    Dex, Result,
+};
+
+/// An instruction in the code of a method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, CopyGetters)]
+#[get_copy = "pub"]
+pub struct CodeLocation {
+    /// The class defining the method.
+    class: TypeId,
+    /// The method.
+    method: MethodId,
+    /// Address of the instruction, in code units from the start of the method's code.
+    address: usize,
+}
+
+/// Calls `visit` with each instruction of the methods of `dex` which have code, in the
+/// order of the class definitions.
+pub(crate) fn visit_instructions<T, F>(dex: &Dex<T>, mut visit: F) -> Result<()>
+where
+    T: AsRef<[u8]>,
+    F: FnMut(CodeLocation, &Instruction<'_>) -> Result<()>,
+{
+    for class_def in dex.class_defs() {
+        let class_def = class_def?;
+        let class_data = match dex.get_class_data(class_def.class_data_off())? {
+            Some(class_data) => class_data,
+            None => continue,
+        };
+        let methods = class_data
+            .direct_methods()
+            .into_iter()
+            .chain(class_data.virtual_methods())
+            .flat_map(|methods| methods.inner());
+        for method in methods {
+            let code_off = *method.code_offset();
+            let code = match dex.get_method_code_item(method.method_id(), code_off)? {
+                Some(code) => code,
+                None => continue,
+            };
+            for instruction in code.instructions().with_offsets() {
+                let (address, instruction) = instruction?;
+                let location = CodeLocation {
+                    class: class_def.class_idx(),
+                    method: method.method_id(),
+                    address,
+                };
+                visit(location, &instruction)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The `const-string` and `const-string/jumbo` instructions loading the string `string_id`.
+pub fn string_xrefs<T: AsRef<[u8]>>(
+    dex: &Dex<T>,
+    string_id: StringId,
+) -> Result<Vec<CodeLocation>> {
+    let mut locations = Vec::new();
+    visit_instructions(dex, |location, instruction| {
+        let loads_string = matches!(
+            instruction.opcode(),
+            Opcode::ConstString | Opcode::ConstStringJumbo
+        );
+        if loads_string && instruction.index() == Some(u64::from(string_id)) {
+            locations.push(location);
+        }
+        Ok(())
+    })?;
+    Ok(locations)
+}
+
+/// The method invoked by `instruction`, if it is an invoke.
+fn invoked_method(instruction: &Instruction<'_>) -> Option<MethodId> {
+    if instruction.opcode().index_type() == IndexType::Method {
+        instruction.index()
+    } else {
+        None
+    }
+}
+
+/// The invokes of the method `method_id`: `invoke-*` and `invoke-polymorphic` instructions
+/// referencing it. Quickened invokes only have a vtable offset so they aren't found.
+pub fn callers_of<T: AsRef<[u8]>>(dex: &Dex<T>, method_id: MethodId) -> Result<Vec<CodeLocation>> {
+    let mut locations = Vec::new();
+    visit_instructions(dex, |location, instruction| {
+        if invoked_method(instruction) == Some(method_id) {
+            locations.push(location);
+        }
+        Ok(())
+    })?;
+    Ok(locations)
+}
+
+/// Whether an instruction reads or writes a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldAccessKind {
+    /// An `iget-*` or `sget-*` instruction.
+    Read,
+    /// An `iput-*` or `sput-*` instruction.
+    Write,
+}
+
+/// An instruction accessing a field, see `field_accesses`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, CopyGetters)]
+#[get_copy = "pub"]
+pub struct FieldAccess {
+    /// The instruction.
+    location: CodeLocation,
+    /// Whether it reads or writes the field.
+    kind: FieldAccessKind,
+}
+
+/// The field accessed by `instruction` and how, if it is a field access.
+fn accessed_field(instruction: &Instruction<'_>) -> Option<(FieldId, FieldAccessKind)> {
+    use Opcode::*;
+
+    if instruction.opcode().index_type() != IndexType::Field {
+        return None;
+    }
+    let kind = match instruction.opcode() {
+        Iput | IputWide | IputObject | IputBoolean | IputByte | IputChar | IputShort
+        | IputVolatile | IputWideVolatile | IputObjectVolatile | Sput | SputWide | SputObject
+        | SputBoolean | SputByte | SputChar | SputShort | SputVolatile | SputWideVolatile
+        | SputObjectVolatile => FieldAccessKind::Write,
+        _ => FieldAccessKind::Read,
+    };
+    Some((instruction.index()?, kind))
+}
+
+/// The instructions reading or writing the field `field_id`. Quickened field accesses only
+/// have the offset of the field so they aren't found.
+pub fn field_accesses<T: AsRef<[u8]>>(dex: &Dex<T>, field_id: FieldId) -> Result<Vec<FieldAccess>> {
+    let mut accesses = Vec::new();
+    visit_instructions(dex, |location, instruction| {
+        if let Some((accessed, kind)) = accessed_field(instruction) {
+            if accessed == field_id {
+                accesses.push(FieldAccess { location, kind });
+            }
+        }
+        Ok(())
+    })?;
+    Ok(accesses)
+}
+
+/// Cross references of all the code of a `Dex`, which is decoded once when the index is
+/// built.
+#[derive(Debug, Clone, Default)]
+pub struct XrefIndex {
+    callers: HashMap<MethodId, Vec<CodeLocation>>,
+    field_accesses: HashMap<FieldId, Vec<FieldAccess>>,
+}
+
+impl XrefIndex {
+    /// Index of the code of `dex`.
+    pub fn new<T: AsRef<[u8]>>(dex: &Dex<T>) -> Result<Self> {
+        let mut index = Self::default();
+        visit_instructions(dex, |location, instruction| {
+            if let Some(method_id) = invoked_method(instruction) {
+                index.callers.entry(method_id).or_default().push(location);
+            }
+            if let Some((field_id, kind)) = accessed_field(instruction) {
+                let access = FieldAccess { location, kind };
+                index
+                    .field_accesses
+                    .entry(field_id)
+                    .or_default()
+                    .push(access);
+            }
+            Ok(())
+        })?;
+        Ok(index)
+    }
+
+    /// The invokes of the method `method_id`, see `callers_of`.
+    pub fn callers_of(&self, method_id: MethodId) -> &[CodeLocation] {
+        self.callers.get(&method_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// The accesses of the field `field_id`, see `field_accesses`.
+    pub fn field_accesses(&self, field_id: FieldId) -> &[FieldAccess] {
+        self.field_accesses
+            .get(&field_id)
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldAccessKind, XrefIndex};
+    use crate::{string::StringId, strings, DexReader};
+
+    #[test]
+    fn test_string_xrefs() {
+        let dex = DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let references = strings::string_references(&dex).unwrap();
+        let reference = references
+            .iter()
+            .find(|reference| reference.source() == strings::StringSource::Constant)
+            .expect("no const-string in the dex");
+        let mut expected: Vec<_> = references
+            .iter()
+            .filter(|other| {
+                other.source() == strings::StringSource::Constant
+                    && other.value() == reference.value()
+            })
+            .map(|other| (other.method(), other.address()))
+            .collect();
+        expected.sort_unstable();
+
+        let locations = dex.string_literal_xrefs(reference.value()).unwrap();
+        let mut found: Vec<_> = locations
+            .iter()
+            .map(|location| (location.method(), location.address()))
+            .collect();
+        found.sort_unstable();
+        assert_eq!(found, expected);
+        let method = dex.get_method_item(locations[0].method()).unwrap();
+        assert_eq!(
+            u64::from(method.class_idx()),
+            u64::from(locations[0].class())
+        );
+
+        let string_id = dex
+            .strings_uncached()
+            .position(|string| *string.unwrap() == **reference.value())
+            .unwrap();
+        assert_eq!(dex.string_xrefs(string_id as StringId).unwrap(), locations);
+        assert!(dex
+            .string_literal_xrefs("not a string of the dex")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_callers_of() {
+        let dex = DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let method = dex
+            .find_method("Ljava/lang/StringBuilder;->toString()Ljava/lang/String;")
+            .unwrap()
+            .expect("StringBuilder.toString isn't referenced");
+        let callers = dex.callers_of(method.id()).unwrap();
+        assert!(!callers.is_empty());
+        let code_items = dex.method_code_items().unwrap();
+        for caller in &callers {
+            let (_, code) = code_items
+                .iter()
+                .find(|(method_id, _)| *method_id == caller.method())
+                .expect("the caller has no code");
+            let (_, instruction) = code
+                .instructions()
+                .with_offsets()
+                .map(Result::unwrap)
+                .find(|(address, _)| *address == caller.address())
+                .expect("no instruction at the call site");
+            assert!(instruction.opcode().mnemonic().starts_with("invoke-"));
+            assert_eq!(instruction.index(), Some(method.id()));
+        }
+
+        let index = XrefIndex::new(&dex).unwrap();
+        assert_eq!(index.callers_of(method.id()), callers.as_slice());
+        let method_ids = dex.header().method_ids_size() as u64;
+        assert!(index.callers_of(method_ids).is_empty());
+    }
+
+    #[test]
+    fn test_field_accesses() {
+        let dex = DexReader::from_file("resources/classes.dex").expect("failed to open dex");
+        let code_items = dex.method_code_items().unwrap();
+        let index = XrefIndex::new(&dex).unwrap();
+        let mut found = (false, false);
+        let mut scanned = 0;
+        for field in dex.field_ids() {
+            let field_id = field.unwrap().id();
+            let accesses = index.field_accesses(field_id);
+            if !accesses.is_empty() && scanned < 3 {
+                assert_eq!(dex.field_accesses(field_id).unwrap(), accesses);
+                scanned += 1;
+            }
+            for access in accesses {
+                let location = access.location();
+                let (_, code) = code_items
+                    .iter()
+                    .find(|(method_id, _)| *method_id == location.method())
+                    .unwrap();
+                let (_, instruction) = code
+                    .instructions()
+                    .with_offsets()
+                    .map(Result::unwrap)
+                    .find(|(address, _)| *address == location.address())
+                    .unwrap();
+                let mnemonic = instruction.opcode().mnemonic();
+                assert_eq!(instruction.index(), Some(field_id));
+                match access.kind() {
+                    FieldAccessKind::Read => {
+                        found.0 = true;
+                        assert!(mnemonic.starts_with("iget") || mnemonic.starts_with("sget"));
+                    }
+                    FieldAccessKind::Write => {
+                        found.1 = true;
+                        assert!(mnemonic.starts_with("iput") || mnemonic.starts_with("sput"));
+                    }
+                }
+            }
+        }
+        assert_eq!(found, (true, true));
+    }
+}
@@ -194,6 +194,14 @@ test!(
             }
         "#
     };
+    {
+        "DefaultAnnotation.java" => r#"
+            public @interface DefaultAnnotation {
+                public String name();
+                public int value() default 5;
+            }
+        "#
+    };
     {
         "RuntimeAnnotation.java" => r#"
             import java.lang.annotation.Retention;
@@ -222,6 +230,19 @@ test!(
         let annotation_class = annotation_class.unwrap();
         assert_has_access_flags!(annotation_class, [is_public, is_annotation]);
         assert_eq!(annotation_class.methods().count(), 2);
+        assert!(annotation_class.annotation_defaults().expect("malformed annotations").is_empty());
+
+        let default_annotation_class = dex.find_class_by_name("LDefaultAnnotation;");
+        assert!(default_annotation_class.is_ok());
+        let default_annotation_class = default_annotation_class.unwrap();
+        assert!(default_annotation_class.is_some());
+        let default_annotation_class = default_annotation_class.unwrap();
+        let defaults = default_annotation_class.annotation_defaults().expect("malformed annotations");
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(
+            defaults.iter().next().map(|(name, value)| (name.to_string(), *value)),
+            Some(("value".to_string(), &EncodedValue::Int(5)))
+        );
 
         let runtime_annotation_class = dex.find_class_by_name("LRuntimeAnnotation;");
         assert!(runtime_annotation_class.is_ok());
@@ -466,6 +487,7 @@ test!(
         assert_eq!(get_value("bo"), Some(&EncodedValue::Boolean(true)));
         assert_eq!(get_value("nbo"), Some(&EncodedValue::Boolean(false)));
         assert_eq!(get_value("c"), Some(&EncodedValue::Char(b'm'.into())));
+        assert_eq!(get_value("c").and_then(EncodedValue::to_char), Some('m'));
         assert_eq!(get_value("nullString"), Some(&EncodedValue::Null));
         assert_eq!(get_value("nonNullString"), Some(&EncodedValue::String(DexString::from("fjdljfdlj".to_string()))));
         assert_eq!(get_value("remapper"), Some(&EncodedValue::Null));
@@ -563,6 +585,12 @@ test!(
         assert!(method.is_some());
         let method = method.unwrap();
         assert_has_access_flags!(method, [is_static]);
+        let interface3_type = interface3.companion_of().expect("companion class isn't linked");
+        assert_eq!(interface3_type.type_descriptor(), "LMyInterface3;");
+        let interface3_interface = dex.find_class_by_name("LMyInterface3;").unwrap().unwrap();
+        assert_eq!(interface3_interface.companion_class(), Some(interface3.jtype()));
+        assert_eq!(interface3_interface.companion_of(), None);
+        assert!(interface2.companion_class().is_some());
     }
 );
 